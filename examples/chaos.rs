@@ -0,0 +1,354 @@
+//! Chaos
+//!
+//! A Jepsen-style soak test: a 5-node cluster runs over TCP on localhost while
+//! node 1 proposes values non-stop, and the network is partitioned and healed
+//! and nodes are killed and restarted at random. Once the time is up, every
+//! node is brought back and the logs are checked for consistency.
+//!
+//! ```text
+//! cargo run --release --example chaos -- [seconds] [seed]
+//! ```
+//!
+//! A killed node loses its state, like a process crashing without stable
+//! storage. On restart its `Acceptor` copies state from a Phase-1 quorum
+//! before voting again, and its `Learner` catches up from its peers.
+//!
+//! Node 1 is partitioned but never killed: its `Proposer` can't be restored
+//! with the proposal numbers it used, and reusing one for another value could
+//! get two values accepted under it.
+
+use paxos_rust::invariants::{check_agreement, check_single_decree};
+use paxos_rust::sim::Rng;
+use paxos_rust::tcp::{read_frame, write_frame};
+use paxos_rust::{Acceptor, ClusterConfig, Effect, Message, Node, NodeId, Slot};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{env, process};
+
+type Value = Vec<u8>;
+
+const SIZE: u64 = 5;
+const LEADER: NodeId = 1;
+/// Milliseconds between proposals, which keeps the logs held in memory to a
+/// few hundred thousand entries an hour
+const PACE: u64 = 10;
+
+/// The network between the nodes: where they listen, which are up, and which
+/// links are cut.
+struct Network {
+    addrs: BTreeMap<NodeId, SocketAddr>,
+    up: Mutex<BTreeSet<NodeId>>,
+    cut: Mutex<BTreeSet<(NodeId, NodeId)>>,
+}
+
+impl Network {
+    fn is_up(&self, id: NodeId) -> bool {
+        self.up.lock().unwrap().contains(&id)
+    }
+
+    fn is_cut(&self, from: NodeId, to: NodeId) -> bool {
+        self.cut.lock().unwrap().contains(&(from, to))
+    }
+
+    fn partition(&self, side: &BTreeSet<NodeId>) {
+        let mut cut = self.cut.lock().unwrap();
+        cut.clear();
+        for a in 1..=SIZE {
+            for b in 1..=SIZE {
+                if side.contains(&a) != side.contains(&b) {
+                    cut.insert((a, b));
+                }
+            }
+        }
+    }
+
+    fn heal(&self) {
+        self.cut.lock().unwrap().clear();
+    }
+}
+
+/// Connections a node sends through, opened as needed.
+struct Outbox {
+    id: NodeId,
+    net: Arc<Network>,
+    conns: BTreeMap<NodeId, TcpStream>,
+}
+
+impl Outbox {
+    /// Sends the message of `effect` to every node it's meant for, itself
+    /// included. Messages to a node that is down or cut off are lost.
+    fn send(&mut self, effect: &Effect<Value>) {
+        for to in 1..=SIZE {
+            let Some(msg) = effect.message_for(to) else {
+                continue;
+            };
+            if self.net.is_cut(self.id, to) {
+                continue;
+            }
+            if !self.conns.contains_key(&to) {
+                match TcpStream::connect(self.net.addrs[&to]) {
+                    Ok(stream) => {
+                        stream.set_nodelay(true).ok();
+                        self.conns.insert(to, stream);
+                    }
+                    Err(_) => continue,
+                }
+            }
+            if write_frame(self.conns.get_mut(&to).unwrap(), msg).is_err() {
+                self.conns.remove(&to);
+            }
+        }
+    }
+}
+
+/// Accepts connections to node `id` forever, and forwards the messages read
+/// from them to `inbox` while the node is up.
+fn listen(id: NodeId, listener: TcpListener, net: Arc<Network>, inbox: Sender<Message<Value>>) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let net = net.clone();
+        let inbox = inbox.clone();
+        thread::spawn(move || loop {
+            match read_frame(&mut stream) {
+                Ok(msg) if net.is_up(id) => {
+                    let _ = inbox.send(msg);
+                }
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::InvalidData => {
+                    panic!("node {} received a malformed message: {}", id, err)
+                }
+                Err(_) => return,
+            }
+        });
+    }
+}
+
+/// What the nodes share with the harness.
+struct Shared {
+    net: Arc<Network>,
+    config: ClusterConfig,
+    start: Instant,
+    /// Values proposed so far, numbered from 0
+    proposed: AtomicU64,
+    /// The first value any node delivered for each slot
+    log: Mutex<BTreeMap<Slot, Value>>,
+    /// Inconsistencies noticed as values were delivered
+    violations: Mutex<Vec<String>>,
+}
+
+/// A running incarnation of a node.
+struct Running {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<(Node<Value>, Receiver<Message<Value>>)>,
+}
+
+/// Runs node `id` until `stop` is set. A restarted node joins the cluster
+/// rather than vote with empty state.
+fn run(
+    id: NodeId,
+    shared: Arc<Shared>,
+    inbox: Receiver<Message<Value>>,
+    stop: Arc<AtomicBool>,
+    restarted: bool,
+) -> (Node<Value>, Receiver<Message<Value>>) {
+    // Messages received while down are lost.
+    while inbox.try_recv().is_ok() {}
+    let mut node: Node<Value> = Node::new(id, shared.config.clone());
+    node.proposer_mut().set_timeout(Some(100), 2);
+    node.proposer_mut().set_window(8);
+    let mut outbox = Outbox {
+        id,
+        net: shared.net.clone(),
+        conns: BTreeMap::new(),
+    };
+    let mut joined_at = None;
+    let mut proposed_at = 0;
+    if restarted {
+        *node.acceptor_mut() = Acceptor::joining(id, shared.config.clone());
+    }
+
+    while !stop.load(Ordering::SeqCst) {
+        let now = shared.start.elapsed().as_millis() as u64;
+        let mut effects = match inbox.recv_timeout(Duration::from_millis(10)) {
+            Ok(msg) => node.step(msg),
+            Err(RecvTimeoutError::Timeout) => Vec::new(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        // The `State` replies may be lost: ask again until a quorum answered.
+        if !node.acceptor().is_voting() && joined_at.is_none_or(|at| now >= at + 500) {
+            node.acceptor_mut().join();
+            joined_at = Some(now);
+        }
+        if id == LEADER && node.proposer().queued() == 0 && now >= proposed_at + PACE {
+            proposed_at = now;
+            let value = shared.proposed.fetch_add(1, Ordering::SeqCst);
+            node.propose(value.to_be_bytes().to_vec());
+        }
+        node.tick(now);
+        effects.extend(node.take_effects());
+        for effect in &effects {
+            outbox.send(effect);
+        }
+        deliver(id, &mut node, &shared);
+    }
+    (node, inbox)
+}
+
+/// Checks the values node `id` delivers against those delivered before.
+fn deliver(id: NodeId, node: &mut Node<Value>, shared: &Shared) {
+    let decided = node.poll_decided();
+    if decided.is_empty() {
+        return;
+    }
+    let mut log = shared.log.lock().unwrap();
+    let mut violations = shared.violations.lock().unwrap();
+    for (slot, value) in decided {
+        let first = log.entry(slot).or_insert_with(|| (*value).clone());
+        if *first != *value {
+            violations.push(format!(
+                "node {} delivered another value for slot {}",
+                id, slot
+            ));
+        }
+        let proposed = shared.proposed.load(Ordering::SeqCst);
+        let valid = <[u8; 8]>::try_from(value.as_slice())
+            .is_ok_and(|bytes| u64::from_be_bytes(bytes) < proposed);
+        if !valid {
+            violations.push(format!(
+                "node {} delivered an unproposed value for slot {}",
+                id, slot
+            ));
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let secs: u64 = args.next().map_or(30, |s| s.parse().expect("seconds"));
+    let seed: u64 = args.next().map_or(0, |s| s.parse().expect("seed"));
+    let mut rng = Rng::new(seed);
+
+    let mut listeners = BTreeMap::new();
+    let mut addrs = BTreeMap::new();
+    for id in 1..=SIZE {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        addrs.insert(id, listener.local_addr().unwrap());
+        listeners.insert(id, listener);
+    }
+    let net = Arc::new(Network {
+        addrs,
+        up: Mutex::new((1..=SIZE).collect()),
+        cut: Mutex::new(BTreeSet::new()),
+    });
+    let shared = Arc::new(Shared {
+        net: net.clone(),
+        config: ClusterConfig::new((1..=SIZE).collect()),
+        start: Instant::now(),
+        proposed: AtomicU64::new(0),
+        log: Mutex::new(BTreeMap::new()),
+        violations: Mutex::new(Vec::new()),
+    });
+
+    let start = |id: NodeId, inbox, restarted| {
+        let stop = Arc::new(AtomicBool::new(false));
+        let shared = shared.clone();
+        let flag = stop.clone();
+        let handle = thread::spawn(move || run(id, shared, inbox, flag, restarted));
+        Running { stop, handle }
+    };
+    let stop = |running: Running| match running.handle.join() {
+        Ok(result) => result,
+        Err(_) => {
+            eprintln!("a node panicked");
+            process::exit(1);
+        }
+    };
+
+    let mut running = BTreeMap::new();
+    for (id, listener) in listeners {
+        let (tx, rx) = mpsc::channel();
+        let listen_net = net.clone();
+        thread::spawn(move || listen(id, listener, listen_net, tx));
+        running.insert(id, start(id, rx, false));
+    }
+
+    // Nodes that are down, with the inbox they'll restart with
+    let mut down = BTreeMap::new();
+    let (mut kills, mut partitions) = (0, 0);
+    let deadline = Instant::now() + Duration::from_secs(secs);
+    while Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(rng.between(200, 2000)));
+        match rng.between(0, 4) {
+            // Keep a majority up, so that restarted nodes can rejoin.
+            0 if down.len() < 2 => {
+                let id = rng.between(2, SIZE + 1);
+                if let Some(node) = running.remove(&id) {
+                    net.up.lock().unwrap().remove(&id);
+                    node.stop.store(true, Ordering::SeqCst);
+                    let (_, inbox) = stop(node);
+                    down.insert(id, inbox);
+                    kills += 1;
+                }
+            }
+            1 => {
+                if let Some(id) = down.keys().next().copied() {
+                    let inbox = down.remove(&id).unwrap();
+                    net.up.lock().unwrap().insert(id);
+                    running.insert(id, start(id, inbox, true));
+                }
+            }
+            2 => {
+                let side = (1..=SIZE).filter(|_| rng.chance(0.5)).collect();
+                net.partition(&side);
+                partitions += 1;
+            }
+            _ => net.heal(),
+        }
+    }
+
+    // Bring every node back, and let them catch up.
+    net.heal();
+    for (id, inbox) in down {
+        net.up.lock().unwrap().insert(id);
+        running.insert(id, start(id, inbox, true));
+    }
+    thread::sleep(Duration::from_secs(5));
+    let nodes: Vec<Node<Value>> = running
+        .into_values()
+        .map(|node| {
+            node.stop.store(true, Ordering::SeqCst);
+            stop(node).0
+        })
+        .collect();
+
+    let mut violations = shared.violations.lock().unwrap().clone();
+    if let Err(err) = check_agreement(nodes.iter().map(Node::learner)) {
+        violations.push(err.to_string());
+    }
+    if let Err(err) = check_single_decree(nodes.iter().map(Node::acceptor)) {
+        violations.push(err.to_string());
+    }
+    let decided = shared.log.lock().unwrap().len();
+    println!(
+        "{} values proposed, {} decided, {} kills, {} partitions",
+        shared.proposed.load(Ordering::SeqCst),
+        decided,
+        kills,
+        partitions
+    );
+    if !violations.is_empty() {
+        for violation in violations {
+            eprintln!("{}", violation);
+        }
+        process::exit(1);
+    }
+}
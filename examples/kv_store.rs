@@ -0,0 +1,211 @@
+//! Key-value store
+//!
+//! A key-value store replicated over the log: clients run `Get`, `Put` and
+//! compare-and-swap commands through the leader, and every replica applies the
+//! decided commands to its own copy of the store, through `Learner::apply`.
+//! Reads go through the log as well, so that they're linearizable.
+//!
+//! The cluster runs in a `SimCluster`, whose network delays and reorders
+//! messages. Run with `cargo run --example kv_store`; `cargo test` runs the
+//! tests at the bottom.
+
+use paxos_rust::sim::SimCluster;
+use paxos_rust::{Slot, StateMachine};
+use std::collections::BTreeMap;
+
+/// A command of the log.
+#[derive(Debug, PartialEq, Clone)]
+enum Command {
+    Get(String),
+    Put(String, String),
+    /// Sets the key to `new` if its value is `expected`
+    Cas {
+        key: String,
+        expected: Option<String>,
+        new: String,
+    },
+    /// The whole store, carried as a value of the log when compacted
+    Snapshot(BTreeMap<String, String>),
+}
+
+/// What a replica replies to a command.
+#[derive(Debug, PartialEq, Clone)]
+enum Reply {
+    /// The value of the key, before a `Put`
+    Value(Option<String>),
+    /// Whether a compare-and-swap succeeded
+    Swapped(bool),
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct KvStore {
+    data: BTreeMap<String, String>,
+}
+
+impl StateMachine<Command> for KvStore {
+    type Output = Reply;
+
+    fn apply(&mut self, _slot: Slot, command: &Command) -> Reply {
+        match command {
+            Command::Get(key) => Reply::Value(self.data.get(key).cloned()),
+            Command::Put(key, value) => Reply::Value(self.data.insert(key.clone(), value.clone())),
+            Command::Cas { key, expected, new } => {
+                let swapped = self.data.get(key) == expected.as_ref();
+                if swapped {
+                    self.data.insert(key.clone(), new.clone());
+                }
+                Reply::Swapped(swapped)
+            }
+            Command::Snapshot(data) => {
+                self.data = data.clone();
+                Reply::Value(None)
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Command {
+        Command::Snapshot(self.data.clone())
+    }
+
+    fn restore(&mut self, snapshot: &Command) {
+        if let Command::Snapshot(data) = snapshot {
+            self.data = data.clone();
+        }
+    }
+}
+
+fn put(key: &str, value: &str) -> Command {
+    Command::Put(key.into(), value.into())
+}
+
+fn get(key: &str) -> Command {
+    Command::Get(key.into())
+}
+
+fn cas(key: &str, expected: Option<&str>, new: &str) -> Command {
+    Command::Cas {
+        key: key.into(),
+        expected: expected.map(Into::into),
+        new: new.into(),
+    }
+}
+
+/// Runs `commands` through the leader of a 3-node cluster, and returns every
+/// replica's store along with the leader's replies.
+fn run(commands: &[Command], seed: u64) -> (Vec<KvStore>, Vec<(Slot, Reply)>) {
+    let mut sim: SimCluster<Command> = SimCluster::new(3, seed);
+    for command in commands {
+        sim.propose(1, command.clone());
+    }
+    sim.run(100_000);
+
+    let mut replies = Vec::new();
+    let mut stores = Vec::new();
+    for id in 1..=3 {
+        let mut store = KvStore::default();
+        let applied = sim.node_mut(id).learner_mut().apply(&mut store);
+        if id == 1 {
+            replies = applied;
+        }
+        stores.push(store);
+    }
+    (stores, replies)
+}
+
+fn main() {
+    let commands = [
+        put("x", "1"),
+        get("x"),
+        cas("x", Some("1"), "2"),
+        cas("x", Some("1"), "3"),
+        put("y", "a"),
+        get("x"),
+    ];
+    let (stores, replies) = run(&commands, 42);
+    for (slot, reply) in replies {
+        println!("{}: {:?} -> {:?}", slot, commands[slot as usize], reply);
+    }
+    println!("{:?}", stores[0].data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use paxos_rust::{ClusterConfig, Learner, Message, SnapshotData};
+
+    #[test]
+    fn kv_store_replies() {
+        let commands = [
+            put("x", "1"),
+            get("x"),
+            cas("x", Some("1"), "2"),
+            cas("x", Some("1"), "3"),
+            cas("y", None, "a"),
+            put("x", "4"),
+            get("x"),
+        ];
+        let (stores, replies) = run(&commands, 7);
+        let replies: Vec<Reply> = replies.into_iter().map(|(_, reply)| reply).collect();
+
+        assert_eq!(
+            replies,
+            vec![
+                Reply::Value(None),
+                Reply::Value(Some("1".into())),
+                Reply::Swapped(true),
+                Reply::Swapped(false),
+                Reply::Swapped(true),
+                Reply::Value(Some("2".into())),
+                Reply::Value(Some("4".into())),
+            ]
+        );
+        assert_eq!(stores[0].data["x"], "4");
+        assert_eq!(stores[0].data["y"], "a");
+    }
+
+    #[test]
+    fn kv_store_replicas_agree() {
+        let commands: Vec<Command> = (0..50)
+            .map(|i| match i % 3 {
+                0 => put(&format!("k{}", i % 7), &i.to_string()),
+                1 => cas(&format!("k{}", i % 5), Some(&(i - 1).to_string()), "cas"),
+                _ => get(&format!("k{}", i % 7)),
+            })
+            .collect();
+
+        for seed in 0..10 {
+            let (stores, replies) = run(&commands, seed);
+
+            assert_eq!(replies.len(), commands.len());
+            assert!(stores.iter().all(|store| *store == stores[0]));
+        }
+    }
+
+    #[test]
+    fn kv_store_snapshot() {
+        let mut sim: SimCluster<Command> = SimCluster::new(3, 1);
+        sim.propose(1, put("x", "1"));
+        sim.propose(1, put("y", "2"));
+        sim.run(100_000);
+
+        let mut store = KvStore::default();
+        let learner = sim.node_mut(1).learner_mut();
+        learner.apply(&mut store);
+        learner.compact(&store);
+
+        let (index, value) = learner.snapshot().map(|(i, v)| (i, v.clone())).unwrap();
+
+        // A lagging replica restores the store from the snapshot.
+        let mut lagging: Learner<Command> = Learner::new(2, ClusterConfig::new(vec![1, 2, 3]));
+        lagging.receive_install_snapshot(Message::InstallSnapshot(SnapshotData {
+            from: 1,
+            to: 2,
+            index,
+            value,
+        }));
+        let mut restored = KvStore::default();
+        lagging.apply(&mut restored);
+
+        assert_eq!(restored, store);
+    }
+}
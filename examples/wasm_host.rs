@@ -0,0 +1,108 @@
+//! WASM host
+//!
+//! The roles are plain state machines, so they run wherever the crate builds,
+//! WASM included: the host hands them messages and the time, and they never
+//! block, spawn threads or read a clock. This example exports a 3-node
+//! `SimCluster` to a WASM host, which drives it with its own time:
+//!
+//! ```text
+//! cargo build --example wasm_host --target wasm32-unknown-unknown
+//! ```
+//!
+//! and then, e.g. from JavaScript:
+//!
+//! ```text
+//! const { instance } = await WebAssembly.instantiate(bytes);
+//! const { paxos_start, paxos_propose, paxos_run_until, paxos_decided } = instance.exports;
+//! paxos_start(42n);
+//! paxos_propose(1n, 7n);
+//! paxos_run_until(BigInt(Math.floor(performance.now())));
+//! paxos_decided(2n); // 1n
+//! ```
+//!
+//! A WASM module has a single thread, so the cluster lives in a
+//! `thread_local`. `cargo test` runs the tests at the bottom natively.
+
+use paxos_rust::sim::SimCluster;
+use std::cell::RefCell;
+
+thread_local! {
+    static CLUSTER: RefCell<Option<SimCluster<u64>>> = const { RefCell::new(None) };
+}
+
+fn with_cluster<R>(f: impl FnOnce(&mut SimCluster<u64>) -> R) -> Option<R> {
+    CLUSTER.with(|cluster| cluster.borrow_mut().as_mut().map(f))
+}
+
+/// Starts a 3-node cluster whose network is driven by `seed`, replacing any
+/// previous one.
+#[no_mangle]
+pub extern "C" fn paxos_start(seed: u64) {
+    CLUSTER.with(|cluster| *cluster.borrow_mut() = Some(SimCluster::new(3, seed)));
+}
+
+/// Proposes `value` through node `id`. Returns 0 if no cluster was started,
+/// or there's no such node.
+#[no_mangle]
+pub extern "C" fn paxos_propose(id: u64, value: u64) -> u32 {
+    with_cluster(|sim| {
+        if sim.ids().any(|node| node == id) {
+            sim.propose(id, value);
+            1
+        } else {
+            0
+        }
+    })
+    .unwrap_or(0)
+}
+
+/// Runs every event due up to `now`, the host's time in milliseconds.
+#[no_mangle]
+pub extern "C" fn paxos_run_until(now: u64) {
+    with_cluster(|sim| sim.run_until(now.max(sim.now())));
+}
+
+/// The number of values node `id` learned were decided.
+#[no_mangle]
+pub extern "C" fn paxos_decided(id: u64) -> u64 {
+    with_cluster(|sim| {
+        if sim.ids().any(|node| node == id) {
+            sim.decided(id).len() as u64
+        } else {
+            0
+        }
+    })
+    .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use paxos_rust::Clock;
+    use std::cell::Cell;
+
+    /// Drives the cluster with `clock` until every node learned `count` values,
+    /// as a host would from its event loop.
+    fn drive(clock: impl Clock, count: u64, mut wait: impl FnMut()) {
+        while (1..=3).any(|id| paxos_decided(id) < count) {
+            wait();
+            paxos_run_until(clock.now());
+        }
+    }
+
+    #[test]
+    fn host_drives_cluster() {
+        paxos_start(7);
+        assert_eq!(paxos_propose(1, 10), 1);
+        assert_eq!(paxos_propose(1, 11), 1);
+        assert_eq!(paxos_propose(4, 12), 0);
+
+        // The host's time, injected as a closure.
+        let now = Cell::new(0);
+        drive(|| now.get(), 2, || now.set(now.get() + 5));
+
+        for id in 1..=3 {
+            assert_eq!(paxos_decided(id), 2);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+/*
+ * C bindings for the Paxos roles of paxos-rust.
+ *
+ * A PaxosNode runs the Proposer, Acceptor and Learner of a node as a pure
+ * state machine over byte string values. The host owns all I/O: it feeds the
+ * node the messages it receives and the current time, then drains the
+ * resulting effects with paxos_node_next_effect, carrying each of them out in
+ * order. See ffi/src/lib.rs for the details.
+ *
+ * Link against the paxos_ffi static or shared library, built with
+ * `cargo build -p paxos-ffi --release`.
+ */
+
+#ifndef PAXOS_H
+#define PAXOS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A message to send to every node, data holding its wire encoding. */
+#define PAXOS_EFFECT_SEND 1
+/* A value decided, data for slot. Reported once for every slot, in order. */
+#define PAXOS_EFFECT_DECIDE 2
+/* A vote to make durable before carrying out the effects that follow: the
+ * highest proposal number promised, ballot, and if accepted, the value data
+ * accepted for slot under accepted_ballot. */
+#define PAXOS_EFFECT_PERSIST 3
+/* A timer to start: call paxos_node_tick once at is reached. */
+#define PAXOS_EFFECT_TIMER 4
+/* The node's proposal number accepted_ballot was outbid by ballot, promised
+ * by an acceptor, whose low 16 bits hold the ID of the node using it. */
+#define PAXOS_EFFECT_PREEMPTED 5
+
+typedef struct PaxosNode PaxosNode;
+
+/* An effect for the host to carry out. Fields that don't apply to its kind
+ * are zeroed. */
+typedef struct PaxosEffect {
+    uint32_t kind;
+    uint64_t slot;
+    uint64_t ballot;
+    bool accepted;
+    uint64_t accepted_ballot;
+    uint64_t at;
+    /* Valid until the next call on the node */
+    const uint8_t *data;
+    size_t len;
+} PaxosEffect;
+
+/* Creates node id of the cluster made up of the count members at members. */
+PaxosNode *paxos_node_new(uint64_t id, const uint64_t *members, size_t count);
+
+/* Frees a node. Does nothing if node is NULL. */
+void paxos_node_free(PaxosNode *node);
+
+/* Proposes the len bytes at value for the next slot, returning the ID of the
+ * proposal's handle. */
+uint64_t paxos_node_propose(PaxosNode *node, const uint8_t *value, size_t len);
+
+/* Hands the node the wire encoded message of len bytes at msg. Returns 0, or
+ * the error code of a message that couldn't be decoded. */
+int32_t paxos_node_step(PaxosNode *node, const uint8_t *msg, size_t len);
+
+/* Advances the node's clock to now, in milliseconds. */
+void paxos_node_tick(PaxosNode *node, uint64_t now);
+
+/* Takes the node's next effect into effect. Returns false if none is left. */
+bool paxos_node_next_effect(PaxosNode *node, PaxosEffect *effect);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PAXOS_H */
@@ -0,0 +1,333 @@
+//! C bindings for the Paxos roles of `paxos-rust`.
+//!
+//! A `PaxosNode` runs the `Proposer`, `Acceptor` and `Learner` of a node as a
+//! pure state machine over byte string values, driven through the
+//! `extern "C"` functions below, as declared in `include/paxos.h`. The host
+//! owns all I/O: it feeds the node the messages it receives, in their `wire`
+//! encoding, and the current time, then drains the resulting effects with
+//! `paxos_node_next_effect`, carrying each of them out in order:
+//!
+//! | Kind                     | What the host does                                  |
+//! |--------------------------|-----------------------------------------------------|
+//! | `PAXOS_EFFECT_SEND`      | Sends `data` to every node, this one included       |
+//! | `PAXOS_EFFECT_DECIDE`    | Reports `data` as the value decided for `slot`      |
+//! | `PAXOS_EFFECT_PERSIST`   | Makes the `Acceptor`'s vote durable before going on |
+//! | `PAXOS_EFFECT_TIMER`     | Calls `paxos_node_tick` once `at` is reached        |
+//! | `PAXOS_EFFECT_PREEMPTED` | May redirect requests to the node using `ballot`    |
+//!
+//! Functions returning an `int32_t` return 0 on success, or the stable code
+//! of the `paxos_rust::Error` raised otherwise. A `PaxosNode` isn't
+//! thread-safe: calls on the same node must not overlap.
+
+use paxos_rust::wire::{decode, encode};
+use paxos_rust::{ClusterConfig, Effect, Error, Message, Node, NodeId};
+use std::collections::VecDeque;
+use std::{ptr, slice};
+
+/// A message to send to every node, `data` holding its `wire` encoding.
+pub const PAXOS_EFFECT_SEND: u32 = 1;
+/// A value decided, `data` for `slot`. Reported once for every slot, in slot
+/// order.
+pub const PAXOS_EFFECT_DECIDE: u32 = 2;
+/// A vote to make durable: the highest proposal number promised, `ballot`,
+/// and if `accepted`, the value `data` accepted for `slot` under
+/// `accepted_ballot`.
+pub const PAXOS_EFFECT_PERSIST: u32 = 3;
+/// A timer to start, firing at `at`.
+pub const PAXOS_EFFECT_TIMER: u32 = 4;
+/// The node's proposal number `accepted_ballot` was outbid by `ballot`,
+/// promised by an `Acceptor`, whose low 16 bits hold the ID of the node using
+/// it.
+pub const PAXOS_EFFECT_PREEMPTED: u32 = 5;
+
+/// A node of the cluster, behind an opaque pointer.
+pub struct PaxosNode {
+    node: Node<Vec<u8>>,
+    /// Effects produced and not yet taken by the host
+    effects: VecDeque<Effect<Vec<u8>>>,
+    /// Bytes pointed to by the last effect taken
+    current: Vec<u8>,
+}
+
+impl PaxosNode {
+    /// Queues `effects`, reporting decided values through `poll_decided`
+    /// instead, so that each is reported once and in slot order.
+    fn queue(&mut self, effects: Vec<Effect<Vec<u8>>>) {
+        self.effects.extend(
+            effects
+                .into_iter()
+                .filter(|effect| !matches!(effect, Effect::Decide(..))),
+        );
+        for (slot, value) in self.node.poll_decided() {
+            self.effects.push_back(Effect::Decide(slot, value));
+        }
+    }
+
+    fn collect(&mut self) {
+        let effects = self.node.take_effects();
+        self.queue(effects);
+    }
+}
+
+/// An effect for the host to carry out. Fields that don't apply to its
+/// `kind` are zeroed.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PaxosEffect {
+    /// One of the `PAXOS_EFFECT_*` constants
+    pub kind: u32,
+    /// Slot of a decided or accepted value
+    pub slot: u64,
+    /// Highest proposal number promised
+    pub ballot: u64,
+    /// Whether a value was accepted, along with the vote to persist
+    pub accepted: bool,
+    /// Proposal number the value was accepted under, or the one preempted
+    pub accepted_ballot: u64,
+    /// Time a timer fires at, in milliseconds
+    pub at: u64,
+    /// Message or value bytes, valid until the next call on the node
+    pub data: *const u8,
+    /// Length of `data`
+    pub len: usize,
+}
+
+impl Default for PaxosEffect {
+    fn default() -> Self {
+        Self {
+            kind: 0,
+            slot: 0,
+            ballot: 0,
+            accepted: false,
+            accepted_ballot: 0,
+            at: 0,
+            data: ptr::null(),
+            len: 0,
+        }
+    }
+}
+
+/// The bytes `data` points to, empty if `len` is 0.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, unless `len` is 0.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+/// Creates node `id` of the cluster made up of the `count` members at
+/// `members`. The node is freed by `paxos_node_free`.
+///
+/// # Safety
+///
+/// `members` must point to `count` IDs, unless `count` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn paxos_node_new(
+    id: NodeId,
+    members: *const NodeId,
+    count: usize,
+) -> *mut PaxosNode {
+    let members = if count == 0 {
+        Vec::new()
+    } else {
+        slice::from_raw_parts(members, count).to_vec()
+    };
+    Box::into_raw(Box::new(PaxosNode {
+        node: Node::new(id, ClusterConfig::new(members)),
+        effects: VecDeque::new(),
+        current: Vec::new(),
+    }))
+}
+
+/// Frees a node created by `paxos_node_new`. Does nothing if `node` is null.
+///
+/// # Safety
+///
+/// `node` must have been returned by `paxos_node_new`, and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn paxos_node_free(node: *mut PaxosNode) {
+    if !node.is_null() {
+        drop(Box::from_raw(node));
+    }
+}
+
+/// Proposes the `len` bytes at `value` for the next slot, returning the ID
+/// of the proposal's handle.
+///
+/// # Safety
+///
+/// `node` must be a live node, and `value` must point to `len` bytes unless
+/// `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn paxos_node_propose(
+    node: *mut PaxosNode,
+    value: *const u8,
+    len: usize,
+) -> u64 {
+    let node = &mut *node;
+    let handle = node.node.propose(bytes(value, len).to_vec());
+    node.collect();
+    handle.id()
+}
+
+/// Hands the node the `wire` encoded message of `len` bytes at `msg`. A
+/// message that can't be decoded is dropped, and its `DecodeError`'s code
+/// returned.
+///
+/// # Safety
+///
+/// `node` must be a live node, and `msg` must point to `len` bytes unless
+/// `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn paxos_node_step(node: *mut PaxosNode, msg: *const u8, len: usize) -> i32 {
+    let node = &mut *node;
+    let msg: Message<Vec<u8>> = match decode(bytes(msg, len)) {
+        Ok(msg) => msg,
+        Err(err) => return Error::from(err).code().into(),
+    };
+    let effects = node.node.step(msg);
+    node.queue(effects);
+    0
+}
+
+/// Advances the node's clock to `now`, in milliseconds. See `Node::tick`.
+///
+/// # Safety
+///
+/// `node` must be a live node.
+#[no_mangle]
+pub unsafe extern "C" fn paxos_node_tick(node: *mut PaxosNode, now: u64) {
+    let node = &mut *node;
+    node.node.tick(now);
+    node.collect();
+}
+
+/// Takes the node's next effect into `effect`. Returns `false`, leaving
+/// `effect` untouched, if there's none left.
+///
+/// # Safety
+///
+/// `node` must be a live node, and `effect` must point to a writable
+/// `PaxosEffect`.
+#[no_mangle]
+pub unsafe extern "C" fn paxos_node_next_effect(
+    node: *mut PaxosNode,
+    effect: *mut PaxosEffect,
+) -> bool {
+    let node = &mut *node;
+    let next = match node.effects.pop_front() {
+        Some(next) => next,
+        None => return false,
+    };
+    let mut out = PaxosEffect::default();
+    node.current = match next {
+        // Effects carry no recipients over the C API: targeted messages go
+        // to every node.
+        Effect::SendMessage(msg) | Effect::SendMessageTo(_, msg) => {
+            out.kind = PAXOS_EFFECT_SEND;
+            encode(&msg)
+        }
+        Effect::Decide(slot, value) => {
+            out.kind = PAXOS_EFFECT_DECIDE;
+            out.slot = slot;
+            value.to_vec()
+        }
+        Effect::PersistState {
+            promised_n,
+            accepted,
+        } => {
+            out.kind = PAXOS_EFFECT_PERSIST;
+            out.ballot = promised_n;
+            match accepted {
+                Some((slot, proposal)) => {
+                    out.accepted = true;
+                    out.slot = slot;
+                    out.accepted_ballot = proposal.n;
+                    proposal.value.to_vec()
+                }
+                None => Vec::new(),
+            }
+        }
+        Effect::StartTimer { at } => {
+            out.kind = PAXOS_EFFECT_TIMER;
+            out.at = at;
+            Vec::new()
+        }
+        Effect::Preempted { ballot, by } => {
+            out.kind = PAXOS_EFFECT_PREEMPTED;
+            out.ballot = by;
+            out.accepted_ballot = ballot;
+            Vec::new()
+        }
+        // Effects the C API has no kind for are skipped.
+        _ => return paxos_node_next_effect(node, effect),
+    };
+    if !node.current.is_empty() {
+        out.data = node.current.as_ptr();
+        out.len = node.current.len();
+    }
+    *effect = out;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drains every node's effects, delivering the messages sent to every
+    /// node until none is left. Returns the values decided, by node.
+    unsafe fn run(nodes: &[*mut PaxosNode]) -> Vec<Vec<(u64, Vec<u8>)>> {
+        let mut decided = vec![Vec::new(); nodes.len()];
+        loop {
+            let mut sent = Vec::new();
+            for (i, node) in nodes.iter().enumerate() {
+                let mut effect = PaxosEffect::default();
+                while paxos_node_next_effect(*node, &mut effect) {
+                    let data = bytes(effect.data, effect.len).to_vec();
+                    match effect.kind {
+                        PAXOS_EFFECT_SEND => sent.push(data),
+                        PAXOS_EFFECT_DECIDE => decided[i].push((effect.slot, data)),
+                        _ => {}
+                    }
+                }
+            }
+            if sent.is_empty() {
+                return decided;
+            }
+            for msg in sent {
+                for node in nodes {
+                    assert_eq!(paxos_node_step(*node, msg.as_ptr(), msg.len()), 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ffi_cluster_decides() {
+        unsafe {
+            let members = [1, 2, 3];
+            let nodes: Vec<*mut PaxosNode> = members
+                .iter()
+                .map(|id| paxos_node_new(*id, members.as_ptr(), members.len()))
+                .collect();
+
+            paxos_node_propose(nodes[0], b"x".as_ptr(), 1);
+            let decided = run(&nodes);
+            for values in decided {
+                assert_eq!(values, vec![(0, b"x".to_vec())]);
+            }
+
+            // Garbage is refused with the code of its `DecodeError`.
+            assert_eq!(paxos_node_step(nodes[1], [0xff].as_ptr(), 1), 401);
+
+            for node in nodes {
+                paxos_node_free(node);
+            }
+        }
+    }
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use paxos_rust::invariants::check_single_decree;
+use paxos_rust::{Acceptor, ClusterConfig, Message};
+
+fuzz_target!(|msgs: Vec<Message<u64>>| {
+    let mut acceptor: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+    for msg in msgs {
+        acceptor.step(msg);
+        check_single_decree([&acceptor]).unwrap();
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use paxos_rust::{ClusterConfig, Learner, Message};
+
+fuzz_target!(|msgs: Vec<Message<u64>>| {
+    let mut learner: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1, 2, 3]));
+    // Arbitrary slots would have the `Learner` track every gap below them.
+    learner.set_max_lead(Some(1024));
+    for msg in msgs {
+        learner.step(msg);
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use paxos_rust::{ClusterConfig, Message, Node};
+
+fuzz_target!(|input: (Vec<u64>, Vec<Message<u64>>)| {
+    let (values, msgs) = input;
+    let mut node: Node<u64> = Node::new(1, ClusterConfig::new(vec![1, 2, 3]));
+    // Arbitrary slots would have the `Learner` track every gap below them.
+    node.learner_mut().set_max_lead(Some(1024));
+    for value in values {
+        node.propose(value);
+    }
+    for msg in msgs {
+        node.step(msg);
+        node.poll_decided();
+    }
+});
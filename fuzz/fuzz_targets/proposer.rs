@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use paxos_rust::{ClusterConfig, Message, Proposer};
+
+fuzz_target!(|input: (Vec<u64>, Vec<Message<u64>>)| {
+    let (values, msgs) = input;
+    let mut proposer: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+    for value in values {
+        proposer.prepare(value);
+    }
+    for msg in msgs {
+        proposer.step(msg);
+    }
+});
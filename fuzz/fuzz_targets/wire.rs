@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use paxos_rust::wire::{decode, encode};
+use paxos_rust::Message;
+
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(msg) = decode::<Vec<u8>>(bytes) {
+        let decoded: Message<Vec<u8>> = decode(&encode(&msg)).unwrap();
+        assert_eq!(decoded, msg);
+    }
+});
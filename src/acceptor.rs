@@ -1,127 +1,1030 @@
 //! Acceptor
 
-use message::{AcceptedData, Message, Messenger, PromiseData};
-use std::sync::Arc;
+use crate::anti_entropy::AntiEntropy;
+use crate::config::{ClusterConfig, NodeId};
+use crate::effect::Effect;
+#[cfg(feature = "paranoid-checks")]
+use crate::error::InvariantViolation;
+use crate::event::{EventSink, Observer, ObserverSink, PaxosEvent};
+use crate::message::{
+    AcceptedData, BoxedMessenger, Handler, JoinData, Message, Messenger, NackData, PromiseData,
+    ReadReplyData, Slot, StateData,
+};
+use crate::metrics::{Metrics, MetricsSink};
+use crate::nack::NackLimiter;
+use crate::proposer::ballot_owner;
+use crate::storage::RecoveryReport;
+use crate::validate::Validator;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A value accepted by an `Acceptor` for a single slot.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AcceptedProposal<T> {
+    /// The proposal number the value was accepted under
+    pub n: u64,
+    /// The accepted value
+    pub value: Arc<T>,
+}
 
 /// The Acceptors act as the fault-tolerant "memory" of the protocol. Acceptors
 /// are collected into groups called Quorums. Any message sent to an Acceptor
 /// must be sent to a Quorum of Acceptors. Any message received from an Acceptor
 /// is ignored unless a copy is received from each Acceptor in a Quorum.
-pub struct Acceptor<T> {
+pub struct Acceptor<T, M = BoxedMessenger<T>> {
     /// `Acceptor`'s ID
-    pub id: u64,
-    /// The highest proposal number promised
-    pub proposal_n: u64,
-    /// The currently promised value
-    pub value: Option<Arc<T>>,
+    pub(crate) id: NodeId,
+    /// The highest proposal number promised, across all slots
+    pub(crate) promised_n: u64,
+    /// The last accepted proposal of each slot
+    pub(crate) accepted: BTreeMap<Slot, AcceptedProposal<T>>,
+    /// Fast rounds opened (slot => proposal_n). A slot whose value was
+    /// accepted under the same proposal number was accepted in a fast round
+    pub(crate) fast_rounds: BTreeMap<Slot, u64>,
     /// `Messenger` specifying communication with other nodes
-    pub messenger: Option<Box<Messenger<T>>>,
+    pub(crate) messenger: Option<M>,
+    /// Effects waiting to be taken, while no `Messenger` is set
+    pub(crate) effects: Vec<Effect<T>>,
+    /// Callback notified of the `Acceptor`'s progress
+    pub(crate) events: Option<EventSink>,
+    /// `Metrics` the `Acceptor` reports to
+    pub(crate) metrics: Option<MetricsSink>,
+    /// `Observer` of the `Acceptor`'s transitions
+    pub(crate) observer: Option<ObserverSink<T>>,
+    /// The cluster the `Acceptor` is a member of
+    pub(crate) config: ClusterConfig,
+    /// Whether the `Acceptor` takes part in votes. A joining `Acceptor` only
+    /// does once it has copied the state of a Phase-1 quorum
+    pub(crate) voting: bool,
+    /// Nodes whose state a joining `Acceptor` has copied so far
+    pub(crate) transferred: Vec<NodeId>,
+    /// Slot below which accepted values were dropped, once covered by a
+    /// snapshot
+    pub(crate) truncated: Slot,
+    /// The `Proposer` whose `Prepare` or `Any` was last promised, named in
+    /// `Nack`s so that others can redirect to it
+    pub(crate) leader: Option<NodeId>,
+    /// Damage found in the log the `Acceptor` was restored from. It doesn't
+    /// vote until acknowledged
+    pub(crate) recovery: Option<RecoveryReport>,
+    /// Checks the envelopes handed to `receive_envelope`, if set
+    pub(crate) validator: Option<Validator<T>>,
+    /// Limits the `Nack`s sent to each `Proposer`
+    pub(crate) nacks: NackLimiter,
+    /// When to gossip a `Digest` to the other `Acceptor`s
+    pub(crate) gossip: AntiEntropy,
+    /// Transitions refused for breaking an invariant, waiting to be taken
+    #[cfg(feature = "paranoid-checks")]
+    pub(crate) violations: Vec<InvariantViolation>,
 }
 
 impl<T> Acceptor<T> {
     /// Creates a new `Acceptor`.
-    pub fn new(id: u64) -> Self {
+    pub fn new(id: NodeId, config: ClusterConfig) -> Self {
+        Self::init(id, config)
+    }
+
+    /// Sets the `Messenger` the `Acceptor` sends through.
+    pub fn set_messenger<N>(&mut self, messenger: N)
+    where
+        N: Messenger<T> + Send + 'static,
+    {
+        self.messenger = Some(Box::new(messenger));
+    }
+}
+
+impl<T, M: Messenger<T>> Acceptor<T, M> {
+    /// Creates a new `Acceptor` sending through `messenger`, without boxing it.
+    pub fn with_messenger(id: NodeId, config: ClusterConfig, messenger: M) -> Self {
+        Self {
+            messenger: Some(messenger),
+            ..Self::init(id, config)
+        }
+    }
+
+    fn init(id: NodeId, config: ClusterConfig) -> Self {
         Self {
             id,
-            proposal_n: 0,
-            value: None,
+            promised_n: 0,
+            accepted: BTreeMap::new(),
+            fast_rounds: BTreeMap::new(),
             messenger: None,
+            effects: Vec::new(),
+            events: None,
+            metrics: None,
+            observer: None,
+            config,
+            voting: true,
+            transferred: Vec::new(),
+            truncated: 0,
+            leader: None,
+            recovery: None,
+            validator: None,
+            nacks: NackLimiter::default(),
+            gossip: AntiEntropy::default(),
+            #[cfg(feature = "paranoid-checks")]
+            violations: Vec::new(),
+        }
+    }
+
+    /// The `Acceptor`'s ID.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Advances the `Acceptor`'s clock to `now`, in milliseconds, answering
+    /// the proposals held back by Nack suppression that may be answered now,
+    /// and gossiping a `Digest` if anti-entropy is due.
+    pub fn set_time(&mut self, now: u64) {
+        self.flush_nacks(now);
+        self.gossip(now);
+    }
+
+    /// The cluster the `Acceptor` is a member of.
+    pub fn config(&self) -> &ClusterConfig {
+        &self.config
+    }
+
+    /// The highest proposal number promised.
+    pub fn current_ballot(&self) -> u64 {
+        self.promised_n
+    }
+
+    /// The last proposal accepted for `slot`, if any.
+    pub fn accepted(&self, slot: Slot) -> Option<&AcceptedProposal<T>> {
+        self.accepted.get(&slot)
+    }
+
+    /// Whether the `Acceptor` takes part in votes: neither joining, nor
+    /// waiting for damage to its log to be acknowledged.
+    pub fn is_voting(&self) -> bool {
+        self.voting && self.recovery.is_none()
+    }
+
+    /// The slot below which accepted values were dropped.
+    pub fn truncated(&self) -> Slot {
+        self.truncated
+    }
+
+    /// Sets the callback notified of the `Acceptor`'s progress.
+    pub fn set_events<F>(&mut self, events: F)
+    where
+        F: FnMut(PaxosEvent) + Send + 'static,
+    {
+        self.events = Some(Box::new(events));
+    }
+
+    /// Sets the `Metrics` the `Acceptor` reports to.
+    pub fn set_metrics<X>(&mut self, metrics: X)
+    where
+        X: Metrics + Send + 'static,
+    {
+        self.metrics = Some(Box::new(metrics));
+    }
+
+    /// Sets the `Observer` of the `Acceptor`'s transitions.
+    pub fn set_observer<O>(&mut self, observer: O)
+    where
+        O: Observer<T> + Send + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Takes the transitions refused since the last call for breaking an
+    /// invariant, each of which is a bug.
+    #[cfg(feature = "paranoid-checks")]
+    pub fn take_violations(&mut self) -> Vec<InvariantViolation> {
+        core::mem::take(&mut self.violations)
+    }
+
+    /// Moves to `config`, as decided through the log. A joining `Acceptor`
+    /// copies its state from a quorum of `config` from then on. See
+    /// `Membership`.
+    pub fn reconfigure(&mut self, config: ClusterConfig) {
+        self.config = config;
+    }
+
+    /// Drops the values accepted below `index`, once a snapshot covering them
+    /// has been taken. Proposals for those slots are ignored from then on, as
+    /// the `Acceptor` could no longer report what it accepted.
+    pub fn truncate(&mut self, index: Slot) {
+        if index > self.truncated {
+            self.truncated = index;
+            self.accepted = self.accepted.split_off(&index);
+            self.fast_rounds = self.fast_rounds.split_off(&index);
+        }
+    }
+
+    /// Creates a new `Acceptor` joining an existing cluster. It ignores every
+    /// proposal until `join` has copied enough state over: voting with empty
+    /// state could let a value be decided over one chosen before it joined.
+    pub fn joining(id: NodeId, config: ClusterConfig) -> Self {
+        Self {
+            voting: false,
+            ..Self::init(id, config)
+        }
+    }
+
+    /// Asks the other members for their state.
+    pub fn join(&mut self) {
+        let join = Message::Join(JoinData { from: self.id });
+        self.effect(Effect::SendMessage(join));
+    }
+
+    /// Receives a `Join` message from a joining node, replying with the
+    /// promised and accepted state. Ignored while joining itself.
+    pub fn receive_join(&mut self, msg: &Message<T>) {
+        if let Message::Join(data) = msg {
+            span!("receive_join", acceptor = self.id, from = data.from);
+            if !self.is_voting() || data.from == self.id {
+                return;
+            }
+            let state = Message::State(StateData {
+                from: self.id,
+                to: data.from,
+                promised_n: self.promised_n,
+                accepted: self
+                    .accepted
+                    .iter()
+                    .map(|(slot, a)| (*slot, a.n, a.value.clone()))
+                    .collect(),
+                decided: Vec::new(),
+            });
+            self.effect(Effect::SendMessage(state));
+        }
+    }
+
+    /// Receives a `State` message in reply to a `Join`, merging it with the
+    /// state copied so far: the highest promise, and the highest accepted
+    /// proposal of every slot. The `Acceptor` starts voting once the senders
+    /// form a Phase-1 quorum, as a single node may have missed accepted values.
+    pub fn receive_state(&mut self, msg: &Message<T>) {
+        if let Message::State(data) = msg {
+            span!("receive_state", acceptor = self.id, from = data.from);
+            if self.voting
+                || data.to != self.id
+                || !self.config.is_member(data.from)
+                || self.transferred.contains(&data.from)
+            {
+                return;
+            }
+            self.transferred.push(data.from);
+            self.promised_n = self.promised_n.max(data.promised_n);
+            for (slot, n, value) in &data.accepted {
+                match self.accepted.get(slot) {
+                    Some(a) if a.n >= *n => {}
+                    _ => {
+                        self.accepted.insert(
+                            *slot,
+                            AcceptedProposal {
+                                n: *n,
+                                value: value.clone(),
+                            },
+                        );
+                    }
+                }
+            }
+            if self.config.is_phase1_quorum(&self.transferred) {
+                self.voting = true;
+                self.transferred.clear();
+            }
         }
     }
 
-    /// Receives a `Prepare` message from a `Proposer`.
+    /// Receives a `Prepare` message from a `Proposer`. A `Prepare` under the
+    /// proposal number promised already is answered again, as a `Proposer`
+    /// with several slots in flight runs the first phase of each under the
+    /// same number. No two `Proposer`s use the same number.
     pub fn receive_prepare(&mut self, msg: &Message<T>) {
         if let Message::Prepare(data) = msg {
-            if data.id > self.proposal_n {
-                self.proposal_n = data.id;
+            span!(
+                "receive_prepare",
+                acceptor = self.id,
+                slot = data.slot,
+                ballot = data.id,
+                promised = self.promised_n
+            );
+            if !self.votes() || data.slot < self.truncated {
+                return;
+            }
+            if data.id < self.promised_n {
+                self.nack(data.slot, data.id);
+            } else if self.promise(data.id) {
+                self.leader = Some(data.from);
+                let accepted = self.accepted.get(&data.slot);
                 let promise = Message::Promise(PromiseData {
-                    id: self.proposal_n,
-                    value: self.value.clone(),
+                    slot: data.slot,
+                    id: self.promised_n,
+                    accepted_n: accepted.map(|a| a.n),
+                    value: accepted.map(|a| a.value.clone()),
                     from: self.id,
                 });
-                if let Some(ref mut messenger) = self.messenger {
-                    messenger.send_promise(promise);
-                }
+                self.effect(Effect::PersistState {
+                    promised_n: self.promised_n,
+                    accepted: None,
+                });
+                self.effect(Effect::SendMessage(promise));
+                event!("promised");
+                self.emit(PaxosEvent::PromiseSent {
+                    instance: data.slot,
+                    n: data.id,
+                });
+                self.measure(|m| m.promise_sent());
+                self.observe(|o| o.on_promised(data.slot, data.id));
             }
         }
     }
 
     /// Receives an `Accept` message from a `Proposer`.
+    ///
+    /// An `Accept` with `implicit_prepare` set is handled as a `Prepare`
+    /// immediately followed by an `Accept`: a previously accepted value takes
+    /// precedence over the proposed one, exactly as a `Proposer` would have
+    /// chosen after the first phase.
     pub fn receive_accept(&mut self, msg: &Message<T>) {
         if let Message::Accept(data) = msg {
-            if data.id >= self.proposal_n {
-                self.value = Some(data.value.clone());
-                self.proposal_n = data.id;
-                let accepted = Message::Accepted(AcceptedData {
-                    id: self.proposal_n,
-                    value: data.value.clone(),
-                    from: self.id,
-                });
-                if let Some(ref mut messenger) = self.messenger {
-                    messenger.send_accepted(accepted);
+            span!(
+                "receive_accept",
+                acceptor = self.id,
+                slot = data.slot,
+                ballot = data.id,
+                promised = self.promised_n
+            );
+            if !self.accepts() || data.slot < self.truncated {
+                return;
+            }
+            let value = if data.implicit_prepare {
+                if data.id <= self.promised_n {
+                    self.nack(data.slot, data.id);
+                    return;
+                }
+                self.accepted
+                    .get(&data.slot)
+                    .map(|a| a.value.clone())
+                    .unwrap_or_else(|| data.value.clone())
+            } else {
+                if data.id < self.promised_n {
+                    self.nack(data.slot, data.id);
+                    return;
+                }
+                data.value.clone()
+            };
+
+            if self.promise(data.id) {
+                self.accept(data.slot, data.id, value, false);
+            }
+        }
+    }
+
+    /// Receives a `Read` message from a `Learner`, replying with the slot
+    /// after the highest one a value was accepted for. Only `Acceptor`s
+    /// storing values reply.
+    pub fn receive_read(&mut self, msg: &Message<T>) {
+        if let Message::Read(data) = msg {
+            span!("receive_read", acceptor = self.id, from = data.from);
+            if !self.accepts() {
+                return;
+            }
+            let horizon = match self.accepted.keys().next_back() {
+                Some(slot) => (slot + 1).max(self.truncated),
+                None => self.truncated,
+            };
+            let reply = Message::ReadReply(ReadReplyData {
+                from: self.id,
+                to: data.from,
+                id: data.id,
+                horizon,
+            });
+            self.effect(Effect::SendMessage(reply));
+        }
+    }
+
+    /// Receives an `Any` message from a `Proposer`, opening a fast round: the
+    /// first value proposed for the slot is accepted without going through
+    /// the `Proposer`.
+    pub fn receive_any(&mut self, msg: &Message<T>) {
+        if let Message::Any(data) = msg {
+            span!(
+                "receive_any",
+                acceptor = self.id,
+                slot = data.slot,
+                ballot = data.id,
+                promised = self.promised_n
+            );
+            if !self.accepts() || data.slot < self.truncated {
+                return;
+            }
+            if data.id < self.promised_n {
+                self.nack(data.slot, data.id);
+                return;
+            }
+            if !self.promise(data.id) {
+                return;
+            }
+            self.leader = Some(data.from);
+            self.fast_rounds.insert(data.slot, data.id);
+            self.effect(Effect::PersistState {
+                promised_n: self.promised_n,
+                accepted: None,
+            });
+        }
+    }
+
+    /// Receives a `Propose` message from a client. Ignored unless a fast round
+    /// is open for the slot, and no higher proposal has been promised since,
+    /// or if the `Acceptor` no longer accepts values for the slot.
+    pub fn receive_propose(&mut self, msg: &Message<T>) {
+        if let Message::Propose(data) = msg {
+            span!("receive_propose", acceptor = self.id, slot = data.slot);
+            if !self.accepts() || data.slot < self.truncated {
+                return;
+            }
+            match self.fast_rounds.get(&data.slot) {
+                Some(&n) if n == self.promised_n && !self.accepted_fast(data.slot) => {
+                    self.accept(data.slot, n, data.value.clone(), true);
                 }
+                _ => {}
             }
         }
     }
+
+    /// Whether the value accepted for `slot` was accepted in a fast round.
+    pub(crate) fn accepted_fast(&self, slot: Slot) -> bool {
+        let fast_round = self.fast_rounds.get(&slot);
+        self.accepted
+            .get(&slot)
+            .is_some_and(|a| fast_round == Some(&a.n))
+    }
+
+    /// Whether the `Acceptor` votes in the first phase. Observers never do.
+    fn votes(&self) -> bool {
+        self.is_voting() && !self.config.is_observer(self.id)
+    }
+
+    /// Whether the `Acceptor` votes in the second phase. Witnesses never do,
+    /// as they store no values.
+    pub(crate) fn accepts(&self) -> bool {
+        self.votes() && !self.config.is_witness(self.id)
+    }
+
+    /// Rejects proposal `id` for `slot`, as a higher one was promised, unless
+    /// its `Proposer` was sent a `Nack` too recently.
+    fn nack(&mut self, slot: Slot, id: u64) {
+        if !self.nacks.admit(ballot_owner(id), slot, id) {
+            event!("nack held back");
+            return;
+        }
+        self.send_nack(slot, id);
+    }
+
+    /// Sends a `Nack` for proposal `id` for `slot`, carrying the promise.
+    pub(crate) fn send_nack(&mut self, slot: Slot, id: u64) {
+        let nack = Message::Nack(NackData {
+            slot,
+            id,
+            from: self.id,
+            promised_n: self.promised_n,
+            leader: self.leader,
+        });
+        self.effect(Effect::SendMessage(nack));
+        event!("rejected");
+    }
+
+    /// Raises the promise to `n`. With `paranoid-checks`, lowering it is
+    /// refused instead.
+    pub(crate) fn promise(&mut self, n: u64) -> bool {
+        #[cfg(feature = "paranoid-checks")]
+        if n < self.promised_n {
+            self.violate(InvariantViolation::PromiseRegressed {
+                promised: self.promised_n,
+                n,
+            });
+            return false;
+        }
+        self.promised_n = n;
+        true
+    }
+
+    #[cfg(feature = "paranoid-checks")]
+    fn violate(&mut self, violation: InvariantViolation) {
+        event!(%violation, "invariant violated");
+        self.violations.push(violation);
+    }
+
+    pub(crate) fn accept(&mut self, slot: Slot, n: u64, value: Arc<T>, fast: bool) {
+        #[cfg(feature = "paranoid-checks")]
+        if n < self.promised_n {
+            self.violate(InvariantViolation::BrokenPromise {
+                slot,
+                n,
+                promised: self.promised_n,
+            });
+            return;
+        }
+        self.accepted.insert(
+            slot,
+            AcceptedProposal {
+                n,
+                value: value.clone(),
+            },
+        );
+        self.effect(Effect::PersistState {
+            promised_n: self.promised_n,
+            accepted: Some((
+                slot,
+                AcceptedProposal {
+                    n,
+                    value: value.clone(),
+                },
+            )),
+        });
+        let accepted = Message::Accepted(AcceptedData {
+            slot,
+            id: n,
+            value: value.clone(),
+            from: self.id,
+            fast,
+        });
+        self.effect(Effect::SendMessage(accepted));
+        event!(slot, ballot = n, fast, "accepted");
+        self.emit(PaxosEvent::Accepted { instance: slot, n });
+        self.measure(|m| m.value_accepted());
+        self.observe(|o| o.on_accepted(slot, n, &value));
+    }
+
+    fn emit(&mut self, event: PaxosEvent) {
+        if let Some(ref mut events) = self.events {
+            events(event);
+        }
+    }
+
+    fn measure<F: FnOnce(&mut dyn Metrics)>(&mut self, f: F) {
+        if let Some(ref mut metrics) = self.metrics {
+            f(metrics.as_mut());
+        }
+    }
+
+    fn observe<F: FnOnce(&mut dyn Observer<T>)>(&mut self, f: F) {
+        if let Some(ref mut observer) = self.observer {
+            f(observer.as_mut());
+        }
+    }
+}
+
+impl<T, M: Messenger<T>> Handler<T> for Acceptor<T, M> {
+    fn handle(&mut self, msg: Message<T>) {
+        match msg {
+            Message::Prepare(_) => self.receive_prepare(&msg),
+            Message::Accept(_) => self.receive_accept(&msg),
+            Message::Any(_) => self.receive_any(&msg),
+            Message::Propose(_) => self.receive_propose(&msg),
+            Message::Join(_) => self.receive_join(&msg),
+            Message::State(_) => self.receive_state(&msg),
+            Message::Read(_) => self.receive_read(&msg),
+            Message::Digest(_) => self.receive_digest(&msg),
+            Message::Repair(_) => self.receive_repair(&msg),
+            _ => {}
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use message::{AcceptData, ProposalData};
+    use crate::message::{AcceptData, ProposalData, ProposeData, StateData};
+    use alloc::vec;
+    use std::sync::Mutex;
+
+    fn accepted(n: u64, value: u64) -> AcceptedProposal<u64> {
+        AcceptedProposal {
+            n,
+            value: Arc::new(value),
+        }
+    }
 
     #[test]
     fn acceptor_new() {
-        let a: Acceptor<u64> = Acceptor::new(1);
+        let a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1]));
 
         assert_eq!(a.id, 1);
-        assert_eq!(a.proposal_n, 0);
-        assert_eq!(a.value, None);
+        assert_eq!(a.promised_n, 0);
+        assert!(a.accepted.is_empty());
         assert!(a.messenger.is_none());
     }
 
     #[test]
     fn acceptor_receive_prepare() {
-        let mut a: Acceptor<u64> = Acceptor::new(1);
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1]));
 
-        let msg = Message::Prepare(ProposalData { id: 8 });
+        let msg = Message::Prepare(ProposalData {
+            slot: 0,
+            id: 8,
+            from: 1,
+        });
 
         a.receive_prepare(&msg);
 
-        assert_eq!(a.proposal_n, 8);
+        assert_eq!(a.promised_n, 8);
+        assert!(a.accepted.is_empty());
 
         // ignore proposals less than N
-        let msg = Message::Prepare(ProposalData { id: 6 });
+        let msg = Message::Prepare(ProposalData {
+            slot: 0,
+            id: 6,
+            from: 1,
+        });
 
         a.receive_prepare(&msg);
 
-        assert_eq!(a.proposal_n, 8);
+        assert_eq!(a.promised_n, 8);
+    }
+
+    #[test]
+    fn acceptor_nack() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let nack = |id, leader| {
+            Effect::SendMessage(Message::Nack(NackData {
+                slot: 0,
+                id,
+                from: 1,
+                promised_n: 8,
+                leader,
+            }))
+        };
+
+        a.step(Message::Prepare(ProposalData {
+            slot: 0,
+            id: 8,
+            from: 2,
+        }));
+
+        // Lower proposals are rejected, naming the `Proposer` promised.
+        assert_eq!(
+            a.step(Message::Prepare(ProposalData {
+                slot: 0,
+                id: 6,
+                from: 3,
+            })),
+            vec![nack(6, Some(2))]
+        );
+        assert_eq!(
+            a.step(Message::Accept(AcceptData {
+                slot: 0,
+                id: 7,
+                value: Arc::new(60),
+                implicit_prepare: false,
+            })),
+            vec![nack(7, Some(2))]
+        );
+        assert_eq!(a.promised_n, 8);
+        assert!(a.accepted.is_empty());
+    }
+
+    #[test]
+    fn acceptor_promise_pipelined_slots() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1]));
+        let sink = events.clone();
+        a.events = Some(Box::new(move |e| sink.lock().unwrap().push(e)));
+
+        a.receive_prepare(&Message::Prepare(ProposalData {
+            slot: 0,
+            id: 8,
+            from: 1,
+        }));
+        a.receive_prepare(&Message::Prepare(ProposalData {
+            slot: 1,
+            id: 8,
+            from: 1,
+        }));
+        a.receive_prepare(&Message::Prepare(ProposalData {
+            slot: 2,
+            id: 7,
+            from: 1,
+        }));
+
+        // Further slots are promised under the same proposal number.
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                PaxosEvent::PromiseSent { instance: 0, n: 8 },
+                PaxosEvent::PromiseSent { instance: 1, n: 8 },
+            ]
+        );
+    }
+
+    #[test]
+    fn acceptor_witness_and_observer() {
+        let config = ClusterConfig::new(vec![1, 2])
+            .with_witnesses(vec![3])
+            .with_observers(vec![4]);
+        let mut witness: Acceptor<u64> = Acceptor::new(3, config.clone());
+        let mut observer: Acceptor<u64> = Acceptor::new(4, config);
+        let accept = Message::Accept(AcceptData {
+            slot: 0,
+            id: 8,
+            value: Arc::new(10),
+            implicit_prepare: false,
+        });
+
+        witness.receive_prepare(&Message::Prepare(ProposalData {
+            slot: 0,
+            id: 8,
+            from: 1,
+        }));
+        witness.receive_accept(&accept);
+
+        assert_eq!(witness.promised_n, 8);
+        assert!(witness.accepted.is_empty());
+
+        observer.receive_prepare(&Message::Prepare(ProposalData {
+            slot: 0,
+            id: 8,
+            from: 1,
+        }));
+        observer.receive_accept(&accept);
+
+        assert_eq!(observer.promised_n, 0);
+        assert!(observer.accepted.is_empty());
     }
 
     #[test]
     fn acceptor_receive_accept() {
-        let mut a: Acceptor<u64> = Acceptor::new(1);
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1]));
 
         let msg = Message::Accept(AcceptData {
+            slot: 0,
             id: 3,
             value: Arc::new(60),
+            implicit_prepare: false,
         });
 
         a.receive_accept(&msg);
 
-        assert_eq!(a.value, Some(Arc::new(60)));
-        assert_eq!(a.proposal_n, 3);
+        assert_eq!(a.accepted[&0], accepted(3, 60));
+        assert_eq!(a.promised_n, 3);
 
         // ignore Accept messages less than N
 
         let msg = Message::Accept(AcceptData {
+            slot: 0,
             id: 2,
             value: Arc::new(60),
+            implicit_prepare: false,
         });
 
         a.receive_accept(&msg);
 
-        assert_eq!(a.value, Some(Arc::new(60)));
-        assert_eq!(a.proposal_n, 3);
+        assert_eq!(a.accepted[&0], accepted(3, 60));
+    }
+
+    #[test]
+    fn acceptor_promise_keeps_accepted_n() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1]));
+
+        a.receive_accept(&Message::Accept(AcceptData {
+            slot: 0,
+            id: 3,
+            value: Arc::new(60),
+            implicit_prepare: false,
+        }));
+        a.receive_prepare(&Message::Prepare(ProposalData {
+            slot: 0,
+            id: 5,
+            from: 1,
+        }));
+
+        // A later promise must not be mistaken for the accepted proposal.
+        assert_eq!(a.promised_n, 5);
+        assert_eq!(a.accepted[&0], accepted(3, 60));
+    }
+
+    #[test]
+    fn acceptor_slots_are_independent() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1]));
+
+        a.receive_accept(&Message::Accept(AcceptData {
+            slot: 0,
+            id: 3,
+            value: Arc::new(60),
+            implicit_prepare: false,
+        }));
+        a.receive_accept(&Message::Accept(AcceptData {
+            slot: 1,
+            id: 3,
+            value: Arc::new(25),
+            implicit_prepare: false,
+        }));
+
+        assert_eq!(a.accepted[&0], accepted(3, 60));
+        assert_eq!(a.accepted[&1], accepted(3, 25));
+    }
+
+    #[test]
+    fn acceptor_receive_accept_implicit_prepare() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1]));
+
+        a.receive_accept(&Message::Accept(AcceptData {
+            slot: 0,
+            id: 1,
+            value: Arc::new(60),
+            implicit_prepare: true,
+        }));
+
+        assert_eq!(a.promised_n, 1);
+        assert_eq!(a.accepted[&0], accepted(1, 60));
+
+        // The value accepted first is kept by later proposals.
+        a.receive_accept(&Message::Accept(AcceptData {
+            slot: 0,
+            id: 2,
+            value: Arc::new(25),
+            implicit_prepare: true,
+        }));
+
+        assert_eq!(a.accepted[&0], accepted(2, 60));
+
+        // Stale proposals are ignored, even for the same proposal number.
+        a.receive_accept(&Message::Accept(AcceptData {
+            slot: 0,
+            id: 2,
+            value: Arc::new(25),
+            implicit_prepare: true,
+        }));
+
+        assert_eq!(a.accepted[&0], accepted(2, 60));
+    }
+
+    #[test]
+    fn acceptor_fast_round() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let propose = |value| {
+            Message::Propose(ProposeData {
+                slot: 0,
+                value: Arc::new(value),
+            })
+        };
+
+        // Proposed values are ignored until a fast round is opened.
+        a.receive_propose(&propose(60));
+
+        assert!(a.accepted.is_empty());
+
+        a.receive_any(&Message::Any(ProposalData {
+            slot: 0,
+            id: 2,
+            from: 1,
+        }));
+        a.receive_propose(&propose(60));
+        a.receive_propose(&propose(25));
+
+        assert_eq!(a.promised_n, 2);
+        assert_eq!(a.accepted[&0], accepted(2, 60));
+
+        // A higher promise closes the fast round.
+        a.receive_any(&Message::Any(ProposalData {
+            slot: 1,
+            id: 3,
+            from: 1,
+        }));
+        a.receive_prepare(&Message::Prepare(ProposalData {
+            slot: 1,
+            id: 4,
+            from: 1,
+        }));
+        a.receive_propose(&Message::Propose(ProposeData {
+            slot: 1,
+            value: Arc::new(25),
+        }));
+
+        assert!(!a.accepted.contains_key(&1));
+    }
+
+    #[test]
+    fn acceptor_fast_round_witness() {
+        let mut a: Acceptor<u64> = Acceptor::new(4, ClusterConfig::new(vec![1, 2, 3, 4]));
+        a.receive_any(&Message::Any(ProposalData {
+            slot: 0,
+            id: 2,
+            from: 1,
+        }));
+
+        // Reconfigured as a witness while the fast round is open, the
+        // Acceptor stores no values.
+        a.reconfigure(ClusterConfig::new(vec![1, 2, 3]).with_witnesses(vec![4]));
+        a.receive_propose(&Message::Propose(ProposeData {
+            slot: 0,
+            value: Arc::new(60),
+        }));
+
+        assert!(a.accepted.is_empty());
+    }
+
+    #[test]
+    fn acceptor_state_transfer() {
+        let mut a: Acceptor<u64> = Acceptor::joining(4, ClusterConfig::new(vec![1, 2, 3, 4]));
+        let state = |from, promised_n, accepted| {
+            Message::State(StateData {
+                from,
+                to: 4,
+                promised_n,
+                accepted,
+                decided: vec![],
+            })
+        };
+
+        // Proposals are ignored until enough state has been copied.
+        a.receive_prepare(&Message::Prepare(ProposalData {
+            slot: 0,
+            id: 8,
+            from: 1,
+        }));
+        a.receive_state(&state(1, 5, vec![(0, 3, Arc::new(60))]));
+        a.receive_state(&state(
+            2,
+            7,
+            vec![(0, 4, Arc::new(25)), (1, 2, Arc::new(5))],
+        ));
+
+        assert!(!a.voting);
+        assert_eq!(a.promised_n, 7);
+
+        // Retransmissions don't count twice.
+        a.receive_state(&state(2, 7, vec![]));
+
+        assert!(!a.voting);
+
+        a.receive_state(&state(3, 6, vec![(0, 2, Arc::new(60))]));
+
+        assert!(a.voting);
+        assert_eq!(a.promised_n, 7);
+        assert_eq!(a.accepted[&0], accepted(4, 25));
+        assert_eq!(a.accepted[&1], accepted(2, 5));
+
+        a.receive_prepare(&Message::Prepare(ProposalData {
+            slot: 0,
+            id: 8,
+            from: 1,
+        }));
+
+        assert_eq!(a.promised_n, 8);
+    }
+
+    #[test]
+    fn acceptor_truncate() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1]));
+        let accept = |slot, id| {
+            Message::Accept(AcceptData {
+                slot,
+                id,
+                value: Arc::new(60),
+                implicit_prepare: false,
+            })
+        };
+
+        a.receive_accept(&accept(0, 1));
+        a.receive_accept(&accept(2, 1));
+        a.truncate(1);
+
+        assert_eq!(a.accepted.keys().copied().collect::<Vec<_>>(), vec![2]);
+
+        a.receive_accept(&accept(0, 2));
+        a.receive_prepare(&Message::Prepare(ProposalData {
+            slot: 0,
+            id: 3,
+            from: 1,
+        }));
+
+        assert!(!a.accepted.contains_key(&0));
+        assert_eq!(a.promised_n, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "paranoid-checks")]
+    fn acceptor_paranoid_checks() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        a.promised_n = 8;
+
+        // Transitions the handlers' guards should have ruled out are refused.
+        assert!(!a.promise(5));
+        a.accept(0, 5, Arc::new(10), false);
+
+        assert_eq!(a.promised_n, 8);
+        assert!(a.accepted.is_empty());
+        assert!(a.take_effects().is_empty());
+        assert_eq!(
+            a.take_violations(),
+            [
+                InvariantViolation::PromiseRegressed { promised: 8, n: 5 },
+                InvariantViolation::BrokenPromise {
+                    slot: 0,
+                    n: 5,
+                    promised: 8
+                },
+            ]
+        );
+        assert!(a.take_violations().is_empty());
     }
 }
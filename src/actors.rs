@@ -0,0 +1,340 @@
+//! Actors
+//!
+//! Runs a `Node` as an [actix](https://docs.rs/actix) actor, for
+//! applications already built on actors. A `NodeActor`'s mailbox takes the
+//! `Message`s of the cluster as they are, along with `Propose` and
+//! `WithNode` requests, and the effects of each are carried
+//! out once the `Acceptor`'s state is durable in its `Storage`. Timers are
+//! run on the actor's context, against a `SystemClock`.
+//!
+//! Started with `NodeActor::start_supervised`, an actor which stops, because
+//! its storage failed or it was sent `Restart`, is started again with a fresh
+//! `Node` whose `Acceptor` is restored from storage, as after a process
+//! crash. Should the storage not load either, the `Acceptor` rejoins the
+//! cluster instead, copying state from its peers before voting again.
+//!
+//! `ActorMessenger` broadcasts messages to the mailboxes of the actors of a
+//! single process, e.g. for tests; any other `Messenger` works as well.
+
+use crate::acceptor::Acceptor;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{ClusterConfig, NodeId};
+use crate::effect::{dispatch, Effect};
+use crate::message::{Message, Messenger, Slot};
+use crate::node::Node;
+use crate::proposal::ProposalHandle;
+use crate::storage::{GroupCommit, Storage};
+use actix::{
+    Actor, ActorContext, Addr, AsyncContext, Context, Handler, Recipient, Supervised, Supervisor,
+};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+use std::sync::{Mutex, PoisonError};
+
+impl<T: 'static> actix::Message for Message<T> {
+    type Result = ();
+}
+
+/// Proposes a value for the next slot, answered with its handle.
+pub struct Propose<T>(pub T);
+
+impl<T: 'static> actix::Message for Propose<T> {
+    type Result = ProposalHandle;
+}
+
+/// Runs a function on the actor's `Node`, answered with its result, e.g. to
+/// read its status or take the values it decided. The effects it produces
+/// are carried out afterwards.
+pub struct WithNode<T, R>(NodeFn<T, R>);
+
+type NodeFn<T, R> = Box<dyn FnOnce(&mut Node<T>) -> R + Send>;
+
+impl<T, R> WithNode<T, R> {
+    /// Runs `f` on the actor's `Node`.
+    pub fn new(f: impl FnOnce(&mut Node<T>) -> R + Send + 'static) -> Self {
+        Self(Box::new(f))
+    }
+}
+
+impl<T: 'static, R: 'static> actix::Message for WithNode<T, R> {
+    type Result = R;
+}
+
+/// Stops the actor, losing all but the state in its storage. A supervised
+/// actor is started again right away.
+pub struct Restart;
+
+impl actix::Message for Restart {
+    type Result = ();
+}
+
+/// A `Node` run as an actor. See the module documentation.
+pub struct NodeActor<T, S, N> {
+    id: NodeId,
+    config: ClusterConfig,
+    node: Node<T>,
+    storage: GroupCommit<T, S>,
+    messenger: N,
+    clock: SystemClock,
+}
+
+impl<T, S, N> NodeActor<T, S, N>
+where
+    T: PartialEq + Clone + Unpin + 'static,
+    S: Storage<T> + Unpin + 'static,
+    N: Messenger<T> + Unpin + 'static,
+{
+    /// Creates the actor of node `id` of `config`, whose `Acceptor` state is
+    /// made durable in `storage`, and whose messages are sent through
+    /// `messenger`. Its `Node` is built, and restored from `storage`, once
+    /// the actor starts.
+    pub fn new(id: NodeId, config: ClusterConfig, storage: S, messenger: N) -> Self {
+        Self {
+            id,
+            node: Node::new(id, config.clone()),
+            config,
+            storage: GroupCommit::new(storage, 0),
+            messenger,
+            clock: SystemClock::new(),
+        }
+    }
+
+    /// Starts the actor under a `Supervisor`, which restarts it whenever it
+    /// stops.
+    pub fn start_supervised(self) -> Addr<Self> {
+        Supervisor::start(|_| self)
+    }
+
+    /// Replaces the `Node` with a fresh one, restored from storage.
+    fn reload(&mut self) {
+        let mut node = Node::new(self.id, self.config.clone());
+        match self.storage.storage_mut().load() {
+            Ok(state) => node.acceptor_mut().restore(state),
+            Err(_) => {
+                node.acceptor = Acceptor::joining(self.id, self.config.clone());
+                node.acceptor.join();
+            }
+        }
+        self.node = node;
+    }
+
+    /// Carries out `effects` once the state they persist is durable. Stops
+    /// the actor if it can't be.
+    fn carry_out(&mut self, effects: Vec<Effect<T>>, ctx: &mut Context<Self>) {
+        let ready = match self.storage.submit(effects, self.clock.now()) {
+            Ok(ready) => ready,
+            Err(_) => return ctx.stop(),
+        };
+        for effect in ready {
+            match effect {
+                Effect::StartTimer { at } => {
+                    let delay = at.saturating_sub(self.clock.now());
+                    ctx.run_later(Duration::from_millis(delay), |actor, ctx| {
+                        actor.node.tick(actor.clock.now());
+                        let effects = actor.node.take_effects();
+                        actor.carry_out(effects, ctx);
+                    });
+                }
+                effect => dispatch(&mut self.messenger, effect),
+            }
+        }
+    }
+}
+
+impl<T, S, N> Actor for NodeActor<T, S, N>
+where
+    T: PartialEq + Clone + Unpin + 'static,
+    S: Storage<T> + Unpin + 'static,
+    N: Messenger<T> + Unpin + 'static,
+{
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.reload();
+        let effects = self.node.take_effects();
+        self.carry_out(effects, ctx);
+    }
+}
+
+impl<T, S, N> Supervised for NodeActor<T, S, N>
+where
+    T: PartialEq + Clone + Unpin + 'static,
+    S: Storage<T> + Unpin + 'static,
+    N: Messenger<T> + Unpin + 'static,
+{
+}
+
+impl<T, S, N> Handler<Message<T>> for NodeActor<T, S, N>
+where
+    T: PartialEq + Clone + Unpin + 'static,
+    S: Storage<T> + Unpin + 'static,
+    N: Messenger<T> + Unpin + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: Message<T>, ctx: &mut Context<Self>) {
+        let effects = self.node.step(msg);
+        self.carry_out(effects, ctx);
+    }
+}
+
+impl<T, S, N> Handler<Propose<T>> for NodeActor<T, S, N>
+where
+    T: PartialEq + Clone + Unpin + 'static,
+    S: Storage<T> + Unpin + 'static,
+    N: Messenger<T> + Unpin + 'static,
+{
+    type Result = actix::MessageResult<Propose<T>>;
+
+    fn handle(&mut self, msg: Propose<T>, ctx: &mut Context<Self>) -> Self::Result {
+        let handle = self.node.propose(msg.0);
+        let effects = self.node.take_effects();
+        self.carry_out(effects, ctx);
+        actix::MessageResult(handle)
+    }
+}
+
+impl<T, S, N, R> Handler<WithNode<T, R>> for NodeActor<T, S, N>
+where
+    T: PartialEq + Clone + Unpin + 'static,
+    S: Storage<T> + Unpin + 'static,
+    N: Messenger<T> + Unpin + 'static,
+    R: 'static,
+{
+    type Result = actix::MessageResult<WithNode<T, R>>;
+
+    fn handle(&mut self, msg: WithNode<T, R>, ctx: &mut Context<Self>) -> Self::Result {
+        let result = (msg.0)(&mut self.node);
+        let effects = self.node.take_effects();
+        self.carry_out(effects, ctx);
+        actix::MessageResult(result)
+    }
+}
+
+impl<T, S, N> Handler<Restart> for NodeActor<T, S, N>
+where
+    T: PartialEq + Clone + Unpin + 'static,
+    S: Storage<T> + Unpin + 'static,
+    N: Messenger<T> + Unpin + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, _: Restart, ctx: &mut Context<Self>) {
+        ctx.stop();
+    }
+}
+
+/// A `Messenger` broadcasting every message to the mailboxes of a set of
+/// actors. Cloning an `ActorMessenger` yields another handle to the same
+/// set.
+pub struct ActorMessenger<T: Send + Sync + 'static> {
+    peers: Arc<Mutex<Vec<Recipient<Message<T>>>>>,
+}
+
+impl<T: Send + Sync + 'static> Clone for ActorMessenger<T> {
+    fn clone(&self) -> Self {
+        Self {
+            peers: self.peers.clone(),
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> Default for ActorMessenger<T> {
+    fn default() -> Self {
+        Self {
+            peers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> ActorMessenger<T> {
+    /// Creates an `ActorMessenger` with no actors to send to.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `peer` to the actors messages are sent to.
+    pub fn add(&self, peer: Recipient<Message<T>>) {
+        self.peers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(peer);
+    }
+
+    fn broadcast(&self, msg: Message<T>) {
+        let peers = self.peers.lock().unwrap_or_else(PoisonError::into_inner);
+        // A full or closed mailbox is as good as a lost message.
+        for peer in peers.iter() {
+            peer.do_send(msg.clone());
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Messenger<T> for ActorMessenger<T> {
+    fn send_prepare(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_promise(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_accept(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_accepted(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn on_resolution(&mut self, _: Slot, _: Arc<T>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use alloc::vec;
+
+    #[actix::test]
+    async fn actors_decide_and_restart() {
+        let messenger = ActorMessenger::new();
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let addrs: Vec<_> = (1..=3)
+            .map(|id| {
+                NodeActor::new(id, config.clone(), MemoryStorage::new(), messenger.clone())
+                    .start_supervised()
+            })
+            .collect();
+        for addr in &addrs {
+            messenger.add(addr.clone().recipient());
+        }
+
+        addrs[0].send(Propose(7u64)).await.unwrap();
+        for addr in &addrs {
+            let mut decided = Vec::new();
+            while decided.is_empty() {
+                decided = addr
+                    .send(WithNode::new(|node| node.poll_decided()))
+                    .await
+                    .unwrap();
+                actix::clock::sleep(Duration::from_millis(1)).await;
+            }
+            assert_eq!(decided, vec![(0, Arc::new(7))]);
+        }
+
+        // The restarted node only remembers what its `Acceptor` persisted.
+        addrs[1].send(Restart).await.unwrap();
+        let (accepted, decided) = addrs[1]
+            .send(WithNode::new(|node| {
+                let accepted = node.acceptor().accepted(0).map(|p| *p.value);
+                (accepted, node.learner().decided_value(0).cloned())
+            }))
+            .await
+            .unwrap();
+        assert_eq!(accepted, Some(7));
+        assert_eq!(decided, None);
+    }
+}
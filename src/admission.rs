@@ -0,0 +1,140 @@
+//! Admission control
+//!
+//! A `Proposer` queues the values it has no room in its window for, however
+//! many there are, so a flood of client requests grows the queue without
+//! bound and keeps the `Acceptor`s as busy as the window allows.
+//! `AdmissionLimits` bound both:
+//!
+//! - `max_pending` caps the proposals pending at once, in flight or queued.
+//!   `Proposer::try_prepare` turns values away past it with
+//!   `ProposerError::Busy`, for clients to back off and retry.
+//! - `max_per_sec` caps the rate values are sent at, in bursts of up to as
+//!   many. Values beyond it wait in the queue, to be sent as `tick` lets
+//!   them; `try_prepare` turns them away instead.
+//!
+//! `prepare` never turns a value away, so it only heeds the rate.
+
+use crate::error::ProposerError;
+use crate::message::Messenger;
+use crate::proposal::ProposalHandle;
+use crate::proposer::Proposer;
+
+/// Limits on the proposals a `Proposer` admits. See the module documentation.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct AdmissionLimits {
+    /// Most proposals pending at once, in flight or queued
+    pub max_pending: Option<usize>,
+    /// Most values sent per second
+    pub max_per_sec: Option<u32>,
+}
+
+/// The state of a `Proposer`'s admission control.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Admission {
+    pub(crate) limits: AdmissionLimits,
+    /// Values that may be sent right away, in thousandths
+    credit: u64,
+    /// When `credit` was last topped up, in milliseconds
+    refilled_at: u64,
+}
+
+impl Admission {
+    /// Most `credit` saved up: a second's worth of values.
+    fn burst(&self) -> u64 {
+        self.limits
+            .max_per_sec
+            .map_or(0, |rate| u64::from(rate) * 1000)
+    }
+
+    /// Tops `credit` up with the values `now` allows for since the last
+    /// time.
+    pub(crate) fn refill(&mut self, now: u64) {
+        if let Some(rate) = self.limits.max_per_sec {
+            let elapsed = now.saturating_sub(self.refilled_at);
+            self.credit = (self.credit + elapsed * u64::from(rate)).min(self.burst());
+        }
+        self.refilled_at = self.refilled_at.max(now);
+    }
+
+    /// Whether the rate lets a value be sent now.
+    pub(crate) fn has_credit(&self) -> bool {
+        self.limits.max_per_sec.is_none() || self.credit >= 1000
+    }
+
+    /// Counts a value sent against the rate.
+    pub(crate) fn spend(&mut self) {
+        self.credit = self.credit.saturating_sub(1000);
+    }
+}
+
+impl<T: PartialEq + Clone, M: Messenger<T>> Proposer<T, M> {
+    /// Sets the limits on the proposals admitted, starting with a full
+    /// second's worth of values.
+    pub fn set_admission(&mut self, limits: AdmissionLimits) {
+        self.admission.limits = limits;
+        self.admission.credit = self.admission.burst();
+        self.admission.refilled_at = self.now;
+        self.propose_queued();
+    }
+
+    /// Number of proposals pending, in flight or queued, not counting slots
+    /// finalized on behalf of a predecessor.
+    pub fn pending(&self) -> usize {
+        let in_flight = self.in_flight.values().filter(|f| f.handle.is_some());
+        in_flight.count() + self.queued.len()
+    }
+
+    /// Like `prepare`, but turns `value` away with `ProposerError::Busy`
+    /// rather than queue it past the `AdmissionLimits`.
+    pub fn try_prepare(&mut self, value: T) -> Result<ProposalHandle, ProposerError> {
+        let limits = self.admission.limits;
+        let crowded = limits.max_pending.is_some_and(|max| self.pending() >= max);
+        let throttled = limits.max_per_sec.is_some()
+            && (!self.admission.has_credit() || !self.queued.is_empty());
+        if crowded || throttled {
+            event!(pending = self.pending(), "busy");
+            return Err(ProposerError::Busy);
+        }
+        Ok(self.prepare(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::node::Node;
+    use alloc::vec;
+
+    #[test]
+    fn admission_limits() {
+        let mut node: Node<u64> = Node::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        node.proposer_mut().set_window(8);
+        node.set_admission(AdmissionLimits {
+            max_pending: Some(3),
+            max_per_sec: Some(2),
+        });
+
+        assert!(node.try_propose(10).is_ok());
+        assert!(node.try_propose(20).is_ok());
+
+        // The second's worth of values is spent.
+        assert_eq!(node.try_propose(30), Err(ProposerError::Busy));
+
+        node.propose(30);
+
+        assert_eq!(node.proposer().queued(), 1);
+        assert_eq!(node.try_propose(40), Err(ProposerError::Busy));
+
+        // Half a second later, another value may be sent.
+        node.tick(499);
+
+        assert_eq!(node.proposer().queued(), 1);
+
+        node.tick(500);
+
+        assert_eq!(node.proposer().queued(), 0);
+        assert_eq!(node.proposer().pending(), 3);
+        assert_eq!(node.try_propose(40), Err(ProposerError::Busy));
+    }
+}
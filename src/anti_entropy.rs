@@ -0,0 +1,360 @@
+//! Anti-entropy
+//!
+//! An `Acceptor` which missed an `Accept` keeps a hole in its log for good:
+//! `Learner`s catch up through `InstallSnapshot`s or `Learn`s, but nothing
+//! ever sends the `Acceptor` the value again, so the holes of several
+//! `Acceptor`s add up until a quorum of them no longer holds a decided value.
+//! With anti-entropy set, each `Acceptor` gossips its log to the others every
+//! so often:
+//!
+//! - it sends them a `Digest` holding a hash of the proposals it accepted in
+//!   each range of slots, from its truncation point on;
+//! - an `Acceptor` whose own hash of a range differs answers with a `Repair`
+//!   carrying every proposal it accepted in that range, along with whether
+//!   it was accepted in a fast round;
+//! - the proposals repaired are accepted as if their `Accept` or `Propose`
+//!   had just come through, unless the promise or a later proposal for the
+//!   slot forbids it.
+//!
+//! `Acceptor`s truncated at different slots compare what both of them still
+//! hold: the receiver of a `Digest` hashes its own log from the sender's
+//! truncation point on, and leaves alone the ranges reaching below its own,
+//! which only `Acceptor`s truncated no further can repair.
+//!
+//! Hashes only cover slots and proposal numbers, as values needn't be
+//! hashable: within a classic round a proposal number carries a single value.
+//!
+//! Like Nack suppression, anti-entropy runs on the clock `Acceptor::set_time`
+//! advances.
+
+use crate::acceptor::{AcceptedProposal, Acceptor};
+use crate::effect::Effect;
+use crate::message::{DigestData, Message, Messenger, RepairData, Slot};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// The state of an `Acceptor`'s anti-entropy.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct AntiEntropy {
+    /// Milliseconds between two `Digest`s, if gossiping
+    interval: Option<u64>,
+    /// Number of slots each hash covers
+    range: Slot,
+    /// When the next `Digest` is due
+    next_at: u64,
+}
+
+/// Hashes the proposals of `accepted` from `from` on, by range of `range`
+/// slots starting at `start`, keyed by the index of the range. Ranges without
+/// any are left out.
+fn hashes<T>(
+    accepted: &BTreeMap<Slot, AcceptedProposal<T>>,
+    start: Slot,
+    from: Slot,
+    range: Slot,
+) -> BTreeMap<u64, u64> {
+    let mut hashes = BTreeMap::new();
+    for (slot, proposal) in accepted.range(start.max(from)..) {
+        let hash = hashes.entry((slot - start) / range).or_insert(FNV_OFFSET);
+        for n in [*slot, proposal.n] {
+            for byte in n.to_be_bytes() {
+                *hash = (*hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME);
+            }
+        }
+    }
+    hashes
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+impl<T, M: Messenger<T>> Acceptor<T, M> {
+    /// Sets the milliseconds between two `Digest`s gossiped to the other
+    /// `Acceptor`s, each hash covering `range` slots. The first one is sent
+    /// on the next call to `set_time`. Never gossips if `None`, the default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is 0.
+    pub fn set_anti_entropy(&mut self, interval: Option<u64>, range: Slot) {
+        assert!(range > 0, "ranges must cover at least a slot");
+        self.gossip = AntiEntropy {
+            interval,
+            range,
+            next_at: 0,
+        };
+    }
+
+    /// Sends the other `Acceptor`s a `Digest` of the log, if due by `now`.
+    pub(crate) fn gossip(&mut self, now: u64) {
+        let Some(interval) = self.gossip.interval else {
+            return;
+        };
+        if now < self.gossip.next_at || !self.accepts() {
+            return;
+        }
+        self.gossip.next_at = now + interval;
+        // Ranges are aligned on multiples of `range`, for those of
+        // `Acceptor`s truncated at different slots to line up. The range
+        // holding the truncation point only covers the slots from it on.
+        let range = self.gossip.range;
+        let start = self.truncated - self.truncated % range;
+        let hashes = hashes(&self.accepted, start, self.truncated, range);
+        let count = hashes.keys().next_back().map_or(0, |last| last + 1);
+        let digest = Message::Digest(DigestData {
+            from: self.id,
+            start,
+            truncated: self.truncated,
+            range,
+            hashes: (0..count)
+                .map(|i| hashes.get(&i).copied().unwrap_or(0))
+                .collect(),
+        });
+        self.effect(Effect::SendMessage(digest));
+        event!(acceptor = self.id, ranges = count, "digest gossiped");
+    }
+
+    /// Receives a `Digest` message from another `Acceptor`, answering with a
+    /// `Repair` carrying the proposals accepted in the ranges it disagrees
+    /// on. A range the digest doesn't cover is taken as empty. Slots below
+    /// the sender's truncation point are left out on both sides, and ranges
+    /// reaching below the receiver's aren't compared.
+    pub fn receive_digest(&mut self, msg: &Message<T>) {
+        if let Message::Digest(data) = msg {
+            span!("receive_digest", acceptor = self.id, from = data.from);
+            if !self.accepts() || data.from == self.id || data.range == 0 {
+                return;
+            }
+            let ours = hashes(&self.accepted, data.start, data.truncated, data.range);
+            // The sender may hold slots dropped here, in ranges the hashes of
+            // can't match.
+            let comparable = |i: u64| {
+                let first = data.start.saturating_add(i.saturating_mul(data.range));
+                data.truncated >= self.truncated || first >= self.truncated
+            };
+            let theirs = |i: u64| {
+                let i = usize::try_from(i).ok()?;
+                data.hashes.get(i).copied()
+            };
+            let accepted: Vec<_> = self
+                .accepted
+                .range(data.start.max(data.truncated)..)
+                .filter(|(slot, _)| {
+                    let i = (*slot - data.start) / data.range;
+                    comparable(i) && theirs(i) != ours.get(&i).copied()
+                })
+                .map(|(&slot, proposal)| {
+                    let fast = self.accepted_fast(slot);
+                    (slot, proposal.n, fast, proposal.value.clone())
+                })
+                .collect();
+            if accepted.is_empty() {
+                return;
+            }
+            event!(slots = accepted.len(), "repairing");
+            let repair = Message::Repair(RepairData {
+                from: self.id,
+                to: data.from,
+                accepted,
+            });
+            self.effect(Effect::SendMessage(repair));
+        }
+    }
+
+    /// Receives a `Repair` message from another `Acceptor`, accepting each
+    /// proposal numbered at least as high as the promise, and higher than
+    /// the one accepted for its slot so far, in the same kind of round.
+    pub fn receive_repair(&mut self, msg: &Message<T>) {
+        if let Message::Repair(data) = msg {
+            span!("receive_repair", acceptor = self.id, from = data.from);
+            if data.to != self.id || !self.accepts() {
+                return;
+            }
+            for (slot, n, fast, value) in &data.accepted {
+                let superseded = self.accepted.get(slot).is_some_and(|a| a.n >= *n);
+                if *slot < self.truncated || *n < self.promised_n || superseded {
+                    continue;
+                }
+                if !self.promise(*n) {
+                    return;
+                }
+                // A fast round's votes are announced as such, for `Learner`s
+                // to wait for a fast quorum of matching ones.
+                if *fast {
+                    self.fast_rounds.insert(*slot, *n);
+                } else {
+                    self.fast_rounds.remove(slot);
+                }
+                self.accept(*slot, *n, value.clone(), *fast);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::acceptor::Acceptor;
+    use crate::config::ClusterConfig;
+    use crate::effect::Effect;
+    use crate::learner::Learner;
+    use crate::message::{AcceptData, AcceptedData, Handler, Message, ProposalData};
+    use crate::proposer::ballot;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn sent(a: &mut Acceptor<u64>) -> Vec<Message<u64>> {
+        a.take_effects()
+            .into_iter()
+            .filter_map(|effect| match effect {
+                Effect::SendMessage(msg) => Some(msg),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn anti_entropy_repair() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut a: Acceptor<u64> = Acceptor::new(1, config.clone());
+        let mut b: Acceptor<u64> = Acceptor::new(2, config);
+        let n = ballot(1, 3);
+        let prepare = Message::Prepare(ProposalData {
+            slot: 0,
+            id: n,
+            from: 3,
+        });
+        a.handle(prepare.clone());
+        b.handle(prepare);
+        for slot in 0..5 {
+            let accept = Message::Accept(AcceptData {
+                slot,
+                id: n,
+                value: Arc::new(slot * 10),
+                implicit_prepare: false,
+            });
+            // `b` misses the `Accept` for slot 3.
+            if slot != 3 {
+                b.handle(accept.clone());
+            }
+            a.handle(accept);
+        }
+        a.take_effects();
+        b.take_effects();
+        b.set_anti_entropy(Some(100), 2);
+        b.set_time(0);
+
+        let digest = sent(&mut b);
+        assert!(matches!(&digest[..], [Message::Digest(d)] if d.hashes.len() == 3));
+
+        // Only the range holding the hole is repaired.
+        a.handle(digest[0].clone());
+        let repair = sent(&mut a);
+        assert!(matches!(&repair[..], [Message::Repair(r)] if r.accepted.len() == 2));
+
+        b.handle(repair[0].clone());
+
+        assert_eq!(
+            sent(&mut b),
+            vec![Message::Accepted(AcceptedData {
+                slot: 3,
+                id: n,
+                value: Arc::new(30),
+                from: 2,
+                fast: false,
+            })]
+        );
+
+        // The logs now agree, until the next digest is due.
+        b.set_time(99);
+
+        assert!(sent(&mut b).is_empty());
+
+        b.set_time(100);
+        a.handle(sent(&mut b).remove(0));
+
+        assert!(sent(&mut a).is_empty());
+    }
+
+    #[test]
+    fn anti_entropy_truncated() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut a: Acceptor<u64> = Acceptor::new(1, config.clone());
+        let mut b: Acceptor<u64> = Acceptor::new(2, config);
+        let n = ballot(1, 3);
+        for acceptor in [&mut a, &mut b] {
+            acceptor.handle(Message::Prepare(ProposalData {
+                slot: 0,
+                id: n,
+                from: 3,
+            }));
+            for slot in 0..10 {
+                acceptor.handle(Message::Accept(AcceptData {
+                    slot,
+                    id: n,
+                    value: Arc::new(slot * 10),
+                    implicit_prepare: false,
+                }));
+            }
+            acceptor.take_effects();
+            acceptor.set_anti_entropy(Some(100), 4);
+        }
+        a.truncate(5);
+        b.truncate(2);
+
+        // The logs agree on every slot both still hold: neither side repairs
+        // the slots the other dropped, over and over.
+        a.set_time(0);
+        b.set_time(0);
+        let (from_a, from_b) = (sent(&mut a), sent(&mut b));
+        for msg in from_b {
+            a.handle(msg);
+        }
+        for msg in from_a {
+            b.handle(msg);
+        }
+
+        assert!(sent(&mut a).is_empty());
+        assert!(sent(&mut b).is_empty());
+    }
+
+    #[test]
+    fn anti_entropy_completes_classic_quorum() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut a: Acceptor<u64> = Acceptor::new(1, config.clone());
+        let mut b: Acceptor<u64> = Acceptor::new(2, config.clone());
+        let mut l: Learner<u64> = Learner::new(4, config);
+        let n = ballot(1, 3);
+        for acceptor in [&mut a, &mut b] {
+            acceptor.handle(Message::Prepare(ProposalData {
+                slot: 0,
+                id: n,
+                from: 3,
+            }));
+        }
+        b.take_effects();
+
+        // Node 3 goes down once `a` accepted, and `b` misses the `Accept`.
+        a.handle(Message::Accept(AcceptData {
+            slot: 0,
+            id: n,
+            value: Arc::new(10),
+            implicit_prepare: false,
+        }));
+        for msg in sent(&mut a) {
+            l.handle(msg);
+        }
+
+        assert_eq!(l.decided_value(0), None);
+
+        b.set_anti_entropy(Some(100), 8);
+        b.set_time(0);
+        a.handle(sent(&mut b).remove(0));
+        b.handle(sent(&mut a).remove(0));
+        for msg in sent(&mut b) {
+            l.handle(msg);
+        }
+
+        assert_eq!(l.decided_value(0), Some(&Arc::new(10)));
+    }
+}
@@ -0,0 +1,219 @@
+//! Backups
+//!
+//! `Node::export_backup` writes everything a node must not lose into a single
+//! stream: its `Acceptor`'s promises and votes, and its `Learner`'s snapshot
+//! and decided log. `Node::import_backup` loads it into a fresh `Node`, e.g.
+//! to move a node to another machine, or to bring back one whose disk was
+//! lost. In the notation of the `wire` module:
+//!
+//! ```text
+//! "PAXB" version:u8 id:u64 promised_n:u64 truncated:u64
+//! accepted:list<slot:u64 n:u64 value:bytes>
+//! snapshot:opt<index:u64 value:bytes>
+//! decided:list<slot:u64 value:bytes>
+//! crc:u32
+//! ```
+//!
+//! The trailing CRC-32 covers everything before it, so a backup damaged in
+//! transit or cut short is refused rather than loaded.
+
+use crate::acceptor::AcceptedProposal;
+use crate::message::{Messenger, Slot};
+use crate::node::Node;
+use crate::storage::{crc32, DurableState};
+use crate::wire::{put_bytes, put_u32, put_u64, DecodeError, Reader};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use std::io::{self, Read, Write};
+use std::vec::Vec;
+
+const MAGIC: &[u8] = b"PAXB";
+const VERSION: u8 = 1;
+
+impl<T, M> Node<T, M>
+where
+    T: PartialEq + Clone + AsRef<[u8]> + for<'a> From<&'a [u8]>,
+    M: Messenger<T>,
+{
+    /// Writes the `Node`'s durable state to `writer`. See the module
+    /// documentation.
+    pub fn export_backup<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        put_u64(&mut out, self.id);
+        put_u64(&mut out, self.acceptor.promised_n);
+        put_u64(&mut out, self.acceptor.truncated);
+        put_u32(&mut out, self.acceptor.accepted.len() as u32);
+        for (slot, accepted) in &self.acceptor.accepted {
+            put_u64(&mut out, *slot);
+            put_u64(&mut out, accepted.n);
+            put_bytes(&mut out, accepted.value.as_ref().as_ref());
+        }
+        match &self.learner.snapshot {
+            Some((index, value)) => {
+                out.push(1);
+                put_u64(&mut out, *index);
+                put_bytes(&mut out, value.as_ref().as_ref());
+            }
+            None => out.push(0),
+        }
+        put_u32(&mut out, self.learner.decided.len() as u32);
+        for (slot, value) in &self.learner.decided {
+            put_u64(&mut out, *slot);
+            put_bytes(&mut out, value.as_ref().as_ref());
+        }
+        let crc = crc32(&out);
+        put_u32(&mut out, crc);
+        writer.write_all(&out)?;
+        writer.flush()
+    }
+
+    /// Replaces the `Node`'s durable state with the backup read from
+    /// `reader`, which must have been exported by a `Node` of the same ID.
+    /// Nothing is changed if the backup is damaged. Values decided in the
+    /// backup are reported by `poll_decided`, and the state machine is
+    /// restored from its snapshot on the next `apply`.
+    pub fn import_backup<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() < MAGIC.len() + 5 || !bytes.starts_with(MAGIC) {
+            return Err(invalid("not a backup"));
+        }
+        let (body, crc) = bytes.split_at(bytes.len() - 4);
+        if crc32(body) != u32::from_be_bytes(crc.try_into().unwrap()) {
+            return Err(invalid("backup checksum mismatch"));
+        }
+        let mut r = Reader {
+            bytes: &body[MAGIC.len()..],
+        };
+        if r.u8().map_err(decode)? != VERSION {
+            return Err(invalid("unknown backup version"));
+        }
+        if r.u64().map_err(decode)? != self.id {
+            return Err(invalid("backup of another node"));
+        }
+        let backup = read_backup(&mut r).map_err(decode)?;
+        if !r.bytes.is_empty() {
+            return Err(decode(DecodeError::TrailingBytes));
+        }
+        self.acceptor.restore(backup.state);
+        self.acceptor.truncated = backup.truncated;
+        self.learner.decided = backup.decided;
+        self.learner.ballots.clear();
+        self.learner.snapshot = backup.snapshot;
+        let compacted = self.learner.compacted();
+        let next = self.learner.decided.keys().next_back().map_or(0, |s| s + 1);
+        self.learner.horizon = self.learner.horizon.max(compacted).max(next);
+        self.delivered = self.delivered.min(compacted);
+        self.learner.notify_watchers();
+        Ok(())
+    }
+}
+
+/// The state read back from a backup.
+struct Backup<T> {
+    state: DurableState<T>,
+    truncated: Slot,
+    snapshot: Option<(Slot, Arc<T>)>,
+    decided: BTreeMap<Slot, Arc<T>>,
+}
+
+fn read_backup<T>(r: &mut Reader) -> Result<Backup<T>, DecodeError>
+where
+    T: for<'a> From<&'a [u8]>,
+{
+    let mut state = DurableState {
+        promised_n: r.u64()?,
+        ..DurableState::default()
+    };
+    let truncated = r.u64()?;
+    for _ in 0..r.u32()? {
+        let (slot, n) = (r.u64()?, r.u64()?);
+        let value = Arc::new(T::from(r.bytes()?));
+        state.accepted.insert(slot, AcceptedProposal { n, value });
+    }
+    let snapshot = if r.bool()? {
+        Some((r.u64()?, Arc::new(T::from(r.bytes()?))))
+    } else {
+        None
+    };
+    let mut decided = BTreeMap::new();
+    for _ in 0..r.u32()? {
+        let slot = r.u64()?;
+        decided.insert(slot, Arc::new(T::from(r.bytes()?)));
+    }
+    Ok(Backup {
+        state,
+        truncated,
+        snapshot,
+        decided,
+    })
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn decode(err: DecodeError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::message::{AcceptData, AcceptedData, Message};
+    use alloc::vec;
+
+    type Value = Vec<u8>;
+
+    fn node() -> Node<Value> {
+        let mut node: Node<Value> = Node::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        node.step(Message::Accept(AcceptData {
+            slot: 1,
+            id: 5,
+            value: Arc::new(b"b".to_vec()),
+            implicit_prepare: true,
+        }));
+        for from in 1..=2 {
+            node.step(Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 4,
+                value: Arc::new(b"a".to_vec()),
+                from,
+                fast: false,
+            }));
+        }
+        node
+    }
+
+    #[test]
+    fn backup_roundtrip() {
+        let mut backup = Vec::new();
+        node().export_backup(&mut backup).unwrap();
+
+        let mut restored: Node<Value> = Node::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        restored.import_backup(&backup[..]).unwrap();
+
+        assert_eq!(restored.acceptor().promised_n, 5);
+        assert_eq!(restored.acceptor().accepted, node().acceptor().accepted);
+        assert_eq!(restored.poll_decided(), vec![(0, Arc::new(b"a".to_vec()))]);
+    }
+
+    #[test]
+    fn backup_integrity() {
+        let mut backup = Vec::new();
+        node().export_backup(&mut backup).unwrap();
+        let mut restored: Node<Value> = Node::new(1, ClusterConfig::new(vec![1, 2, 3]));
+
+        let mut damaged = backup.clone();
+        damaged[20] ^= 1;
+        assert!(restored.import_backup(&damaged[..]).is_err());
+        assert!(restored.import_backup(&backup[..backup.len() - 1]).is_err());
+        assert_eq!(restored.acceptor().promised_n, 0);
+
+        // A backup is only restored to the node it was taken from.
+        let mut other: Node<Value> = Node::new(2, ClusterConfig::new(vec![1, 2, 3]));
+        assert!(other.import_backup(&backup[..]).is_err());
+    }
+}
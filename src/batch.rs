@@ -0,0 +1,276 @@
+//! Batching
+//!
+//! Under load, running an instance of the protocol per client value wastes
+//! round trips. A `Batcher` accumulates values and hands them over as a single
+//! `Batch`, proposed by a `Proposer<Batch<T>>` like any other value. Learners
+//! unpack decided batches into their entries before applying them: `Batched`
+//! applies the entries of each batch in turn to a `StateMachine` of entries,
+//! through `Learner::apply`.
+//!
+//! The crate has no clock of its own: callers pass the current time in
+//! milliseconds, from whatever source suits them.
+
+use crate::error::LearnerError;
+use crate::learner::Learner;
+use crate::message::Slot;
+use crate::priority::Priority;
+use crate::state_machine::StateMachine;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Values proposed together, in the order they were submitted.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Default)]
+pub struct Batch<T>(pub Vec<T>);
+
+/// Accumulates values until either enough of them, or enough time, has gone
+/// by.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Batcher<T> {
+    /// Number of values a batch is cut at
+    pub max_entries: usize,
+    /// Milliseconds the oldest pending value may wait for a batch to fill up
+    pub max_delay: u64,
+    /// Values waiting to be batched
+    pub pending: Vec<T>,
+    /// When the oldest pending value was submitted, in milliseconds
+    pub since: Option<u64>,
+}
+
+impl<T> Batcher<T> {
+    /// Creates a new `Batcher`, cutting batches at `max_entries` values or
+    /// after `max_delay` milliseconds.
+    pub fn new(max_entries: usize, max_delay: u64) -> Self {
+        Self {
+            max_entries,
+            max_delay,
+            pending: Vec::new(),
+            since: None,
+        }
+    }
+
+    /// Submits `value` at time `now`, returning a batch once `max_entries`
+    /// values are pending.
+    pub fn push(&mut self, value: T, now: u64) -> Option<Batch<T>> {
+        self.since.get_or_insert(now);
+        self.pending.push(value);
+        if self.pending.len() >= self.max_entries {
+            return self.flush();
+        }
+        None
+    }
+
+    /// Submits `value` at time `now` like `push`, unless `priority` is
+    /// `High`: a batch is then cut right away, with `value` ahead of the
+    /// values pending, to be proposed at `High` priority.
+    pub fn push_with_priority(
+        &mut self,
+        value: T,
+        priority: Priority,
+        now: u64,
+    ) -> Option<Batch<T>> {
+        if priority < Priority::High {
+            return self.push(value, now);
+        }
+        self.pending.insert(0, value);
+        self.flush()
+    }
+
+    /// Returns a batch of the pending values if the oldest one has waited for
+    /// `max_delay` milliseconds at time `now`. Expected to be called
+    /// periodically.
+    pub fn poll(&mut self, now: u64) -> Option<Batch<T>> {
+        match self.since {
+            Some(since) if now.saturating_sub(since) >= self.max_delay => self.flush(),
+            _ => None,
+        }
+    }
+
+    /// Returns a batch of the pending values, if any, regardless of its size.
+    pub fn flush(&mut self) -> Option<Batch<T>> {
+        self.since = None;
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(Batch(core::mem::take(&mut self.pending)))
+    }
+}
+
+/// Applies decided `Batch`es to `S`, a `StateMachine` of their entries: the
+/// entries of a batch are applied in order, each under the slot of its batch.
+/// Snapshots are carried as a batch of a single entry: any other batch a
+/// snapshot is restored from is ignored, and reported through `take_errors`.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Batched<S> {
+    inner: S,
+    /// Snapshots refused, waiting to be taken
+    errors: Vec<LearnerError>,
+}
+
+impl<S> Batched<S> {
+    /// Applies batches to `inner`.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            errors: Vec::new(),
+        }
+    }
+
+    /// The state machine of entries.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// The state machine of entries, mutably.
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Takes the errors of the snapshots refused since the last call, for
+    /// not holding a single entry.
+    pub fn take_errors(&mut self) -> Vec<LearnerError> {
+        core::mem::take(&mut self.errors)
+    }
+}
+
+impl<T, S: StateMachine<T>> StateMachine<Batch<T>> for Batched<S> {
+    type Output = Vec<S::Output>;
+
+    fn apply(&mut self, slot: Slot, batch: &Batch<T>) -> Self::Output {
+        batch
+            .0
+            .iter()
+            .map(|value| self.inner.apply(slot, value))
+            .collect()
+    }
+
+    fn snapshot(&self) -> Batch<T> {
+        Batch(vec![self.inner.snapshot()])
+    }
+
+    fn restore(&mut self, snapshot: &Batch<T>) {
+        match &snapshot.0[..] {
+            [snapshot] => self.inner.restore(snapshot),
+            entries => {
+                event!(entries = entries.len(), "malformed snapshot");
+                self.errors
+                    .push(LearnerError::MalformedSnapshot(entries.len()));
+            }
+        }
+    }
+}
+
+impl<T: PartialEq, M> Learner<Batch<T>, M> {
+    /// Unpacks the decided batches, yielding every entry along with the slot
+    /// its batch was decided in, in log order. Stops at the first slot not
+    /// decided yet, as the entries past it can't be applied until it is.
+    pub fn entries(&self) -> impl Iterator<Item = (Slot, &T)> {
+        let start = self.snapshot.as_ref().map_or(0, |(index, _)| *index);
+        self.decided
+            .range(start..)
+            .zip(start..)
+            .take_while(|((slot, _), next)| **slot == *next)
+            .flat_map(|((slot, batch), _)| batch.0.iter().map(move |value| (*slot, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::learner::tests::decide;
+    use crate::message::{AcceptedData, Message};
+    use alloc::sync::Arc;
+
+    #[test]
+    fn batch_cut() {
+        let mut b = Batcher::new(3, 10);
+
+        assert_eq!(b.push(1, 0), None);
+        assert_eq!(b.push(2, 4), None);
+        assert_eq!(b.push(3, 5), Some(Batch(vec![1, 2, 3])));
+
+        // The delay runs from the oldest pending value.
+        assert_eq!(b.poll(20), None);
+        assert_eq!(b.push(4, 20), None);
+        assert_eq!(b.poll(29), None);
+        assert_eq!(b.push(5, 29), None);
+        assert_eq!(b.poll(30), Some(Batch(vec![4, 5])));
+        assert_eq!(b.flush(), None);
+
+        // An urgent value doesn't wait for the batch to fill up.
+        assert_eq!(b.push(6, 40), None);
+        assert_eq!(
+            b.push_with_priority(7, Priority::High, 41),
+            Some(Batch(vec![7, 6]))
+        );
+        assert_eq!(b.poll(50), None);
+    }
+
+    #[test]
+    fn batch_learner_entries() {
+        let mut l: Learner<Batch<u64>> = Learner::new(1, ClusterConfig::new(vec![1]));
+
+        for (slot, batch) in [(1, vec![3]), (0, vec![1, 2]), (3, vec![4])] {
+            l.receive_accepted(Message::Accepted(AcceptedData {
+                slot,
+                id: 1,
+                value: Arc::new(Batch(batch)),
+                from: 1,
+                fast: false,
+            }));
+        }
+
+        // The batch decided past the gap at slot 2 is held back.
+        assert_eq!(
+            l.entries().collect::<Vec<_>>(),
+            vec![(0, &1), (0, &2), (1, &3)]
+        );
+    }
+
+    #[test]
+    fn batch_state_machine() {
+        /// Sums the entries applied, returning the sum so far.
+        struct Sum(u64);
+
+        impl StateMachine<u64> for Sum {
+            type Output = u64;
+
+            fn apply(&mut self, _slot: Slot, value: &u64) -> u64 {
+                self.0 += value;
+                self.0
+            }
+
+            fn snapshot(&self) -> u64 {
+                self.0
+            }
+
+            fn restore(&mut self, snapshot: &u64) {
+                self.0 = *snapshot;
+            }
+        }
+
+        let mut l: Learner<Batch<u64>> = Learner::new(1, ClusterConfig::new(vec![1]));
+        for (slot, batch) in [(0, vec![1, 2]), (2, vec![5]), (1, vec![3])] {
+            decide(&mut l, slot, Batch(batch));
+        }
+        let mut sum = Batched::new(Sum(0));
+
+        assert_eq!(
+            l.apply(&mut sum),
+            vec![(0, vec![1, 3]), (1, vec![6]), (2, vec![11])]
+        );
+
+        l.compact(&sum);
+        let mut restored = Batched::new(Sum(0));
+        restored.restore(l.snapshot().unwrap().1);
+
+        assert_eq!(restored.inner().0, 11);
+        assert!(restored.take_errors().is_empty());
+
+        // A snapshot batch of several entries can't be restored from.
+        restored.restore(&Batch(vec![1, 2]));
+
+        assert_eq!(restored.inner().0, 11);
+        assert_eq!(restored.take_errors(), [LearnerError::MalformedSnapshot(2)]);
+    }
+}
@@ -0,0 +1,414 @@
+//! Byzantine fault tolerance
+//!
+//! With up to `f` of `3f + 1` `Acceptor`s behaving arbitrarily, votes can't be
+//! taken at face value. `Promise` and `Accepted` messages are signed, and a
+//! `Proposer` justifies the value of its `Accept` with a certificate: the
+//! signed `Promise`s of a Byzantine quorum, which `Acceptor`s verify before
+//! accepting anything.
+//!
+//! Signatures are produced and checked by the application, through `Signer`
+//! and `Verifier`, over the [`wire`](crate::wire) encoding of the message.
+
+use crate::acceptor::Acceptor;
+use crate::config::{ClusterConfig, NodeId};
+use crate::message::{AcceptData, Message, Messenger, Slot};
+use crate::wire;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use thiserror::Error;
+
+/// A signature over the encoding of a message.
+pub type Signature = Vec<u8>;
+
+/// Signs the messages sent by a node.
+pub trait Signer {
+    /// Signs `bytes`.
+    fn sign(&self, bytes: &[u8]) -> Signature;
+}
+
+/// Checks the signatures of other nodes.
+pub trait Verifier {
+    /// Whether `signature` was produced by `from` over `bytes`.
+    fn verify(&self, from: NodeId, bytes: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Errors raised by messages that can't be trusted.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+pub enum BftError {
+    /// The signature of a message doesn't match its sender
+    #[error("bad signature from {0}")]
+    BadSignature(NodeId),
+    /// A message was signed by a node outside of the cluster
+    #[error("{0} is not a member")]
+    NotAMember(NodeId),
+    /// A message doesn't belong to the proposal being certified
+    #[error("message belongs to another proposal")]
+    WrongProposal,
+    /// The messages don't come from a Byzantine quorum
+    #[error("no Byzantine quorum")]
+    NoQuorum,
+    /// The value doesn't match the one the certificate vouches for
+    #[error("value not vouched for by the certificate")]
+    UnsafeValue,
+}
+
+/// The largest number of faulty `Acceptor`s the cluster tolerates.
+pub fn max_faulty(config: &ClusterConfig) -> usize {
+    config.members.len().saturating_sub(1) / 3
+}
+
+/// The number of votes needed in either phase, so that any two quorums have
+/// a correct `Acceptor` in common.
+pub fn byzantine_quorum(config: &ClusterConfig) -> usize {
+    (config.members.len() + max_faulty(config)) / 2 + 1
+}
+
+/// A `Promise` or `Accepted` message along with its sender's signature.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Signed<T> {
+    /// The signed message
+    pub message: Message<T>,
+    /// Signature over the wire encoding of `message`
+    pub signature: Signature,
+}
+
+impl<T: AsRef<[u8]>> Signed<T> {
+    /// Signs `message` with `signer`.
+    pub fn new<S: Signer + ?Sized>(message: Message<T>, signer: &S) -> Self {
+        let signature = signer.sign(&wire::encode(&message));
+        Self { message, signature }
+    }
+
+    /// The sender of the message, if it has one.
+    pub fn from(&self) -> Option<NodeId> {
+        match self.message {
+            Message::Promise(ref data) => Some(data.from),
+            Message::Accepted(ref data) => Some(data.from),
+            _ => None,
+        }
+    }
+
+    /// Checks the signature against the sender of the message.
+    pub fn verify<V: Verifier + ?Sized>(&self, verifier: &V) -> Result<NodeId, BftError> {
+        let from = self.from().ok_or(BftError::WrongProposal)?;
+        if verifier.verify(from, &wire::encode(&self.message), &self.signature) {
+            Ok(from)
+        } else {
+            Err(BftError::BadSignature(from))
+        }
+    }
+}
+
+/// Signed `Promise`s of a Byzantine quorum for a single proposal.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Certificate<T> {
+    pub slot: Slot,
+    pub id: u64,
+    /// The signed `Promise`s, one per `Acceptor`
+    pub promises: Vec<Signed<T>>,
+}
+
+impl<T: AsRef<[u8]> + Eq> Certificate<T> {
+    /// Checks that the certificate holds correctly signed `Promise`s for its
+    /// proposal from a Byzantine quorum of distinct members.
+    pub fn verify<V: Verifier + ?Sized>(
+        &self,
+        config: &ClusterConfig,
+        verifier: &V,
+    ) -> Result<(), BftError> {
+        let mut voters = Vec::new();
+        for promise in &self.promises {
+            let from = promise.verify(verifier)?;
+            match promise.message {
+                Message::Promise(ref data) if data.slot == self.slot && data.id == self.id => {}
+                _ => return Err(BftError::WrongProposal),
+            }
+            if !config.is_member(from) {
+                return Err(BftError::NotAMember(from));
+            }
+            if !voters.contains(&from) {
+                voters.push(from);
+            }
+        }
+        if voters.len() < byzantine_quorum(config) {
+            return Err(BftError::NoQuorum);
+        }
+        Ok(())
+    }
+
+    /// The value the `Proposer` must propose, if any: the one accepted under
+    /// the highest proposal number that more than `f` `Acceptor`s vouch for,
+    /// as at least one of them is correct.
+    pub fn safe_value(&self, config: &ClusterConfig) -> Option<Arc<T>> {
+        let mut vouchers: BTreeMap<u64, Vec<(&Arc<T>, usize)>> = BTreeMap::new();
+        for promise in &self.promises {
+            if let Message::Promise(ref data) = promise.message {
+                if let (Some(n), Some(value)) = (data.accepted_n, data.value.as_ref()) {
+                    let values = vouchers.entry(n).or_default();
+                    match values.iter_mut().find(|(v, _)| *v == value) {
+                        Some((_, count)) => *count += 1,
+                        None => values.push((value, 1)),
+                    }
+                }
+            }
+        }
+        vouchers.into_iter().rev().find_map(|(_, values)| {
+            values
+                .into_iter()
+                .find(|(_, count)| *count > max_faulty(config))
+                .map(|(value, _)| value.clone())
+        })
+    }
+}
+
+/// Gathers the signed `Promise`s of a proposal until they make up a
+/// `Certificate`.
+#[derive(Debug, Clone)]
+pub struct Collector<T> {
+    pub slot: Slot,
+    pub id: u64,
+    /// Verified promises received so far (from => promise)
+    pub promises: BTreeMap<NodeId, Signed<T>>,
+}
+
+impl<T: AsRef<[u8]> + Eq + Clone> Collector<T> {
+    /// Creates a new `Collector` for proposal `id` of `slot`.
+    pub fn new(slot: Slot, id: u64) -> Self {
+        Self {
+            slot,
+            id,
+            promises: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a signed `Promise`, returning the `Certificate` once a Byzantine
+    /// quorum has been gathered. Promises that fail verification are dropped.
+    pub fn add<V: Verifier + ?Sized>(
+        &mut self,
+        promise: Signed<T>,
+        config: &ClusterConfig,
+        verifier: &V,
+    ) -> Option<Certificate<T>> {
+        let from = promise.verify(verifier).ok()?;
+        match promise.message {
+            Message::Promise(ref data) if data.slot == self.slot && data.id == self.id => {}
+            _ => return None,
+        }
+        if !config.is_member(from) || self.promises.contains_key(&from) {
+            return None;
+        }
+        self.promises.insert(from, promise);
+        if self.promises.len() != byzantine_quorum(config) {
+            return None;
+        }
+        Some(Certificate {
+            slot: self.slot,
+            id: self.id,
+            promises: self.promises.values().cloned().collect(),
+        })
+    }
+}
+
+/// An `Accept` justified by a `Certificate` of the same proposal.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CertifiedAccept<T> {
+    pub accept: AcceptData<T>,
+    pub certificate: Certificate<T>,
+}
+
+impl<T: AsRef<[u8]> + Eq> CertifiedAccept<T> {
+    /// Checks the certificate, and that the value is the one it vouches for,
+    /// if any.
+    pub fn verify<V: Verifier + ?Sized>(
+        &self,
+        config: &ClusterConfig,
+        verifier: &V,
+    ) -> Result<(), BftError> {
+        let cert = &self.certificate;
+        if cert.slot != self.accept.slot || cert.id != self.accept.id {
+            return Err(BftError::WrongProposal);
+        }
+        cert.verify(config, verifier)?;
+        match cert.safe_value(config) {
+            Some(ref value) if *value != self.accept.value => Err(BftError::UnsafeValue),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether the signed `Accepted` messages decide `value` for proposal `id` of
+/// `slot`. Messages failing verification, or for anything else, don't count.
+pub fn is_decided<T, V>(
+    config: &ClusterConfig,
+    verifier: &V,
+    slot: Slot,
+    id: u64,
+    value: &Arc<T>,
+    accepted: &[Signed<T>],
+) -> bool
+where
+    T: AsRef<[u8]> + Eq,
+    V: Verifier + ?Sized,
+{
+    let mut voters = Vec::new();
+    for msg in accepted {
+        let from = match msg.verify(verifier) {
+            Ok(from) => from,
+            Err(_) => continue,
+        };
+        if let Message::Accepted(ref data) = msg.message {
+            let matches = data.slot == slot && data.id == id && data.value == *value;
+            if matches && config.is_member(from) && !voters.contains(&from) {
+                voters.push(from);
+            }
+        }
+    }
+    voters.len() >= byzantine_quorum(config)
+}
+
+impl<T: AsRef<[u8]> + Eq, M: Messenger<T>> Acceptor<T, M> {
+    /// Receives an `Accept` along with the certificate justifying it. The
+    /// `Accept` is only handled if the certificate checks out.
+    pub fn receive_certified_accept<V: Verifier + ?Sized>(
+        &mut self,
+        msg: &CertifiedAccept<T>,
+        verifier: &V,
+    ) -> Result<(), BftError> {
+        msg.verify(&self.config, verifier)?;
+        let accept = &msg.accept;
+        self.receive_accept(&Message::Accept(AcceptData {
+            slot: accept.slot,
+            id: accept.id,
+            value: accept.value.clone(),
+            implicit_prepare: accept.implicit_prepare,
+        }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{AcceptedData, PromiseData};
+    use alloc::vec;
+
+    /// Toy signatures: the signer's ID followed by a checksum of the bytes.
+    struct Key(NodeId);
+
+    fn checksum(bytes: &[u8]) -> u8 {
+        bytes.iter().fold(0u8, |acc, b| acc.rotate_left(1) ^ b)
+    }
+
+    impl Signer for Key {
+        fn sign(&self, bytes: &[u8]) -> Signature {
+            vec![self.0 as u8, checksum(bytes)]
+        }
+    }
+
+    struct Keys;
+
+    impl Verifier for Keys {
+        fn verify(&self, from: NodeId, bytes: &[u8], signature: &[u8]) -> bool {
+            signature == [from as u8, checksum(bytes)]
+        }
+    }
+
+    fn promise(from: NodeId, accepted: Option<(u64, u8)>) -> Signed<Vec<u8>> {
+        let msg = Message::Promise(PromiseData {
+            slot: 0,
+            id: 5,
+            accepted_n: accepted.map(|(n, _)| n),
+            value: accepted.map(|(_, v)| Arc::new(vec![v])),
+            from,
+        });
+        Signed::new(msg, &Key(from))
+    }
+
+    #[test]
+    fn bft_quorums() {
+        let c = ClusterConfig::new((1..=4).collect());
+
+        assert_eq!(max_faulty(&c), 1);
+        assert_eq!(byzantine_quorum(&c), 3);
+
+        let c = ClusterConfig::new((1..=7).collect());
+
+        assert_eq!(max_faulty(&c), 2);
+        assert_eq!(byzantine_quorum(&c), 5);
+    }
+
+    #[test]
+    fn bft_certified_accept() {
+        let config = ClusterConfig::new((1..=4).collect());
+        let mut collector = Collector::new(0, 5);
+
+        // A lone (possibly lying) Acceptor can't force a value.
+        assert!(collector
+            .add(promise(1, Some((3, 9))), &config, &Keys)
+            .is_none());
+        assert!(collector.add(promise(2, None), &config, &Keys).is_none());
+
+        let mut forged = promise(3, None);
+        forged.signature[0] = 4;
+        assert!(collector.add(forged, &config, &Keys).is_none());
+
+        let cert = collector.add(promise(3, None), &config, &Keys).unwrap();
+
+        assert_eq!(cert.safe_value(&config), None);
+
+        let mut a: Acceptor<Vec<u8>> = Acceptor::new(4, config.clone());
+        let mut accept = CertifiedAccept {
+            accept: AcceptData {
+                slot: 0,
+                id: 5,
+                value: Arc::new(vec![1]),
+                implicit_prepare: false,
+            },
+            certificate: cert,
+        };
+
+        assert_eq!(a.receive_certified_accept(&accept, &Keys), Ok(()));
+        assert_eq!(a.accepted[&0].value, Arc::new(vec![1]));
+
+        // Two vouchers (more than f) constrain the value.
+        accept.certificate.promises[1] = promise(2, Some((3, 9)));
+
+        assert_eq!(
+            accept.certificate.safe_value(&config),
+            Some(Arc::new(vec![9]))
+        );
+        assert_eq!(
+            a.receive_certified_accept(&accept, &Keys),
+            Err(BftError::UnsafeValue)
+        );
+
+        accept.certificate.promises.pop();
+
+        assert_eq!(
+            a.receive_certified_accept(&accept, &Keys),
+            Err(BftError::NoQuorum)
+        );
+    }
+
+    #[test]
+    fn bft_is_decided() {
+        let config = ClusterConfig::new((1..=4).collect());
+        let value = Arc::new(vec![1]);
+        let accepted: Vec<_> = (1..=3)
+            .map(|from| {
+                let msg = Message::Accepted(AcceptedData {
+                    slot: 0,
+                    id: 5,
+                    value: value.clone(),
+                    from,
+                    fast: false,
+                });
+                Signed::new(msg, &Key(from))
+            })
+            .collect();
+
+        assert!(is_decided(&config, &Keys, 0, 5, &value, &accepted));
+        assert!(!is_decided(&config, &Keys, 0, 5, &value, &accepted[1..]));
+    }
+}
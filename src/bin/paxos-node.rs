@@ -0,0 +1,313 @@
+//! paxos-node
+//!
+//! Runs a single node of a cluster over the TCP transport:
+//!
+//! ```text
+//! paxos-node node.toml
+//! ```
+//!
+//! The configuration names the node, its peers and the port clients connect
+//! to:
+//!
+//! ```toml
+//! id = 1
+//! client = "127.0.0.1:8001"
+//! storage = "data/1"
+//! leader = 1                     # optional, the lowest ID by default
+//! sync_delay = 2                 # optional, in milliseconds
+//! acknowledge_recovery = false   # optional, see below
+//!
+//! [quorums]                      # optional, majorities by default
+//! phase1 = 2
+//! phase2 = 2
+//!
+//! [[peers]]
+//! id = 1
+//! addr = "127.0.0.1:7001"
+//! zone = "us-east"               # optional, see below
+//!
+//! [[peers]]
+//! id = 2
+//! addr = "127.0.0.1:7002"
+//! ```
+//!
+//! Peers tagged with a zone form a `Topology`: broadcasts to another zone go
+//! through a single peer there, which relays them to the rest of its zone.
+//!
+//! Clients send one command per line, and get one line back, as described in
+//! the `client` module, whose `PaxosClient` speaks the protocol. Only the
+//! leader takes proposals, and replies once the value is decided; the others
+//! reply with a hint naming the leader.
+//!
+//! The storage directory holds the decided log, one `<slot> <value>` line per
+//! value, the highest proposal number the node used, and the `Acceptor`'s
+//! state in a `FileStorage`. The votes cast within `sync_delay` milliseconds
+//! of each other share a single sync, and are only sent once it's done. A
+//! node restarted over an existing directory votes again right away, and the
+//! leader takes over the log where it left off.
+//!
+//! If records of the `Acceptor`'s log turn out to be damaged on restart, the
+//! node reports what was lost and stops voting, as it may have forgotten a
+//! promise or a vote. Once the other nodes are known to be intact, restarting
+//! it with `acknowledge_recovery = true` lets it vote again.
+
+use paxos_rust::storage::{FileStorage, GroupCommit, Storage};
+use paxos_rust::tcp::TcpTransport;
+use paxos_rust::topology::Topology;
+use paxos_rust::{ClusterConfig, Effect, Node, NodeId, QuorumConfig, Slot};
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+use std::{env, process, thread};
+
+type Value = Vec<u8>;
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    /// The node's ID, among those of `peers`
+    id: NodeId,
+    /// The address clients connect to
+    client: SocketAddr,
+    /// Directory the node stores its state in
+    storage: PathBuf,
+    /// The node taking proposals
+    leader: Option<NodeId>,
+    /// Milliseconds votes may wait for a sync shared with the following ones
+    sync_delay: Option<u64>,
+    /// Whether to vote again after records of the log were lost
+    #[serde(default)]
+    acknowledge_recovery: bool,
+    /// Quorum sizes, if not majorities
+    quorums: Option<QuorumConfig>,
+    /// Every node of the cluster, this one included
+    peers: Vec<Peer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Peer {
+    id: NodeId,
+    addr: SocketAddr,
+    /// The zone the peer is deployed in, if tagged
+    zone: Option<String>,
+}
+
+/// A client's command, along with where to send the reply.
+enum Request {
+    Propose(Value, Sender<String>),
+    Get(Slot, Sender<String>),
+}
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: paxos-node <config.toml>");
+            process::exit(2);
+        }
+    };
+    if let Err(err) = run(Path::new(&path)) {
+        eprintln!("paxos-node: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(path: &Path) -> Result<(), Box<dyn Error>> {
+    let config: Config = toml::from_str(&fs::read_to_string(path)?)?;
+    let peers: BTreeMap<NodeId, SocketAddr> = config
+        .peers
+        .iter()
+        .map(|peer| (peer.id, peer.addr))
+        .collect();
+    let mut cluster = ClusterConfig::new(peers.keys().copied().collect());
+    if let Some(quorums) = config.quorums {
+        cluster = cluster.with_quorums(quorums)?;
+    }
+    let leader = config.leader.unwrap_or(cluster.members[0]);
+    let id = config.id;
+    if !cluster.is_member(id) || !cluster.is_member(leader) {
+        return Err("the node and the leader must be among the peers".into());
+    }
+
+    fs::create_dir_all(&config.storage)?;
+    let ballot_path = config.storage.join("ballot");
+    let restarted = ballot_path.exists();
+    let mut ballot = if restarted {
+        fs::read_to_string(&ballot_path)?.trim().parse()?
+    } else {
+        0
+    };
+    store_ballot(&ballot_path, ballot)?;
+    // A restarted node learns the log over again from its peers.
+    let mut log = File::create(config.storage.join("log"))?;
+    let mut acceptor = FileStorage::open(config.storage.join("acceptor"))?;
+    if let Some(report) = acceptor.recovery() {
+        eprintln!("paxos-node: damaged acceptor log: {:?}", report);
+        if config.acknowledge_recovery {
+            acceptor.acknowledge_recovery()?;
+        } else {
+            eprintln!("paxos-node: not voting until acknowledge_recovery is set");
+        }
+    }
+    let state = Storage::<Value>::load(&mut acceptor)?;
+    let mut storage = GroupCommit::new(acceptor, config.sync_delay.unwrap_or(2));
+
+    let zones: BTreeMap<NodeId, String> = config
+        .peers
+        .iter()
+        .filter_map(|peer| Some((peer.id, peer.zone.clone()?)))
+        .collect();
+    let mut transport: TcpTransport<Value> = TcpTransport::bind(id, peers)?;
+    if !zones.is_empty() {
+        transport.set_topology(Topology::new(zones));
+    }
+    let (requests, inbox) = channel();
+    let listener = TcpListener::bind(config.client)?;
+    thread::spawn(move || serve(listener, requests));
+
+    let mut node: Node<Value> = Node::new(id, cluster);
+    node.proposer_mut().set_timeout(Some(200), 2);
+    node.proposer_mut().resume(ballot);
+    node.acceptor_mut().restore(state);
+    // Whether the leader is ready to take proposals
+    let mut ready = id == leader && !restarted;
+    let mut pending: VecDeque<(Value, Sender<String>)> = VecDeque::new();
+    let start = Instant::now();
+
+    loop {
+        let now = start.elapsed().as_millis() as u64;
+        let mut effects = match transport.recv_timeout(Duration::from_millis(10)) {
+            Some(msg) => node.step(msg),
+            None => Vec::new(),
+        };
+        if id == leader && !ready {
+            let learner = node.learner();
+            let unresolved = learner.unresolved().into_iter().chain([learner.horizon()]);
+            node.proposer_mut().take_over(unresolved, Vec::new());
+            ready = true;
+        }
+        handle(&inbox, &mut node, &mut pending, ready, leader);
+        // The leader sends its `Accept`s to the nearest quorum first.
+        if node.proposer().latencies() != transport.latencies() {
+            node.proposer_mut()
+                .set_latencies(transport.latencies().clone());
+        }
+        node.tick(now);
+        effects.extend(node.take_effects());
+
+        // The proposal numbers sent must survive a restart.
+        if node.proposer().current_ballot() > ballot {
+            ballot = node.proposer().current_ballot();
+            store_ballot(&ballot_path, ballot)?;
+        }
+        let mut released = storage.submit(effects, now)?;
+        released.extend(storage.tick(now)?);
+        for effect in released {
+            match effect {
+                Effect::SendMessage(msg) => transport.broadcast(&msg),
+                Effect::SendMessageTo(to, msg) => {
+                    for peer in to {
+                        transport.send(peer, &msg);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for (slot, value) in node.poll_decided() {
+            writeln!(log, "{} {}", slot, String::from_utf8_lossy(&value))?;
+            if let Some(i) = pending.iter().position(|(v, _)| *v == *value) {
+                let (_, reply) = pending.remove(i).unwrap();
+                let _ = reply.send(format!("DECIDED {}", slot));
+            }
+        }
+    }
+}
+
+/// Handles the commands clients sent since the last call.
+fn handle(
+    inbox: &Receiver<Request>,
+    node: &mut Node<Value>,
+    pending: &mut VecDeque<(Value, Sender<String>)>,
+    ready: bool,
+    leader: NodeId,
+) {
+    while let Ok(request) = inbox.try_recv() {
+        match request {
+            Request::Propose(value, reply) if ready => {
+                node.propose(value.clone());
+                pending.push_back((value, reply));
+            }
+            Request::Propose(_, reply) if node.id() == leader => {
+                let _ = reply.send("RETRY".into());
+            }
+            Request::Propose(_, reply) => {
+                let _ = reply.send(format!("NOT_LEADER {}", leader));
+            }
+            Request::Get(slot, reply) => {
+                let _ = reply.send(match node.learner().decided_value(slot) {
+                    Some(value) => format!("VALUE {}", String::from_utf8_lossy(value)),
+                    None => "NONE".into(),
+                });
+            }
+        }
+    }
+}
+
+/// Writes `ballot` to `path`, replacing the previous one only once it's on
+/// disk.
+fn store_ballot(path: &Path, ballot: u64) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    writeln!(file, "{}", ballot)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)
+}
+
+/// Accepts client connections, each served by a thread of its own.
+fn serve(listener: TcpListener, requests: Sender<Request>) {
+    for stream in listener.incoming().flatten() {
+        let requests = requests.clone();
+        thread::spawn(move || {
+            let _ = client(stream, requests);
+        });
+    }
+}
+
+/// Serves a client: one command per line, one reply per line.
+fn client(stream: TcpStream, requests: Sender<Request>) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let (reply, replies) = channel();
+        let request = match line.trim_end().split_once(' ') {
+            Some(("PROPOSE", value)) if !value.is_empty() => {
+                Request::Propose(value.as_bytes().to_vec(), reply)
+            }
+            Some(("GET", slot)) => match slot.parse() {
+                Ok(slot) => Request::Get(slot, reply),
+                Err(_) => {
+                    writeln!(writer, "ERR invalid slot")?;
+                    continue;
+                }
+            },
+            _ => {
+                writeln!(writer, "ERR unknown command")?;
+                continue;
+            }
+        };
+        if requests.send(request).is_err() {
+            return Ok(());
+        }
+        match replies.recv() {
+            Ok(reply) => writeln!(writer, "{}", reply)?,
+            Err(_) => return Ok(()),
+        }
+    }
+    Ok(())
+}
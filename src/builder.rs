@@ -0,0 +1,618 @@
+//! Builders
+//!
+//! Roles can be created with `new` and configured by setting their fields, but
+//! nothing then checks the result makes sense: a `Proposer` could be given a
+//! window of zero slots, or quorums that don't intersect. The builders here set
+//! every field up front and validate them before handing the role over.
+
+use crate::acceptor::Acceptor;
+use crate::config::{ClusterConfig, ConfigError, NodeId, QuorumConfig};
+use crate::event::{EventSink, PaxosEvent};
+use crate::learner::Learner;
+use crate::message::Messenger;
+use crate::node::Node;
+use crate::proposer::Proposer;
+use crate::retry::{RetryPolicy, RetrySink};
+use alloc::boxed::Box;
+use thiserror::Error;
+
+/// Errors raised when building a role.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+pub enum BuildError {
+    /// No ID was given
+    #[error("no ID given")]
+    MissingId,
+    /// No `ClusterConfig` was given
+    #[error("no ClusterConfig given")]
+    MissingConfig,
+    /// The quorums given don't fit the cluster
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// A `Proposer` may keep no slot in flight
+    #[error("a Proposer must keep a slot in flight")]
+    EmptyWindow,
+    /// Slots are partitioned among leaders the `Proposer` isn't one of
+    #[error("Proposer is not a leader")]
+    NotALeader,
+}
+
+/// Resolves the ID, configuration and quorums common to every builder.
+fn cluster(
+    id: Option<NodeId>,
+    config: Option<ClusterConfig>,
+    quorums: Option<QuorumConfig>,
+) -> Result<(NodeId, ClusterConfig), BuildError> {
+    let id = id.ok_or(BuildError::MissingId)?;
+    let mut config = config.ok_or(BuildError::MissingConfig)?;
+    if let Some(quorums) = quorums {
+        config = config.with_quorums(quorums)?;
+    }
+    Ok((id, config))
+}
+
+/// Builds a `Proposer`.
+pub struct ProposerBuilder<T> {
+    id: Option<NodeId>,
+    config: Option<ClusterConfig>,
+    quorums: Option<QuorumConfig>,
+    messenger: Option<Box<dyn Messenger<T> + Send>>,
+    events: Option<EventSink>,
+    window: usize,
+    timeout: Option<(u64, u32)>,
+    retry_policy: Option<RetrySink>,
+}
+
+impl<T> ProposerBuilder<T>
+where
+    T: PartialEq + Clone,
+{
+    /// Creates a new `ProposerBuilder`, with a single slot in flight at a time.
+    pub fn new() -> Self {
+        Self {
+            id: None,
+            config: None,
+            quorums: None,
+            messenger: None,
+            events: None,
+            window: 1,
+            timeout: None,
+            retry_policy: None,
+        }
+    }
+
+    /// Sets the `Proposer`'s ID.
+    pub fn id(mut self, id: NodeId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Sets the cluster the `Proposer` proposes to.
+    pub fn config(mut self, config: ClusterConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Sets per-phase quorum sizes, in place of simple majorities.
+    pub fn quorums(mut self, quorums: QuorumConfig) -> Self {
+        self.quorums = Some(quorums);
+        self
+    }
+
+    /// Sets the `Messenger` the `Proposer` sends through.
+    pub fn messenger<M>(mut self, messenger: M) -> Self
+    where
+        M: Messenger<T> + Send + 'static,
+    {
+        self.messenger = Some(Box::new(messenger));
+        self
+    }
+
+    /// Sets the callback notified of the `Proposer`'s progress.
+    pub fn events<F>(mut self, events: F) -> Self
+    where
+        F: FnMut(PaxosEvent) + Send + 'static,
+    {
+        self.events = Some(Box::new(events));
+        self
+    }
+
+    /// Sets the number of slots kept in flight at once.
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Sets the milliseconds a phase may go without completing before it is
+    /// sent again, up to `max_retransmits` times. See `Proposer::set_timeout`.
+    pub fn timeout(mut self, millis: u64, max_retransmits: u32) -> Self {
+        self.timeout = Some((millis, max_retransmits));
+        self
+    }
+
+    /// Sets the `RetryPolicy` failed rounds back off by. See
+    /// `Proposer::set_retry_policy`.
+    pub fn retry_policy<R>(mut self, policy: R) -> Self
+    where
+        R: RetryPolicy + Send + 'static,
+    {
+        self.retry_policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Builds the `Proposer`.
+    pub fn build(self) -> Result<Proposer<T>, BuildError> {
+        let (id, config) = cluster(self.id, self.config, self.quorums)?;
+        if self.window == 0 {
+            return Err(BuildError::EmptyWindow);
+        }
+        if !config.leaders.is_empty() && !config.leaders.contains(&id) {
+            return Err(BuildError::NotALeader);
+        }
+        let mut proposer = Proposer::new(id, config);
+        proposer.messenger = self.messenger;
+        proposer.events = self.events;
+        proposer.window = self.window;
+        proposer.retry_policy = self.retry_policy;
+        if let Some((timeout, max_retransmits)) = self.timeout {
+            proposer.set_timeout(Some(timeout), max_retransmits);
+        }
+        Ok(proposer)
+    }
+}
+
+impl<T> Default for ProposerBuilder<T>
+where
+    T: PartialEq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Proposer<T>
+where
+    T: PartialEq + Clone,
+{
+    /// Starts building a `Proposer`.
+    pub fn builder() -> ProposerBuilder<T> {
+        ProposerBuilder::new()
+    }
+}
+
+/// Builds an `Acceptor`.
+pub struct AcceptorBuilder<T> {
+    id: Option<NodeId>,
+    config: Option<ClusterConfig>,
+    quorums: Option<QuorumConfig>,
+    messenger: Option<Box<dyn Messenger<T> + Send>>,
+    events: Option<EventSink>,
+    joining: bool,
+}
+
+impl<T> AcceptorBuilder<T> {
+    /// Creates a new `AcceptorBuilder`.
+    pub fn new() -> Self {
+        Self {
+            id: None,
+            config: None,
+            quorums: None,
+            messenger: None,
+            events: None,
+            joining: false,
+        }
+    }
+
+    /// Sets the `Acceptor`'s ID.
+    pub fn id(mut self, id: NodeId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Sets the cluster the `Acceptor` is a member of.
+    pub fn config(mut self, config: ClusterConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Sets per-phase quorum sizes, in place of simple majorities.
+    pub fn quorums(mut self, quorums: QuorumConfig) -> Self {
+        self.quorums = Some(quorums);
+        self
+    }
+
+    /// Sets the `Messenger` the `Acceptor` sends through.
+    pub fn messenger<M>(mut self, messenger: M) -> Self
+    where
+        M: Messenger<T> + Send + 'static,
+    {
+        self.messenger = Some(Box::new(messenger));
+        self
+    }
+
+    /// Sets the callback notified of the `Acceptor`'s progress.
+    pub fn events<F>(mut self, events: F) -> Self
+    where
+        F: FnMut(PaxosEvent) + Send + 'static,
+    {
+        self.events = Some(Box::new(events));
+        self
+    }
+
+    /// Has the `Acceptor` join an existing cluster, ignoring proposals until
+    /// it has copied the state of the others. See `Acceptor::joining`.
+    pub fn joining(mut self, joining: bool) -> Self {
+        self.joining = joining;
+        self
+    }
+
+    /// Builds the `Acceptor`.
+    pub fn build(self) -> Result<Acceptor<T>, BuildError> {
+        let (id, config) = cluster(self.id, self.config, self.quorums)?;
+        let mut acceptor = if self.joining {
+            Acceptor::joining(id, config)
+        } else {
+            Acceptor::new(id, config)
+        };
+        acceptor.messenger = self.messenger;
+        acceptor.events = self.events;
+        Ok(acceptor)
+    }
+}
+
+impl<T> Default for AcceptorBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Acceptor<T> {
+    /// Starts building an `Acceptor`.
+    pub fn builder() -> AcceptorBuilder<T> {
+        AcceptorBuilder::new()
+    }
+}
+
+/// Builds a `Learner`.
+pub struct LearnerBuilder<T> {
+    id: Option<NodeId>,
+    config: Option<ClusterConfig>,
+    quorums: Option<QuorumConfig>,
+    messenger: Option<Box<dyn Messenger<T> + Send>>,
+    events: Option<EventSink>,
+    instance_ttl: Option<u64>,
+    require_certificates: bool,
+}
+
+impl<T: PartialEq> LearnerBuilder<T> {
+    /// Creates a new `LearnerBuilder`.
+    pub fn new() -> Self {
+        Self {
+            id: None,
+            config: None,
+            quorums: None,
+            messenger: None,
+            events: None,
+            instance_ttl: None,
+            require_certificates: false,
+        }
+    }
+
+    /// Sets the `Learner`'s ID.
+    pub fn id(mut self, id: NodeId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Sets the cluster values are learned from.
+    pub fn config(mut self, config: ClusterConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Sets per-phase quorum sizes, in place of simple majorities.
+    pub fn quorums(mut self, quorums: QuorumConfig) -> Self {
+        self.quorums = Some(quorums);
+        self
+    }
+
+    /// Sets the `Messenger` the `Learner` sends through.
+    pub fn messenger<M>(mut self, messenger: M) -> Self
+    where
+        M: Messenger<T> + Send + 'static,
+    {
+        self.messenger = Some(Box::new(messenger));
+        self
+    }
+
+    /// Sets the callback notified of every decided slot.
+    pub fn events<F>(mut self, events: F) -> Self
+    where
+        F: FnMut(PaxosEvent) + Send + 'static,
+    {
+        self.events = Some(Box::new(events));
+        self
+    }
+
+    /// Sets the number of ticks an undecided slot may go without activity
+    /// before it is reported as abandoned.
+    pub fn instance_ttl(mut self, ticks: u64) -> Self {
+        self.instance_ttl = Some(ticks);
+        self
+    }
+
+    /// Has the `Learner` ignore `Learn` messages not certified by a quorum.
+    pub fn require_certificates(mut self, require: bool) -> Self {
+        self.require_certificates = require;
+        self
+    }
+
+    /// Builds the `Learner`.
+    pub fn build(self) -> Result<Learner<T>, BuildError> {
+        let (id, config) = cluster(self.id, self.config, self.quorums)?;
+        let mut learner = Learner::new(id, config);
+        learner.messenger = self.messenger;
+        learner.events = self.events;
+        learner.instance_ttl = self.instance_ttl;
+        learner.require_certificates = self.require_certificates;
+        Ok(learner)
+    }
+}
+
+impl<T: PartialEq> Default for LearnerBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: PartialEq> Learner<T> {
+    /// Starts building a `Learner`.
+    pub fn builder() -> LearnerBuilder<T> {
+        LearnerBuilder::new()
+    }
+}
+
+/// Builds a `Node`. Settings are shared by all of its roles.
+pub struct NodeBuilder<T> {
+    proposer: ProposerBuilder<T>,
+    acceptor: AcceptorBuilder<T>,
+    learner: LearnerBuilder<T>,
+}
+
+impl<T> NodeBuilder<T>
+where
+    T: PartialEq + Clone,
+{
+    /// Creates a new `NodeBuilder`.
+    pub fn new() -> Self {
+        Self {
+            proposer: ProposerBuilder::new(),
+            acceptor: AcceptorBuilder::new(),
+            learner: LearnerBuilder::new(),
+        }
+    }
+
+    /// Sets the `Node`'s ID.
+    pub fn id(self, id: NodeId) -> Self {
+        Self {
+            proposer: self.proposer.id(id),
+            acceptor: self.acceptor.id(id),
+            learner: self.learner.id(id),
+        }
+    }
+
+    /// Sets the cluster the `Node` is a member of.
+    pub fn config(self, config: ClusterConfig) -> Self {
+        Self {
+            proposer: self.proposer.config(config.clone()),
+            acceptor: self.acceptor.config(config.clone()),
+            learner: self.learner.config(config),
+        }
+    }
+
+    /// Sets per-phase quorum sizes, in place of simple majorities.
+    pub fn quorums(self, quorums: QuorumConfig) -> Self {
+        Self {
+            proposer: self.proposer.quorums(quorums),
+            acceptor: self.acceptor.quorums(quorums),
+            learner: self.learner.quorums(quorums),
+        }
+    }
+
+    /// Sets the `Messenger` every role sends through, each with its own copy.
+    pub fn messenger<M>(self, messenger: M) -> Self
+    where
+        M: Messenger<T> + Clone + Send + 'static,
+    {
+        Self {
+            proposer: self.proposer.messenger(messenger.clone()),
+            acceptor: self.acceptor.messenger(messenger.clone()),
+            learner: self.learner.messenger(messenger),
+        }
+    }
+
+    /// Sets the callback notified of the progress of every role, each with its
+    /// own copy.
+    pub fn events<F>(self, events: F) -> Self
+    where
+        F: FnMut(PaxosEvent) + Clone + Send + 'static,
+    {
+        Self {
+            proposer: self.proposer.events(events.clone()),
+            acceptor: self.acceptor.events(events.clone()),
+            learner: self.learner.events(events),
+        }
+    }
+
+    /// Sets the number of slots the `Proposer` keeps in flight at once.
+    pub fn window(mut self, window: usize) -> Self {
+        self.proposer = self.proposer.window(window);
+        self
+    }
+
+    /// Sets the milliseconds a phase of the `Proposer` may go without
+    /// completing before it is sent again, up to `max_retransmits` times.
+    pub fn timeout(mut self, millis: u64, max_retransmits: u32) -> Self {
+        self.proposer = self.proposer.timeout(millis, max_retransmits);
+        self
+    }
+
+    /// Sets the number of ticks an undecided slot may go without activity
+    /// before the `Learner` reports it as abandoned.
+    pub fn instance_ttl(mut self, ticks: u64) -> Self {
+        self.learner = self.learner.instance_ttl(ticks);
+        self
+    }
+
+    /// Builds the `Node`.
+    pub fn build(self) -> Result<Node<T>, BuildError> {
+        let proposer = self.proposer.build()?;
+        Ok(Node {
+            id: proposer.id,
+            proposer,
+            acceptor: self.acceptor.build()?,
+            learner: self.learner.build()?,
+            delivered: 0,
+        })
+    }
+}
+
+impl<T> Default for NodeBuilder<T>
+where
+    T: PartialEq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Node<T>
+where
+    T: PartialEq + Clone,
+{
+    /// Starts building a `Node`.
+    pub fn builder() -> NodeBuilder<T> {
+        NodeBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Message, Slot};
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use std::sync::Mutex;
+
+    /// Records every message sent.
+    #[derive(Clone)]
+    struct RecordingMessenger(Arc<Mutex<Vec<Message<u64>>>>);
+
+    impl Messenger<u64> for RecordingMessenger {
+        fn send_prepare(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_promise(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_accept(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_accepted(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn on_resolution(&mut self, _slot: Slot, _value: Arc<u64>) {}
+    }
+
+    #[test]
+    fn builder_proposer() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::builder()
+            .id(1)
+            .config(ClusterConfig::new(vec![1, 2, 3]))
+            .messenger(RecordingMessenger(sent.clone()))
+            .window(4)
+            .build()
+            .unwrap();
+
+        assert_eq!(p.id, 1);
+        assert_eq!(p.window, 4);
+
+        p.prepare(10);
+
+        assert_eq!(sent.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn builder_invalid() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+
+        assert_eq!(
+            Proposer::<u64>::builder()
+                .config(config.clone())
+                .build()
+                .err(),
+            Some(BuildError::MissingId)
+        );
+        assert_eq!(
+            Acceptor::<u64>::builder().id(1).build().err(),
+            Some(BuildError::MissingConfig)
+        );
+        assert_eq!(
+            Learner::<u64>::builder()
+                .id(1)
+                .config(config.clone())
+                .quorums(QuorumConfig {
+                    phase1: 1,
+                    phase2: 1
+                })
+                .build()
+                .err(),
+            Some(BuildError::Config(ConfigError::QuorumsDoNotIntersect))
+        );
+        assert_eq!(
+            Proposer::<u64>::builder()
+                .id(1)
+                .config(config.clone())
+                .window(0)
+                .build()
+                .err(),
+            Some(BuildError::EmptyWindow)
+        );
+        assert_eq!(
+            Proposer::<u64>::builder()
+                .id(1)
+                .config(config.with_leaders(vec![2, 3]))
+                .build()
+                .err(),
+            Some(BuildError::NotALeader)
+        );
+    }
+
+    #[test]
+    fn builder_node() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let n: Node<u64> = Node::builder()
+            .id(2)
+            .config(ClusterConfig::new(vec![1, 2, 3]))
+            .messenger(RecordingMessenger(sent))
+            .events(|_| {})
+            .window(2)
+            .timeout(100, 3)
+            .instance_ttl(5)
+            .build()
+            .unwrap();
+
+        assert_eq!(n.id, 2);
+        assert_eq!(n.acceptor.id, 2);
+        assert!(n.learner.messenger.is_some());
+        assert!(n.acceptor.events.is_some());
+        assert_eq!(n.proposer.window, 2);
+        assert_eq!(n.proposer.timeout, Some(100));
+        assert_eq!(n.learner.instance_ttl, Some(5));
+    }
+}
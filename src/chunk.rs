@@ -0,0 +1,246 @@
+//! Chunking
+//!
+//! Values may be far larger than a transport can carry in one frame or
+//! datagram. `Chunker::split` encodes a message with the `wire` module and
+//! cuts the encoding into chunks of bounded size, and a `Reassembler` at the
+//! receiving end puts them back together, handing back the message once its
+//! last chunk is in. Each chunk is laid out as:
+//!
+//! ```text
+//! id:u64 index:u32 count:u32 data:bytes
+//! ```
+//!
+//! `id` tells apart the messages of one sender, so chunks may arrive out of
+//! order or interleaved with those of other messages. A `Reassembler` serves
+//! a single sender, e.g. one connection, and bounds the bytes it holds for
+//! incomplete messages: past `max_pending`, the oldest are dropped, which
+//! Paxos tolerates like any other lost message.
+
+use crate::message::Message;
+use crate::wire::{self, put_bytes, put_u32, put_u64, DecodeError, Reader};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Default size of the data carried by a chunk, in bytes.
+pub const MAX_CHUNK: usize = 64 * 1024;
+
+/// Default bound on the bytes a `Reassembler` holds for incomplete messages.
+pub const MAX_PENDING: usize = 64 * 1024 * 1024;
+
+/// Cuts messages into chunks. See the module documentation.
+#[derive(Debug, Clone)]
+pub struct Chunker {
+    max_chunk: usize,
+    /// ID of the next message split
+    next_id: u64,
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::new(MAX_CHUNK)
+    }
+}
+
+impl Chunker {
+    /// Creates a `Chunker` carrying at most `max_chunk` bytes of a message
+    /// in each chunk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_chunk` is 0.
+    pub fn new(max_chunk: usize) -> Self {
+        assert!(max_chunk > 0, "chunks must carry some data");
+        Self {
+            max_chunk,
+            next_id: 0,
+        }
+    }
+
+    /// The most bytes of a message carried by each chunk.
+    pub fn max_chunk(&self) -> usize {
+        self.max_chunk
+    }
+
+    /// Encodes `msg` and cuts it into chunks, to be sent in order.
+    pub fn split<T: AsRef<[u8]>>(&mut self, msg: &Message<T>) -> Vec<Vec<u8>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let bytes = wire::encode(msg);
+        let count = bytes.len().div_ceil(self.max_chunk) as u32;
+        bytes
+            .chunks(self.max_chunk)
+            .enumerate()
+            .map(|(index, data)| {
+                let mut out = Vec::with_capacity(data.len() + 20);
+                put_u64(&mut out, id);
+                put_u32(&mut out, index as u32);
+                put_u32(&mut out, count);
+                put_bytes(&mut out, data);
+                out
+            })
+            .collect()
+    }
+}
+
+/// The chunks received so far of a message.
+#[derive(Debug)]
+struct Partial {
+    count: u32,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    /// Bytes held in `chunks`
+    size: usize,
+}
+
+/// Puts back together the messages cut by a `Chunker`. See the module
+/// documentation.
+#[derive(Debug)]
+pub struct Reassembler {
+    max_pending: usize,
+    /// Bytes held for incomplete messages
+    pending: usize,
+    /// Incomplete messages, by ID
+    partial: BTreeMap<u64, Partial>,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(MAX_PENDING)
+    }
+}
+
+impl Reassembler {
+    /// Creates a `Reassembler` holding at most `max_pending` bytes for
+    /// incomplete messages.
+    pub fn new(max_pending: usize) -> Self {
+        Self {
+            max_pending,
+            pending: 0,
+            partial: BTreeMap::new(),
+        }
+    }
+
+    /// The bytes held for incomplete messages.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Takes in `chunk`, returning its message if it was the last one
+    /// missing.
+    pub fn receive<T>(&mut self, chunk: &[u8]) -> Result<Option<Message<T>>, DecodeError>
+    where
+        T: for<'a> From<&'a [u8]>,
+    {
+        let mut r = Reader { bytes: chunk };
+        let (id, index, count) = (r.u64()?, r.u32()?, r.u32()?);
+        let data = r.bytes()?;
+        if !r.bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        if index >= count {
+            return Err(DecodeError::InvalidChunk);
+        }
+        if count == 1 {
+            return wire::decode(data).map(Some);
+        }
+
+        let partial = self.partial.entry(id).or_insert_with(|| Partial {
+            count,
+            chunks: BTreeMap::new(),
+            size: 0,
+        });
+        if partial.count != count {
+            let size = partial.size;
+            self.partial.remove(&id);
+            self.pending -= size;
+            return Err(DecodeError::InvalidChunk);
+        }
+        if let Some(old) = partial.chunks.insert(index, data.to_vec()) {
+            partial.size -= old.len();
+            self.pending -= old.len();
+        }
+        partial.size += data.len();
+        self.pending += data.len();
+
+        if partial.chunks.len() == count as usize {
+            let partial = self.partial.remove(&id).unwrap();
+            self.pending -= partial.size;
+            let mut bytes = Vec::with_capacity(partial.size);
+            for data in partial.chunks.into_values() {
+                bytes.extend_from_slice(&data);
+            }
+            return wire::decode(&bytes).map(Some);
+        }
+        self.evict();
+        Ok(None)
+    }
+
+    /// Drops the oldest incomplete messages until within `max_pending`.
+    fn evict(&mut self) {
+        while self.pending > self.max_pending {
+            match self.partial.pop_first() {
+                Some((_, partial)) => self.pending -= partial.size,
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{AcceptData, JoinData};
+    use alloc::sync::Arc;
+
+    fn accept(len: usize) -> Message<Vec<u8>> {
+        Message::Accept(AcceptData {
+            slot: 1,
+            id: 2,
+            value: Arc::new((0..len).map(|i| i as u8).collect()),
+            implicit_prepare: false,
+        })
+    }
+
+    #[test]
+    fn chunks_reassemble() {
+        let mut chunker = Chunker::new(16);
+        let large = accept(100);
+        let small: Message<Vec<u8>> = Message::Join(JoinData { from: 1 });
+        let mut a = chunker.split(&large);
+        let b = chunker.split(&small);
+        assert_eq!(a.len(), 8);
+        assert_eq!(b.len(), 1);
+
+        // Out of order, and interleaved with another message.
+        a.reverse();
+        let mut r = Reassembler::default();
+        for chunk in &a[..7] {
+            assert_eq!(r.receive::<Vec<u8>>(chunk), Ok(None));
+        }
+        assert_eq!(r.receive(&b[0]), Ok(Some(small)));
+        assert_eq!(r.receive(&a[7]), Ok(Some(large)));
+        assert_eq!(r.pending(), 0);
+
+        let mut bad = a[0].clone();
+        bad[15] = 0;
+        assert_eq!(r.receive::<Vec<u8>>(&bad), Err(DecodeError::InvalidChunk));
+    }
+
+    #[test]
+    fn reassembler_bounds_pending() {
+        let mut chunker = Chunker::new(16);
+        let first = chunker.split(&accept(100));
+        let second = chunker.split(&accept(100));
+        let mut r = Reassembler::new(64);
+
+        for chunk in &first[..4] {
+            assert_eq!(r.receive::<Vec<u8>>(chunk), Ok(None));
+        }
+        assert_eq!(r.pending(), 64);
+        // The first message is dropped to make room for the second.
+        assert_eq!(r.receive::<Vec<u8>>(&second[0]), Ok(None));
+        assert_eq!(r.pending(), 16);
+        for chunk in &first[4..] {
+            assert_eq!(r.receive::<Vec<u8>>(chunk), Ok(None));
+        }
+    }
+}
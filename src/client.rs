@@ -0,0 +1,320 @@
+//! Client
+//!
+//! A `PaxosClient` runs commands against a cluster of `paxos-node`s, over
+//! their client ports. Only the leader takes proposals: the client starts
+//! with the node it last found leading, follows the hint of a node naming
+//! another leader, and moves on to the next node when one can't be reached
+//! or doesn't reply in time.
+//!
+//! The client protocol is line based. Each command is a single line, and so
+//! is each reply:
+//!
+//! | Command           | Replies                                      |
+//! |-------------------|----------------------------------------------|
+//! | `PROPOSE <value>` | `DECIDED <slot>`, `NOT_LEADER <id>`, `RETRY` |
+//! | `GET <slot>`      | `VALUE <value>`, `NONE`                      |
+//!
+//! Any command may also be answered with `ERR <reason>`.
+//!
+//! Every node answers `GET`s from the values it learned. Placed in a
+//! `Topology`, a client reads from the nodes of its own zone first, in the
+//! order of `Topology::read_order`, and only turns to the leader when none of
+//! them holds the value.
+
+use crate::config::NodeId;
+use crate::message::Slot;
+use crate::topology::Topology;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Errors raised by a `PaxosClient`.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum ClientError {
+    /// Values must be non-empty and fit on a single line
+    #[error("values must be non-empty and fit on a single line")]
+    InvalidValue,
+    /// No node completed the command within the attempts allowed
+    #[error("no node completed the command")]
+    Unavailable,
+    /// A node replied with an error, or something the client didn't expect
+    #[error("unexpected reply from node {0}")]
+    Protocol(NodeId),
+}
+
+/// Runs commands against a cluster, finding its leader on the way.
+pub struct PaxosClient {
+    /// Client address of every node
+    nodes: BTreeMap<NodeId, SocketAddr>,
+    /// The node last found leading, if any
+    leader: Option<NodeId>,
+    /// The open connection, and the node it goes to
+    conn: Option<(NodeId, BufReader<TcpStream>)>,
+    /// How long to wait for a node to connect, or to reply
+    timeout: Duration,
+    /// How long to wait before trying again, after a failed attempt
+    retry_delay: Duration,
+    /// Number of nodes tried, or tried again, before giving up on a command
+    max_attempts: u32,
+    /// The client's ID in the `Topology` reads favor the zone of, if any
+    topology: Option<(NodeId, Topology)>,
+}
+
+impl PaxosClient {
+    /// Creates a new `PaxosClient` for the nodes listening for clients at
+    /// `nodes`. It waits 5 seconds for each reply, and tries up to 10 times.
+    pub fn new(nodes: BTreeMap<NodeId, SocketAddr>) -> Self {
+        Self {
+            nodes,
+            leader: None,
+            conn: None,
+            timeout: Duration::from_secs(5),
+            retry_delay: Duration::from_millis(100),
+            max_attempts: 10,
+            topology: None,
+        }
+    }
+
+    /// Sets how long to wait for a node to connect, or to reply.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets how long to wait before trying again, after a failed attempt.
+    pub fn set_retry_delay(&mut self, delay: Duration) {
+        self.retry_delay = delay;
+    }
+
+    /// Sets the number of attempts made at a command before giving up.
+    pub fn set_max_attempts(&mut self, attempts: u32) {
+        self.max_attempts = attempts;
+    }
+
+    /// Places the client at `id` in `topology`, for `get` to read from the
+    /// nodes of its zone first. See the module documentation.
+    pub fn set_topology(&mut self, id: NodeId, topology: Topology) {
+        self.topology = Some((id, topology));
+    }
+
+    /// The node last found leading, if any.
+    pub fn leader(&self) -> Option<NodeId> {
+        self.leader
+    }
+
+    /// Proposes `value` to the leader, and returns the slot it was decided
+    /// for.
+    ///
+    /// A proposal which timed out may still be decided: proposing it again
+    /// may then decide it twice. Applications needing exactly-once semantics
+    /// tag their values with a `session::Session`, and apply them through
+    /// `session::Sessions`.
+    pub fn propose(&mut self, value: &str) -> Result<Slot, ClientError> {
+        if value.is_empty() || value.contains(['\n', '\r']) {
+            return Err(ClientError::InvalidValue);
+        }
+        let (id, reply) = self.request(&format!("PROPOSE {}", value))?;
+        match reply.strip_prefix("DECIDED ").map(str::parse) {
+            Some(Ok(slot)) => Ok(slot),
+            _ => Err(ClientError::Protocol(id)),
+        }
+    }
+
+    /// Reads the value decided for `slot`, if any, from a node of the
+    /// client's zone holding it, or else from the leader.
+    pub fn get(&mut self, slot: Slot) -> Result<Option<String>, ClientError> {
+        let command = format!("GET {}", slot);
+        if let Some(value) = self.read_local(&command) {
+            return Ok(Some(value));
+        }
+        let (id, reply) = self.request(&command)?;
+        match reply.as_str() {
+            "NONE" => Ok(None),
+            _ => match reply.strip_prefix("VALUE ") {
+                Some(value) => Ok(Some(value.into())),
+                None => Err(ClientError::Protocol(id)),
+            },
+        }
+    }
+
+    /// Sends the `GET` of `command` to the nodes of the client's zone, in
+    /// `Topology::read_order`, returning the first value one of them holds.
+    fn read_local(&mut self, command: &str) -> Option<String> {
+        let (id, topology) = self.topology.as_ref()?;
+        if !topology.prefer_local_reads {
+            return None;
+        }
+        let ids: Vec<NodeId> = self.nodes.keys().copied().collect();
+        let local: Vec<NodeId> = topology
+            .read_order(*id, &ids)
+            .into_iter()
+            .take_while(|node| topology.same_zone(*id, *node))
+            .collect();
+        for node in local {
+            match self.exchange(node, command) {
+                Ok(reply) => {
+                    if let Some(value) = reply.strip_prefix("VALUE ") {
+                        return Some(value.into());
+                    }
+                }
+                Err(_) => self.conn = None,
+            }
+        }
+        None
+    }
+
+    /// Sends `command` until a node other than a follower or a recovering
+    /// leader answers it, and returns the node along with its reply.
+    fn request(&mut self, command: &str) -> Result<(NodeId, String), ClientError> {
+        let ids: Vec<NodeId> = self.nodes.keys().copied().collect();
+        let mut target = match self.leader.or(ids.first().copied()) {
+            Some(id) => id,
+            None => return Err(ClientError::Unavailable),
+        };
+        for attempt in 0..self.max_attempts {
+            if attempt > 0 {
+                thread::sleep(self.retry_delay);
+            }
+            let reply = match self.exchange(target, command) {
+                Ok(reply) => reply,
+                Err(_) => {
+                    // Try the next node.
+                    self.conn = None;
+                    self.leader = None;
+                    let next = ids.iter().position(|&id| id == target).map_or(0, |i| i + 1);
+                    target = ids[next % ids.len()];
+                    continue;
+                }
+            };
+            if reply == "RETRY" {
+                continue;
+            }
+            if let Some(hint) = reply.strip_prefix("NOT_LEADER ") {
+                match hint.parse() {
+                    Ok(id) if self.nodes.contains_key(&id) => {
+                        self.leader = Some(id);
+                        target = id;
+                        continue;
+                    }
+                    _ => return Err(ClientError::Protocol(target)),
+                }
+            }
+            if reply.starts_with("ERR") {
+                return Err(ClientError::Protocol(target));
+            }
+            self.leader = Some(target);
+            return Ok((target, reply));
+        }
+        Err(ClientError::Unavailable)
+    }
+
+    /// Sends `command` to node `id`, over the open connection if it goes
+    /// there, and reads the reply.
+    fn exchange(&mut self, id: NodeId, command: &str) -> io::Result<String> {
+        if self.conn.as_ref().is_none_or(|(to, _)| *to != id) {
+            let stream = TcpStream::connect_timeout(&self.nodes[&id], self.timeout)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            self.conn = Some((id, BufReader::new(stream)));
+        }
+        let (_, conn) = self.conn.as_mut().unwrap();
+        writeln!(conn.get_mut(), "{}", command)?;
+        let mut reply = String::new();
+        if conn.read_line(&mut reply)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(reply.trim_end().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use std::net::TcpListener;
+
+    /// Serves clients on a free port, answering each line with `reply`.
+    fn serve(reply: fn(&str) -> String) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                thread::spawn(move || {
+                    let mut writer = stream.try_clone().unwrap();
+                    for line in BufReader::new(stream).lines() {
+                        let line = line.unwrap();
+                        writeln!(writer, "{}", reply(&line)).unwrap();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    /// An address nothing listens on.
+    fn unreachable() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[test]
+    fn client_follows_hints() {
+        let follower = serve(|_| "NOT_LEADER 3".to_string());
+        let leader = serve(|line| match line {
+            "PROPOSE x" => "DECIDED 4".to_string(),
+            "GET 4" => "VALUE x".to_string(),
+            _ => "NONE".to_string(),
+        });
+        let mut client = PaxosClient::new([(1, unreachable()), (2, follower), (3, leader)].into());
+        client.set_retry_delay(Duration::ZERO);
+
+        assert_eq!(client.propose("x"), Ok(4));
+        assert_eq!(client.leader(), Some(3));
+        assert_eq!(client.get(4), Ok(Some("x".to_string())));
+        assert_eq!(client.get(5), Ok(None));
+        assert_eq!(client.propose("a\nb"), Err(ClientError::InvalidValue));
+    }
+
+    #[test]
+    fn client_reads_locally() {
+        let leader = serve(|line| match line {
+            "GET 5" => "VALUE x".to_string(),
+            _ => "NONE".to_string(),
+        });
+        let follower = serve(|line| match line {
+            "GET 4" => "VALUE y".to_string(),
+            _ => "NONE".to_string(),
+        });
+        let mut client = PaxosClient::new([(1, leader), (2, follower)].into());
+        let zones = [(1, "us"), (2, "eu"), (9, "eu")]
+            .into_iter()
+            .map(|(id, zone)| (id, zone.to_string()))
+            .collect();
+        client.set_topology(9, Topology::new(zones));
+
+        // Read from the follower of the client's zone, unless it lags behind.
+        assert_eq!(client.get(4), Ok(Some("y".to_string())));
+        assert_eq!(client.get(5), Ok(Some("x".to_string())));
+        assert_eq!(client.leader(), Some(1));
+    }
+
+    #[test]
+    fn client_gives_up() {
+        let recovering = serve(|_| "RETRY".to_string());
+        let mut client = PaxosClient::new([(1, recovering)].into());
+        client.set_retry_delay(Duration::ZERO);
+        client.set_max_attempts(3);
+
+        assert_eq!(client.propose("x"), Err(ClientError::Unavailable));
+
+        let failing = serve(|_| "ERR unknown command".to_string());
+        let mut client = PaxosClient::new([(1, failing)].into());
+
+        assert_eq!(client.propose("x"), Err(ClientError::Protocol(1)));
+    }
+}
@@ -0,0 +1,107 @@
+//! Clocks
+//!
+//! Roles never read the time themselves: it is passed to their `tick`
+//! methods, in milliseconds. A `Clock` is where that time comes from, so that
+//! tests can swap the system's clock for a `MockClock` they advance by hand.
+//!
+//! `SystemClock` isn't built for WASM, whose hosts have no monotonic clock in
+//! common. Any `Fn() -> u64` is a `Clock` as well, so that the host's time
+//! can be injected instead, e.g. `|| performance_now() as u64`.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// A source of time, in milliseconds since an arbitrary starting point.
+pub trait Clock {
+    /// The current time. Never goes backwards.
+    fn now(&self) -> u64;
+}
+
+/// A `Clock` that only moves when told to, for deterministic tests. Cloning a
+/// `MockClock` yields another handle to the same time.
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    now: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Creates a new `MockClock`, starting at 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the time forward by `millis`.
+    pub fn advance(&self, millis: u64) {
+        self.now.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+impl<F: Fn() -> u64> Clock for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// The system's monotonic clock, counting from its creation.
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: std::time::Instant,
+}
+
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+impl SystemClock {
+    /// Creates a new `SystemClock`, starting at 0.
+    pub fn new() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_mock() {
+        let clock = MockClock::new();
+        let handle = clock.clone();
+
+        assert_eq!(clock.now(), 0);
+
+        handle.advance(15);
+
+        assert_eq!(clock.now(), 15);
+    }
+
+    #[test]
+    fn clock_closure() {
+        let clock = MockClock::new();
+        let injected = || clock.now() * 2;
+
+        clock.advance(4);
+
+        assert_eq!(injected.now(), 8);
+    }
+}
@@ -0,0 +1,197 @@
+//! Commutative commands
+//!
+//! Commands that don't conflict may be applied in either order with the same
+//! result, as with increments of a counter or inserts into a set. The
+//! `Scheduler` tags every command proposed with a barrier: the slot below
+//! which every conflicting command lies. `Executor`s then apply a decided
+//! command as soon as everything below its barrier has been applied, rather
+//! than waiting for every lower slot to be decided.
+
+use crate::message::Slot;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Declares which commands may be reordered.
+pub trait Commutes {
+    /// Whether applying `self` and `other` in different orders can lead to
+    /// different results.
+    fn conflicts(&self, other: &Self) -> bool;
+}
+
+/// A command along with its barrier. Used as the value of proposals.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct Command<C> {
+    /// Every command conflicting with this one lies below this slot
+    pub barrier: Slot,
+    /// The command itself
+    pub command: C,
+}
+
+/// Computes the barriers of the commands proposed by a leader.
+///
+/// A new leader can't know which commands its predecessor left in flight,
+/// so it must start its `Scheduler` at the first slot it proposes for.
+#[derive(Debug, Clone)]
+pub struct Scheduler<C> {
+    /// Barrier of commands conflicting with nothing scheduled
+    pub floor: Slot,
+    /// Commands scheduled at or above `floor` (slot => command)
+    pub scheduled: BTreeMap<Slot, C>,
+}
+
+impl<C: Commutes + Clone> Scheduler<C> {
+    /// Creates a new `Scheduler` for a leader proposing from `start` onwards.
+    pub fn new(start: Slot) -> Self {
+        Self {
+            floor: start,
+            scheduled: BTreeMap::new(),
+        }
+    }
+
+    /// Wraps `command`, to be proposed for `slot`, along with its barrier.
+    pub fn schedule(&mut self, slot: Slot, command: C) -> Command<C> {
+        let barrier = self
+            .scheduled
+            .range(..slot)
+            .filter(|(_, c)| c.conflicts(&command))
+            .map(|(s, _)| s + 1)
+            .fold(self.floor, Slot::max);
+        self.scheduled.insert(slot, command.clone());
+        Command { barrier, command }
+    }
+
+    /// Forgets the commands below `slot`, once they have been applied. Later
+    /// commands conservatively wait for all of them.
+    pub fn retire(&mut self, slot: Slot) {
+        if slot > self.floor {
+            self.floor = slot;
+            self.scheduled = self.scheduled.split_off(&slot);
+        }
+    }
+}
+
+/// Applies decided commands as soon as their barrier allows.
+#[derive(Debug, Clone)]
+pub struct Executor<C> {
+    /// The lowest slot not applied yet
+    pub low: Slot,
+    /// Slots above `low` applied already
+    pub applied: BTreeSet<Slot>,
+    /// Decided commands waiting on their barrier (slot => command)
+    pub pending: BTreeMap<Slot, Arc<Command<C>>>,
+}
+
+impl<C> Default for Executor<C> {
+    fn default() -> Self {
+        Self {
+            low: 0,
+            applied: BTreeSet::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<C> Executor<C> {
+    /// Creates a new `Executor`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the command decided for `slot`, and returns the commands which
+    /// can now be applied, in the order they should be.
+    pub fn decided(
+        &mut self,
+        slot: Slot,
+        command: Arc<Command<C>>,
+    ) -> Vec<(Slot, Arc<Command<C>>)> {
+        if slot < self.low || self.applied.contains(&slot) {
+            return Vec::new();
+        }
+        self.pending.insert(slot, command);
+
+        let mut ready = Vec::new();
+        loop {
+            let next = self
+                .pending
+                .iter()
+                .find(|(_, c)| c.barrier <= self.low)
+                .map(|(s, _)| *s);
+            let slot = match next {
+                Some(slot) => slot,
+                None => return ready,
+            };
+            let command = self.pending.remove(&slot).unwrap();
+            self.applied.insert(slot);
+            while self.applied.remove(&self.low) {
+                self.low += 1;
+            }
+            ready.push((slot, command));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+    enum Counter {
+        Add(u64),
+        Reset,
+    }
+
+    impl Commutes for Counter {
+        fn conflicts(&self, other: &Self) -> bool {
+            *self == Counter::Reset || *other == Counter::Reset
+        }
+    }
+
+    #[test]
+    fn commute_out_of_order() {
+        let mut scheduler = Scheduler::new(0);
+        let commands: Vec<_> = [
+            Counter::Add(1),
+            Counter::Add(2),
+            Counter::Reset,
+            Counter::Add(3),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(slot, c)| Arc::new(scheduler.schedule(slot as Slot, c)))
+        .collect();
+
+        assert_eq!(
+            commands.iter().map(|c| c.barrier).collect::<Vec<_>>(),
+            vec![0, 0, 2, 3]
+        );
+
+        let mut executor = Executor::new();
+        let mut applied = |slot: usize| {
+            executor
+                .decided(slot as Slot, commands[slot].clone())
+                .into_iter()
+                .map(|(s, _)| s)
+                .collect::<Vec<_>>()
+        };
+
+        // Additions overtake each other, but never a reset.
+        assert_eq!(applied(1), vec![1]);
+        assert_eq!(applied(3), Vec::<Slot>::new());
+        assert_eq!(applied(0), vec![0]);
+        assert_eq!(applied(2), vec![2, 3]);
+    }
+
+    #[test]
+    fn commute_retire() {
+        let mut scheduler = Scheduler::new(5);
+
+        assert_eq!(scheduler.schedule(5, Counter::Reset).barrier, 5);
+
+        scheduler.retire(6);
+
+        assert!(scheduler.scheduled.is_empty());
+        assert_eq!(scheduler.schedule(7, Counter::Add(1)).barrier, 6);
+    }
+}
@@ -0,0 +1,170 @@
+//! Compression
+//!
+//! Values and snapshots often compress well, and bandwidth between distant
+//! sites is scarce. `compress` runs bytes through a `Compression` codec and
+//! prefixes the result with the codec's tag, so `decompress` needs nothing
+//! but the bytes:
+//!
+//! | Tag    | Codec    | Feature  |
+//! |--------|----------|----------|
+//! | `0x00` | none     |          |
+//! | `0x01` | LZ4      | `lz4`    |
+//! | `0x02` | Snappy   | `snappy` |
+//!
+//! Payloads under `MIN_SIZE` bytes, or which don't shrink, are sent as is.
+//! The codec used on a connection is negotiated when it opens: the sender
+//! offers the codecs it prefers, and the receiver picks the first of them it
+//! was built with, through `negotiate`.
+
+use crate::wire::DecodeError;
+use alloc::vec::Vec;
+
+/// Payloads smaller than this are never compressed, as it rarely pays.
+pub const MIN_SIZE: usize = 256;
+
+/// A compression codec. See the module documentation.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Compression {
+    /// Bytes are sent as is
+    None,
+    /// [LZ4](https://lz4.org), fast at a modest ratio
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// [Snappy](https://google.github.io/snappy/)
+    #[cfg(feature = "snappy")]
+    Snappy,
+}
+
+impl Compression {
+    /// The codec's tag on the wire.
+    pub fn tag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => 1,
+            #[cfg(feature = "snappy")]
+            Compression::Snappy => 2,
+        }
+    }
+
+    /// The codec tagged `tag`, if it was built in.
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Compression::None),
+            #[cfg(feature = "lz4")]
+            1 => Some(Compression::Lz4),
+            #[cfg(feature = "snappy")]
+            2 => Some(Compression::Snappy),
+            _ => None,
+        }
+    }
+
+    /// Every codec built in, fastest first.
+    pub fn supported() -> Vec<Self> {
+        [
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "snappy")]
+            Compression::Snappy,
+            Compression::None,
+        ]
+        .to_vec()
+    }
+}
+
+/// The first of the codecs `offered`, by tag, that was built in, or
+/// `Compression::None` if there's none.
+pub fn negotiate(offered: &[u8]) -> Compression {
+    offered
+        .iter()
+        .find_map(|tag| Compression::from_tag(*tag))
+        .unwrap_or(Compression::None)
+}
+
+/// Compresses `bytes` with `codec`, tagging the result with the codec used.
+pub fn compress(codec: Compression, bytes: &[u8]) -> Vec<u8> {
+    let compressed: Option<Vec<u8>> = match codec {
+        _ if bytes.len() < MIN_SIZE => None,
+        Compression::None => None,
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => Some(lz4_flex::block::compress_prepend_size(bytes)),
+        #[cfg(feature = "snappy")]
+        Compression::Snappy => snap::raw::Encoder::new().compress_vec(bytes).ok(),
+    };
+    let mut out = Vec::new();
+    match compressed {
+        Some(compressed) if compressed.len() < bytes.len() => {
+            out.reserve_exact(compressed.len() + 1);
+            out.push(codec.tag());
+            out.extend_from_slice(&compressed);
+        }
+        _ => {
+            out.reserve_exact(bytes.len() + 1);
+            out.push(Compression::None.tag());
+            out.extend_from_slice(bytes);
+        }
+    }
+    out
+}
+
+/// Decompresses bytes compressed by `compress`. Payloads claiming to expand
+/// past what either codec can achieve are refused before anything is
+/// allocated for them.
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let (tag, payload) = bytes.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+    match Compression::from_tag(*tag) {
+        Some(Compression::None) => Ok(payload.to_vec()),
+        #[cfg(feature = "lz4")]
+        Some(Compression::Lz4) => {
+            let len = payload.get(..4).ok_or(DecodeError::UnexpectedEnd)?;
+            check_len(
+                u32::from_le_bytes(len.try_into().unwrap()) as usize,
+                payload,
+            )?;
+            lz4_flex::block::decompress_size_prepended(payload).map_err(|_| DecodeError::Decompress)
+        }
+        #[cfg(feature = "snappy")]
+        Some(Compression::Snappy) => {
+            let len = snap::raw::decompress_len(payload).map_err(|_| DecodeError::Decompress)?;
+            check_len(len, payload)?;
+            snap::raw::Decoder::new()
+                .decompress_vec(payload)
+                .map_err(|_| DecodeError::Decompress)
+        }
+        None => Err(DecodeError::UnknownCompression(*tag)),
+    }
+}
+
+/// Checks that `payload` may plausibly decompress to `len` bytes.
+#[cfg(any(feature = "lz4", feature = "snappy"))]
+fn check_len(len: usize, payload: &[u8]) -> Result<(), DecodeError> {
+    // Neither codec does better than 255:1.
+    if len > payload.len().saturating_mul(255) {
+        return Err(DecodeError::Decompress);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn compress_roundtrip() {
+        let bytes = vec![7; 4096];
+        for codec in Compression::supported() {
+            let compressed = compress(codec, &bytes);
+            assert_eq!(compressed[0], codec.tag());
+            assert_eq!(decompress(&compressed), Ok(bytes.clone()));
+        }
+
+        // Too small to be worth it.
+        assert_eq!(compress(negotiate(&[1, 2]), b"abc"), b"\0abc");
+        assert_eq!(negotiate(&[9, 0]), Compression::None);
+        assert_eq!(
+            decompress(&[9, 1, 2]),
+            Err(DecodeError::UnknownCompression(9))
+        );
+    }
+}
@@ -0,0 +1,527 @@
+//! Cluster configuration
+
+use crate::message::Slot;
+use crate::quorum::{QuorumSystem, Voters};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use thiserror::Error;
+
+/// Identifies a node in the cluster.
+pub type NodeId = u64;
+
+/// Quorum sizes for each phase of the protocol (Flexible Paxos).
+///
+/// Phase-1 and Phase-2 quorums don't need to be majorities, they only need to
+/// intersect. Smaller Phase-2 quorums make every decision cheaper, at the cost
+/// of larger Phase-1 quorums whenever a new `Proposer` takes over.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuorumConfig {
+    /// Number of `Promise`s needed to complete the first phase
+    pub phase1: usize,
+    /// Number of `Accepted` messages needed to decide a value
+    pub phase2: usize,
+}
+
+/// Errors raised by an invalid `ClusterConfig`.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+pub enum ConfigError {
+    /// A quorum is empty or larger than the cluster
+    #[error("quorum size out of range")]
+    QuorumOutOfRange,
+    /// Some Phase-1 quorum and Phase-2 quorum have no member in common
+    #[error("phase 1 and phase 2 quorums do not intersect")]
+    QuorumsDoNotIntersect,
+}
+
+/// Describes the nodes taking part in the protocol. Quorum sizes are derived
+/// from the membership rather than configured by hand, so every role agrees
+/// on them.
+#[derive(Debug, Clone, Default)]
+pub struct ClusterConfig {
+    /// IDs of the `Acceptor`s whose votes count towards a quorum
+    pub members: Vec<NodeId>,
+    /// Per-phase quorum sizes, or simple majorities if `None`
+    pub quorums: Option<QuorumConfig>,
+    /// Decides quorums in place of counting votes, if set
+    pub quorum_system: Option<Arc<dyn QuorumSystem + Send + Sync>>,
+    /// `Proposer`s taking turns at owning slots (Mencius), if any
+    pub leaders: Vec<NodeId>,
+    /// IDs of shadow `Acceptor`s, which follow the protocol without their
+    /// votes counting towards any quorum
+    pub shadows: Vec<NodeId>,
+    /// IDs of the `Learner`s `Accepted` messages are sent to, which relay
+    /// decisions to the others. Every `Learner` counts votes if empty
+    pub distinguished_learners: Vec<NodeId>,
+    /// Members of the configuration being left, while transitioning to
+    /// `members` (joint consensus). Quorums need a majority of them as well
+    pub joint: Option<Vec<NodeId>>,
+    /// IDs of witness `Acceptor`s, which vote in the first phase only and
+    /// store no values
+    pub witnesses: Vec<NodeId>,
+    /// IDs of observers, replicas running a `Learner` only. Their `Acceptor`s,
+    /// if any, never vote
+    pub observers: Vec<NodeId>,
+}
+
+impl ClusterConfig {
+    /// Creates a new `ClusterConfig`. Duplicate members are ignored.
+    pub fn new(mut members: Vec<NodeId>) -> Self {
+        members.sort_unstable();
+        members.dedup();
+        Self {
+            members,
+            quorums: None,
+            quorum_system: None,
+            leaders: Vec::new(),
+            shadows: Vec::new(),
+            distinguished_learners: Vec::new(),
+            joint: None,
+            witnesses: Vec::new(),
+            observers: Vec::new(),
+        }
+    }
+
+    /// Has `Acceptor`s send their `Accepted` messages to `learners` only,
+    /// which relay decisions to the other `Learner`s. This cuts the fan-out of
+    /// every vote in large clusters, at the cost of an extra message delay.
+    ///
+    /// Routing is up to the `Acceptor`s' `Messenger`s.
+    pub fn with_distinguished_learners(mut self, mut learners: Vec<NodeId>) -> Self {
+        learners.sort_unstable();
+        learners.dedup();
+        self.distinguished_learners = learners;
+        self
+    }
+
+    /// Whether `id` is a distinguished `Learner`.
+    pub fn is_distinguished_learner(&self, id: NodeId) -> bool {
+        self.distinguished_learners.contains(&id)
+    }
+
+    /// Starts a transition to `members` (joint consensus). Until it completes,
+    /// both the current and the new members must form a quorum, so that no
+    /// value can be decided by either of them alone. Unlike switching straight
+    /// to `members`, this is safe however much the two sets differ.
+    ///
+    /// Explicit quorum sizes and quorum systems describe the current members,
+    /// and are dropped in favor of majorities. Fast rounds shouldn't be used
+    /// during a transition.
+    pub fn begin_transition(&self, mut members: Vec<NodeId>) -> Self {
+        members.sort_unstable();
+        members.dedup();
+        Self {
+            joint: Some(self.members.clone()),
+            members,
+            quorums: None,
+            quorum_system: None,
+            ..self.clone()
+        }
+    }
+
+    /// Completes the transition to the new members, which alone make up
+    /// quorums from then on.
+    pub fn complete_transition(&self) -> Self {
+        Self {
+            joint: None,
+            ..self.clone()
+        }
+    }
+
+    /// Whether a transition between two sets of members is in progress.
+    pub fn is_transitioning(&self) -> bool {
+        self.joint.is_some()
+    }
+
+    /// Adds shadow `Acceptor`s, e.g. to validate a new version or new hardware
+    /// under real load before promoting it. Members can't be shadows.
+    pub fn with_shadows(mut self, mut shadows: Vec<NodeId>) -> Self {
+        shadows.retain(|s| !self.members.contains(s));
+        shadows.sort_unstable();
+        shadows.dedup();
+        self.shadows = shadows;
+        self
+    }
+
+    /// Whether `id` is a shadow `Acceptor`.
+    pub fn is_shadow(&self, id: NodeId) -> bool {
+        self.shadows.contains(&id)
+    }
+
+    /// Adds witness `Acceptor`s, which count towards Phase-1 quorums but
+    /// neither accept nor store values. A witness breaks ties cheaply, e.g.
+    /// two replicas and a witness tolerate the failure of any one of them,
+    /// though deciding a value then needs both replicas. Members can't be
+    /// witnesses, and fast rounds shouldn't be used alongside them.
+    pub fn with_witnesses(mut self, mut witnesses: Vec<NodeId>) -> Self {
+        witnesses.retain(|w| !self.members.contains(w));
+        witnesses.sort_unstable();
+        witnesses.dedup();
+        self.witnesses = witnesses;
+        self
+    }
+
+    /// Whether `id` is a witness `Acceptor`.
+    pub fn is_witness(&self, id: NodeId) -> bool {
+        self.witnesses.contains(&id)
+    }
+
+    /// Adds observers, replicas which learn every decided value without
+    /// voting, to scale reads. Members can't be observers.
+    pub fn with_observers(mut self, mut observers: Vec<NodeId>) -> Self {
+        observers.retain(|o| !self.members.contains(o));
+        observers.sort_unstable();
+        observers.dedup();
+        self.observers = observers;
+        self
+    }
+
+    /// Whether `id` is an observer.
+    pub fn is_observer(&self, id: NodeId) -> bool {
+        self.observers.contains(&id)
+    }
+
+    /// Turns the shadow `id` into a voting member. Returns `false` if `id`
+    /// isn't a shadow.
+    pub fn promote(&mut self, id: NodeId) -> bool {
+        if !self.is_shadow(id) {
+            return false;
+        }
+        self.shadows.retain(|s| *s != id);
+        self.members.push(id);
+        self.members.sort_unstable();
+        true
+    }
+
+    /// Partitions the slots round-robin among `leaders`: slot `i` is owned by
+    /// `leaders[i % leaders.len()]`, and only ever proposed for by its owner,
+    /// unless it is finalized with a no-op.
+    pub fn with_leaders(mut self, leaders: Vec<NodeId>) -> Self {
+        self.leaders = leaders;
+        self
+    }
+
+    /// The leader owning `slot`, if slots are partitioned among leaders.
+    pub fn owner(&self, slot: Slot) -> Option<NodeId> {
+        if self.leaders.is_empty() {
+            return None;
+        }
+        Some(self.leaders[(slot % self.leaders.len() as u64) as usize])
+    }
+
+    /// The first slot from `slot` onwards owned by `leader`, if any.
+    pub fn next_owned(&self, leader: NodeId, slot: Slot) -> Option<Slot> {
+        let n = self.leaders.len() as u64;
+        let turn = self.leaders.iter().position(|l| *l == leader)? as u64;
+        Some(slot + (turn + n - slot % n) % n)
+    }
+
+    /// Consults `system` to decide quorums, e.g. to weigh the votes of some
+    /// members more heavily than others.
+    pub fn with_quorum_system<Q>(mut self, system: Q) -> Self
+    where
+        Q: QuorumSystem + Send + Sync + 'static,
+    {
+        self.quorum_system = Some(Arc::new(system));
+        self
+    }
+
+    /// Uses `quorums` in place of simple majorities, provided that any two
+    /// Phase-1 and Phase-2 quorums are guaranteed to intersect.
+    pub fn with_quorums(mut self, quorums: QuorumConfig) -> Result<Self, ConfigError> {
+        let n = self.members.len() + self.witnesses.len();
+        if quorums.phase1 == 0
+            || quorums.phase2 == 0
+            || quorums.phase1 > n
+            || quorums.phase2 > self.members.len()
+        {
+            return Err(ConfigError::QuorumOutOfRange);
+        }
+        if quorums.phase1 + quorums.phase2 <= n {
+            return Err(ConfigError::QuorumsDoNotIntersect);
+        }
+        self.quorums = Some(quorums);
+        Ok(self)
+    }
+
+    /// The number of votes that make up a majority of the members and
+    /// witnesses.
+    pub fn quorum(&self) -> usize {
+        (self.members.len() + self.witnesses.len()) / 2 + 1
+    }
+
+    /// The number of `Promise`s needed to complete the first phase.
+    pub fn phase1_quorum(&self) -> usize {
+        self.quorums.map_or_else(|| self.quorum(), |q| q.phase1)
+    }
+
+    /// The number of `Accepted` messages needed to decide a value.
+    pub fn phase2_quorum(&self) -> usize {
+        self.quorums.map_or_else(|| self.quorum(), |q| q.phase2)
+    }
+
+    /// The number of matching `Accepted` messages needed to decide a value in
+    /// a fast round (Fast Paxos). Any two fast quorums and a Phase-1 quorum
+    /// must intersect, so that recovering from a collision is safe. Fast
+    /// quorums are always counted, even with a `quorum_system` set.
+    pub fn fast_quorum(&self) -> usize {
+        (2 * self.members.len()).saturating_sub(self.phase1_quorum()) / 2 + 1
+    }
+
+    /// Whether the `Promise`s of `voters` complete the first phase.
+    pub fn is_phase1_quorum(&self, voters: &[NodeId]) -> bool {
+        self.is_phase1_quorum_of(&voters.iter().copied().collect())
+    }
+
+    /// Whether the `Accepted` messages of `voters` decide a value.
+    pub fn is_phase2_quorum(&self, voters: &[NodeId]) -> bool {
+        self.is_phase2_quorum_of(&voters.iter().copied().collect())
+    }
+
+    /// Like `is_phase1_quorum`, counting `Voters` without allocating unless
+    /// a `quorum_system` is set.
+    pub fn is_phase1_quorum_of(&self, voters: &Voters) -> bool {
+        let quorum = match self.quorum_system {
+            Some(ref system) => system.is_phase1_quorum(&voters.iter().collect::<Vec<_>>()),
+            None => {
+                let witnesses = voters.count_in(&self.witnesses);
+                voters.count_in(&self.members) + witnesses >= self.phase1_quorum()
+            }
+        };
+        quorum && self.is_joint_quorum(voters)
+    }
+
+    /// Like `is_phase2_quorum`, counting `Voters` without allocating unless
+    /// a `quorum_system` is set.
+    pub fn is_phase2_quorum_of(&self, voters: &Voters) -> bool {
+        let quorum = match self.quorum_system {
+            Some(ref system) => system.is_phase2_quorum(&voters.iter().collect::<Vec<_>>()),
+            None => voters.count_in(&self.members) >= self.phase2_quorum(),
+        };
+        quorum && self.is_joint_quorum(voters)
+    }
+
+    /// Whether `id` votes in the first phase: a member or a witness.
+    pub fn is_phase1_voter(&self, id: NodeId) -> bool {
+        self.is_member(id) || self.is_witness(id)
+    }
+
+    /// Whether `id` is a voting member of the cluster, including the members
+    /// being left during a transition.
+    pub fn is_member(&self, id: NodeId) -> bool {
+        self.members.contains(&id) || self.joint.as_ref().is_some_and(|j| j.contains(&id))
+    }
+
+    /// Whether `id` is any node of the cluster: a member, a witness, a
+    /// shadow, an observer, a leader or a distinguished learner.
+    pub fn is_known(&self, id: NodeId) -> bool {
+        self.is_phase1_voter(id)
+            || self.is_shadow(id)
+            || self.is_observer(id)
+            || self.leaders.contains(&id)
+            || self.is_distinguished_learner(id)
+    }
+
+    /// Whether `voters` include a majority of the members being left, if
+    /// transitioning.
+    fn is_joint_quorum(&self, voters: &Voters) -> bool {
+        match self.joint {
+            Some(ref joint) => voters.count_in(joint) > joint.len() / 2,
+            None => true,
+        }
+    }
+}
+
+impl PartialEq for ClusterConfig {
+    fn eq(&self, other: &Self) -> bool {
+        let same_system = match (&self.quorum_system, &other.quorum_system) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        self.members == other.members
+            && self.quorums == other.quorums
+            && same_system
+            && self.leaders == other.leaders
+            && self.shadows == other.shadows
+            && self.distinguished_learners == other.distinguished_learners
+            && self.joint == other.joint
+            && self.witnesses == other.witnesses
+            && self.observers == other.observers
+    }
+}
+
+impl Eq for ClusterConfig {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quorum::Weighted;
+    use alloc::vec;
+
+    #[test]
+    fn cluster_config_new() {
+        let c = ClusterConfig::new(vec![3, 1, 2, 1]);
+
+        assert_eq!(c.members, vec![1, 2, 3]);
+        assert!(c.is_member(2));
+        assert!(!c.is_member(4));
+    }
+
+    #[test]
+    fn cluster_config_quorum() {
+        assert_eq!(ClusterConfig::new(vec![1]).quorum(), 1);
+        assert_eq!(ClusterConfig::new(vec![1, 2]).quorum(), 2);
+        assert_eq!(ClusterConfig::new(vec![1, 2, 3]).quorum(), 2);
+        assert_eq!(ClusterConfig::new(vec![1, 2, 3, 4]).quorum(), 3);
+        assert_eq!(ClusterConfig::new((1..=7).collect()).quorum(), 4);
+    }
+
+    #[test]
+    fn cluster_config_leaders() {
+        let c = ClusterConfig::new(vec![1, 2, 3]);
+
+        assert_eq!(c.owner(4), None);
+        assert_eq!(c.next_owned(1, 4), None);
+
+        let c = c.with_leaders(vec![3, 1]);
+
+        assert_eq!(c.owner(0), Some(3));
+        assert_eq!(c.owner(5), Some(1));
+        assert_eq!(c.next_owned(3, 4), Some(4));
+        assert_eq!(c.next_owned(3, 5), Some(6));
+        assert_eq!(c.next_owned(1, 4), Some(5));
+        assert_eq!(c.next_owned(2, 4), None);
+    }
+
+    #[test]
+    fn cluster_config_shadows() {
+        let mut c = ClusterConfig::new(vec![1, 2, 3]).with_shadows(vec![4, 1]);
+
+        assert_eq!(c.shadows, vec![4]);
+        assert!(c.is_shadow(4));
+        assert!(!c.is_member(4));
+        assert!(!c.is_phase2_quorum(&[1, 4]));
+
+        assert!(c.promote(4));
+        assert!(!c.promote(4));
+        assert_eq!(c.members, vec![1, 2, 3, 4]);
+        assert!(c.shadows.is_empty());
+    }
+
+    #[test]
+    fn cluster_config_fast_quorum() {
+        assert_eq!(ClusterConfig::new(vec![1]).fast_quorum(), 1);
+        assert_eq!(ClusterConfig::new(vec![1, 2, 3]).fast_quorum(), 3);
+        assert_eq!(ClusterConfig::new(vec![1, 2, 3, 4]).fast_quorum(), 3);
+        assert_eq!(ClusterConfig::new((1..=5).collect()).fast_quorum(), 4);
+        assert_eq!(ClusterConfig::new((1..=7).collect()).fast_quorum(), 6);
+
+        // Larger Phase-1 quorums allow for smaller fast quorums.
+        let c = ClusterConfig::new((1..=5).collect())
+            .with_quorums(QuorumConfig {
+                phase1: 5,
+                phase2: 1,
+            })
+            .unwrap();
+
+        assert_eq!(c.fast_quorum(), 3);
+    }
+
+    #[test]
+    fn cluster_config_with_quorums() {
+        let members: Vec<NodeId> = (1..=5).collect();
+
+        let c = ClusterConfig::new(members.clone())
+            .with_quorums(QuorumConfig {
+                phase1: 4,
+                phase2: 2,
+            })
+            .unwrap();
+
+        assert_eq!(c.quorum(), 3);
+        assert_eq!(c.phase1_quorum(), 4);
+        assert_eq!(c.phase2_quorum(), 2);
+
+        let c = ClusterConfig::new(members.clone()).with_quorums(QuorumConfig {
+            phase1: 3,
+            phase2: 2,
+        });
+
+        assert_eq!(c, Err(ConfigError::QuorumsDoNotIntersect));
+
+        let c = ClusterConfig::new(members).with_quorums(QuorumConfig {
+            phase1: 6,
+            phase2: 1,
+        });
+
+        assert_eq!(c, Err(ConfigError::QuorumOutOfRange));
+    }
+
+    #[test]
+    fn cluster_config_is_quorum() {
+        let c = ClusterConfig::new(vec![1, 2, 3]);
+
+        assert!(!c.is_phase1_quorum(&[1]));
+        assert!(c.is_phase1_quorum(&[1, 2]));
+        assert!(!c.is_phase2_quorum(&[1, 4]));
+
+        let weights = [(1, 3), (2, 1), (3, 1)].into_iter().collect();
+        let c = c.with_quorum_system(Weighted::new(weights));
+
+        assert!(c.is_phase1_quorum(&[1]));
+        assert!(!c.is_phase2_quorum(&[2, 3]));
+    }
+
+    #[test]
+    fn cluster_config_distinguished_learners() {
+        let c = ClusterConfig::new(vec![1, 2, 3]);
+
+        assert!(!c.is_distinguished_learner(1));
+
+        let c = c.with_distinguished_learners(vec![5, 4, 5]);
+
+        assert_eq!(c.distinguished_learners, vec![4, 5]);
+        assert!(c.is_distinguished_learner(4));
+        assert!(!c.is_distinguished_learner(1));
+        assert_ne!(c, ClusterConfig::new(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn cluster_config_joint() {
+        let c = ClusterConfig::new(vec![1, 2, 3]).begin_transition(vec![3, 4, 5]);
+
+        assert!(c.is_transitioning());
+        assert!(c.is_member(1));
+        assert!(c.is_member(5));
+
+        // A majority of either set alone isn't enough.
+        assert!(!c.is_phase1_quorum(&[1, 2]));
+        assert!(!c.is_phase2_quorum(&[4, 5]));
+        assert!(c.is_phase1_quorum(&[1, 3, 4]));
+        assert!(c.is_phase2_quorum(&[1, 2, 4, 5]));
+
+        let c = c.complete_transition();
+
+        assert!(!c.is_transitioning());
+        assert!(!c.is_member(1));
+        assert!(c.is_phase2_quorum(&[4, 5]));
+    }
+
+    #[test]
+    fn cluster_config_witnesses() {
+        let c = ClusterConfig::new(vec![1, 2])
+            .with_witnesses(vec![3, 1])
+            .with_observers(vec![4]);
+
+        assert_eq!(c.witnesses, vec![3]);
+        assert!(c.is_phase1_voter(3));
+        assert!(!c.is_member(3));
+        assert!(c.is_observer(4));
+        assert!(!c.is_phase1_voter(4));
+
+        // The witness breaks ties in the first phase, but can't accept.
+        assert!(c.is_phase1_quorum(&[1, 3]));
+        assert!(!c.is_phase2_quorum(&[1, 3]));
+        assert!(c.is_phase2_quorum(&[1, 2]));
+    }
+}
@@ -0,0 +1,570 @@
+//! Wire conformance fixtures
+//!
+//! Golden encodings of every kind of `Message`, against which encoders and
+//! decoders of the [`wire`](crate::wire) format can be validated. The same
+//! fixtures are shipped as `fixtures/wire.json` for implementations in other
+//! languages, with integers written as decimal strings (so they survive
+//! parsers limited to doubles), and values and encodings as hex strings.
+
+use crate::message::{
+    AcceptData, AcceptedData, DigestData, JoinData, LearnData, Message, NackData, PromiseData,
+    ProposalData, ProposeData, ReadData, ReadReplyData, RepairData, SkipData, SnapshotData,
+    StateData,
+};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// A message along with its expected encoding.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Fixture {
+    /// Unique name of the fixture
+    pub name: &'static str,
+    /// What the fixture covers
+    pub description: &'static str,
+    /// The decoded message
+    pub message: Message<Vec<u8>>,
+    /// The encoded message
+    pub bytes: Vec<u8>,
+}
+
+/// Names the fixture an encoder or decoder disagreed with.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Mismatch {
+    /// Name of the offending fixture
+    pub fixture: &'static str,
+}
+
+/// All fixtures, in the order they appear in `fixtures/wire.json`.
+pub fn fixtures() -> Vec<Fixture> {
+    vec![
+        Fixture {
+            name: "prepare",
+            description: "Prepare for the first slot",
+            message: Message::Prepare(ProposalData {
+                slot: 0,
+                id: 1,
+                from: 1,
+            }),
+            bytes: hex("00 0000000000000000 0000000000000001 0000000000000001"),
+        },
+        Fixture {
+            name: "prepare_big_endian",
+            description: "Integers are big-endian and use all 64 bits",
+            message: Message::Prepare(ProposalData {
+                slot: u64::MAX,
+                id: 0x0102030405060708,
+                from: 2,
+            }),
+            bytes: hex("00 ffffffffffffffff 0102030405060708 0000000000000002"),
+        },
+        Fixture {
+            name: "promise_empty",
+            description: "Promise from an Acceptor that has accepted nothing",
+            message: Message::Promise(PromiseData {
+                slot: 0,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from: 2,
+            }),
+            bytes: hex("01 0000000000000000 0000000000000001 0000000000000002 00 00"),
+        },
+        Fixture {
+            name: "promise_accepted",
+            description: "Promise carrying a previously accepted value",
+            message: Message::Promise(PromiseData {
+                slot: 4,
+                id: 9,
+                accepted_n: Some(7),
+                value: Some(Arc::new(b"abc".to_vec())),
+                from: 3,
+            }),
+            bytes: hex("01 0000000000000004 0000000000000009 0000000000000003 \
+                 01 0000000000000007 01 00000003 616263"),
+        },
+        Fixture {
+            name: "accept_empty_value",
+            description: "Accept of a zero-length value",
+            message: Message::Accept(AcceptData {
+                slot: 1,
+                id: 2,
+                value: Arc::new(Vec::new()),
+                implicit_prepare: false,
+            }),
+            bytes: hex("02 0000000000000001 0000000000000002 00 00000000"),
+        },
+        Fixture {
+            name: "accept_implicit_prepare",
+            description: "Accept that runs the first phase on the Proposer's behalf",
+            message: Message::Accept(AcceptData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(b"x".to_vec()),
+                implicit_prepare: true,
+            }),
+            bytes: hex("02 0000000000000000 0000000000000001 01 00000001 78"),
+        },
+        Fixture {
+            name: "accepted",
+            description: "Accepted carrying a binary value",
+            message: Message::Accepted(AcceptedData {
+                slot: 2,
+                id: 5,
+                value: Arc::new(vec![0xde, 0xad, 0xbe, 0xef]),
+                from: 1,
+                fast: false,
+            }),
+            bytes: hex(
+                "03 0000000000000002 0000000000000005 0000000000000001 00 00000004 deadbeef",
+            ),
+        },
+        Fixture {
+            name: "accepted_fast",
+            description: "Accepted in a fast round",
+            message: Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 3,
+                value: Arc::new(b"y".to_vec()),
+                from: 4,
+                fast: true,
+            }),
+            bytes: hex("03 0000000000000000 0000000000000003 0000000000000004 01 00000001 79"),
+        },
+        Fixture {
+            name: "nack",
+            description: "Nack naming the leader the Acceptor promised",
+            message: Message::Nack(NackData {
+                slot: 2,
+                id: 3,
+                from: 4,
+                promised_n: 5,
+                leader: Some(1),
+            }),
+            bytes: hex(
+                "04 0000000000000002 0000000000000003 0000000000000004 0000000000000005 \
+                 01 0000000000000001",
+            ),
+        },
+        Fixture {
+            name: "nack_no_leader",
+            description: "Nack from an Acceptor that knows no leader",
+            message: Message::Nack(NackData {
+                slot: 0,
+                id: 1,
+                from: 2,
+                promised_n: 1,
+                leader: None,
+            }),
+            bytes: hex("04 0000000000000000 0000000000000001 0000000000000002 0000000000000001 00"),
+        },
+        Fixture {
+            name: "any",
+            description: "Any opening a fast round",
+            message: Message::Any(ProposalData {
+                slot: 6,
+                id: 2,
+                from: 1,
+            }),
+            bytes: hex("05 0000000000000006 0000000000000002 0000000000000001"),
+        },
+        Fixture {
+            name: "propose",
+            description: "Value proposed straight to the Acceptors",
+            message: Message::Propose(ProposeData {
+                slot: 6,
+                value: Arc::new(b"z".to_vec()),
+            }),
+            bytes: hex("06 0000000000000006 00000001 7a"),
+        },
+        Fixture {
+            name: "skip",
+            description: "Skip of a leader's slots up to, but excluding, the end",
+            message: Message::Skip(SkipData {
+                from: 2,
+                start: 1,
+                end: 7,
+                value: Arc::new(Vec::new()),
+            }),
+            bytes: hex("07 0000000000000002 0000000000000001 0000000000000007 00000000"),
+        },
+        Fixture {
+            name: "join",
+            description: "Join of a new node, asking for the state of existing ones",
+            message: Message::Join(JoinData { from: 4 }),
+            bytes: hex("08 0000000000000004"),
+        },
+        Fixture {
+            name: "state",
+            description: "State copied to a joining node, as lists of accepted and decided slots",
+            message: Message::State(StateData {
+                from: 1,
+                to: 4,
+                promised_n: 9,
+                accepted: vec![(2, 7, Arc::new(b"b".to_vec()))],
+                decided: vec![(0, Arc::new(b"a".to_vec())), (1, Arc::new(Vec::new()))],
+            }),
+            bytes: hex("09 0000000000000001 0000000000000004 0000000000000009 \
+                 00000001 0000000000000002 0000000000000007 00000001 62 \
+                 00000002 0000000000000000 00000001 61 0000000000000001 00000000"),
+        },
+        Fixture {
+            name: "install_snapshot",
+            description: "Snapshot covering every slot below the index, sent to a lagging Learner",
+            message: Message::InstallSnapshot(SnapshotData {
+                from: 1,
+                to: 3,
+                index: 12,
+                value: Arc::new(b"snap".to_vec()),
+            }),
+            bytes: hex("0a 0000000000000001 0000000000000003 000000000000000c 00000004 736e6170"),
+        },
+        Fixture {
+            name: "learn",
+            description: "Decided value announced along with the Acceptors that accepted it",
+            message: Message::Learn(LearnData {
+                slot: 4,
+                id: 2,
+                from: 2,
+                value: Arc::new(b"v".to_vec()),
+                certificate: vec![1, 3],
+            }),
+            bytes: hex("0b 0000000000000004 0000000000000002 0000000000000002 \
+                 00000002 0000000000000001 0000000000000003 00000001 76"),
+        },
+        Fixture {
+            name: "read",
+            description: "Read asking the Acceptors how far their log goes",
+            message: Message::Read(ReadData { from: 2, id: 7 }),
+            bytes: hex("0c 0000000000000002 0000000000000007"),
+        },
+        Fixture {
+            name: "read_reply",
+            description: "Read answered with the slot after the highest accepted",
+            message: Message::ReadReply(ReadReplyData {
+                from: 1,
+                to: 2,
+                id: 7,
+                horizon: 9,
+            }),
+            bytes: hex("0d 0000000000000001 0000000000000002 0000000000000007 0000000000000009"),
+        },
+        Fixture {
+            name: "digest",
+            description: "Hashes of the proposals accepted in each range of slots",
+            message: Message::Digest(DigestData {
+                from: 1,
+                start: 8,
+                truncated: 10,
+                range: 4,
+                hashes: vec![0, 42],
+            }),
+            bytes: hex(
+                "0e 0000000000000001 0000000000000008 000000000000000a 0000000000000004 \
+                 00000002 0000000000000000 000000000000002a",
+            ),
+        },
+        Fixture {
+            name: "repair",
+            description: "Accepted proposals a digest was found to be missing",
+            message: Message::Repair(RepairData {
+                from: 2,
+                to: 1,
+                accepted: vec![(13, 5, false, Arc::new(b"v".to_vec()))],
+            }),
+            bytes: hex("0f 0000000000000002 0000000000000001 \
+                 00000001 000000000000000d 0000000000000005 00 00000001 76"),
+        },
+    ]
+}
+
+/// Checks that `encode` produces the golden bytes of every fixture.
+pub fn check_encoder<F>(mut encode: F) -> Result<(), Mismatch>
+where
+    F: FnMut(&Message<Vec<u8>>) -> Vec<u8>,
+{
+    for fixture in fixtures() {
+        if encode(&fixture.message) != fixture.bytes {
+            return Err(Mismatch {
+                fixture: fixture.name,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `decode` recovers the message of every fixture from its bytes.
+pub fn check_decoder<F, E>(mut decode: F) -> Result<(), Mismatch>
+where
+    F: FnMut(&[u8]) -> Result<Message<Vec<u8>>, E>,
+{
+    for fixture in fixtures() {
+        match decode(&fixture.bytes) {
+            Ok(ref msg) if *msg == fixture.message => {}
+            _ => {
+                return Err(Mismatch {
+                    fixture: fixture.name,
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Renders the fixtures as JSON, i.e. the contents of `fixtures/wire.json`.
+pub fn fixtures_json() -> String {
+    let mut out = String::from("[\n");
+    let fixtures = fixtures();
+    for (i, fixture) in fixtures.iter().enumerate() {
+        out.push_str("  {\n");
+        let _ = writeln!(out, "    \"name\": \"{}\",", fixture.name);
+        let _ = writeln!(out, "    \"description\": \"{}\",", fixture.description);
+        let _ = writeln!(out, "    \"message\": {},", message_json(&fixture.message));
+        let _ = writeln!(out, "    \"bytes\": \"{}\"", to_hex(&fixture.bytes));
+        out.push_str(if i + 1 < fixtures.len() {
+            "  },\n"
+        } else {
+            "  }\n"
+        });
+    }
+    out.push_str("]\n");
+    out
+}
+
+fn message_json(msg: &Message<Vec<u8>>) -> String {
+    match msg {
+        Message::Prepare(data) => alloc::format!(
+            "{{\"type\": \"Prepare\", \"slot\": \"{}\", \"id\": \"{}\", \"from\": \"{}\"}}",
+            data.slot,
+            data.id,
+            data.from
+        ),
+        Message::Promise(data) => alloc::format!(
+            "{{\"type\": \"Promise\", \"slot\": \"{}\", \"id\": \"{}\", \"from\": \"{}\", \
+             \"accepted_n\": {}, \"value\": {}}}",
+            data.slot,
+            data.id,
+            data.from,
+            data.accepted_n
+                .map_or_else(|| String::from("null"), |n| alloc::format!("\"{}\"", n)),
+            data.value.as_ref().map_or_else(
+                || String::from("null"),
+                |v| alloc::format!("\"{}\"", to_hex(v))
+            ),
+        ),
+        Message::Accept(data) => alloc::format!(
+            "{{\"type\": \"Accept\", \"slot\": \"{}\", \"id\": \"{}\", \
+             \"implicit_prepare\": {}, \"value\": \"{}\"}}",
+            data.slot,
+            data.id,
+            data.implicit_prepare,
+            to_hex(&data.value)
+        ),
+        Message::Accepted(data) => alloc::format!(
+            "{{\"type\": \"Accepted\", \"slot\": \"{}\", \"id\": \"{}\", \"from\": \"{}\", \
+             \"fast\": {}, \"value\": \"{}\"}}",
+            data.slot,
+            data.id,
+            data.from,
+            data.fast,
+            to_hex(&data.value)
+        ),
+        Message::Nack(data) => alloc::format!(
+            "{{\"type\": \"Nack\", \"slot\": \"{}\", \"id\": \"{}\", \"from\": \"{}\", \
+             \"promised_n\": \"{}\", \"leader\": {}}}",
+            data.slot,
+            data.id,
+            data.from,
+            data.promised_n,
+            data.leader
+                .map_or_else(|| String::from("null"), |n| alloc::format!("\"{}\"", n)),
+        ),
+        Message::Any(data) => alloc::format!(
+            "{{\"type\": \"Any\", \"slot\": \"{}\", \"id\": \"{}\", \"from\": \"{}\"}}",
+            data.slot,
+            data.id,
+            data.from
+        ),
+        Message::Propose(data) => alloc::format!(
+            "{{\"type\": \"Propose\", \"slot\": \"{}\", \"value\": \"{}\"}}",
+            data.slot,
+            to_hex(&data.value)
+        ),
+        Message::Skip(data) => alloc::format!(
+            "{{\"type\": \"Skip\", \"from\": \"{}\", \"start\": \"{}\", \"end\": \"{}\", \
+             \"value\": \"{}\"}}",
+            data.from,
+            data.start,
+            data.end,
+            to_hex(&data.value)
+        ),
+        Message::Join(data) => {
+            alloc::format!("{{\"type\": \"Join\", \"from\": \"{}\"}}", data.from)
+        }
+        Message::State(data) => {
+            let accepted: Vec<String> = data
+                .accepted
+                .iter()
+                .map(|(slot, n, value)| {
+                    alloc::format!(
+                        "{{\"slot\": \"{}\", \"n\": \"{}\", \"value\": \"{}\"}}",
+                        slot,
+                        n,
+                        to_hex(value)
+                    )
+                })
+                .collect();
+            let decided: Vec<String> = data
+                .decided
+                .iter()
+                .map(|(slot, value)| {
+                    alloc::format!(
+                        "{{\"slot\": \"{}\", \"value\": \"{}\"}}",
+                        slot,
+                        to_hex(value)
+                    )
+                })
+                .collect();
+            alloc::format!(
+                "{{\"type\": \"State\", \"from\": \"{}\", \"to\": \"{}\", \
+                 \"promised_n\": \"{}\", \"accepted\": [{}], \"decided\": [{}]}}",
+                data.from,
+                data.to,
+                data.promised_n,
+                accepted.join(", "),
+                decided.join(", ")
+            )
+        }
+        Message::InstallSnapshot(data) => alloc::format!(
+            "{{\"type\": \"InstallSnapshot\", \"from\": \"{}\", \"to\": \"{}\", \
+             \"index\": \"{}\", \"value\": \"{}\"}}",
+            data.from,
+            data.to,
+            data.index,
+            to_hex(&data.value)
+        ),
+        Message::Learn(data) => {
+            let certificate: Vec<String> = data
+                .certificate
+                .iter()
+                .map(|from| alloc::format!("\"{}\"", from))
+                .collect();
+            alloc::format!(
+                "{{\"type\": \"Learn\", \"slot\": \"{}\", \"id\": \"{}\", \"from\": \"{}\", \
+                 \"certificate\": [{}], \"value\": \"{}\"}}",
+                data.slot,
+                data.id,
+                data.from,
+                certificate.join(", "),
+                to_hex(&data.value)
+            )
+        }
+        Message::Read(data) => alloc::format!(
+            "{{\"type\": \"Read\", \"from\": \"{}\", \"id\": \"{}\"}}",
+            data.from,
+            data.id
+        ),
+        Message::ReadReply(data) => alloc::format!(
+            "{{\"type\": \"ReadReply\", \"from\": \"{}\", \"to\": \"{}\", \"id\": \"{}\", \
+             \"horizon\": \"{}\"}}",
+            data.from,
+            data.to,
+            data.id,
+            data.horizon
+        ),
+        Message::Digest(data) => {
+            let hashes: Vec<String> = data
+                .hashes
+                .iter()
+                .map(|hash| alloc::format!("\"{}\"", hash))
+                .collect();
+            alloc::format!(
+                "{{\"type\": \"Digest\", \"from\": \"{}\", \"start\": \"{}\", \
+                 \"truncated\": \"{}\", \"range\": \"{}\", \"hashes\": [{}]}}",
+                data.from,
+                data.start,
+                data.truncated,
+                data.range,
+                hashes.join(", ")
+            )
+        }
+        Message::Repair(data) => {
+            let accepted: Vec<String> = data
+                .accepted
+                .iter()
+                .map(|(slot, n, fast, value)| {
+                    alloc::format!(
+                        "{{\"slot\": \"{}\", \"n\": \"{}\", \"fast\": {}, \"value\": \"{}\"}}",
+                        slot,
+                        n,
+                        fast,
+                        to_hex(value)
+                    )
+                })
+                .collect();
+            alloc::format!(
+                "{{\"type\": \"Repair\", \"from\": \"{}\", \"to\": \"{}\", \
+                 \"accepted\": [{}]}}",
+                data.from,
+                data.to,
+                accepted.join(", ")
+            )
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for b in bytes {
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
+
+/// Parses hex digits, ignoring whitespace.
+fn hex(s: &str) -> Vec<u8> {
+    let digits: Vec<u8> = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).expect("invalid hex digit") as u8)
+        .collect();
+    digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire;
+
+    #[test]
+    fn conformance_wire() {
+        assert_eq!(check_encoder(wire::encode::<Vec<u8>>), Ok(()));
+        assert_eq!(check_decoder(wire::decode::<Vec<u8>>), Ok(()));
+    }
+
+    #[test]
+    fn conformance_detects_mismatch() {
+        // Little-endian integers are not the wire format.
+        let encode = |msg: &Message<Vec<u8>>| match msg {
+            Message::Prepare(data) => {
+                let mut out = vec![0];
+                out.extend_from_slice(&data.slot.to_le_bytes());
+                out.extend_from_slice(&data.id.to_le_bytes());
+                out
+            }
+            _ => wire::encode(msg),
+        };
+
+        assert_eq!(check_encoder(encode), Err(Mismatch { fixture: "prepare" }));
+    }
+
+    #[test]
+    fn conformance_json_is_up_to_date() {
+        assert_eq!(fixtures_json(), include_str!("../fixtures/wire.json"));
+    }
+}
@@ -0,0 +1,312 @@
+//! Effects
+//!
+//! The protocol logic of a role never performs I/O itself: everything it
+//! needs done is described by an `Effect`. With a `Messenger` set, effects are
+//! carried out as they happen, which is what the `receive_*` methods rely on.
+//! Without one, they pile up until taken, so that the role can be driven as a
+//! pure state machine with `step`: a message goes in, effects come out.
+
+use crate::acceptor::{AcceptedProposal, Acceptor};
+use crate::config::NodeId;
+use crate::learner::Learner;
+use crate::message::{Handler, Message, Messenger, Slot};
+use crate::node::Node;
+use crate::proposer::Proposer;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Something a role needs done on its behalf. New kinds of effects may be
+/// added in minor releases, so matches need a wildcard arm.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub enum Effect<T> {
+    /// Send a message, through the `Messenger` method matching its type
+    SendMessage(Message<T>),
+    /// Send a message to the given nodes only, through `Messenger::send_to`.
+    /// Drivers unable to address single nodes may send it to every node, as
+    /// for `SendMessage`
+    SendMessageTo(Vec<NodeId>, Message<T>),
+    /// Report the value decided for a slot
+    Decide(Slot, Arc<T>),
+    /// Make an `Acceptor`'s state durable before carrying out the effects
+    /// that follow, so that it can't go back on its word after a restart
+    PersistState {
+        /// The highest proposal number promised
+        promised_n: u64,
+        /// A value just accepted, along with its slot
+        accepted: Option<(Slot, AcceptedProposal<T>)>,
+    },
+    /// Call `tick` once this time is reached, in milliseconds
+    StartTimer { at: u64 },
+    /// Report that a `Proposer`'s proposal number was outbid
+    Preempted {
+        /// The proposal number outbid
+        ballot: u64,
+        /// The higher proposal number an `Acceptor` promised instead
+        by: u64,
+    },
+}
+
+impl<T> Effect<T> {
+    /// The message to send to node `id`, if the effect sends one meant for
+    /// it: any `SendMessage`, or a `SendMessageTo` naming `id`.
+    pub fn message_for(&self, id: NodeId) -> Option<&Message<T>> {
+        match self {
+            Effect::SendMessage(msg) => Some(msg),
+            Effect::SendMessageTo(to, msg) if to.contains(&id) => Some(msg),
+            _ => None,
+        }
+    }
+}
+
+/// Carries out `effect` through `messenger`. Effects a `Messenger` has no
+/// means of carrying out, persisting state and starting timers, are dropped.
+pub fn dispatch<T, M: Messenger<T> + ?Sized>(messenger: &mut M, effect: Effect<T>) {
+    match effect {
+        Effect::SendMessage(msg) => match msg {
+            Message::Prepare(_) => messenger.send_prepare(msg),
+            Message::Promise(_) => messenger.send_promise(msg),
+            Message::Accept(_) => messenger.send_accept(msg),
+            Message::Accepted(_) => messenger.send_accepted(msg),
+            Message::Any(_) => messenger.send_any(msg),
+            Message::Propose(_) => messenger.send_propose(msg),
+            Message::Skip(_) => messenger.send_skip(msg),
+            Message::Join(_) => messenger.send_join(msg),
+            Message::State(_) => messenger.send_state(msg),
+            Message::Learn(_) => messenger.send_learn(msg),
+            Message::InstallSnapshot(_) => messenger.send_install_snapshot(msg),
+            Message::Nack(_) => messenger.send_nack(msg),
+            Message::Read(_) => messenger.send_read(msg),
+            Message::ReadReply(_) => messenger.send_read_reply(msg),
+            Message::Digest(_) => messenger.send_digest(msg),
+            Message::Repair(_) => messenger.send_repair(msg),
+        },
+        Effect::SendMessageTo(to, msg) => messenger.send_to(&to, msg),
+        Effect::Decide(slot, value) => messenger.on_resolution(slot, value),
+        Effect::Preempted { ballot, by } => messenger.on_preempted(ballot, by),
+        Effect::PersistState { .. } | Effect::StartTimer { .. } => {}
+    }
+}
+
+impl<T, M> Proposer<T, M>
+where
+    T: PartialEq + Clone,
+    M: Messenger<T>,
+{
+    /// Takes the effects produced since the last call. Always empty while a
+    /// `Messenger` is set, as it carries them out as they happen.
+    pub fn take_effects(&mut self) -> Vec<Effect<T>> {
+        core::mem::take(&mut self.effects)
+    }
+
+    /// Handles `msg` and returns the resulting effects, bypassing any
+    /// `Messenger`.
+    pub fn step(&mut self, msg: Message<T>) -> Vec<Effect<T>> {
+        let messenger = self.messenger.take();
+        self.handle(msg);
+        self.messenger = messenger;
+        self.take_effects()
+    }
+
+    pub(crate) fn effect(&mut self, effect: Effect<T>) {
+        match self.messenger {
+            Some(ref mut messenger) => dispatch(messenger, effect),
+            None => self.effects.push(effect),
+        }
+    }
+}
+
+impl<T, M: Messenger<T>> Acceptor<T, M> {
+    /// Takes the effects produced since the last call. Always empty while a
+    /// `Messenger` is set, as it carries them out as they happen.
+    pub fn take_effects(&mut self) -> Vec<Effect<T>> {
+        core::mem::take(&mut self.effects)
+    }
+
+    /// Handles `msg` and returns the resulting effects, bypassing any
+    /// `Messenger`.
+    pub fn step(&mut self, msg: Message<T>) -> Vec<Effect<T>> {
+        let messenger = self.messenger.take();
+        self.handle(msg);
+        self.messenger = messenger;
+        self.take_effects()
+    }
+
+    pub(crate) fn effect(&mut self, effect: Effect<T>) {
+        match self.messenger {
+            Some(ref mut messenger) => dispatch(messenger, effect),
+            None => self.effects.push(effect),
+        }
+    }
+}
+
+impl<T: PartialEq, M: Messenger<T>> Learner<T, M> {
+    /// Takes the effects produced since the last call. Always empty while a
+    /// `Messenger` is set, as it carries them out as they happen.
+    pub fn take_effects(&mut self) -> Vec<Effect<T>> {
+        core::mem::take(&mut self.effects)
+    }
+
+    /// Handles `msg` and returns the resulting effects, bypassing any
+    /// `Messenger`.
+    pub fn step(&mut self, msg: Message<T>) -> Vec<Effect<T>> {
+        let messenger = self.messenger.take();
+        self.handle(msg);
+        self.messenger = messenger;
+        self.take_effects()
+    }
+
+    pub(crate) fn effect(&mut self, effect: Effect<T>) {
+        match self.messenger {
+            Some(ref mut messenger) => dispatch(messenger, effect),
+            None => self.effects.push(effect),
+        }
+    }
+}
+
+impl<T, M> Node<T, M>
+where
+    T: PartialEq + Clone,
+    M: Messenger<T>,
+{
+    /// Takes the effects produced by every role since the last call.
+    pub fn take_effects(&mut self) -> Vec<Effect<T>> {
+        let mut effects = self.acceptor.take_effects();
+        effects.append(&mut self.proposer.take_effects());
+        effects.append(&mut self.learner.take_effects());
+        effects
+    }
+
+    /// Handles `msg` and returns the resulting effects of every role,
+    /// bypassing any `Messenger`. The `Acceptor`'s come first, so that its
+    /// state is persisted before anything else is sent.
+    pub fn step(&mut self, msg: Message<T>) -> Vec<Effect<T>> {
+        let proposer = self.proposer.messenger.take();
+        let acceptor = self.acceptor.messenger.take();
+        let learner = self.learner.messenger.take();
+        self.handle(msg);
+        self.proposer.messenger = proposer;
+        self.acceptor.messenger = acceptor;
+        self.learner.messenger = learner;
+        self.take_effects()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::message::{AcceptData, AcceptedData, PromiseData, ProposalData};
+    use alloc::vec;
+
+    #[test]
+    fn effect_step_acceptor() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+
+        assert_eq!(
+            a.step(Message::Prepare(ProposalData {
+                slot: 0,
+                id: 2,
+                from: 1,
+            })),
+            vec![
+                Effect::PersistState {
+                    promised_n: 2,
+                    accepted: None
+                },
+                Effect::SendMessage(Message::Promise(PromiseData {
+                    slot: 0,
+                    id: 2,
+                    accepted_n: None,
+                    value: None,
+                    from: 1,
+                })),
+            ]
+        );
+
+        let accepted = AcceptedProposal {
+            n: 2,
+            value: Arc::new(10),
+        };
+
+        assert_eq!(
+            a.step(Message::Accept(AcceptData {
+                slot: 0,
+                id: 2,
+                value: Arc::new(10),
+                implicit_prepare: false,
+            })),
+            vec![
+                Effect::PersistState {
+                    promised_n: 2,
+                    accepted: Some((0, accepted)),
+                },
+                Effect::SendMessage(Message::Accepted(AcceptedData {
+                    slot: 0,
+                    id: 2,
+                    value: Arc::new(10),
+                    from: 1,
+                    fast: false,
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn effect_step_proposer() {
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.set_timeout(Some(10), 1);
+        p.prepare(10);
+
+        assert_eq!(
+            p.take_effects(),
+            vec![
+                Effect::SendMessage(Message::Prepare(ProposalData {
+                    slot: 0,
+                    id: 1,
+                    from: 1,
+                })),
+                Effect::StartTimer { at: 10 },
+            ]
+        );
+
+        let promise = |from| {
+            Message::Promise(PromiseData {
+                slot: 0,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from,
+            })
+        };
+
+        assert!(p.step(promise(1)).is_empty());
+        assert_eq!(
+            p.step(promise(2)),
+            vec![
+                Effect::SendMessage(Message::Accept(AcceptData {
+                    slot: 0,
+                    id: 1,
+                    value: Arc::new(10),
+                    implicit_prepare: false,
+                })),
+                Effect::StartTimer { at: 10 },
+            ]
+        );
+
+        let accepted = |from| {
+            Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(10),
+                from,
+                fast: false,
+            })
+        };
+        p.step(accepted(1));
+
+        assert!(p
+            .step(accepted(2))
+            .contains(&Effect::Decide(0, Arc::new(10))));
+    }
+}
@@ -0,0 +1,325 @@
+//! Errors
+//!
+//! Every fault the crate can report is a variant of `Error`, grouped by the
+//! role or layer it comes from. Each error has a stable numeric `code`, meant
+//! for logs and metrics, which is never reused once assigned:
+//!
+//! | Codes | Error            |
+//! |-------|------------------|
+//! | 1xx   | `ProposerError`  |
+//! | 2xx   | `AcceptorError`  |
+//! | 3xx   | `LearnerError`   |
+//! | 4xx   | `TransportError` |
+//! | 5xx   | `ConfigError`    |
+//! | 6xx   | `BuildError`     |
+//! | 7xx   | `BftError`       |
+//! | 8xx   | `ClientError`    |
+//! | 9xx   | `InvariantViolation` |
+
+#[cfg(feature = "bft")]
+use crate::bft::BftError;
+use crate::builder::BuildError;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+use crate::client::ClientError;
+use crate::config::{ConfigError, NodeId};
+use crate::message::Slot;
+use crate::wire::DecodeError;
+use thiserror::Error;
+
+/// Errors raised by a `Proposer`.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum ProposerError {
+    /// Slots are partitioned among leaders the `Proposer` isn't one of
+    #[error("Proposer {0} is not a leader")]
+    NotALeader(NodeId),
+    /// A `Proposer` may keep no slot in flight
+    #[error("a Proposer must keep a slot in flight")]
+    EmptyWindow,
+    /// The `Proposer` admits no more proposals for now
+    #[error("Proposer is busy")]
+    Busy,
+}
+
+impl ProposerError {
+    /// The error's stable code.
+    pub fn code(&self) -> u16 {
+        match self {
+            ProposerError::NotALeader(_) => 100,
+            ProposerError::EmptyWindow => 101,
+            ProposerError::Busy => 102,
+        }
+    }
+}
+
+/// Errors raised by an `Acceptor`.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum AcceptorError {
+    /// The `Acceptor` is still joining, and may not vote yet
+    #[error("Acceptor is not voting yet")]
+    NotVoting,
+    /// The slot was truncated from the `Acceptor`'s log
+    #[error("slot {0} was truncated")]
+    Truncated(Slot),
+    /// A higher proposal number was promised already
+    #[error("proposal {0} was superseded")]
+    Superseded(u64),
+    /// A message came from a node unknown to the cluster
+    #[error("{0} is not a node of the cluster")]
+    UnknownSender(NodeId),
+    /// A message belongs to another group
+    #[error("message belongs to group {0}")]
+    WrongGroup(u64),
+    /// A proposal number is implausibly far above the promise
+    #[error("proposal {0} is too far ahead")]
+    BallotTooHigh(u64),
+    /// A value is larger than allowed, in bytes
+    #[error("value of {0} bytes is too large")]
+    ValueTooLarge(usize),
+}
+
+impl AcceptorError {
+    /// The error's stable code.
+    pub fn code(&self) -> u16 {
+        match self {
+            AcceptorError::NotVoting => 200,
+            AcceptorError::Truncated(_) => 201,
+            AcceptorError::Superseded(_) => 202,
+            AcceptorError::UnknownSender(_) => 203,
+            AcceptorError::WrongGroup(_) => 204,
+            AcceptorError::BallotTooHigh(_) => 205,
+            AcceptorError::ValueTooLarge(_) => 206,
+        }
+    }
+}
+
+/// Errors raised by a `Learner`. Mismatches mean that the safety of the
+/// protocol was violated, e.g. by misconfigured quorums or a faulty
+/// `Acceptor`: the message at fault is dropped, and the error reported through
+/// `Learner::take_errors`. Snapshots that can't be restored from are ignored,
+/// and reported through `Batched::take_errors`.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum LearnerError {
+    /// A value other than the one decided was reported for a slot
+    #[error("Value mismatch for slot {0}")]
+    SlotMismatch(Slot),
+    /// A proposal number was reported to carry several values
+    #[error("Value mismatch for proposal {0}")]
+    ProposalMismatch(u64),
+    /// A batch restored from as a snapshot held this many entries, rather
+    /// than one
+    #[error("snapshot batch of {0} entries")]
+    MalformedSnapshot(usize),
+}
+
+impl LearnerError {
+    /// The error's stable code.
+    pub fn code(&self) -> u16 {
+        match self {
+            LearnerError::SlotMismatch(_) => 300,
+            LearnerError::ProposalMismatch(_) => 301,
+            LearnerError::MalformedSnapshot(_) => 302,
+        }
+    }
+}
+
+/// Protocol invariants found broken at runtime by the `paranoid-checks`
+/// feature. Each means a bug in the roles themselves, rather than a faulty
+/// peer: the transition at fault is refused instead of corrupting state, and
+/// the violation kept for `Node::take_violations`.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum InvariantViolation {
+    /// An `Acceptor` was about to lower its promise
+    #[error("promise would drop from {promised} to {n}")]
+    PromiseRegressed { promised: u64, n: u64 },
+    /// An `Acceptor` was about to accept a proposal below its promise
+    #[error("proposal {n} would be accepted for slot {slot} below promise {promised}")]
+    BrokenPromise { slot: Slot, n: u64, promised: u64 },
+    /// A `Learner` was about to decide a second value for the slot
+    #[error("a second value would be decided for slot {0}")]
+    ConflictingDecision(Slot),
+}
+
+impl InvariantViolation {
+    /// The error's stable code.
+    pub fn code(&self) -> u16 {
+        match self {
+            InvariantViolation::PromiseRegressed { .. } => 900,
+            InvariantViolation::BrokenPromise { .. } => 901,
+            InvariantViolation::ConflictingDecision(_) => 902,
+        }
+    }
+}
+
+/// Errors raised while moving messages between roles.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum TransportError {
+    /// The bytes received aren't a valid message
+    #[error("malformed message: {0}")]
+    Decode(#[from] DecodeError),
+    /// The receiving end is gone
+    #[error("channel closed")]
+    Closed,
+}
+
+impl TransportError {
+    /// The error's stable code.
+    pub fn code(&self) -> u16 {
+        match self {
+            TransportError::Decode(err) => match err {
+                DecodeError::UnexpectedEnd => 400,
+                DecodeError::UnknownTag(_) => 401,
+                DecodeError::InvalidBool(_) => 402,
+                DecodeError::TrailingBytes => 403,
+                DecodeError::InvalidChunk => 404,
+                DecodeError::UnknownCompression(_) => 405,
+                DecodeError::Decompress => 406,
+            },
+            TransportError::Closed => 410,
+        }
+    }
+}
+
+impl ConfigError {
+    /// The error's stable code.
+    pub fn code(&self) -> u16 {
+        match self {
+            ConfigError::QuorumOutOfRange => 500,
+            ConfigError::QuorumsDoNotIntersect => 501,
+        }
+    }
+}
+
+impl BuildError {
+    /// The error's stable code. Invalid quorums keep the code of their
+    /// `ConfigError`.
+    pub fn code(&self) -> u16 {
+        match self {
+            BuildError::MissingId => 600,
+            BuildError::MissingConfig => 601,
+            BuildError::Config(err) => err.code(),
+            BuildError::EmptyWindow => 602,
+            BuildError::NotALeader => 603,
+        }
+    }
+}
+
+#[cfg(feature = "bft")]
+impl BftError {
+    /// The error's stable code.
+    pub fn code(&self) -> u16 {
+        match self {
+            BftError::BadSignature(_) => 700,
+            BftError::NotAMember(_) => 701,
+            BftError::WrongProposal => 702,
+            BftError::NoQuorum => 703,
+            BftError::UnsafeValue => 704,
+        }
+    }
+}
+
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+impl ClientError {
+    /// The error's stable code.
+    pub fn code(&self) -> u16 {
+        match self {
+            ClientError::InvalidValue => 800,
+            ClientError::Unavailable => 801,
+            ClientError::Protocol(_) => 802,
+        }
+    }
+}
+
+/// Any error raised by the crate.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum Error {
+    /// Raised by a `Proposer`
+    #[error(transparent)]
+    Proposer(#[from] ProposerError),
+    /// Raised by an `Acceptor`
+    #[error(transparent)]
+    Acceptor(#[from] AcceptorError),
+    /// Raised by a `Learner`
+    #[error(transparent)]
+    Learner(#[from] LearnerError),
+    /// Raised while moving messages between roles
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+    /// Raised by an invalid `ClusterConfig`
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// Raised when building a role
+    #[error(transparent)]
+    Build(#[from] BuildError),
+    /// Raised by a message that can't be trusted
+    #[cfg(feature = "bft")]
+    #[error(transparent)]
+    Bft(#[from] BftError),
+    /// Raised by a `PaxosClient`
+    #[cfg(all(feature = "std", not(target_family = "wasm")))]
+    #[error(transparent)]
+    Client(#[from] ClientError),
+    /// Raised by a role breaking a protocol invariant
+    #[error(transparent)]
+    Invariant(#[from] InvariantViolation),
+}
+
+impl Error {
+    /// The error's stable code, for logging. See the module documentation.
+    pub fn code(&self) -> u16 {
+        match self {
+            Error::Proposer(err) => err.code(),
+            Error::Acceptor(err) => err.code(),
+            Error::Learner(err) => err.code(),
+            Error::Transport(err) => err.code(),
+            Error::Config(err) => err.code(),
+            Error::Build(err) => err.code(),
+            #[cfg(feature = "bft")]
+            Error::Bft(err) => err.code(),
+            #[cfg(all(feature = "std", not(target_family = "wasm")))]
+            Error::Client(err) => err.code(),
+            Error::Invariant(err) => err.code(),
+        }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(err: DecodeError) -> Self {
+        Error::Transport(err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn error_codes() {
+        let err: Error = ProposerError::NotALeader(3).into();
+
+        assert_eq!(err.code(), 100);
+        assert_eq!(err.to_string(), "Proposer 3 is not a leader");
+
+        let err: Error = DecodeError::UnknownTag(42).into();
+
+        assert_eq!(
+            err,
+            Error::Transport(TransportError::Decode(DecodeError::UnknownTag(42)))
+        );
+        assert_eq!(err.code(), 401);
+        assert_eq!(
+            err.to_string(),
+            "malformed message: unknown message type 42"
+        );
+
+        let err: Error = BuildError::Config(ConfigError::QuorumsDoNotIntersect).into();
+
+        assert_eq!(err.code(), 501);
+    }
+}
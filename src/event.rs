@@ -0,0 +1,145 @@
+//! Events
+
+use crate::config::NodeId;
+use crate::message::Slot;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+
+/// Notable steps taken by a role, emitted so that applications can render or
+/// log the progress of the protocol however they like.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PaxosEvent {
+    /// A `Proposer` started the first phase for `instance`
+    PrepareSent { instance: Slot, n: u64 },
+    /// An `Acceptor` promised to ignore proposals numbered below `n`
+    PromiseSent { instance: Slot, n: u64 },
+    /// A `Proposer` received a `Promise` for its current proposal
+    PromiseReceived { instance: Slot, from: NodeId },
+    /// A `Proposer` collected a Phase-1 quorum of `Promise`s
+    QuorumReached { instance: Slot, n: u64 },
+    /// A `Proposer` asked the `Acceptor`s to accept its value
+    AcceptSent { instance: Slot, n: u64 },
+    /// An `Acceptor` accepted the value proposed under `n`
+    Accepted { instance: Slot, n: u64 },
+    /// A value was decided for `instance`
+    Decided { instance: Slot },
+    /// A shadow `Acceptor` accepted a different value than the one decided,
+    /// under the same proposal number
+    ShadowDiverged { instance: Slot, from: NodeId },
+    /// A `Proposer`'s leader lease ran out
+    LeaseExpired,
+}
+
+/// Callback every `PaxosEvent` of a role is handed to.
+pub type EventSink = Box<dyn FnMut(PaxosEvent) + Send>;
+
+/// Callbacks on the transitions of the protocol, for applications to build
+/// dashboards, audit trails or test assertions on. Unlike `PaxosEvent`s,
+/// they carry the values involved. Each callback does nothing unless
+/// overridden.
+pub trait Observer<T> {
+    /// A `Proposer` received a `Promise` from `from` for its proposal in
+    /// `slot`.
+    fn on_promise_received(&mut self, slot: Slot, from: NodeId) {
+        let _ = (slot, from);
+    }
+
+    /// A `Proposer` collected a Phase-1 quorum of `Promise`s for `slot`.
+    fn on_quorum_reached(&mut self, slot: Slot, n: u64) {
+        let _ = (slot, n);
+    }
+
+    /// A `Proposer`'s proposal number `ballot` was outbid by `by`, which an
+    /// `Acceptor` promised instead, as reported by the first `Nack` saying
+    /// so.
+    fn on_preempted(&mut self, ballot: u64, by: u64) {
+        let _ = (ballot, by);
+    }
+
+    /// An `Acceptor` promised to ignore proposals numbered below `n`.
+    fn on_promised(&mut self, slot: Slot, n: u64) {
+        let _ = (slot, n);
+    }
+
+    /// An `Acceptor` accepted `value` for `slot` under `n`.
+    fn on_accepted(&mut self, slot: Slot, n: u64, value: &Arc<T>) {
+        let _ = (slot, n, value);
+    }
+
+    /// A `Proposer` or a `Learner` saw `value` decided for `slot`.
+    fn on_decided(&mut self, slot: Slot, value: &Arc<T>) {
+        let _ = (slot, value);
+    }
+}
+
+/// `Observer` a role reports to.
+pub type ObserverSink<T> = Box<dyn Observer<T> + Send>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::effect::Effect;
+    use crate::node::Node;
+    use alloc::string::String;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use std::format;
+    use std::sync::Mutex;
+
+    /// Logs every callback, across the roles it is shared by.
+    #[derive(Clone, Default)]
+    struct Audit(Arc<Mutex<Vec<String>>>);
+
+    impl Observer<u64> for Audit {
+        fn on_quorum_reached(&mut self, slot: Slot, n: u64) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("quorum {} {}", slot, n));
+        }
+
+        fn on_accepted(&mut self, slot: Slot, n: u64, value: &Arc<u64>) {
+            let accepted = format!("accepted {} {} {}", slot, n, value);
+            self.0.lock().unwrap().push(accepted);
+        }
+
+        fn on_decided(&mut self, slot: Slot, value: &Arc<u64>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("decided {} {}", slot, value));
+        }
+    }
+
+    #[test]
+    fn event_observer() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let audit = Audit::default();
+        let mut nodes: Vec<Node<u64>> = (1..=3).map(|id| Node::new(id, config.clone())).collect();
+        nodes[0].set_observer(audit.clone());
+
+        nodes[0].propose(10);
+        let mut sent = nodes[0].take_effects();
+        while !sent.is_empty() {
+            for effect in core::mem::take(&mut sent) {
+                if let Effect::SendMessage(msg) = effect {
+                    for n in nodes.iter_mut() {
+                        sent.extend(n.step(msg.clone()));
+                    }
+                }
+            }
+        }
+
+        // The `Proposer` and the `Learner` both see the decision.
+        assert_eq!(
+            *audit.0.lock().unwrap(),
+            vec![
+                "quorum 0 1",
+                "accepted 0 1 10",
+                "decided 0 10",
+                "decided 0 10"
+            ]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Fencing
+//!
+//! A leader can be deposed without knowing it, e.g. while paused or cut off,
+//! and keep acting on the state it last saw. Systems outside the cluster,
+//! such as storage or a lock service, guard against it the way Chubby's
+//! sequencers do: every write carries a `FencingToken`, and they refuse any
+//! write whose token is lower than the highest they've seen.
+//!
+//! A token is the proposal number of a leader's round, which only grows from
+//! one leader to the next. `Node::fencing_token` is the token of the node's
+//! own round, to attach to writes it makes as leader, and every value handed
+//! back by `Node::poll_decided_entries` carries the token it was decided
+//! under.
+
+use crate::learner::Learner;
+use crate::message::{Messenger, Slot};
+use crate::node::Node;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Orders the writes of successive leaders. See the module documentation.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub struct FencingToken(pub u64);
+
+impl FencingToken {
+    /// The proposal number the token stands for.
+    pub fn value(self) -> u64 {
+        self.0
+    }
+}
+
+/// A decided value, along with the token of the round it was decided in.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DecidedEntry<T> {
+    /// The slot decided
+    pub slot: Slot,
+    /// The value decided
+    pub value: Arc<T>,
+    /// The token of the round the value was decided in. `None` when the
+    /// value was learned from another node's state or a snapshot, rather
+    /// than through votes.
+    pub token: Option<FencingToken>,
+}
+
+impl<T: PartialEq, M: Messenger<T>> Learner<T, M> {
+    /// The token of the round the value of `slot` was decided in, if known.
+    pub fn fencing_token(&self, slot: Slot) -> Option<FencingToken> {
+        self.ballots.get(&slot).copied().map(FencingToken)
+    }
+}
+
+impl<T: PartialEq + Clone, M: Messenger<T>> Node<T, M> {
+    /// The token of the node's current round, to attach to the writes it
+    /// makes while leading. Once another node takes over, its token is
+    /// higher.
+    pub fn fencing_token(&self) -> FencingToken {
+        FencingToken(self.proposer.current_ballot())
+    }
+
+    /// Like `poll_decided`, but with the token each value was decided under.
+    /// The two share their position in the log.
+    pub fn poll_decided_entries(&mut self) -> Vec<DecidedEntry<T>> {
+        self.poll_decided()
+            .into_iter()
+            .map(|(slot, value)| DecidedEntry {
+                slot,
+                value,
+                token: self.learner.fencing_token(slot),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::effect::Effect;
+    use alloc::vec;
+
+    /// Delivers messages between the nodes of `cluster` until none are left.
+    fn run(cluster: &mut [Node<u64>]) {
+        loop {
+            let mut effects: Vec<_> = cluster.iter_mut().flat_map(|n| n.take_effects()).collect();
+            if effects.is_empty() {
+                return;
+            }
+            while !effects.is_empty() {
+                let mut next = Vec::new();
+                for effect in effects {
+                    if let Effect::SendMessage(msg) = effect {
+                        for node in cluster.iter_mut() {
+                            next.extend(node.step(msg.clone()));
+                        }
+                    }
+                }
+                effects = next;
+            }
+        }
+    }
+
+    #[test]
+    fn tokens_grow_with_leaders() {
+        let mut cluster: Vec<Node<u64>> = (1..=3)
+            .map(|id| Node::new(id, ClusterConfig::new(vec![1, 2, 3])))
+            .collect();
+
+        cluster[0].propose(10);
+        run(&mut cluster);
+        let first = cluster[0].fencing_token();
+        // Node 2 takes over, finding slot 0 decided already.
+        cluster[1].propose(20);
+        run(&mut cluster);
+        cluster[1].propose(20);
+        run(&mut cluster);
+        let second = cluster[1].fencing_token();
+
+        assert!(second > first);
+        assert_eq!(
+            cluster[2].poll_decided_entries(),
+            vec![
+                DecidedEntry {
+                    slot: 0,
+                    value: Arc::new(10),
+                    token: Some(first),
+                },
+                DecidedEntry {
+                    slot: 1,
+                    value: Arc::new(20),
+                    token: Some(second),
+                },
+            ]
+        );
+        // The deposed leader's token stays behind its successor's.
+        assert!(cluster[0].fencing_token() < second);
+        assert!(cluster[2].poll_decided().is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+//! Groups
+//!
+//! A single Paxos log serializes every value it decides, which caps the
+//! throughput of the data it protects. Sharded systems instead run many
+//! independent logs, each deciding the values of its own shard. A
+//! `MultiGroup` hosts a `Node` for every group this process is a member of,
+//! identified by a `GroupId`, so that a single thread and transport drive them
+//! all. Each group has a membership of its own, and groups never share
+//! messages: every effect a `MultiGroup` hands back names the group it comes
+//! from, and every message handed in names the group it's for.
+
+use crate::config::{ClusterConfig, NodeId};
+use crate::effect::Effect;
+use crate::message::{Message, Slot};
+use crate::node::Node;
+use crate::proposal::ProposalHandle;
+use crate::router::Envelope;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Identifies a Paxos group.
+pub type GroupId = u64;
+
+/// The `Node`s of the groups a process is a member of. See the module
+/// documentation.
+pub struct MultiGroup<T> {
+    /// ID of the process, shared by its `Node` in every group
+    id: NodeId,
+    groups: BTreeMap<GroupId, Node<T>>,
+}
+
+impl<T: PartialEq + Clone> MultiGroup<T> {
+    /// Creates a `MultiGroup` with no groups, for the process `id`.
+    pub fn new(id: NodeId) -> Self {
+        Self {
+            id,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// The ID of the process.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Starts taking part in `group`, among the members of `config`. A group
+    /// added again is reset.
+    pub fn add_group(&mut self, group: GroupId, config: ClusterConfig) -> &mut Node<T> {
+        self.groups.insert(group, Node::new(self.id, config));
+        self.groups.get_mut(&group).unwrap()
+    }
+
+    /// Stops taking part in `group`, returning its `Node`.
+    pub fn remove_group(&mut self, group: GroupId) -> Option<Node<T>> {
+        self.groups.remove(&group)
+    }
+
+    /// The `Node` of `group`.
+    pub fn group(&self, group: GroupId) -> Option<&Node<T>> {
+        self.groups.get(&group)
+    }
+
+    /// The `Node` of `group`, mutably.
+    pub fn group_mut(&mut self, group: GroupId) -> Option<&mut Node<T>> {
+        self.groups.get_mut(&group)
+    }
+
+    /// The groups the process is a member of, in order.
+    pub fn groups(&self) -> impl Iterator<Item = GroupId> + '_ {
+        self.groups.keys().copied()
+    }
+
+    /// Proposes `value` to `group`. Returns `None` if the process isn't a
+    /// member of `group`.
+    pub fn propose(&mut self, group: GroupId, value: T) -> Option<ProposalHandle> {
+        self.groups.get_mut(&group).map(|node| node.propose(value))
+    }
+
+    /// Handles `msg`, sent to `group`, and returns the resulting effects.
+    /// Messages for groups the process isn't a member of are dropped, as are
+    /// those the `Validator` of the group's `Node` refuses. See
+    /// `Node::set_validator`.
+    pub fn step(&mut self, group: GroupId, msg: Message<T>) -> Vec<(GroupId, Effect<T>)> {
+        let Some(node) = self.groups.get_mut(&group) else {
+            return Vec::new();
+        };
+        let envelope = Envelope { group, msg };
+        if node.acceptor_mut().check_envelope(&envelope).is_err() {
+            return Vec::new();
+        }
+        let effects = node.step(envelope.msg);
+        effects.into_iter().map(|e| (group, e)).collect()
+    }
+
+    /// Advances the clock of every group to `now`, in milliseconds.
+    pub fn tick(&mut self, now: u64) {
+        for node in self.groups.values_mut() {
+            node.tick(now);
+        }
+    }
+
+    /// Takes the effects produced by every group since the last call.
+    pub fn take_effects(&mut self) -> Vec<(GroupId, Effect<T>)> {
+        let mut effects = Vec::new();
+        for (group, node) in &mut self.groups {
+            effects.extend(node.take_effects().into_iter().map(|e| (*group, e)));
+        }
+        effects
+    }
+
+    /// Returns the values decided by every group since the last call, in
+    /// log order within each group. See `Node::poll_decided`.
+    pub fn poll_decided(&mut self) -> Vec<(GroupId, Slot, Arc<T>)> {
+        let mut decided = Vec::new();
+        for (group, node) in &mut self.groups {
+            for (slot, value) in node.poll_decided() {
+                decided.push((*group, slot, value));
+            }
+        }
+        decided
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Delivers effects between the `MultiGroup`s of a cluster until none
+    /// are left.
+    fn run(cluster: &mut [MultiGroup<u64>], mut effects: Vec<(GroupId, Effect<u64>)>) {
+        while !effects.is_empty() {
+            let mut next = Vec::new();
+            for (group, effect) in effects {
+                for process in cluster.iter_mut() {
+                    if let Some(msg) = effect.message_for(process.id()) {
+                        next.extend(process.step(group, msg.clone()));
+                    }
+                }
+            }
+            effects = next;
+        }
+    }
+
+    #[test]
+    fn groups_decide_independently() {
+        let mut cluster: Vec<MultiGroup<u64>> = (1..=3).map(MultiGroup::new).collect();
+        for process in &mut cluster {
+            process.add_group(1, ClusterConfig::new(vec![1, 2, 3]));
+            // Process 3 isn't a member of group 2.
+            if process.id() != 3 {
+                process.add_group(2, ClusterConfig::new(vec![1, 2]));
+            }
+        }
+
+        cluster[0].propose(1, 10).unwrap();
+        cluster[1].propose(2, 20).unwrap();
+        assert!(cluster[2].propose(2, 30).is_none());
+        let mut effects = cluster[0].take_effects();
+        effects.extend(cluster[1].take_effects());
+        run(&mut cluster, effects);
+
+        assert_eq!(
+            cluster[0].poll_decided(),
+            vec![(1, 0, Arc::new(10)), (2, 0, Arc::new(20))]
+        );
+        assert_eq!(cluster[2].poll_decided(), vec![(1, 0, Arc::new(10))]);
+    }
+}
@@ -0,0 +1,211 @@
+//! Invariants
+//!
+//! Checkers for the safety invariants of Paxos, meant for tests: drive roles
+//! through any schedule of messages, then check that the invariants still
+//! hold across them.
+
+use crate::acceptor::Acceptor;
+use crate::config::NodeId;
+use crate::learner::Learner;
+use crate::message::Slot;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use thiserror::Error;
+
+/// A violated invariant.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum InvariantError {
+    /// `Learner`s decided different values for the slot
+    #[error("Learners decided different values for slot {0}")]
+    Agreement(Slot),
+    /// A value never proposed was decided for the slot
+    #[error("an unproposed value was decided for slot {0}")]
+    Validity(Slot),
+    /// `Acceptor`s accepted different values for the slot under a single
+    /// proposal number
+    #[error("proposal {n} carried different values for slot {slot}")]
+    SingleValue { slot: Slot, n: u64 },
+    /// The `Acceptor` accepted a proposal numbered above its promise
+    #[error("Acceptor {0} accepted a proposal above its promise")]
+    Promise(NodeId),
+    /// A value other than the one chosen for the slot was accepted under a
+    /// higher proposal number
+    #[error("a value other than the one chosen was accepted for slot {0}")]
+    Chosen(Slot),
+}
+
+/// Checks that no two `Learner`s decided different values for a slot.
+pub fn check_agreement<'a, T, M>(
+    learners: impl IntoIterator<Item = &'a Learner<T, M>>,
+) -> Result<(), InvariantError>
+where
+    T: PartialEq + 'a,
+    M: 'a,
+{
+    let mut decided: BTreeMap<Slot, &Arc<T>> = BTreeMap::new();
+    for learner in learners {
+        for (&slot, value) in &learner.decided {
+            if **decided.entry(slot).or_insert(value) != *value {
+                return Err(InvariantError::Agreement(slot));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `Learner`s only decided values among `proposed`.
+pub fn check_validity<'a, T, M>(
+    learners: impl IntoIterator<Item = &'a Learner<T, M>>,
+    proposed: &[T],
+) -> Result<(), InvariantError>
+where
+    T: PartialEq + 'a,
+    M: 'a,
+{
+    for learner in learners {
+        for (&slot, value) in &learner.decided {
+            if !proposed.iter().any(|v| *v == **value) {
+                return Err(InvariantError::Validity(slot));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the invariants `Acceptor`s maintain for each slot, each of which
+/// is a single-decree instance:
+///
+/// - A proposal number carries a single value
+/// - No `Acceptor` accepted a proposal numbered above its promise
+/// - Once a Phase-2 quorum accepted a value, every proposal numbered higher
+///   carries that value
+///
+/// Quorums are those of the first `Acceptor`'s configuration. `Acceptor`s
+/// may accept different values under the number of a fast round, so the
+/// first invariant doesn't hold for clusters using them.
+pub fn check_single_decree<'a, T, M>(
+    acceptors: impl IntoIterator<Item = &'a Acceptor<T, M>>,
+) -> Result<(), InvariantError>
+where
+    T: PartialEq + 'a,
+    M: 'a,
+{
+    let acceptors: Vec<&Acceptor<T, M>> = acceptors.into_iter().collect();
+    let config = match acceptors.first() {
+        Some(acceptor) => &acceptor.config,
+        None => return Ok(()),
+    };
+
+    // Voters of each proposal, by (slot, proposal number)
+    let mut votes = BTreeMap::new();
+    for acceptor in &acceptors {
+        for (&slot, accepted) in &acceptor.accepted {
+            if accepted.n > acceptor.promised_n {
+                return Err(InvariantError::Promise(acceptor.id));
+            }
+            let (value, voters) = votes
+                .entry((slot, accepted.n))
+                .or_insert((&accepted.value, Vec::new()));
+            if **value != accepted.value {
+                return Err(InvariantError::SingleValue {
+                    slot,
+                    n: accepted.n,
+                });
+            }
+            voters.push(acceptor.id);
+        }
+    }
+
+    for (&(slot, n), (chosen, voters)) in &votes {
+        if !config.is_phase2_quorum(voters) {
+            continue;
+        }
+        let later = votes.range((slot, n + 1)..(slot + 1, 0));
+        if later.into_iter().any(|(_, (value, _))| value != chosen) {
+            return Err(InvariantError::Chosen(slot));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceptor::AcceptedProposal;
+    use crate::config::ClusterConfig;
+    use alloc::vec;
+
+    fn acceptor(id: NodeId, promised_n: u64, accepted: &[(Slot, u64, u64)]) -> Acceptor<u64> {
+        let mut acceptor = Acceptor::new(id, ClusterConfig::new(vec![1, 2, 3]));
+        acceptor.promised_n = promised_n;
+        for &(slot, n, value) in accepted {
+            let value = Arc::new(value);
+            acceptor
+                .accepted
+                .insert(slot, AcceptedProposal { n, value });
+        }
+        acceptor
+    }
+
+    #[test]
+    fn invariants_agreement() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut learners: Vec<Learner<u64>> =
+            (1..=3).map(|id| Learner::new(id, config.clone())).collect();
+        learners[0].decided.insert(0, Arc::new(10));
+        learners[1].decided.insert(0, Arc::new(10));
+        learners[1].decided.insert(1, Arc::new(20));
+
+        assert_eq!(check_agreement(&learners), Ok(()));
+        assert_eq!(check_validity(&learners, &[10, 20]), Ok(()));
+        assert_eq!(
+            check_validity(&learners, &[10]),
+            Err(InvariantError::Validity(1))
+        );
+
+        learners[2].decided.insert(1, Arc::new(30));
+
+        assert_eq!(
+            check_agreement(&learners),
+            Err(InvariantError::Agreement(1))
+        );
+    }
+
+    #[test]
+    fn invariants_single_decree() {
+        let acceptors = [
+            acceptor(1, 3, &[(0, 1, 10), (1, 3, 30)]),
+            acceptor(2, 3, &[(0, 1, 10)]),
+            acceptor(3, 3, &[(0, 3, 10), (1, 2, 20)]),
+        ];
+
+        assert_eq!(check_single_decree(&acceptors), Ok(()));
+
+        let acceptors = [acceptor(1, 1, &[(0, 1, 10)]), acceptor(2, 1, &[(0, 1, 20)])];
+
+        assert_eq!(
+            check_single_decree(&acceptors),
+            Err(InvariantError::SingleValue { slot: 0, n: 1 })
+        );
+
+        let acceptors = [acceptor(1, 1, &[(0, 2, 10)])];
+
+        assert_eq!(
+            check_single_decree(&acceptors),
+            Err(InvariantError::Promise(1))
+        );
+
+        let acceptors = [
+            acceptor(1, 1, &[(0, 1, 10)]),
+            acceptor(2, 1, &[(0, 1, 10)]),
+            acceptor(3, 2, &[(0, 2, 20)]),
+        ];
+
+        assert_eq!(
+            check_single_decree(&acceptors),
+            Err(InvariantError::Chosen(0))
+        );
+    }
+}
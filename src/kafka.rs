@@ -0,0 +1,217 @@
+//! Kafka bridge
+//!
+//! Publishes the decided log to a [Kafka](https://kafka.apache.org) topic,
+//! through [rdkafka](https://docs.rs/rdkafka), so that downstream consumers
+//! can tail it without becoming `Learner`s themselves. The value decided for
+//! each slot becomes a record keyed by the slot, in big-endian bytes, on a
+//! single partition so that records follow slot order.
+//!
+//! Keys are published exactly once: the producer is idempotent, so that its
+//! retries are never duplicated, and `KafkaSink::watch` resumes from the slot
+//! after the partition's last record, so that a restarted node neither
+//! publishes a slot again nor skips one. Should a record fail to be
+//! delivered, the sink stops, to be watched from again once Kafka is back.
+//! A single sink should publish to a partition at a time, e.g. the leader's.
+
+use crate::learner::Learner;
+use crate::message::{Messenger, Slot};
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
+use rdkafka::message::Message as _;
+use rdkafka::producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer};
+use rdkafka::{ClientContext, Offset, TopicPartitionList};
+
+/// Notes records that failed to be delivered.
+#[derive(Default)]
+struct SinkContext {
+    failed: AtomicBool,
+}
+
+impl ClientContext for SinkContext {}
+
+impl ProducerContext for SinkContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if result.is_err() {
+            self.failed.store(true, Ordering::Release);
+        }
+    }
+}
+
+/// Publishes decided values to a partition of a Kafka topic. See the module
+/// documentation.
+pub struct KafkaSink {
+    /// Client settings, for reading the topic
+    config: ClientConfig,
+    producer: ThreadedProducer<SinkContext>,
+    topic: String,
+    partition: i32,
+}
+
+impl KafkaSink {
+    /// Creates a sink publishing to partition 0 of `topic` through the Kafka
+    /// cluster at `brokers`, e.g. `"10.0.0.1:9092,10.0.0.2:9092"`.
+    pub fn new(brokers: &str, topic: &str) -> KafkaResult<Self> {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", brokers);
+        Self::with_config(config, topic, 0)
+    }
+
+    /// Creates a sink publishing to `partition` of `topic`, with the client
+    /// settings of `config`. Idempotence is turned on regardless.
+    pub fn with_config(config: ClientConfig, topic: &str, partition: i32) -> KafkaResult<Self> {
+        let producer = config
+            .clone()
+            .set("enable.idempotence", "true")
+            .create_with_context(SinkContext::default())?;
+        Ok(Self {
+            config,
+            producer,
+            topic: topic.into(),
+            partition,
+        })
+    }
+
+    /// The slot after the last one published to the partition, waiting up to
+    /// `timeout` for Kafka to answer.
+    pub fn next_slot(&self, timeout: Duration) -> KafkaResult<Slot> {
+        // Offsets are given, never committed, but assigning them takes a
+        // group all the same.
+        let consumer: BaseConsumer = self
+            .config
+            .clone()
+            .set("group.id", "paxos-kafka-sink")
+            .set("enable.auto.commit", "false")
+            .create()?;
+        let (low, high) = consumer.fetch_watermarks(&self.topic, self.partition, timeout)?;
+        if high <= low {
+            return Ok(0);
+        }
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition_offset(&self.topic, self.partition, Offset::Offset(high - 1))?;
+        consumer.assign(&assignment)?;
+        let record = consumer
+            .poll(timeout)
+            .ok_or(KafkaError::MessageConsumption(
+                RDKafkaErrorCode::OperationTimedOut,
+            ))??;
+        let key = record.key().and_then(|key| key.try_into().ok()).ok_or(
+            KafkaError::MessageConsumption(RDKafkaErrorCode::InvalidRecord),
+        )?;
+        Ok(Slot::from_be_bytes(key) + 1)
+    }
+
+    /// Publishes `value` as decided for `slot`, waiting for room in the
+    /// producer's queue if it's full.
+    pub fn publish(&self, slot: Slot, value: &[u8]) -> KafkaResult<()> {
+        let key = slot.to_be_bytes();
+        let mut record = BaseRecord::to(&self.topic)
+            .partition(self.partition)
+            .key(&key)
+            .payload(value);
+        loop {
+            match self.producer.send(record) {
+                Ok(()) => return Ok(()),
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), r)) => {
+                    record = r;
+                    self.producer.poll(Duration::from_millis(100));
+                }
+                Err((err, _)) => return Err(err),
+            }
+        }
+    }
+
+    /// Whether a record failed to be delivered, stopping the sink.
+    pub fn failed(&self) -> bool {
+        self.producer.context().failed.load(Ordering::Acquire)
+    }
+
+    /// Waits up to `timeout` for every record published to be delivered.
+    pub fn flush(&self, timeout: Duration) -> KafkaResult<()> {
+        self.producer.flush(timeout)
+    }
+
+    /// Publishes every value `learner` decides, in slot order, from the slot
+    /// after the partition's last record on, waiting up to `timeout` to
+    /// find it. The sink stops once a record fails to be published.
+    pub fn watch<T, M>(
+        self: Arc<Self>,
+        learner: &mut Learner<T, M>,
+        timeout: Duration,
+    ) -> KafkaResult<()>
+    where
+        T: AsRef<[u8]> + PartialEq,
+        M: Messenger<T>,
+    {
+        let next = self.next_slot(timeout)?;
+        learner.watch_from(next, move |slot, value| {
+            !self.failed() && self.publish(slot, (*value).as_ref()).is_ok()
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::learner::tests::decide;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use rdkafka::mocking::MockCluster;
+
+    #[test]
+    fn kafka_sink_resumes_after_last_slot() {
+        let timeout = Duration::from_secs(10);
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("log", 1, 1).unwrap();
+        let brokers = cluster.bootstrap_servers();
+
+        let mut l: Learner<Vec<u8>> = Learner::new(4, ClusterConfig::new(vec![1, 2, 3]));
+        decide(&mut l, 0, b"a".to_vec());
+        let sink = Arc::new(KafkaSink::new(&brokers, "log").unwrap());
+        sink.clone().watch(&mut l, timeout).unwrap();
+        decide(&mut l, 1, b"b".to_vec());
+        sink.flush(timeout).unwrap();
+
+        // A restarted node's sink picks up where the topic ends, although
+        // its `Learner` decided every slot again.
+        let mut l: Learner<Vec<u8>> = Learner::new(4, ClusterConfig::new(vec![1, 2, 3]));
+        decide(&mut l, 0, b"a".to_vec());
+        decide(&mut l, 1, b"b".to_vec());
+        let sink = Arc::new(KafkaSink::new(&brokers, "log").unwrap());
+        assert_eq!(sink.next_slot(timeout).unwrap(), 2);
+        sink.clone().watch(&mut l, timeout).unwrap();
+        decide(&mut l, 2, b"c".to_vec());
+        sink.flush(timeout).unwrap();
+        assert!(!sink.failed());
+
+        let mut config = ClientConfig::new();
+        config
+            .set("bootstrap.servers", &brokers)
+            .set("group.id", "tail");
+        let consumer: BaseConsumer = config.create().unwrap();
+        let mut assignment = TopicPartitionList::new();
+        assignment
+            .add_partition_offset("log", 0, Offset::Beginning)
+            .unwrap();
+        consumer.assign(&assignment).unwrap();
+        let records: Vec<(Slot, Vec<u8>)> = (0..3)
+            .map(|_| {
+                let record = consumer.poll(timeout).unwrap().unwrap();
+                let key = record.key().unwrap().try_into().unwrap();
+                (Slot::from_be_bytes(key), record.payload().unwrap().to_vec())
+            })
+            .collect();
+        assert_eq!(
+            records,
+            vec![(0, b"a".to_vec()), (1, b"b".to_vec()), (2, b"c".to_vec())]
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Latency
+//!
+//! Across sites, the round trip to one `Acceptor` may be ten times that to
+//! another, and a value is only decided as fast as the slowest of the quorum
+//! that accepts it. `PeerLatencies` keeps a smoothed round-trip time for
+//! every peer, as measured by the transport (see `TcpTransport::probe`), and
+//! picks the quorums answering soonest. Handed to a `Proposer`, it lets the
+//! leader send its `Accept`s to `Proposer::nearby_quorum` first, falling
+//! back to every member should they not answer in time. Any quorum system
+//! works, but only flexible ones (e.g. small Phase-2 quorums, or grids) leave
+//! much choice: the `Accept`s go to every member unless the nearby quorum
+//! leaves some out, and the round trips to all of its members were measured.
+
+use crate::config::{ClusterConfig, NodeId};
+use crate::message::{Messenger, Slot};
+use crate::proposer::Proposer;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Smoothed round-trip times to each peer. See the module documentation.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct PeerLatencies {
+    /// Peer => smoothed round-trip time, in microseconds
+    rtts: BTreeMap<NodeId, u64>,
+}
+
+impl PeerLatencies {
+    /// Creates `PeerLatencies` with no samples.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a round trip to `peer` taking `rtt` microseconds, smoothed
+    /// with the previous ones as TCP does (RFC 6298), so that a single slow
+    /// sample doesn't reorder peers.
+    pub fn observe(&mut self, peer: NodeId, rtt: u64) {
+        let srtt = self.rtts.entry(peer).or_insert(rtt);
+        *srtt = (*srtt * 7 + rtt) / 8;
+    }
+
+    /// The smoothed round-trip time to `peer`, in microseconds, if measured.
+    pub fn rtt(&self, peer: NodeId) -> Option<u64> {
+        self.rtts.get(&peer).copied()
+    }
+
+    /// Forgets the samples of `peer`, e.g. once it left the cluster.
+    pub fn forget(&mut self, peer: NodeId) {
+        self.rtts.remove(&peer);
+    }
+
+    /// Orders `peers` by round-trip time, nearest first. Peers never measured
+    /// come last, in their given order.
+    pub fn order(&self, peers: &[NodeId]) -> Vec<NodeId> {
+        let mut ordered = peers.to_vec();
+        ordered.sort_by_key(|peer| self.rtt(*peer).unwrap_or(u64::MAX));
+        ordered
+    }
+
+    /// The nearest members of `config` making up a Phase-2 quorum: the
+    /// shortest prefix of the members ordered by round-trip time which
+    /// decides a value. `None` if no set of members does.
+    pub fn nearest_quorum(&self, config: &ClusterConfig) -> Option<Vec<NodeId>> {
+        let ordered = self.order(&voters(config));
+        (1..=ordered.len())
+            .map(|n| &ordered[..n])
+            .find(|quorum| config.is_phase2_quorum(quorum))
+            .map(<[NodeId]>::to_vec)
+    }
+}
+
+impl<T: PartialEq + Clone, M: Messenger<T>> Proposer<T, M> {
+    /// Records a round trip to `peer` taking `rtt` microseconds. See
+    /// `PeerLatencies::observe`.
+    pub fn observe_rtt(&mut self, peer: NodeId, rtt: u64) {
+        self.latencies.observe(peer, rtt);
+    }
+
+    /// Replaces the round-trip times known to the `Proposer`, e.g. with those
+    /// measured by its transport.
+    pub fn set_latencies(&mut self, latencies: PeerLatencies) {
+        self.latencies = latencies;
+    }
+
+    /// The round-trip times known to the `Proposer`.
+    pub fn latencies(&self) -> &PeerLatencies {
+        &self.latencies
+    }
+
+    /// The members to send `Accept`s to first, those making up the nearest
+    /// Phase-2 quorum of the current configuration.
+    pub fn nearby_quorum(&self) -> Option<Vec<NodeId>> {
+        self.latencies.nearest_quorum(&self.config)
+    }
+
+    /// The nodes to send the `Accept` for `slot` to, if not every node: the
+    /// nearest Phase-2 quorum of its configuration, along with the shadows,
+    /// unless the `Accept` is retransmitted. See the module documentation.
+    pub(crate) fn accept_targets(&self, slot: Slot) -> Option<Vec<NodeId>> {
+        if self.in_flight.get(&slot)?.retransmits > 0 {
+            return None;
+        }
+        let config = self.config_at(slot);
+        let mut quorum = self.latencies.nearest_quorum(config)?;
+        let measured = quorum.iter().all(|id| self.latencies.rtt(*id).is_some());
+        if !measured || quorum.len() == voters(config).len() {
+            return None;
+        }
+        quorum.extend(&config.shadows);
+        Some(quorum)
+    }
+}
+
+/// The members of `config`, along with those of the configuration it's
+/// joint with.
+fn voters(config: &ClusterConfig) -> Vec<NodeId> {
+    let mut voters = config.members.clone();
+    if let Some(joint) = &config.joint {
+        voters.extend(joint.iter().filter(|id| !config.members.contains(id)));
+    }
+    voters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceptor::Acceptor;
+    use crate::config::QuorumConfig;
+    use crate::effect::Effect;
+    use crate::message::Message;
+    use alloc::vec;
+
+    #[test]
+    fn nearest_quorum_follows_rtts() {
+        let mut latencies = PeerLatencies::new();
+        for (peer, rtt) in [(1, 80_000), (2, 500), (3, 90_000), (4, 700)] {
+            latencies.observe(peer, rtt);
+        }
+        // A slow sample is smoothed over.
+        latencies.observe(2, 20_000);
+        assert_eq!(latencies.rtt(2), Some(2_937));
+
+        let config = ClusterConfig::new(vec![1, 2, 3, 4, 5]);
+        assert_eq!(latencies.order(&[5, 1, 2]), vec![2, 1, 5]);
+        assert_eq!(latencies.nearest_quorum(&config), Some(vec![4, 2, 1]));
+
+        // Flexible quorums leave the nearest two to decide.
+        let config = config
+            .with_quorums(QuorumConfig {
+                phase1: 4,
+                phase2: 2,
+            })
+            .unwrap();
+        assert_eq!(latencies.nearest_quorum(&config), Some(vec![4, 2]));
+    }
+
+    #[test]
+    fn accepts_go_to_nearest_quorum() {
+        let config = ClusterConfig::new(vec![1, 2, 3, 4, 5])
+            .with_quorums(QuorumConfig {
+                phase1: 4,
+                phase2: 2,
+            })
+            .unwrap();
+        let mut acceptors: Vec<Acceptor<u64>> = (1..=5)
+            .map(|id| Acceptor::new(id, config.clone()))
+            .collect();
+        let mut p: Proposer<u64> = Proposer::new(1, config);
+        p.set_timeout(Some(100), 2);
+        for (peer, rtt) in [(1, 80_000), (2, 500), (3, 90_000), (4, 700)] {
+            p.observe_rtt(peer, rtt);
+        }
+
+        p.prepare(10);
+        let prepare = match p.take_effects().remove(0) {
+            Effect::SendMessage(msg) => msg,
+            effect => panic!("unexpected {:?}", effect),
+        };
+        let mut sent = Vec::new();
+        for a in &mut acceptors {
+            sent.extend(a.step(prepare.clone()));
+        }
+        let mut replies = Vec::new();
+        for effect in sent {
+            if let Effect::SendMessage(msg @ Message::Promise(_)) = effect {
+                replies.extend(p.step(msg));
+            }
+        }
+        let accept = |effect: &Effect<u64>| match effect {
+            Effect::SendMessage(msg @ Message::Accept(_)) => Some((None, msg.clone())),
+            Effect::SendMessageTo(to, msg @ Message::Accept(_)) => {
+                Some((Some(to.clone()), msg.clone()))
+            }
+            _ => None,
+        };
+
+        // The nearest two of the five members make up a Phase-2 quorum.
+        let (to, msg) = replies.iter().find_map(accept).unwrap();
+        assert_eq!(to, Some(vec![2, 4]));
+
+        // They don't answer in time: every member is sent the `Accept`.
+        p.tick(100);
+        let sent = p.take_effects();
+        assert_eq!(sent.iter().find_map(accept), Some((None, msg)));
+    }
+}
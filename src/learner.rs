@@ -1,145 +1,1365 @@
 //! Learner
 
-use message::AcceptedData;
-use message::Message;
-use message::Messenger;
-use std::collections::hash_map::HashMap;
-use std::collections::hash_set::HashSet;
-use std::hash::Hash;
-use std::sync::Arc;
+use crate::config::{ClusterConfig, NodeId};
+use crate::effect::Effect;
+#[cfg(feature = "paranoid-checks")]
+use crate::error::InvariantViolation;
+use crate::error::LearnerError;
+use crate::event::{EventSink, Observer, ObserverSink, PaxosEvent};
+use crate::membership::config_at;
+use crate::message::AcceptedData;
+use crate::message::Handler;
+use crate::message::Message;
+use crate::message::Messenger;
+use crate::message::SkipData;
+use crate::message::Slot;
+use crate::message::{BoxedMessenger, JoinData, LearnData, SnapshotData, StateData};
+use crate::metrics::{Metrics, MetricsSink, RecordedLatencies};
+use crate::proposer::ballot_owner;
+use crate::quorum::voters;
+use crate::vertical::epoch_of;
+use crate::watch::Watcher;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 /// Learners act as the replication factor for the protocol. Once a Client
 /// request has been agreed on by the Acceptors, the Learner may take action
 /// (i.e.: execute the request and send a response to the client). To improve
 /// availability of processing, additional Learners can be added.
-pub struct Learner<T> {
-    /// `Proposer`'s ID
-    pub id: u64,
+pub struct Learner<T, M = BoxedMessenger<T>> {
+    /// `Learner`'s ID
+    pub(crate) id: NodeId,
     /// `Messenger` specifying communication with other nodes
-    pub messenger: Option<Box<Messenger<T>>>,
+    pub(crate) messenger: Option<M>,
+    /// Effects waiting to be taken, while no `Messenger` is set
+    pub(crate) effects: Vec<Effect<T>>,
+    /// Callback notified of every decided slot
+    pub(crate) events: Option<EventSink>,
+    /// `Metrics` the `Learner` reports to
+    pub(crate) metrics: Option<MetricsSink>,
+    /// `Observer` of the `Learner`'s transitions
+    pub(crate) observer: Option<ObserverSink<T>>,
+    /// Records the latency of each decision until it's applied, along with
+    /// how a value's batch size is measured, if recording
+    pub(crate) latency_recorder: Option<RecordedLatencies<T>>,
     /// The last proposal that was accepted
-    pub last_accepted_n: u64,
-    /// Accepted messages received (proposal_n => data)
-    pub accepted_received: HashMap<u64, HashSet<AcceptedData<T>>>,
+    pub(crate) last_accepted_n: u64,
+    /// Accepted messages received ((slot, proposal_n) => from => data)
+    pub(crate) accepted_received: BTreeMap<(Slot, u64), BTreeMap<NodeId, AcceptedData<T>>>,
+    /// Votes of shadow `Acceptor`s for undecided slots ((slot, proposal_n) =>
+    /// from => value)
+    pub(crate) shadow_votes: BTreeMap<(Slot, u64), BTreeMap<NodeId, Arc<T>>>,
     /// The last accepted value
-    pub value: Option<Arc<T>>,
-    /// Quorum size
-    pub quorum: u8,
+    pub(crate) value: Option<Arc<T>>,
+    /// Values decided so far (slot => value)
+    pub(crate) decided: BTreeMap<Slot, Arc<T>>,
+    /// Proposal numbers the kept values were decided under, if known
+    pub(crate) ballots: BTreeMap<Slot, u64>,
+    /// Number of ticks an undecided slot may go without any activity before it
+    /// is considered abandoned. Abandoned slots are never reported if `None`.
+    pub(crate) instance_ttl: Option<u64>,
+    /// Undecided slots seen so far (slot => ticks since last activity)
+    pub(crate) idle: BTreeMap<Slot, u64>,
+    /// The slot after the highest one seen so far
+    pub(crate) horizon: Slot,
+    /// Number of slots beyond `horizon` a message may concern, as every slot
+    /// skipped over is tracked. Unbounded if `None`
+    pub(crate) max_lead: Option<Slot>,
+    /// Number of slots below `horizon` whose votes are counted while they
+    /// are undecided. Votes for slots further behind are dropped. Unbounded
+    /// if `None`
+    pub(crate) vote_window: Option<Slot>,
+    /// The cluster values are learned from
+    pub(crate) config: ClusterConfig,
+    /// Configurations votes are counted against, by the epoch they were cast
+    /// in (Vertical Paxos). `config` is used if empty
+    pub(crate) epochs: BTreeMap<u64, ClusterConfig>,
+    /// Configurations decided through the log, by the slot they take effect
+    /// at. These take precedence over `epochs`
+    pub(crate) reconfigurations: BTreeMap<Slot, ClusterConfig>,
+    /// The latest snapshot, along with the slot after the last one it covers.
+    /// Decided values below that slot have been dropped
+    pub(crate) snapshot: Option<(Slot, Arc<T>)>,
+    /// The next slot to apply to the state machine
+    pub(crate) apply_index: Slot,
+    /// Whether `Learn` messages are ignored unless certified by a quorum
+    pub(crate) require_certificates: bool,
+    /// Reads awaiting a Phase-1 quorum of replies (id => from => horizon)
+    pub(crate) reads: BTreeMap<u64, BTreeMap<NodeId, Slot>>,
+    /// Reads a quorum answered, awaiting every slot below their index to be
+    /// decided (id => index)
+    pub(crate) ready_reads: BTreeMap<u64, Slot>,
+    /// The ID of the next read
+    pub(crate) next_read: u64,
+    /// When each read still in progress was started (id => time)
+    pub(crate) read_starts: BTreeMap<u64, u64>,
+    /// The `Learner`'s clock, in milliseconds
+    pub(crate) now: u64,
+    /// When the latest completed read was started: every value decided
+    /// before then is decided locally
+    pub(crate) synced_at: Option<u64>,
+    /// Callbacks watching the log, see `watch_from`
+    pub(crate) watchers: Vec<Watcher<T>>,
+    /// Messages dropped for conflicting with what was learned, waiting to be
+    /// taken
+    pub(crate) errors: Vec<LearnerError>,
+    /// Transitions refused for breaking an invariant, waiting to be taken
+    #[cfg(feature = "paranoid-checks")]
+    pub(crate) violations: Vec<InvariantViolation>,
 }
 
-impl<T> Learner<T>
-where
-    T: Hash + Eq,
-{
-    pub fn new(id: u64, quorum: u8) -> Self {
+impl<T: PartialEq> Learner<T> {
+    /// Creates a new `Learner`.
+    pub fn new(id: NodeId, config: ClusterConfig) -> Self {
+        Self::init(id, config)
+    }
+
+    /// Sets the `Messenger` the `Learner` sends through.
+    pub fn set_messenger<N>(&mut self, messenger: N)
+    where
+        N: Messenger<T> + Send + 'static,
+    {
+        self.messenger = Some(Box::new(messenger));
+    }
+}
+
+impl<T: PartialEq, M: Messenger<T>> Learner<T, M> {
+    /// Creates a new `Learner` sending through `messenger`, without boxing it.
+    pub fn with_messenger(id: NodeId, config: ClusterConfig, messenger: M) -> Self {
+        Self {
+            messenger: Some(messenger),
+            ..Self::init(id, config)
+        }
+    }
+
+    fn init(id: NodeId, config: ClusterConfig) -> Self {
         Self {
             id,
             messenger: None,
+            effects: Vec::new(),
+            events: None,
+            metrics: None,
+            observer: None,
+            latency_recorder: None,
             last_accepted_n: 0,
-            accepted_received: HashMap::new(),
+            accepted_received: BTreeMap::new(),
+            shadow_votes: BTreeMap::new(),
             value: None,
-            quorum,
+            decided: BTreeMap::new(),
+            ballots: BTreeMap::new(),
+            instance_ttl: None,
+            idle: BTreeMap::new(),
+            horizon: 0,
+            max_lead: None,
+            vote_window: None,
+            config,
+            epochs: BTreeMap::new(),
+            reconfigurations: BTreeMap::new(),
+            snapshot: None,
+            apply_index: 0,
+            require_certificates: false,
+            reads: BTreeMap::new(),
+            ready_reads: BTreeMap::new(),
+            next_read: 0,
+            read_starts: BTreeMap::new(),
+            now: 0,
+            synced_at: None,
+            watchers: Vec::new(),
+            errors: Vec::new(),
+            #[cfg(feature = "paranoid-checks")]
+            violations: Vec::new(),
         }
     }
 
-    /// Receives an `Accepted` message from an `Acceptor`.
+    /// The `Learner`'s ID.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// The cluster values are learned from.
+    pub fn config(&self) -> &ClusterConfig {
+        &self.config
+    }
+
+    /// The configuration in force at `slot`, once reconfigured through the
+    /// log.
+    pub fn config_at(&self, slot: Slot) -> &ClusterConfig {
+        config_at(&self.reconfigurations, &self.config, slot)
+    }
+
+    /// The proposal number of the last value decided.
+    pub fn last_accepted_n(&self) -> u64 {
+        self.last_accepted_n
+    }
+
+    /// The value decided for `slot`, if it is decided and wasn't compacted.
+    pub fn decided_value(&self, slot: Slot) -> Option<&Arc<T>> {
+        self.decided.get(&slot)
+    }
+
+    /// The values decided so far and not compacted, in slot order.
+    pub fn decided(&self) -> impl Iterator<Item = (Slot, &Arc<T>)> {
+        self.decided.iter().map(|(slot, value)| (*slot, value))
+    }
+
+    /// The slot after the highest one seen so far.
+    pub fn horizon(&self) -> Slot {
+        self.horizon
+    }
+
+    /// The latest snapshot, along with the slot after the last one it covers.
+    pub fn snapshot(&self) -> Option<(Slot, &Arc<T>)> {
+        self.snapshot.as_ref().map(|(index, value)| (*index, value))
+    }
+
+    /// Sets the callback notified of every decided slot.
+    pub fn set_events<F>(&mut self, events: F)
+    where
+        F: FnMut(PaxosEvent) + Send + 'static,
+    {
+        self.events = Some(Box::new(events));
+    }
+
+    /// Sets the `Metrics` the `Learner` reports to.
+    pub fn set_metrics<X>(&mut self, metrics: X)
+    where
+        X: Metrics + Send + 'static,
+    {
+        self.metrics = Some(Box::new(metrics));
+    }
+
+    /// Sets the `Observer` of the `Learner`'s transitions.
+    pub fn set_observer<O>(&mut self, observer: O)
+    where
+        O: Observer<T> + Send + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Sets the number of ticks an undecided slot may go without activity
+    /// before it is reported as abandoned, or never if `None`.
+    pub fn set_instance_ttl(&mut self, ticks: Option<u64>) {
+        self.instance_ttl = ticks;
+    }
+
+    /// Sets whether `Learn` messages not certified by a quorum are ignored.
+    pub fn set_require_certificates(&mut self, require: bool) {
+        self.require_certificates = require;
+    }
+
+    /// Sets the number of slots beyond the highest one seen that messages
+    /// may concern, or any if `None`. Messages for slots further ahead are
+    /// dropped, so that a faulty node can't have the `Learner` track an
+    /// unbounded number of gaps; they're caught up on once the log grows
+    /// towards them.
+    pub fn set_max_lead(&mut self, slots: Option<Slot>) {
+        self.max_lead = slots;
+    }
+
+    /// Sets the number of slots below the highest one seen whose votes are
+    /// counted while undecided, or all if `None`. Votes for slots falling
+    /// further behind are dropped, so that slots a leader gave up on don't
+    /// hold on to memory; they're still decided by `Learn` messages, state
+    /// transfer or `Proposer::finalize`, as reported by `abandoned`.
+    pub fn set_vote_window(&mut self, slots: Option<Slot>) {
+        self.vote_window = slots;
+        self.evict_votes();
+    }
+
+    /// Counts votes cast from `epoch` onwards against `config`. Votes of
+    /// earlier epochs are still counted against the configuration they were
+    /// cast in.
+    pub fn activate(&mut self, epoch: u64, config: ClusterConfig) {
+        if self.epochs.is_empty() {
+            self.epochs.insert(0, self.config.clone());
+        }
+        self.epochs.insert(epoch, config.clone());
+        self.config = config;
+    }
+
+    /// Counts votes for slots from `from` onwards against `config`, as decided
+    /// through the log. See `Membership`.
+    pub fn reconfigure(&mut self, from: Slot, config: ClusterConfig) {
+        self.reconfigurations.insert(from, config);
+    }
+
+    /// Receives an `Accepted` message from an `Acceptor`. Messages from nodes
+    /// outside of the cluster are ignored, and each `Acceptor` is counted once.
+    ///
+    /// Votes of shadow `Acceptor`s don't count either, but are checked against
+    /// the decided value; a `ShadowDiverged` event is emitted on a mismatch.
+    /// An `Accepted` conflicting with the value decided, or with the votes
+    /// counted for its proposal number, is dropped: only a faulty `Acceptor`
+    /// or misconfigured quorums could have sent it.
     pub fn receive_accepted(&mut self, msg: Message<T>) {
         if let Message::Accepted(data) = msg {
-            let id = data.id;
-            if id == self.last_accepted_n {
-                if let Some(ref val) = self.value {
-                    if *val != data.value {
-                        panic!("Value mismatch for proposal {}", id);
-                    }
+            span!(
+                "receive_accepted",
+                learner = self.id,
+                slot = data.slot,
+                ballot = data.id,
+                from = data.from
+            );
+            let config = config_for(
+                &self.epochs,
+                &self.reconfigurations,
+                &self.config,
+                data.slot,
+                data.id,
+            );
+            if config.is_shadow(data.from) {
+                if !self.is_decided(data.slot) && !self.left_behind(data.slot) {
+                    self.shadow_votes
+                        .entry((data.slot, data.id))
+                        .or_default()
+                        .insert(data.from, data.value);
                 }
+                return;
+            }
+            if !config.is_member(data.from) {
+                return;
+            }
+            let (slot, id, fast) = (data.slot, data.id, data.fast);
+            if slot < self.compacted() || self.out_of_reach(slot) {
+                return;
+            }
+            if let Some(value) = self.decided.get(&slot) {
+                if *value != data.value {
+                    self.reject(LearnerError::SlotMismatch(slot));
+                }
+                return;
+            }
+            if self.left_behind(slot) {
+                return;
             }
+            self.observe(slot);
 
-            self.accepted_received.entry(id).or_insert(HashSet::new());
+            let config = config_for(&self.epochs, &self.reconfigurations, &self.config, slot, id);
+            let votes = self.accepted_received.entry((slot, id)).or_default();
 
-            self.accepted_received.get_mut(&id).unwrap().insert(data);
+            // A single proposal number can only ever carry one value, except in
+            // fast rounds where values may collide.
+            if !fast && votes.values().any(|v| v.value != data.value) {
+                self.reject(LearnerError::ProposalMismatch(id));
+                return;
+            }
+
+            let value = data.value.clone();
+            votes.insert(data.from, data);
+
+            let decided = if fast {
+                let matching = votes.values().filter(|v| v.value == value).count();
+                matching >= config.fast_quorum()
+            } else {
+                config.is_phase2_quorum_of(&voters(votes))
+            };
 
-            if self.accepted_received.get(&id).unwrap().len() == self.quorum as usize {
-                self.value = Some(
-                    self.accepted_received
-                        .get(&id)
-                        .unwrap()
-                        .iter()
-                        .next()
-                        .unwrap()
-                        .value
-                        .clone(),
-                );
+            if decided {
+                let certificate = votes
+                    .iter()
+                    .filter(|(_, v)| v.value == value)
+                    .map(|(from, _)| *from)
+                    .collect();
                 self.last_accepted_n = id;
-                if let Some(ref mut messenger) = self.messenger {
-                    messenger.on_resolution(id, self.value.clone().unwrap());
+                self.check_shadows(slot, id, &value);
+                if self.relays() {
+                    self.relay(slot, id, value.clone(), certificate);
+                }
+                self.ballots.insert(slot, id);
+                self.decide(slot, value);
+            }
+        }
+    }
+
+    /// Whether the `Learner` is in relay mode, i.e. it is one of the
+    /// distinguished `Learner`s which pass decisions on to the others.
+    pub fn relays(&self) -> bool {
+        self.config.is_distinguished_learner(self.id)
+    }
+
+    /// Passes a decision on to the other `Learner`s, along with the
+    /// `Acceptor`s whose votes decided it.
+    fn relay(&mut self, slot: Slot, id: u64, value: Arc<T>, certificate: Vec<NodeId>) {
+        let learn = Message::Learn(LearnData {
+            slot,
+            id,
+            from: self.id,
+            value,
+            certificate,
+        });
+        self.effect(Effect::SendMessage(learn));
+    }
+
+    /// Receives a `Learn` message from a `Proposer` or a distinguished
+    /// `Learner`, deciding its value without waiting for the `Accepted`
+    /// messages of a quorum. A `Learn` is ignored unless sent by the member
+    /// owning its proposal number, or by a distinguished `Learner`. A
+    /// certified `Learn` is ignored unless its `Acceptor`s form a quorum, and
+    /// so is an uncertified one if `require_certificates` is set. A `Learn`
+    /// conflicting with the value decided is dropped.
+    ///
+    /// Certificates only guard against buggy peers: nothing stops a malicious
+    /// one from naming a quorum that never accepted the value.
+    pub fn receive_learn(&mut self, msg: Message<T>) {
+        if let Message::Learn(LearnData {
+            slot,
+            id,
+            from,
+            value,
+            certificate,
+        }) = msg
+        {
+            span!("receive_learn", learner = self.id, slot, ballot = id);
+            if slot < self.compacted() || self.out_of_reach(slot) {
+                return;
+            }
+            let config = config_for(&self.epochs, &self.reconfigurations, &self.config, slot, id);
+            let owner = config.is_member(from) && ballot_owner(id) == from;
+            if !owner && !config.is_distinguished_learner(from) {
+                return;
+            }
+            let certified = !certificate.is_empty() && config.is_phase2_quorum(&certificate);
+            if !certified && (self.require_certificates || !certificate.is_empty()) {
+                return;
+            }
+            if let Some(decided) = self.decided.get(&slot) {
+                if *decided != value {
+                    self.reject(LearnerError::SlotMismatch(slot));
+                }
+                return;
+            }
+            self.observe(slot);
+            self.last_accepted_n = id;
+            self.check_shadows(slot, id, &value);
+            self.ballots.insert(slot, id);
+            self.decide(slot, value);
+        }
+    }
+
+    /// Receives a `Skip` message from a leader, deciding the skipped slots it
+    /// owns with a no-op, as of the configuration in force at each slot.
+    /// Slots owned by other leaders are left alone.
+    pub fn receive_skip(&mut self, msg: Message<T>) {
+        if let Message::Skip(SkipData {
+            from,
+            start,
+            end,
+            value,
+        }) = msg
+        {
+            span!("receive_skip", learner = self.id, from, start, end);
+            if self.out_of_reach(end.saturating_sub(1)) {
+                return;
+            }
+            // Slots are owned as of the configuration in force at each of
+            // them, which may change within the range.
+            let mut first = start;
+            while first < end {
+                let until = match self.reconfigurations.range(first + 1..end).next() {
+                    Some((slot, _)) => *slot,
+                    None => end,
+                };
+                let config = config_at(&self.reconfigurations, &self.config, first);
+                let step = config.leaders.len() as u64;
+                let mut slot = config.next_owned(from, first).unwrap_or(until);
+                while slot < until {
+                    if !self.is_decided(slot) {
+                        self.observe(slot);
+                        self.decide(slot, value.clone());
+                    }
+                    slot += step;
+                }
+                first = until;
+            }
+        }
+    }
+
+    /// Asks the other members for the log decided so far, on joining the
+    /// cluster.
+    pub fn join(&mut self) {
+        let join = Message::Join(JoinData { from: self.id });
+        self.effect(Effect::SendMessage(join));
+    }
+
+    /// Receives a `Join` message from a joining node, replying with the
+    /// prefix of the log decided so far, up to the first undecided slot. The
+    /// latest snapshot is sent first, in place of the slots it covers.
+    pub fn receive_join(&mut self, msg: Message<T>) {
+        if let Message::Join(JoinData { from }) = msg {
+            span!("receive_join", learner = self.id, from);
+            if from == self.id {
+                return;
+            }
+            if self.snapshot.is_some() {
+                self.send_snapshot(from);
+            }
+            let decided = self
+                .decided
+                .iter()
+                .zip(self.compacted()..)
+                .take_while(|((slot, _), expected)| **slot == *expected)
+                .map(|((slot, value), _)| (*slot, value.clone()))
+                .collect();
+            let state = Message::State(StateData {
+                from: self.id,
+                to: from,
+                promised_n: 0,
+                accepted: Vec::new(),
+                decided,
+            });
+            self.effect(Effect::SendMessage(state));
+        }
+    }
+
+    /// Receives a `State` message in reply to a `Join`, deciding the slots of
+    /// the copied log not decided yet. State from outside the cluster is
+    /// ignored.
+    pub fn receive_state(&mut self, msg: Message<T>) {
+        if let Message::State(data) = msg {
+            span!("receive_state", learner = self.id, from = data.from);
+            if data.to != self.id || !self.config.is_member(data.from) {
+                return;
+            }
+            for (slot, value) in data.decided {
+                if self.out_of_reach(slot) {
+                    continue;
                 }
+                if !self.is_decided(slot) {
+                    self.observe(slot);
+                    self.decide(slot, value);
+                }
+            }
+        }
+    }
+
+    /// Takes the errors of the messages dropped since the last call, for
+    /// conflicting with the votes counted or the value decided.
+    pub fn take_errors(&mut self) -> Vec<LearnerError> {
+        core::mem::take(&mut self.errors)
+    }
+
+    /// Takes the transitions refused since the last call for breaking an
+    /// invariant, each of which is a bug.
+    #[cfg(feature = "paranoid-checks")]
+    pub fn take_violations(&mut self) -> Vec<InvariantViolation> {
+        core::mem::take(&mut self.violations)
+    }
+
+    /// Sends the latest snapshot to the lagging `Learner` `to`, if there is
+    /// one.
+    pub fn send_snapshot(&mut self, to: NodeId) {
+        let (index, value) = match self.snapshot {
+            Some((index, ref value)) => (index, value.clone()),
+            None => return,
+        };
+        let snapshot = Message::InstallSnapshot(SnapshotData {
+            from: self.id,
+            to,
+            index,
+            value,
+        });
+        self.effect(Effect::SendMessage(snapshot));
+    }
+
+    /// Receives an `InstallSnapshot` message, replacing the slots it covers
+    /// with the snapshot. The state machine is restored from it on the next
+    /// `apply`. Snapshots older than the state already applied are ignored,
+    /// as are those from nodes other than the members of the cluster.
+    pub fn receive_install_snapshot(&mut self, msg: Message<T>) {
+        if let Message::InstallSnapshot(data) = msg {
+            span!(
+                "receive_install_snapshot",
+                learner = self.id,
+                from = data.from,
+                index = data.index
+            );
+            if data.to != self.id
+                || !self.config.is_member(data.from)
+                || data.index <= self.apply_index.max(self.compacted())
+            {
+                return;
+            }
+            self.truncate(data.index);
+            self.snapshot = Some((data.index, data.value));
+            self.horizon = self.horizon.max(data.index);
+            self.notify_watchers();
+        }
+    }
+
+    /// Drops everything kept for the slots below `index`.
+    pub(crate) fn truncate(&mut self, index: Slot) {
+        self.decided = self.decided.split_off(&index);
+        self.ballots = self.ballots.split_off(&index);
+        self.accepted_received.retain(|(slot, _), _| *slot >= index);
+        self.shadow_votes.retain(|(slot, _), _| *slot >= index);
+        self.idle = self.idle.split_off(&index);
+        if let Some((recorder, _)) = &mut self.latency_recorder {
+            recorder.skip(index);
+        }
+    }
+
+    /// The slot below which the log has been compacted into the snapshot.
+    pub(crate) fn compacted(&self) -> Slot {
+        self.snapshot.as_ref().map_or(0, |(index, _)| *index)
+    }
+
+    /// Whether a value was decided for `slot`, be it kept or compacted.
+    fn is_decided(&self, slot: Slot) -> bool {
+        slot < self.compacted() || self.decided.contains_key(&slot)
+    }
+
+    /// Reports the shadows that accepted something else than `value` under
+    /// the proposal number it was decided with.
+    fn check_shadows(&mut self, slot: Slot, id: u64, value: &Arc<T>) {
+        let diverged: Vec<NodeId> = match self.shadow_votes.get(&(slot, id)) {
+            Some(votes) => votes
+                .iter()
+                .filter(|(_, v)| *v != value)
+                .map(|(from, _)| *from)
+                .collect(),
+            None => return,
+        };
+        if let Some(ref mut events) = self.events {
+            for from in diverged {
+                events(PaxosEvent::ShadowDiverged {
+                    instance: slot,
+                    from,
+                });
             }
         }
     }
+
+    fn decide(&mut self, slot: Slot, value: Arc<T>) {
+        #[cfg(feature = "paranoid-checks")]
+        if self
+            .decided
+            .get(&slot)
+            .is_some_and(|decided| *decided != value)
+        {
+            let violation = InvariantViolation::ConflictingDecision(slot);
+            event!(%violation, "invariant violated");
+            self.violations.push(violation);
+            return;
+        }
+        self.accepted_received.retain(|(s, _), _| *s != slot);
+        self.shadow_votes.retain(|(s, _), _| *s != slot);
+        self.idle.remove(&slot);
+        if let Some((recorder, batch_size)) = &mut self.latency_recorder {
+            if slot >= self.apply_index && !self.decided.contains_key(&slot) {
+                recorder.decided(slot, self.now, batch_size(&value));
+            }
+        }
+        self.decided.insert(slot, value.clone());
+        event!(slot, "decided");
+        self.value = Some(value.clone());
+        self.effect(Effect::Decide(slot, value.clone()));
+        if let Some(ref mut events) = self.events {
+            events(PaxosEvent::Decided { instance: slot });
+        }
+        if let Some(ref mut metrics) = self.metrics {
+            metrics.decided();
+        }
+        if let Some(ref mut observer) = self.observer {
+            observer.on_decided(slot, &value);
+        }
+        self.notify_watchers();
+    }
+
+    /// Advances the `Learner`'s clock to `now`, in milliseconds, which bounds
+    /// the staleness of `stale_read`s.
+    pub fn set_time(&mut self, now: u64) {
+        self.now = self.now.max(now);
+    }
+
+    /// Advances the logical clock used to detect abandoned slots.
+    pub fn tick(&mut self) {
+        for ticks in self.idle.values_mut() {
+            *ticks += 1;
+        }
+    }
+
+    /// Returns the undecided slots that have seen no activity for at least
+    /// `instance_ttl` ticks, including gaps below decided slots. The leader
+    /// is expected to `Proposer::finalize` them; each slot is only reported
+    /// again once another `instance_ttl` ticks have passed.
+    pub fn abandoned(&mut self) -> Vec<Slot> {
+        let ttl = match self.instance_ttl {
+            Some(ttl) => ttl,
+            None => return Vec::new(),
+        };
+        let mut abandoned = Vec::new();
+        for (slot, ticks) in self.idle.iter_mut() {
+            if *ticks >= ttl {
+                *ticks = 0;
+                abandoned.push(*slot);
+            }
+        }
+        abandoned
+    }
+
+    /// Returns every undecided slot below the highest one seen so far, for a
+    /// new leader to `Proposer::take_over`.
+    pub fn unresolved(&self) -> Vec<Slot> {
+        (self.compacted()..self.horizon)
+            .filter(|slot| !self.is_decided(*slot))
+            .collect()
+    }
+
+    /// Drops a message conflicting with the votes counted or the value
+    /// decided, which only a faulty `Acceptor` or misconfigured quorums could
+    /// have sent. The error is kept for `take_errors`.
+    fn reject(&mut self, error: LearnerError) {
+        event!(%error, "rejected");
+        self.errors.push(error);
+    }
+
+    /// Whether `slot` lies further beyond the horizon than `max_lead` allows.
+    fn out_of_reach(&self, slot: Slot) -> bool {
+        self.max_lead
+            .is_some_and(|lead| slot.saturating_sub(self.horizon) > lead)
+    }
+
+    /// Whether `slot` lies further below the horizon than `vote_window`
+    /// allows.
+    fn left_behind(&self, slot: Slot) -> bool {
+        self.vote_window
+            .is_some_and(|window| slot < self.horizon.saturating_sub(window))
+    }
+
+    /// Drops the votes of the slots `left_behind`.
+    fn evict_votes(&mut self) {
+        if let Some(window) = self.vote_window {
+            let floor = self.horizon.saturating_sub(window);
+            self.accepted_received.retain(|(slot, _), _| *slot >= floor);
+            self.shadow_votes.retain(|(slot, _), _| *slot >= floor);
+        }
+    }
+
+    /// Records activity on an undecided `slot`, tracking any slots skipped
+    /// over on the way to it.
+    fn observe(&mut self, slot: Slot) {
+        for gap in self.horizon..slot {
+            if !self.is_decided(gap) {
+                self.idle.insert(gap, 0);
+            }
+        }
+        if slot >= self.horizon {
+            self.horizon = slot + 1;
+            self.evict_votes();
+        }
+        self.idle.insert(slot, 0);
+    }
+}
+
+/// The configuration votes for `slot` under proposal number `n` are counted
+/// against: the one in force at `slot` if reconfigured through the log,
+/// otherwise the one of the epoch `n` belongs to.
+fn config_for<'a>(
+    epochs: &'a BTreeMap<u64, ClusterConfig>,
+    reconfigurations: &'a BTreeMap<Slot, ClusterConfig>,
+    current: &'a ClusterConfig,
+    slot: Slot,
+    n: u64,
+) -> &'a ClusterConfig {
+    match reconfigurations.range(..=slot).next_back() {
+        Some((_, config)) => config,
+        None => epochs
+            .range(..=epoch_of(n))
+            .next_back()
+            .map_or(current, |(_, config)| config),
+    }
+}
+
+impl<T: PartialEq, M: Messenger<T>> Handler<T> for Learner<T, M> {
+    fn handle(&mut self, msg: Message<T>) {
+        match msg {
+            Message::Accepted(_) => self.receive_accepted(msg),
+            Message::Skip(_) => self.receive_skip(msg),
+            Message::Learn(_) => self.receive_learn(msg),
+            Message::Join(_) => self.receive_join(msg),
+            Message::State(_) => self.receive_state(msg),
+            Message::InstallSnapshot(_) => self.receive_install_snapshot(msg),
+            Message::ReadReply(_) => self.receive_read_reply(msg),
+            _ => {}
+        }
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::vertical::first_ballot;
+    use alloc::vec;
+    use std::sync::Mutex;
+
+    /// A cluster of seven `Acceptor`s.
+    fn cluster() -> ClusterConfig {
+        ClusterConfig::new((1..=7).collect())
+    }
 
     #[test]
     fn learner_new() {
-        let l: Learner<u64> = Learner::new(1, 7);
+        let l: Learner<u64> = Learner::new(1, cluster());
 
         assert_eq!(l.id, 1);
         assert!(l.messenger.is_none());
         assert_eq!(l.last_accepted_n, 0);
         assert!(l.value.is_none());
-        assert_eq!(l.accepted_received, HashMap::new());
+        assert_eq!(l.accepted_received, BTreeMap::new());
     }
 
     #[test]
     fn learner_receive_accepted() {
-        let mut l: Learner<u64> = Learner::new(1, 7);
+        let mut l: Learner<u64> = Learner::new(1, cluster());
 
         let id = 1;
         let msg = Message::Accepted(AcceptedData {
+            slot: 0,
             id,
             value: Arc::new(10),
-            from: 0,
+            from: 1,
+            fast: false,
         });
 
         l.receive_accepted(msg);
 
         assert_eq!(l.value, None);
-        assert_eq!(l.accepted_received.get(&id).unwrap().len(), 1);
+        assert_eq!(l.accepted_received[&(0, id)].len(), 1);
+
+        // Votes from outside the cluster don't count.
+        let msg = Message::Accepted(AcceptedData {
+            slot: 0,
+            id,
+            value: Arc::new(10),
+            from: 9,
+            fast: false,
+        });
+
+        l.receive_accepted(msg);
+
+        assert_eq!(l.accepted_received[&(0, id)].len(), 1);
+
+        // Neither do retransmissions.
+        let msg = Message::Accepted(AcceptedData {
+            slot: 0,
+            id,
+            value: Arc::new(10),
+            from: 1,
+            fast: false,
+        });
+
+        l.receive_accepted(msg);
+
+        assert_eq!(l.accepted_received[&(0, id)].len(), 1);
 
-        for i in 1..l.quorum {
+        for i in 2..=l.config.quorum() {
             let msg = Message::Accepted(AcceptedData {
+                slot: 0,
                 id: 1,
                 value: Arc::new(10),
                 from: i as u64,
+                fast: false,
             });
             l.receive_accepted(msg);
         }
 
         assert_eq!(l.last_accepted_n, 1);
         assert_eq!(l.value, Some(Arc::new(10)));
+        assert_eq!(l.decided[&0], Arc::new(10));
     }
 
-    #[test(should_panic)]
+    #[test]
     fn learner_receive_accepted_mismatch() {
-        let mut l: Learner<u64> = Learner::new(1, 7);
+        let mut l: Learner<u64> = Learner::new(1, cluster());
 
         let id = 1;
         let msg = Message::Accepted(AcceptedData {
+            slot: 0,
             id,
             value: Arc::new(10),
-            from: 0,
+            from: 1,
+            fast: false,
         });
 
         l.receive_accepted(msg);
 
         let msg = Message::Accepted(AcceptedData {
+            slot: 0,
             id: 1,
             value: Arc::new(8), // conflicting value
-            from: 1 as u64,
+            from: 2,
+            fast: false,
         });
         l.receive_accepted(msg);
+
+        // The conflicting vote is dropped.
+        assert_eq!(l.accepted_received[&(0, 1)].len(), 1);
+        assert_eq!(l.take_errors(), [LearnerError::ProposalMismatch(1)]);
+    }
+
+    /// Delivers an `Accepted` for `slot` from a quorum of the cluster. Shared
+    /// by the tests of the modules built on top of the `Learner`.
+    pub(crate) fn decide<T: PartialEq>(l: &mut Learner<T>, slot: Slot, value: T) {
+        let value = Arc::new(value);
+        for from in 1..=l.config.quorum() as u64 {
+            l.receive_accepted(Message::Accepted(AcceptedData {
+                slot,
+                id: 1,
+                value: value.clone(),
+                from,
+                fast: false,
+            }));
+        }
+    }
+
+    #[test]
+    fn learner_receive_accepted_decided_mismatch() {
+        let mut l: Learner<u64> = Learner::new(1, cluster());
+
+        decide(&mut l, 0, 10);
+
+        l.receive_accepted(Message::Accepted(AcceptedData {
+            slot: 0,
+            id: 2,
+            value: Arc::new(8),
+            from: 7,
+            fast: false,
+        }));
+        l.receive_learn(Message::Learn(LearnData {
+            slot: 0,
+            id: 2,
+            from: 2,
+            value: Arc::new(8),
+            certificate: vec![],
+        }));
+
+        assert_eq!(l.decided[&0], Arc::new(10));
+        assert_eq!(
+            l.take_errors(),
+            [LearnerError::SlotMismatch(0), LearnerError::SlotMismatch(0)]
+        );
+        assert!(l.take_errors().is_empty());
+    }
+
+    #[test]
+    fn learner_abandoned() {
+        let mut l: Learner<u64> = Learner::new(1, cluster());
+        l.instance_ttl = Some(2);
+
+        // Slot 1 has a vote, slot 0 was skipped over entirely.
+        l.receive_accepted(Message::Accepted(AcceptedData {
+            slot: 1,
+            id: 1,
+            value: Arc::new(10),
+            from: 1,
+            fast: false,
+        }));
+        decide(&mut l, 2, 20);
+
+        l.tick();
+        assert!(l.abandoned().is_empty());
+
+        // Activity on slot 1 keeps it alive.
+        l.receive_accepted(Message::Accepted(AcceptedData {
+            slot: 1,
+            id: 1,
+            value: Arc::new(10),
+            from: 2,
+            fast: false,
+        }));
+        l.tick();
+
+        assert_eq!(l.abandoned(), vec![0]);
+        assert!(l.abandoned().is_empty());
+
+        l.tick();
+        l.tick();
+
+        assert_eq!(l.abandoned(), vec![0, 1]);
+
+        decide(&mut l, 0, 0);
+        decide(&mut l, 1, 10);
+        l.tick();
+        l.tick();
+
+        assert!(l.abandoned().is_empty());
+    }
+
+    #[test]
+    fn learner_receive_learn() {
+        let mut l: Learner<u64> = Learner::new(1, cluster());
+        let learn = |slot, from, certificate| {
+            Message::Learn(LearnData {
+                slot,
+                id: 1,
+                from,
+                value: Arc::new(10),
+                certificate,
+            })
+        };
+
+        // Only the owner of the proposal number announces its decision.
+        l.receive_learn(learn(0, 2, vec![1, 2, 3, 4]));
+
+        assert!(l.decided.is_empty());
+
+        // A certificate short of a quorum is rejected.
+        l.receive_learn(learn(0, 1, vec![1, 2, 3]));
+
+        assert!(l.decided.is_empty());
+
+        l.receive_learn(learn(0, 1, vec![1, 2, 3, 4]));
+
+        assert_eq!(l.decided[&0], Arc::new(10));
+
+        l.receive_learn(learn(1, 1, vec![]));
+
+        assert_eq!(l.decided[&1], Arc::new(10));
+
+        l.require_certificates = true;
+        l.receive_learn(learn(2, 1, vec![]));
+
+        assert!(!l.decided.contains_key(&2));
+    }
+
+    /// Records the `Learn` messages sent.
+    struct Relayed(Arc<Mutex<Vec<Message<u64>>>>);
+
+    impl Messenger<u64> for Relayed {
+        fn send_prepare(&mut self, _msg: Message<u64>) {}
+
+        fn send_promise(&mut self, _msg: Message<u64>) {}
+
+        fn send_accept(&mut self, _msg: Message<u64>) {}
+
+        fn send_accepted(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn on_resolution(&mut self, _slot: Slot, _value: Arc<u64>) {}
+    }
+
+    #[test]
+    fn learner_distinguished_relay() {
+        let config = ClusterConfig::new(vec![1, 2, 3]).with_distinguished_learners(vec![9]);
+        let relayed = Arc::new(Mutex::new(Vec::new()));
+        let mut distinguished: Learner<u64> = Learner::new(9, config.clone());
+        distinguished.messenger = Some(Box::new(Relayed(relayed.clone())));
+        let mut other: Learner<u64> = Learner::new(8, config);
+
+        assert!(distinguished.relays());
+        assert!(!other.relays());
+
+        for from in 1..=2 {
+            distinguished.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(10),
+                from,
+                fast: false,
+            }));
+        }
+        let relayed = relayed.lock().unwrap().clone();
+
+        assert_eq!(
+            relayed,
+            vec![Message::Learn(LearnData {
+                slot: 0,
+                id: 1,
+                from: 9,
+                value: Arc::new(10),
+                certificate: vec![1, 2],
+            })]
+        );
+
+        for msg in relayed {
+            other.handle(msg);
+        }
+
+        assert_eq!(other.decided[&0], Arc::new(10));
+    }
+
+    #[test]
+    fn learner_unresolved() {
+        let mut l: Learner<u64> = Learner::new(1, cluster());
+
+        decide(&mut l, 1, 10);
+        decide(&mut l, 3, 30);
+
+        assert_eq!(l.unresolved(), vec![0, 2]);
+    }
+
+    #[test]
+    fn learner_fast_round() {
+        let mut l: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1, 2, 3, 4]));
+        let fast_accepted = |from, value| {
+            Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(value),
+                from,
+                fast: true,
+            })
+        };
+
+        // Colliding values don't conflict in a fast round, but a majority of
+        // matching votes isn't enough to decide.
+        l.receive_accepted(fast_accepted(1, 60));
+        l.receive_accepted(fast_accepted(2, 60));
+        l.receive_accepted(fast_accepted(3, 25));
+
+        assert!(l.decided.is_empty());
+
+        l.receive_accepted(fast_accepted(4, 60));
+
+        assert_eq!(l.decided[&0], Arc::new(60));
+    }
+
+    #[test]
+    fn learner_receive_skip() {
+        let config = ClusterConfig::new(vec![1, 2, 3]).with_leaders(vec![1, 2]);
+        let mut l: Learner<u64> = Learner::new(1, config);
+        l.instance_ttl = Some(1);
+
+        l.receive_skip(Message::Skip(SkipData {
+            from: 2,
+            start: 0,
+            end: 5,
+            value: Arc::new(0),
+        }));
+
+        // Only the slots owned by the skipping leader are decided.
+        assert_eq!(l.decided.keys().copied().collect::<Vec<_>>(), vec![1, 3]);
+
+        l.tick();
+
+        assert_eq!(l.abandoned(), vec![0, 2]);
+    }
+
+    #[test]
+    fn learner_receive_skip_reconfigured() {
+        let config = ClusterConfig::new(vec![1, 2, 3]).with_leaders(vec![1, 2]);
+        let mut l: Learner<u64> = Learner::new(1, config);
+        l.reconfigure(
+            4,
+            ClusterConfig::new(vec![1, 2, 3]).with_leaders(vec![1, 2, 3]),
+        );
+
+        l.receive_skip(Message::Skip(SkipData {
+            from: 2,
+            start: 0,
+            end: 10,
+            value: Arc::new(0),
+        }));
+
+        // Leader 2 owns every other slot up to the change, and one in three
+        // from then on.
+        assert_eq!(
+            l.decided.keys().copied().collect::<Vec<_>>(),
+            vec![1, 3, 4, 7]
+        );
+    }
+
+    #[test]
+    fn learner_shadows() {
+        let config = ClusterConfig::new(vec![1, 2, 3]).with_shadows(vec![4, 5]);
+        let mut l: Learner<u64> = Learner::new(1, config);
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        l.events = Some(Box::new(move |e| recorded.lock().unwrap().push(e)));
+        let accepted = |from, value| {
+            Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(value),
+                from,
+                fast: false,
+            })
+        };
+
+        // Shadow votes neither count, nor conflict with the members'.
+        l.receive_accepted(accepted(4, 10));
+        l.receive_accepted(accepted(5, 99));
+        l.receive_accepted(accepted(1, 10));
+
+        assert!(l.decided.is_empty());
+
+        l.receive_accepted(accepted(2, 10));
+
+        assert_eq!(l.decided[&0], Arc::new(10));
+        assert!(l.shadow_votes.is_empty());
+        assert_eq!(
+            events.lock().unwrap()[0],
+            PaxosEvent::ShadowDiverged {
+                instance: 0,
+                from: 5
+            }
+        );
+    }
+
+    #[test]
+    fn learner_activate() {
+        let mut l: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let accepted = |slot, id, from| {
+            Message::Accepted(AcceptedData {
+                slot,
+                id,
+                value: Arc::new(10),
+                from,
+                fast: false,
+            })
+        };
+
+        l.activate(1, ClusterConfig::new(vec![3, 4, 5]));
+
+        // Votes of epoch 0 still count against the old configuration...
+        l.receive_accepted(accepted(0, 1, 1));
+        l.receive_accepted(accepted(0, 1, 2));
+
+        assert_eq!(l.decided[&0], Arc::new(10));
+
+        // ...while those of epoch 1 count against the new one.
+        let id = first_ballot(1) + 1;
+        l.receive_accepted(accepted(1, id, 1));
+        l.receive_accepted(accepted(1, id, 2));
+        l.receive_accepted(accepted(1, id, 4));
+
+        assert!(!l.decided.contains_key(&1));
+
+        l.receive_accepted(accepted(1, id, 5));
+
+        assert_eq!(l.decided[&1], Arc::new(10));
+    }
+
+    #[test]
+    fn learner_max_lead() {
+        let mut l: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        l.set_max_lead(Some(4));
+        let learn = |slot| {
+            Message::Learn(LearnData {
+                slot,
+                id: 1,
+                from: 1,
+                value: Arc::new(slot),
+                certificate: vec![],
+            })
+        };
+
+        l.receive_learn(learn(u64::MAX));
+        l.receive_learn(learn(5));
+
+        assert_eq!(l.horizon(), 0);
+        assert!(l.idle.is_empty());
+
+        // Slots come within reach as the log grows.
+        l.receive_learn(learn(4));
+        l.receive_learn(learn(5));
+
+        assert_eq!(l.horizon(), 6);
+        assert_eq!(l.unresolved(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn learner_vote_window() {
+        let mut l: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        l.set_vote_window(Some(2));
+        let vote = |slot| {
+            Message::Accepted(AcceptedData {
+                slot,
+                id: 1,
+                value: Arc::new(slot),
+                from: 1,
+                fast: false,
+            })
+        };
+
+        for slot in 0..4 {
+            l.receive_accepted(vote(slot));
+        }
+
+        assert_eq!(l.memory().vote_sets, 2);
+        assert_eq!(
+            l.accepted_received.keys().collect::<Vec<_>>(),
+            [&(2, 1), &(3, 1)]
+        );
+
+        // Votes for slots left behind are dropped, yet the slots can still
+        // be abandoned and finalized.
+        l.receive_accepted(vote(0));
+
+        assert_eq!(l.memory().votes, 2);
+        assert_eq!(l.unresolved(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn learner_state_transfer() {
+        let mut l: Learner<u64> = Learner::new(8, cluster());
+
+        decide(&mut l, 1, 20);
+        l.receive_state(Message::State(StateData {
+            from: 1,
+            to: 8,
+            promised_n: 0,
+            accepted: vec![],
+            decided: vec![(0, Arc::new(10)), (1, Arc::new(20))],
+        }));
+
+        assert_eq!(l.decided[&0], Arc::new(10));
+        assert_eq!(l.decided[&1], Arc::new(20));
+        assert!(l.idle.is_empty());
+
+        // State meant for another node is ignored.
+        l.receive_state(Message::State(StateData {
+            from: 1,
+            to: 9,
+            promised_n: 0,
+            accepted: vec![],
+            decided: vec![(2, Arc::new(30))],
+        }));
+
+        assert!(!l.decided.contains_key(&2));
+    }
+
+    #[test]
+    fn learner_install_snapshot() {
+        let mut l: Learner<u64> = Learner::new(8, cluster());
+        let snapshot = |from| {
+            Message::InstallSnapshot(SnapshotData {
+                from,
+                to: 8,
+                index: 2,
+                value: Arc::new(30),
+            })
+        };
+
+        // Snapshots from outside the cluster are ignored.
+        l.receive_install_snapshot(snapshot(9));
+
+        assert_eq!(l.snapshot, None);
+
+        l.receive_install_snapshot(snapshot(1));
+
+        assert_eq!(l.snapshot, Some((2, Arc::new(30))));
+    }
+
+    #[test]
+    #[cfg(feature = "paranoid-checks")]
+    fn learner_paranoid_checks() {
+        let mut l: Learner<u64> = Learner::new(1, cluster());
+        l.decide(0, Arc::new(10));
+        l.decide(0, Arc::new(10));
+
+        assert!(l.take_violations().is_empty());
+
+        // A second value is refused rather than overwriting the first.
+        l.decide(0, Arc::new(20));
+
+        assert_eq!(l.decided[&0], Arc::new(10));
+        assert_eq!(
+            l.take_violations(),
+            [InvariantViolation::ConflictingDecision(0)]
+        );
     }
 }
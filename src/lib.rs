@@ -1,11 +1,171 @@
 //! A lightweight implementation of the Paxos Consensus Algorithm.
+//!
+//! The protocol roles in this crate are plain state machines which only
+//! depend on `core` and `alloc`, so the crate can be built without the
+//! standard library by disabling default features. Enabling the `runtime`
+//! feature adds a [tokio](https://tokio.rs) based runtime for driving roles
+//! over asynchronous channels.
+//!
+//! The crate builds for WASM too, where the `tcp` and `client` modules,
+//! `FileStorage` and `SystemClock` are left out: the host moves messages and
+//! injects the time instead (see `examples/wasm_host.rs`).
+//!
+//! | Feature       | Default | Description                                      |
+//! |---------------|---------|--------------------------------------------------|
+//! | `std`         | yes     | Thread-safe role handles and channels            |
+//! | `runtime`     | no      | tokio powered drivers (implies `std`)            |
+//! | `bft`         | no      | Signed votes tolerating malicious `Acceptor`s    |
+//! | `prometheus`  | no      | `Metrics` exported to prometheus (implies `std`) |
+//! | `tracing`     | no      | Spans and events for every protocol handler      |
+//! | `serde`       | no      | Serializable role status snapshots               |
+//! | `model-check` | no      | Exhaustive `Model` checking (implies `std`)      |
+//! | `arbitrary`   | no      | `Arbitrary` messages for fuzzing (implies `std`) |
+//! | `bin`         | no      | The `paxos-node` binary (implies `std`, `serde`) |
+//! | `bytes`       | no      | Zero-copy decoding of `Bytes` values             |
+//! | `txn`         | no      | Two-phase commit across `MultiGroup` groups      |
+//! | `lz4`         | no      | LZ4 compression of transported messages          |
+//! | `snappy`      | no      | Snappy compression of transported messages (implies `std`) |
+//! | `transport-quic` | no   | QUIC transport through quinn (implies `runtime`) |
+//! | `transport-ws` | no     | WebSocket transport for browser peers (implies `runtime`) |
+//! | `python`      | no      | Python bindings through PyO3 (implies `std`)     |
+//! | `actors`      | no      | Supervised actix actors running a `Node` (implies `std`) |
+//! | `transport-zmq` | no    | ZeroMQ DEALER/ROUTER transport (implies `runtime`) |
+//! | `transport-nats` | no   | NATS transport over per-node subjects (implies `runtime`) |
+//! | `kafka`       | no      | Publishes the decided log to Kafka (implies `std`) |
+//! | `tla`         | no      | Conformance of traces to the TLA+ spec of Paxos  |
+//! | `paranoid-checks` | no  | Runtime assertions of protocol invariants        |
+//!
+//! Roles are `Send` as long as their values are `Send + Sync`, which values
+//! shared through `Arc`s need to be, so a role can be moved into a thread or
+//! a tokio task. They aren't `Sync`: every handler takes `&mut self`, and
+//! boxed `Messenger`s and event callbacks need only be `Send`. Share a role
+//! between threads through `sync::Shared` instead.
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+#[macro_use]
+pub mod trace;
 
 pub mod acceptor;
+#[cfg(feature = "actors")]
+pub mod actors;
+pub mod admission;
+pub mod anti_entropy;
+#[cfg(feature = "std")]
+pub mod backup;
+pub mod batch;
+#[cfg(feature = "bft")]
+pub mod bft;
+pub mod builder;
+pub mod chunk;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+pub mod client;
+pub mod clock;
+pub mod commute;
+pub mod compress;
+pub mod config;
+pub mod conformance;
+pub mod effect;
+pub mod error;
+pub mod event;
+pub mod fencing;
+pub mod group;
+pub mod invariants;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod latency;
 pub mod learner;
+pub mod membership;
 pub mod message;
+pub mod metrics;
+#[cfg(feature = "model-check")]
+pub mod model;
+pub mod nack;
+#[cfg(feature = "transport-nats")]
+pub mod nats;
+pub mod node;
+pub mod priority;
+pub mod proposal;
 pub mod proposer;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "transport-quic")]
+pub mod quic;
+pub mod quorum;
+pub mod read;
+pub mod retry;
+pub mod router;
+#[cfg(feature = "runtime")]
+pub mod runtime;
+pub mod session;
+pub mod sim;
+pub mod state_machine;
+pub mod status;
+pub mod storage;
+#[cfg(feature = "std")]
+pub mod sync;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+pub mod tcp;
+pub mod testing;
+#[cfg(feature = "tla")]
+pub mod tla;
+pub mod topology;
+#[cfg(feature = "txn")]
+pub mod txn;
+pub mod validate;
+pub mod vertical;
+pub mod watch;
+pub mod wire;
+#[cfg(feature = "transport-ws")]
+pub mod ws;
+#[cfg(feature = "transport-zmq")]
+pub mod zmq;
 
 pub use acceptor::*;
+pub use admission::*;
+pub use batch::*;
+pub use builder::*;
+pub use clock::*;
+pub use commute::*;
+pub use config::*;
+pub use effect::*;
+pub use error::*;
+pub use event::*;
+pub use fencing::*;
+pub use group::*;
+pub use latency::*;
 pub use learner::*;
+pub use membership::*;
 pub use message::*;
+pub use node::*;
+pub use priority::*;
+pub use proposal::*;
 pub use proposer::*;
+pub use quorum::*;
+pub use read::*;
+pub use retry::*;
+pub use router::*;
+pub use session::*;
+pub use state_machine::*;
+pub use status::*;
+pub use topology::*;
+#[cfg(feature = "txn")]
+pub use txn::*;
+pub use validate::*;
+pub use vertical::*;
+pub use watch::*;
+
+/// Fails to compile if a role stops being `Send`.
+#[allow(dead_code)]
+fn roles_are_send<T: Send + Sync>() {
+    fn is_send<S: Send>() {}
+    is_send::<Proposer<T>>();
+    is_send::<Acceptor<T>>();
+    is_send::<Learner<T>>();
+    is_send::<Node<T>>();
+    is_send::<Effect<T>>();
+}
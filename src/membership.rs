@@ -0,0 +1,328 @@
+//! Membership changes
+//!
+//! The cluster grows and shrinks online by deciding `AddNode` and `RemoveNode`
+//! commands through the log, like any other value. A change decided in slot
+//! `s` takes effect at slot `s + α`: every replica applies the log in the same
+//! order, so they all switch configurations at the same slot, and the `α`
+//! slots in between let proposals already in flight complete under the
+//! configuration they were started in. Changes are proposed at
+//! `Priority::High`, so that they don't wait behind application values.
+//!
+//! Adding or removing nodes one at a time keeps any two consecutive
+//! configurations' majorities intersecting. Larger changes go through a
+//! transitional configuration instead (joint consensus): `Transition` moves
+//! to a configuration whose quorums need majorities of both the old and the
+//! new members, and `CompleteTransition` then moves to the new members alone.
+//!
+//! A `Proposer` never proposes past `α` slots beyond the decided prefix as
+//! long as its `window` is at most `α`, so the configuration of every slot it
+//! proposes for is known by the time it does.
+
+use crate::config::{ClusterConfig, NodeId};
+use crate::message::{Messenger, Slot};
+use crate::node::Node;
+use crate::priority::Priority;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// An entry of a log whose membership is decided through the log itself.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub enum Entry<T> {
+    /// An application value
+    Apply(T),
+    /// Adds an `Acceptor` to the cluster
+    AddNode(NodeId),
+    /// Removes an `Acceptor` from the cluster
+    RemoveNode(NodeId),
+    /// Starts a transition to a new set of `Acceptor`s
+    Transition(Vec<NodeId>),
+    /// Completes the transition in progress
+    CompleteTransition,
+}
+
+/// Tracks the configuration of every slot, as changed by decided `Entry`s.
+#[derive(Debug, Clone)]
+pub struct Membership {
+    /// Number of slots between a change being decided and taking effect
+    alpha: Slot,
+    /// Configurations by the slot they take effect at
+    configs: BTreeMap<Slot, ClusterConfig>,
+}
+
+impl Membership {
+    /// Creates a new `Membership`, starting out with `config` and activating
+    /// changes `alpha` slots after they are decided.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alpha` is zero.
+    pub fn new(config: ClusterConfig, alpha: Slot) -> Self {
+        assert!(
+            alpha > 0,
+            "changes can't take effect in the slot deciding them"
+        );
+        let mut configs = BTreeMap::new();
+        configs.insert(0, config);
+        Self { alpha, configs }
+    }
+
+    /// Number of slots between a change being decided and taking effect.
+    pub fn alpha(&self) -> Slot {
+        self.alpha
+    }
+
+    /// The configuration in force at `slot`.
+    pub fn config_at(&self, slot: Slot) -> &ClusterConfig {
+        let (_, config) = self.configs.range(..=slot).next_back().unwrap();
+        config
+    }
+
+    /// The configuration the latest change decided leads to.
+    pub fn latest(&self) -> &ClusterConfig {
+        let (_, config) = self.configs.iter().next_back().unwrap();
+        config
+    }
+
+    /// Applies `entry`, decided in `slot`. Entries must be applied in slot
+    /// order. Returns the configuration a membership change leads to, along
+    /// with the slot it takes effect at, to be handed to every role's
+    /// `reconfigure`.
+    ///
+    /// Changes that leave the membership as is, or the cluster empty, are
+    /// ignored, as are changes other than `CompleteTransition` while a
+    /// transition is in progress. Explicit quorum sizes are kept as long as
+    /// they still fit the new membership; majorities are used otherwise.
+    pub fn apply<T>(&mut self, slot: Slot, entry: &Entry<T>) -> Option<(Slot, ClusterConfig)> {
+        let latest = self.latest();
+        let mut members = latest.members.clone();
+        let config = match *entry {
+            Entry::CompleteTransition if latest.is_transitioning() => latest.complete_transition(),
+            _ if latest.is_transitioning() => return None,
+            Entry::Transition(ref to) if !to.is_empty() => {
+                let config = latest.begin_transition(to.clone());
+                if config.members == latest.members {
+                    return None;
+                }
+                config
+            }
+            Entry::AddNode(id) if !latest.is_member(id) => {
+                members.push(id);
+                resize(latest, members)
+            }
+            Entry::RemoveNode(id) if latest.is_member(id) && members.len() > 1 => {
+                members.retain(|m| *m != id);
+                resize(latest, members)
+            }
+            _ => return None,
+        };
+        let from = slot + self.alpha;
+        self.configs.insert(from, config.clone());
+        Some((from, config))
+    }
+}
+
+/// `config` with its members replaced by `members`, keeping its quorum sizes
+/// if they still fit.
+fn resize(config: &ClusterConfig, members: Vec<NodeId>) -> ClusterConfig {
+    let mut config = config.clone();
+    config.members = ClusterConfig::new(members).members;
+    match config.quorums.take() {
+        Some(quorums) => config.clone().with_quorums(quorums).unwrap_or(config),
+        None => config,
+    }
+}
+
+/// The configuration in force at `slot`: the latest of `reconfigurations`
+/// taking effect at or below it, or `current` if none does.
+pub(crate) fn config_at<'a>(
+    reconfigurations: &'a BTreeMap<Slot, ClusterConfig>,
+    current: &'a ClusterConfig,
+    slot: Slot,
+) -> &'a ClusterConfig {
+    reconfigurations
+        .range(..=slot)
+        .next_back()
+        .map_or(current, |(_, config)| config)
+}
+
+impl<T, M> Node<Entry<T>, M>
+where
+    T: PartialEq + Clone,
+    M: Messenger<Entry<T>>,
+{
+    /// Proposes adding `id` to the cluster.
+    pub fn add_node(&mut self, id: NodeId) {
+        self.propose_with_priority(Entry::AddNode(id), Priority::High);
+    }
+
+    /// Proposes removing `id` from the cluster.
+    pub fn remove_node(&mut self, id: NodeId) {
+        self.propose_with_priority(Entry::RemoveNode(id), Priority::High);
+    }
+
+    /// Proposes a transition to `members`, to be completed with
+    /// `complete_transition` once it has taken effect.
+    pub fn transition_to(&mut self, members: Vec<NodeId>) {
+        self.propose_with_priority(Entry::Transition(members), Priority::High);
+    }
+
+    /// Proposes completing the transition in progress.
+    pub fn complete_transition(&mut self) {
+        self.propose_with_priority(Entry::CompleteTransition, Priority::High);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QuorumConfig;
+    use crate::message::{Handler, Message};
+    use alloc::boxed::Box;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use std::sync::Mutex;
+
+    #[test]
+    fn membership_apply() {
+        let mut m = Membership::new(ClusterConfig::new(vec![1, 2, 3]), 2);
+
+        assert_eq!(m.apply(0, &Entry::Apply(10)), None);
+        assert_eq!(m.apply::<u64>(1, &Entry::AddNode(2)), None);
+
+        let (from, config) = m.apply::<u64>(1, &Entry::AddNode(4)).unwrap();
+
+        assert_eq!(from, 3);
+        assert_eq!(config.members, vec![1, 2, 3, 4]);
+        assert_eq!(m.config_at(2).members, vec![1, 2, 3]);
+        assert_eq!(m.config_at(3).members, vec![1, 2, 3, 4]);
+
+        let (from, config) = m.apply::<u64>(2, &Entry::RemoveNode(1)).unwrap();
+
+        assert_eq!(from, 4);
+        assert_eq!(config.members, vec![2, 3, 4]);
+        assert_eq!(m.latest().members, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn membership_quorums() {
+        let config = ClusterConfig::new(vec![1, 2, 3, 4])
+            .with_quorums(QuorumConfig {
+                phase1: 4,
+                phase2: 2,
+            })
+            .unwrap();
+        let mut m = Membership::new(config, 1);
+
+        // Still intersect with five members.
+        let (_, config) = m.apply::<u64>(0, &Entry::AddNode(5)).unwrap();
+
+        assert_eq!(config.quorums.map(|q| q.phase1), Some(4));
+
+        // No longer do with six.
+        let (_, config) = m.apply::<u64>(1, &Entry::AddNode(6)).unwrap();
+
+        assert_eq!(config.quorums, None);
+        assert_eq!(config.quorum(), 4);
+    }
+
+    #[test]
+    fn membership_transition() {
+        let mut m = Membership::new(ClusterConfig::new(vec![1, 2, 3]), 1);
+
+        assert_eq!(m.apply::<u64>(0, &Entry::CompleteTransition), None);
+
+        let (from, config) = m
+            .apply::<u64>(0, &Entry::Transition(vec![4, 5, 6]))
+            .unwrap();
+
+        assert_eq!(from, 1);
+        assert_eq!(config.joint, Some(vec![1, 2, 3]));
+        assert!(!config.is_phase2_quorum(&[4, 5]));
+
+        // Nothing else changes until the transition completes.
+        assert_eq!(m.apply::<u64>(1, &Entry::AddNode(7)), None);
+
+        let (from, config) = m.apply::<u64>(2, &Entry::CompleteTransition).unwrap();
+
+        assert_eq!(from, 3);
+        assert_eq!(config, ClusterConfig::new(vec![4, 5, 6]));
+        assert_eq!(m.config_at(2).members, vec![4, 5, 6]);
+        assert!(m.config_at(2).is_transitioning());
+    }
+
+    /// Posts every message to a mailbox shared by the cluster.
+    struct Mailbox(Arc<Mutex<Vec<Message<Entry<u64>>>>>);
+
+    impl Messenger<Entry<u64>> for Mailbox {
+        fn send_prepare(&mut self, msg: Message<Entry<u64>>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_promise(&mut self, msg: Message<Entry<u64>>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_accept(&mut self, msg: Message<Entry<u64>>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_accepted(&mut self, msg: Message<Entry<u64>>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn on_resolution(&mut self, _slot: Slot, _value: Arc<Entry<u64>>) {}
+    }
+
+    #[test]
+    fn membership_add_node() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mailbox = Arc::new(Mutex::new(Vec::new()));
+        // Node 4 starts out with the others, so it needn't copy their state.
+        let mut nodes: Vec<(Node<Entry<u64>>, Membership)> = (1..=4)
+            .map(|id| {
+                let mut n = Node::new(id, config.clone());
+                n.proposer.messenger = Some(Box::new(Mailbox(mailbox.clone())));
+                n.acceptor.messenger = Some(Box::new(Mailbox(mailbox.clone())));
+                n.learner.messenger = Some(Box::new(Mailbox(mailbox.clone())));
+                (n, Membership::new(config.clone(), 1))
+            })
+            .collect();
+
+        let run = |nodes: &mut Vec<(Node<Entry<u64>>, Membership)>| loop {
+            let sent = core::mem::take(&mut *mailbox.lock().unwrap());
+            if sent.is_empty() {
+                break;
+            }
+            for msg in sent {
+                for (n, _) in nodes.iter_mut() {
+                    n.handle(msg.clone());
+                }
+            }
+            for (n, m) in nodes.iter_mut() {
+                for (slot, entry) in n.poll_decided() {
+                    if let Some((from, config)) = m.apply(slot, &entry) {
+                        n.reconfigure(from, config);
+                    }
+                }
+            }
+        };
+
+        nodes[0].0.add_node(4);
+        run(&mut nodes);
+
+        assert_eq!(nodes[0].0.proposer().config_at(1).members, vec![1, 2, 3, 4]);
+
+        // Slot 1 needs three votes out of four, the new member's included.
+        nodes.retain(|(n, _)| n.id() != 3);
+        nodes[0].0.propose(Entry::Apply(10));
+        run(&mut nodes);
+
+        for (n, _) in nodes.iter() {
+            assert_eq!(
+                n.learner().decided_value(1),
+                Some(&Arc::new(Entry::Apply(10)))
+            );
+        }
+    }
+}
@@ -1,44 +1,252 @@
 //! Describes Paxos messages
 
-use std::sync::Arc;
+use crate::config::NodeId;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Identifies an instance of the protocol, i.e. a position in the replicated log.
+pub type Slot = u64;
 
 /// A message sent between nodes
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Message<T> {
     Prepare(ProposalData),
     Promise(PromiseData<T>),
     Accept(AcceptData<T>),
     Accepted(AcceptedData<T>),
-    Nack,
+    /// Opens a fast round (Fast Paxos)
+    Any(ProposalData),
+    /// A value proposed straight to the `Acceptor`s in a fast round
+    Propose(ProposeData<T>),
+    /// Gives up a leader's unused slots (Mencius)
+    Skip(SkipData<T>),
+    /// Asks existing nodes for their state, on joining the cluster
+    Join(JoinData),
+    /// State copied to a joining node
+    State(StateData<T>),
+    /// Brings a lagging `Learner` up to date with a snapshot
+    InstallSnapshot(SnapshotData<T>),
+    /// Announces a decided value, so `Learner`s needn't count votes
+    Learn(LearnData<T>),
+    /// Rejects a proposal numbered below the `Acceptor`'s promise
+    Nack(NackData),
+    /// Asks the `Acceptor`s how far their log goes, for a linearizable read
+    Read(ReadData),
+    /// Answers a `Read`
+    ReadReply(ReadReplyData),
+    /// Summarizes the sender's accepted proposals, for anti-entropy
+    Digest(DigestData),
+    /// Accepted proposals a `Digest` was found to be missing
+    Repair(RepairData<T>),
+}
+
+impl<T> Message<T> {
+    /// The message's type in snake case, e.g. `"prepare"` or
+    /// `"install_snapshot"`, for naming it outside of Rust.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Prepare(_) => "prepare",
+            Message::Promise(_) => "promise",
+            Message::Accept(_) => "accept",
+            Message::Accepted(_) => "accepted",
+            Message::Any(_) => "any",
+            Message::Propose(_) => "propose",
+            Message::Skip(_) => "skip",
+            Message::Join(_) => "join",
+            Message::State(_) => "state",
+            Message::Learn(_) => "learn",
+            Message::InstallSnapshot(_) => "install_snapshot",
+            Message::Nack(_) => "nack",
+            Message::Read(_) => "read",
+            Message::ReadReply(_) => "read_reply",
+            Message::Digest(_) => "digest",
+            Message::Repair(_) => "repair",
+        }
+    }
 }
 
 /// Proposal data (Proposer -> Acceptor)
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct ProposalData {
+    pub slot: Slot,
     pub id: u64,
+    pub from: NodeId,
 }
 
 /// Promise data (Acceptor -> Proposer)
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct PromiseData<T> {
+    pub slot: Slot,
     pub id: u64,
+    /// The proposal number under which `value` was accepted, if any
+    pub accepted_n: Option<u64>,
     pub value: Option<Arc<T>>,
-    pub from: u64,
+    pub from: NodeId,
+}
+
+/// Nack data (Acceptor -> Proposer)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct NackData {
+    pub slot: Slot,
+    /// The proposal number rejected
+    pub id: u64,
+    pub from: NodeId,
+    /// The highest proposal number promised by the sender
+    pub promised_n: u64,
+    /// The `Proposer` the sender last promised, if known: the leader, as far
+    /// as it can tell
+    pub leader: Option<NodeId>,
 }
 
 /// Accept data (Proposer -> Acceptor)
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct AcceptData<T> {
+    pub slot: Slot,
     pub id: u64,
     pub value: Arc<T>,
+    /// Whether the `Acceptor` should run the first phase on the `Proposer`'s
+    /// behalf. Only safe when a single `Acceptor` makes up the quorum.
+    pub implicit_prepare: bool,
 }
 
 /// Accepted data (Acceptor -> Proposer)
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct AcceptedData<T> {
+    pub slot: Slot,
     pub id: u64,
     pub value: Arc<T>,
-    pub from: u64,
+    pub from: NodeId,
+    /// Whether the value was accepted in a fast round, which takes a fast
+    /// quorum to decide
+    pub fast: bool,
+}
+
+/// Propose data (Client -> Acceptor)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ProposeData<T> {
+    pub slot: Slot,
+    pub value: Arc<T>,
+}
+
+/// Skip data (Proposer -> Learner)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SkipData<T> {
+    pub from: NodeId,
+    /// First slot skipped
+    pub start: Slot,
+    /// Slot after the last one skipped
+    pub end: Slot,
+    /// The no-op the skipped slots are decided with
+    pub value: Arc<T>,
+}
+
+/// Join data (Newcomer -> Acceptor, Learner)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct JoinData {
+    pub from: NodeId,
+}
+
+/// State data (Acceptor, Learner -> Newcomer)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct StateData<T> {
+    pub from: NodeId,
+    /// The joining node the state is copied to
+    pub to: NodeId,
+    /// The highest proposal number promised by the sender
+    pub promised_n: u64,
+    /// The last accepted proposal of each slot (slot, proposal_n, value)
+    pub accepted: Vec<(Slot, u64, Arc<T>)>,
+    /// The prefix of the log decided so far (slot, value)
+    pub decided: Vec<(Slot, Arc<T>)>,
+}
+
+/// Learn data (Proposer -> Learner)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct LearnData<T> {
+    pub slot: Slot,
+    /// The proposal number the value was decided under
+    pub id: u64,
+    /// The `Proposer` owning `id`, or the distinguished `Learner` relaying
+    /// the decision
+    pub from: NodeId,
+    pub value: Arc<T>,
+    /// The `Acceptor`s whose `Accepted` made up the quorum, or empty if not
+    /// certified
+    pub certificate: Vec<NodeId>,
+}
+
+/// Snapshot data (Learner -> Learner)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SnapshotData<T> {
+    pub from: NodeId,
+    pub to: NodeId,
+    /// The slot after the last one the snapshot covers
+    pub index: Slot,
+    /// The state machine's snapshot
+    pub value: Arc<T>,
+}
+
+/// Read data (Learner -> Acceptor)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ReadData {
+    pub from: NodeId,
+    /// Identifies the read among the sender's
+    pub id: u64,
+}
+
+/// Read reply data (Acceptor -> Learner)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ReadReplyData {
+    pub from: NodeId,
+    /// The node reading
+    pub to: NodeId,
+    pub id: u64,
+    /// The slot after the highest one the sender accepted a value for
+    pub horizon: Slot,
+}
+
+/// Digest data (Acceptor -> Acceptor)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DigestData {
+    pub from: NodeId,
+    /// The first slot of the first range
+    pub start: Slot,
+    /// The slot below which the sender dropped its accepted proposals, left
+    /// out of the hashes
+    pub truncated: Slot,
+    /// The number of slots in each range
+    pub range: Slot,
+    /// A hash of the proposals accepted in each range, from `start` on
+    pub hashes: Vec<u64>,
+}
+
+/// Repair data (Acceptor -> Acceptor)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct RepairData<T> {
+    pub from: NodeId,
+    /// The `Acceptor` whose `Digest` is answered
+    pub to: NodeId,
+    /// The last accepted proposal of each slot of the ranges the digests
+    /// disagree on, and whether it was accepted in a fast round
+    /// (slot, proposal_n, fast, value)
+    pub accepted: Vec<(Slot, u64, bool, Arc<T>)>,
 }
 
 pub trait Messenger<T> {
@@ -50,5 +258,204 @@ pub trait Messenger<T> {
 
     fn send_accepted(&mut self, msg: Message<T>);
 
-    fn on_resolution(&mut self, proposal_n: u64, value: Arc<T>);
+    /// Sends an `Any` message. Defaults to `send_accept`, as both are bound
+    /// for the `Acceptor`s.
+    fn send_any(&mut self, msg: Message<T>) {
+        self.send_accept(msg);
+    }
+
+    /// Sends a `Propose` message. Defaults to `send_accept`, as both are bound
+    /// for the `Acceptor`s.
+    fn send_propose(&mut self, msg: Message<T>) {
+        self.send_accept(msg);
+    }
+
+    /// Sends a `Skip` message. Defaults to `send_accepted`, as both are bound
+    /// for the `Learner`s.
+    fn send_skip(&mut self, msg: Message<T>) {
+        self.send_accepted(msg);
+    }
+
+    /// Sends a `Join` message. Defaults to `send_prepare`, as both are bound
+    /// for the `Acceptor`s.
+    fn send_join(&mut self, msg: Message<T>) {
+        self.send_prepare(msg);
+    }
+
+    /// Sends a `State` message. Defaults to `send_promise`, as both answer a
+    /// request from a single node.
+    fn send_state(&mut self, msg: Message<T>) {
+        self.send_promise(msg);
+    }
+
+    /// Sends a `Learn` message. Defaults to `send_accepted`, as both are bound
+    /// for the `Learner`s.
+    fn send_learn(&mut self, msg: Message<T>) {
+        self.send_accepted(msg);
+    }
+
+    /// Sends an `InstallSnapshot` message. Defaults to `send_state`, as both
+    /// bring a single node up to date.
+    fn send_install_snapshot(&mut self, msg: Message<T>) {
+        self.send_state(msg);
+    }
+
+    /// Sends a `Nack` message. Defaults to `send_promise`, as both answer a
+    /// `Proposer`'s request.
+    fn send_nack(&mut self, msg: Message<T>) {
+        self.send_promise(msg);
+    }
+
+    /// Sends a `Read` message. Defaults to `send_prepare`, as both are bound
+    /// for the `Acceptor`s.
+    fn send_read(&mut self, msg: Message<T>) {
+        self.send_prepare(msg);
+    }
+
+    /// Sends a `ReadReply` message. Defaults to `send_promise`, as both
+    /// answer a request from a single node.
+    fn send_read_reply(&mut self, msg: Message<T>) {
+        self.send_promise(msg);
+    }
+
+    /// Sends a `Digest` message. Defaults to `send_prepare`, as both are
+    /// bound for the `Acceptor`s.
+    fn send_digest(&mut self, msg: Message<T>) {
+        self.send_prepare(msg);
+    }
+
+    /// Sends a `Repair` message. Defaults to `send_state`, as both copy
+    /// accepted proposals to a single node.
+    fn send_repair(&mut self, msg: Message<T>) {
+        self.send_state(msg);
+    }
+
+    /// Sends a message to the nodes `to` only, e.g. the `Accept`s of a
+    /// nearby quorum. Defaults to sending it to every node, through the
+    /// method matching its type.
+    fn send_to(&mut self, to: &[NodeId], msg: Message<T>) {
+        let _ = to;
+        crate::effect::dispatch(self, crate::effect::Effect::SendMessage(msg));
+    }
+
+    fn on_resolution(&mut self, slot: Slot, value: Arc<T>);
+
+    /// Reports that the `Proposer`'s proposal number `ballot` was outbid by
+    /// `by`, which an `Acceptor` promised instead, e.g. for the application to
+    /// redirect its requests to `ballot_owner(by)`. Does nothing by default.
+    fn on_preempted(&mut self, ballot: u64, by: u64) {
+        let _ = (ballot, by);
+    }
+
+    /// Whether the transport has room for more messages. A `Proposer` whose
+    /// `Messenger` reports `Readiness::WouldBlock` stops opening new slots,
+    /// queueing new values instead, until it reports `Readiness::Ready` again.
+    /// Always `Ready` by default.
+    fn readiness(&self) -> Readiness {
+        Readiness::Ready
+    }
+}
+
+/// Whether a `Messenger`'s transport has room for more messages.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Readiness {
+    /// Messages are sent as they come
+    #[default]
+    Ready,
+    /// The transport's queue is full: further messages would have to wait or
+    /// be dropped
+    WouldBlock,
+}
+
+/// The boxed `Messenger` roles send through unless given another type, so
+/// that it can be swapped for any implementation at runtime.
+pub type BoxedMessenger<T> = Box<dyn Messenger<T> + Send>;
+
+impl<T, M: Messenger<T> + ?Sized> Messenger<T> for Box<M> {
+    fn send_prepare(&mut self, msg: Message<T>) {
+        (**self).send_prepare(msg);
+    }
+
+    fn send_promise(&mut self, msg: Message<T>) {
+        (**self).send_promise(msg);
+    }
+
+    fn send_accept(&mut self, msg: Message<T>) {
+        (**self).send_accept(msg);
+    }
+
+    fn send_accepted(&mut self, msg: Message<T>) {
+        (**self).send_accepted(msg);
+    }
+
+    fn send_any(&mut self, msg: Message<T>) {
+        (**self).send_any(msg);
+    }
+
+    fn send_propose(&mut self, msg: Message<T>) {
+        (**self).send_propose(msg);
+    }
+
+    fn send_skip(&mut self, msg: Message<T>) {
+        (**self).send_skip(msg);
+    }
+
+    fn send_join(&mut self, msg: Message<T>) {
+        (**self).send_join(msg);
+    }
+
+    fn send_state(&mut self, msg: Message<T>) {
+        (**self).send_state(msg);
+    }
+
+    fn send_learn(&mut self, msg: Message<T>) {
+        (**self).send_learn(msg);
+    }
+
+    fn send_install_snapshot(&mut self, msg: Message<T>) {
+        (**self).send_install_snapshot(msg);
+    }
+
+    fn send_nack(&mut self, msg: Message<T>) {
+        (**self).send_nack(msg);
+    }
+
+    fn send_read(&mut self, msg: Message<T>) {
+        (**self).send_read(msg);
+    }
+
+    fn send_read_reply(&mut self, msg: Message<T>) {
+        (**self).send_read_reply(msg);
+    }
+
+    fn send_digest(&mut self, msg: Message<T>) {
+        (**self).send_digest(msg);
+    }
+
+    fn send_repair(&mut self, msg: Message<T>) {
+        (**self).send_repair(msg);
+    }
+
+    fn send_to(&mut self, to: &[NodeId], msg: Message<T>) {
+        (**self).send_to(to, msg);
+    }
+
+    fn on_resolution(&mut self, slot: Slot, value: Arc<T>) {
+        (**self).on_resolution(slot, value);
+    }
+
+    fn on_preempted(&mut self, ballot: u64, by: u64) {
+        (**self).on_preempted(ballot, by);
+    }
+
+    fn readiness(&self) -> Readiness {
+        (**self).readiness()
+    }
+}
+
+/// A role which can be handed any incoming `Message`.
+pub trait Handler<T> {
+    /// Dispatches `msg` to the matching `receive_*` method. Messages the role
+    /// has no use for are ignored.
+    fn handle(&mut self, msg: Message<T>);
 }
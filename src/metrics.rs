@@ -0,0 +1,750 @@
+//! Metrics
+
+use crate::learner::Learner;
+use crate::node::Node;
+use crate::proposer::Proposer;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Hooks every role calls as the protocol progresses, so that operators can
+/// monitor a cluster. Each hook does nothing unless overridden.
+pub trait Metrics {
+    /// A `Proposer` sent a `Prepare`.
+    fn prepare_sent(&mut self) {}
+
+    /// A `Proposer` received a `Nack`.
+    fn nack_received(&mut self) {}
+
+    /// An `Acceptor` sent a `Promise`.
+    fn promise_sent(&mut self) {}
+
+    /// An `Acceptor` accepted a value.
+    fn value_accepted(&mut self) {}
+
+    /// A `Learner` decided a value.
+    fn decided(&mut self) {}
+
+    /// A value a `Proposer` proposed was decided `latency` milliseconds after
+    /// being proposed.
+    fn decision_latency(&mut self, latency: u64) {
+        let _ = latency;
+    }
+
+    /// A transport sent `raw` bytes of messages as `sent` bytes, once
+    /// compressed.
+    fn compressed(&mut self, raw: usize, sent: usize) {
+        let _ = (raw, sent);
+    }
+
+    /// An `Acceptor` refused a message, for the error of `code`. See the
+    /// `validate` module.
+    fn message_rejected(&mut self, code: u16) {
+        let _ = code;
+    }
+}
+
+/// `Metrics` a role reports to.
+pub type MetricsSink = Box<dyn Metrics + Send>;
+
+/// `Metrics` exported through [prometheus](https://docs.rs/prometheus).
+/// Clones share their counters, so a single `PrometheusMetrics` can be handed
+/// to every role of a node.
+#[cfg(feature = "prometheus")]
+#[derive(Debug, Clone)]
+pub struct PrometheusMetrics {
+    prepares_sent: prometheus::IntCounter,
+    nacks_received: prometheus::IntCounter,
+    promises_sent: prometheus::IntCounter,
+    values_accepted: prometheus::IntCounter,
+    decisions: prometheus::IntCounter,
+    decision_latency: prometheus::Histogram,
+    raw_bytes: prometheus::IntCounter,
+    sent_bytes: prometheus::IntCounter,
+    messages_rejected: prometheus::IntCounterVec,
+}
+
+#[cfg(feature = "prometheus")]
+impl PrometheusMetrics {
+    /// Creates the metrics, registered with the default registry.
+    pub fn new() -> prometheus::Result<Self> {
+        Self::register(prometheus::default_registry())
+    }
+
+    /// Creates the metrics, registered with `registry`.
+    pub fn register(registry: &prometheus::Registry) -> prometheus::Result<Self> {
+        let counter = |name: &str, help: &str| -> prometheus::Result<prometheus::IntCounter> {
+            let counter = prometheus::IntCounter::new(name, help)?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+        let decision_latency = prometheus::Histogram::with_opts(prometheus::HistogramOpts::new(
+            "paxos_decision_latency_seconds",
+            "Time from a value being proposed to it being decided",
+        ))?;
+        registry.register(Box::new(decision_latency.clone()))?;
+        let messages_rejected = prometheus::IntCounterVec::new(
+            prometheus::Opts::new(
+                "paxos_messages_rejected_total",
+                "Messages refused by an Acceptor, by error code",
+            ),
+            &["code"],
+        )?;
+        registry.register(Box::new(messages_rejected.clone()))?;
+        Ok(Self {
+            prepares_sent: counter("paxos_prepares_sent_total", "Prepare messages sent")?,
+            nacks_received: counter("paxos_nacks_received_total", "Nack messages received")?,
+            promises_sent: counter("paxos_promises_sent_total", "Promise messages sent")?,
+            values_accepted: counter("paxos_values_accepted_total", "Values accepted")?,
+            decisions: counter("paxos_decisions_total", "Values decided")?,
+            decision_latency,
+            raw_bytes: counter(
+                "paxos_compression_raw_bytes_total",
+                "Bytes of messages sent, before compression",
+            )?,
+            sent_bytes: counter(
+                "paxos_compression_sent_bytes_total",
+                "Bytes of messages sent, after compression",
+            )?,
+            messages_rejected,
+        })
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl Metrics for PrometheusMetrics {
+    fn prepare_sent(&mut self) {
+        self.prepares_sent.inc();
+    }
+
+    fn nack_received(&mut self) {
+        self.nacks_received.inc();
+    }
+
+    fn promise_sent(&mut self) {
+        self.promises_sent.inc();
+    }
+
+    fn value_accepted(&mut self) {
+        self.values_accepted.inc();
+    }
+
+    fn decided(&mut self) {
+        self.decisions.inc();
+    }
+
+    fn decision_latency(&mut self, latency: u64) {
+        self.decision_latency.observe(latency as f64 / 1000.0);
+    }
+
+    fn compressed(&mut self, raw: usize, sent: usize) {
+        self.raw_bytes.inc_by(raw as u64);
+        self.sent_bytes.inc_by(sent as u64);
+    }
+
+    fn message_rejected(&mut self, code: u16) {
+        self.messages_rejected
+            .with_label_values(&[&alloc::format!("{code}")])
+            .inc();
+    }
+}
+
+/// Number of bits of precision kept within each power of two. Values are
+/// recorded with a relative error of at most 1 / 2^SUB_BUCKET_BITS (~3%).
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// A log-linear (HDR style) histogram of `u64` samples.
+///
+/// Small values are counted exactly, larger values are grouped into buckets
+/// whose width grows with their magnitude, keeping a constant relative error
+/// while using a bounded amount of memory.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    /// Creates an empty `Histogram`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a single sample.
+    pub fn record(&mut self, value: u64) {
+        let index = bucket_index(value);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if value > self.max {
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum += value as u128;
+    }
+
+    /// Adds every sample of `other` to this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.count == 0 {
+            return;
+        }
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+
+        if self.count == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        if other.max > self.max {
+            self.max = other.max;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+    }
+
+    /// The number of recorded samples.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The smallest recorded sample, if any.
+    pub fn min(&self) -> Option<u64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.min)
+        }
+    }
+
+    /// The largest recorded sample, if any.
+    pub fn max(&self) -> Option<u64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.max)
+        }
+    }
+
+    /// The exact mean of all recorded samples, if any.
+    pub fn mean(&self) -> Option<u64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some((self.sum / self.count as u128) as u64)
+        }
+    }
+
+    /// The value below which `quantile` (0.0 ..= 1.0) of the samples fall,
+    /// within the precision of the bucket it lands in.
+    pub fn value_at_quantile(&self, quantile: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let quantile = quantile.clamp(0.0, 1.0);
+        let rank = ((quantile * self.count as f64) as u64).clamp(1, self.count);
+
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper = bucket_upper_bound(index);
+                return Some(upper.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+}
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let mantissa = value >> shift;
+    (SUB_BUCKETS + shift as u64 * SUB_BUCKETS + (mantissa - SUB_BUCKETS)) as usize
+}
+
+fn bucket_upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let offset = index - SUB_BUCKETS;
+    let shift = offset / SUB_BUCKETS;
+    let mantissa = offset % SUB_BUCKETS + SUB_BUCKETS;
+    // The topmost bucket overflows to zero, wrapping round to `u64::MAX`.
+    ((mantissa + 1) << shift).wrapping_sub(1)
+}
+
+/// Latency histograms recorded for a single batch size.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LatencyHistograms {
+    /// Time from a proposal being made to it being decided
+    pub proposal_to_decision: Histogram,
+    /// Time from a value being decided to it being applied
+    pub decision_to_apply: Histogram,
+}
+
+/// Latencies observed for a contiguous range of instances.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LatencySnapshot {
+    /// The lowest and highest instance recorded, if any were
+    pub instances: Option<(u64, u64)>,
+    /// Histograms broken down by the batch size of the decided value
+    pub by_batch_size: BTreeMap<usize, LatencyHistograms>,
+}
+
+impl LatencySnapshot {
+    /// Adds the latencies of `other`, e.g. those another role of the same
+    /// node recorded.
+    pub fn merge(&mut self, other: &LatencySnapshot) {
+        self.instances = match (self.instances, other.instances) {
+            (Some((low, high)), Some((other_low, other_high))) => {
+                Some((low.min(other_low), high.max(other_high)))
+            }
+            (instances, other_instances) => instances.or(other_instances),
+        };
+        for (batch_size, histograms) in &other.by_batch_size {
+            let merged = self.by_batch_size.entry(*batch_size).or_default();
+            merged
+                .proposal_to_decision
+                .merge(&histograms.proposal_to_decision);
+            merged
+                .decision_to_apply
+                .merge(&histograms.decision_to_apply);
+        }
+    }
+
+    /// Merges the histograms of every batch size together.
+    pub fn total(&self) -> LatencyHistograms {
+        let mut total = LatencyHistograms::default();
+        for histograms in self.by_batch_size.values() {
+            total
+                .proposal_to_decision
+                .merge(&histograms.proposal_to_decision);
+            total.decision_to_apply.merge(&histograms.decision_to_apply);
+        }
+        total
+    }
+}
+
+/// Records proposal-to-decision and decision-to-apply latencies per instance.
+///
+/// Timestamps are supplied by the caller in whatever unit it prefers (e.g.
+/// microseconds), which keeps the recorder usable without a clock. Calling
+/// `snapshot` periodically yields the latencies of the instances recorded
+/// since the previous snapshot.
+///
+/// A `Proposer` or `Learner` keeps one once `record_latencies` is called on
+/// it, timing its decisions, and for a `Learner` their `apply` or
+/// `Node::poll_decided`, with its own clock. `Node::latency_snapshot` merges
+/// those of its roles, and their `status` shows the latencies pending.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyRecorder {
+    /// Instance => time it was proposed
+    proposed_at: BTreeMap<u64, u64>,
+    /// Instance => (time it was decided, batch size)
+    decided_at: BTreeMap<u64, (u64, usize)>,
+    current: LatencySnapshot,
+}
+
+impl LatencyRecorder {
+    /// Creates a new `LatencyRecorder`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `instance` was proposed at `now`.
+    pub fn proposed(&mut self, instance: u64, now: u64) {
+        self.proposed_at.insert(instance, now);
+    }
+
+    /// Records that `instance` was decided at `now` with a value made up of
+    /// `batch_size` entries.
+    pub fn decided(&mut self, instance: u64, now: u64, batch_size: usize) {
+        if let Some(proposed) = self.proposed_at.remove(&instance) {
+            self.histograms(instance, batch_size)
+                .proposal_to_decision
+                .record(now.saturating_sub(proposed));
+        }
+        self.decided_at.insert(instance, (now, batch_size));
+    }
+
+    /// Records that `instance`, proposed at `proposed_at`, was decided at
+    /// `now` with a value made up of `batch_size` entries, by a role which
+    /// doesn't apply it.
+    pub fn resolved(&mut self, instance: u64, proposed_at: u64, now: u64, batch_size: usize) {
+        self.proposed_at.remove(&instance);
+        self.histograms(instance, batch_size)
+            .proposal_to_decision
+            .record(now.saturating_sub(proposed_at));
+    }
+
+    /// Records that `instance` was applied to the state machine at `now`.
+    pub fn applied(&mut self, instance: u64, now: u64) {
+        if let Some((decided, batch_size)) = self.decided_at.remove(&instance) {
+            self.histograms(instance, batch_size)
+                .decision_to_apply
+                .record(now.saturating_sub(decided));
+        }
+    }
+
+    /// Forgets the instances below `instance` that were decided but will
+    /// never be applied, e.g. as a snapshot covers them.
+    pub fn skip(&mut self, instance: u64) {
+        self.decided_at = self.decided_at.split_off(&instance);
+    }
+
+    /// The latencies recorded since the previous snapshot.
+    pub fn pending(&self) -> &LatencySnapshot {
+        &self.current
+    }
+
+    /// Returns the latencies recorded since the previous snapshot and starts a
+    /// new one.
+    pub fn snapshot(&mut self) -> LatencySnapshot {
+        core::mem::take(&mut self.current)
+    }
+
+    fn histograms(&mut self, instance: u64, batch_size: usize) -> &mut LatencyHistograms {
+        self.current.instances = match self.current.instances {
+            Some((low, high)) => Some((low.min(instance), high.max(instance))),
+            None => Some((instance, instance)),
+        };
+        self.current.by_batch_size.entry(batch_size).or_default()
+    }
+}
+
+/// A `LatencyRecorder` a role keeps, along with how the batch size of a
+/// value is measured.
+pub(crate) type RecordedLatencies<T> = (LatencyRecorder, fn(&T) -> usize);
+
+impl<T, M> Proposer<T, M> {
+    /// Starts recording the latency of each value decided, keyed by its
+    /// batch size as measured by `batch_size`.
+    pub fn record_latencies(&mut self, batch_size: fn(&T) -> usize) {
+        self.latency_recorder = Some((LatencyRecorder::new(), batch_size));
+    }
+
+    /// Returns the latencies recorded since the last call. Empty unless
+    /// `record_latencies` was called.
+    pub fn latency_snapshot(&mut self) -> LatencySnapshot {
+        self.latency_recorder
+            .as_mut()
+            .map(|(recorder, _)| recorder.snapshot())
+            .unwrap_or_default()
+    }
+}
+
+impl<T, M> Learner<T, M> {
+    /// Starts recording the latency of each value decided, and of its
+    /// `apply` once decided, keyed by its batch size as measured by
+    /// `batch_size`.
+    pub fn record_latencies(&mut self, batch_size: fn(&T) -> usize) {
+        self.latency_recorder = Some((LatencyRecorder::new(), batch_size));
+    }
+
+    /// Returns the latencies recorded since the last call. Empty unless
+    /// `record_latencies` was called.
+    pub fn latency_snapshot(&mut self) -> LatencySnapshot {
+        self.latency_recorder
+            .as_mut()
+            .map(|(recorder, _)| recorder.snapshot())
+            .unwrap_or_default()
+    }
+}
+
+impl<T, M> Node<T, M> {
+    /// Starts recording latencies in the `Proposer` and the `Learner`. See
+    /// `Proposer::record_latencies` and `Learner::record_latencies`.
+    pub fn record_latencies(&mut self, batch_size: fn(&T) -> usize) {
+        self.proposer.record_latencies(batch_size);
+        self.learner.record_latencies(batch_size);
+    }
+
+    /// Returns the latencies the `Proposer` and the `Learner` recorded since
+    /// the last call, merged.
+    pub fn latency_snapshot(&mut self) -> LatencySnapshot {
+        let mut snapshot = self.proposer.latency_snapshot();
+        snapshot.merge(&self.learner.latency_snapshot());
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::message::{Handler, Message, NackData, Slot};
+    use crate::state_machine::StateMachine;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use std::sync::Mutex;
+
+    /// Counts every hook called, across the roles it is shared by.
+    #[derive(Clone, Default)]
+    struct Counts(Arc<Mutex<[u64; 6]>>);
+
+    impl Metrics for Counts {
+        fn prepare_sent(&mut self) {
+            self.0.lock().unwrap()[0] += 1;
+        }
+
+        fn nack_received(&mut self) {
+            self.0.lock().unwrap()[1] += 1;
+        }
+
+        fn promise_sent(&mut self) {
+            self.0.lock().unwrap()[2] += 1;
+        }
+
+        fn value_accepted(&mut self) {
+            self.0.lock().unwrap()[3] += 1;
+        }
+
+        fn decided(&mut self) {
+            self.0.lock().unwrap()[4] += 1;
+        }
+
+        fn decision_latency(&mut self, latency: u64) {
+            self.0.lock().unwrap()[5] += latency;
+        }
+    }
+
+    #[test]
+    fn metrics_hooks() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let counts = Counts::default();
+        let mut nodes: Vec<Node<u64>> = (1..=3)
+            .map(|id| {
+                let mut n = Node::new(id, config.clone());
+                n.set_metrics(counts.clone());
+                n
+            })
+            .collect();
+
+        nodes[0].propose(10);
+        nodes[0].tick(5);
+        let mut sent = nodes[0].take_effects();
+        while !sent.is_empty() {
+            for effect in core::mem::take(&mut sent) {
+                for n in nodes.iter_mut() {
+                    if let Some(msg) = effect.message_for(n.id()) {
+                        sent.extend(n.step(msg.clone()));
+                    }
+                }
+            }
+        }
+        nodes[0].handle(Message::Nack(NackData {
+            slot: 0,
+            id: 1,
+            from: 2,
+            promised_n: 2,
+            leader: Some(2),
+        }));
+
+        // One `Prepare`, three promises and votes, and a decision per node,
+        // 5ms after the proposal.
+        assert_eq!(*counts.0.lock().unwrap(), [1, 1, 3, 3, 3, 5]);
+    }
+
+    #[test]
+    fn metrics_latencies() {
+        struct Sink;
+
+        impl StateMachine<u64> for Sink {
+            type Output = ();
+
+            fn apply(&mut self, _slot: Slot, _value: &u64) {}
+
+            fn snapshot(&self) -> u64 {
+                0
+            }
+
+            fn restore(&mut self, _snapshot: &u64) {}
+        }
+
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut nodes: Vec<Node<u64>> = (1..=3).map(|id| Node::new(id, config.clone())).collect();
+        nodes[0].record_latencies(|_| 4);
+        nodes[1].record_latencies(|_| 4);
+
+        nodes[0].propose(10);
+        nodes[0].tick(5);
+        let mut sent = nodes[0].take_effects();
+        while !sent.is_empty() {
+            for effect in core::mem::take(&mut sent) {
+                for n in nodes.iter_mut() {
+                    if let Some(msg) = effect.message_for(n.id()) {
+                        sent.extend(n.step(msg.clone()));
+                    }
+                }
+            }
+        }
+        nodes[0].tick(8);
+        nodes[0].learner_mut().apply(&mut Sink);
+        nodes[1].tick(7);
+        nodes[1].poll_decided();
+
+        // The `Proposer` timed the decision, and the `Learner` its apply.
+        let snapshot = nodes[0].latency_snapshot();
+        assert_eq!(snapshot.instances, Some((0, 0)));
+        let batched = &snapshot.by_batch_size[&4];
+        assert_eq!(batched.proposal_to_decision.max(), Some(5));
+        assert_eq!(batched.decision_to_apply.max(), Some(3));
+        assert_eq!(nodes[0].latency_snapshot(), LatencySnapshot::default());
+
+        // Values polled count as applied.
+        let pending = nodes[1].learner().status().latencies.unwrap();
+        assert_eq!(pending.total().decision_to_apply.max(), Some(7));
+        assert_eq!(nodes[1].latency_snapshot(), pending);
+        assert_eq!(nodes[2].latency_snapshot(), LatencySnapshot::default());
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn metrics_prometheus() {
+        let registry = prometheus::Registry::new();
+        let mut metrics = PrometheusMetrics::register(&registry).unwrap();
+
+        metrics.prepare_sent();
+        metrics.decision_latency(250);
+
+        let families = registry.gather();
+        let family = |name: &str| families.iter().find(|f| f.name() == name).unwrap();
+
+        assert_eq!(
+            family("paxos_prepares_sent_total").get_metric()[0]
+                .get_counter()
+                .get_value(),
+            1.0
+        );
+        assert_eq!(
+            family("paxos_decision_latency_seconds").get_metric()[0]
+                .get_histogram()
+                .get_sample_sum(),
+            0.25
+        );
+    }
+
+    #[test]
+    fn histogram_new() {
+        let h = Histogram::new();
+
+        assert_eq!(h.count(), 0);
+        assert_eq!(h.min(), None);
+        assert_eq!(h.max(), None);
+        assert_eq!(h.value_at_quantile(0.5), None);
+    }
+
+    #[test]
+    fn histogram_record() {
+        let mut h = Histogram::new();
+
+        for value in 1..=100 {
+            h.record(value);
+        }
+
+        assert_eq!(h.count(), 100);
+        assert_eq!(h.min(), Some(1));
+        assert_eq!(h.max(), Some(100));
+        assert_eq!(h.mean(), Some(50));
+        assert_eq!(h.value_at_quantile(0.1), Some(10));
+        assert_eq!(h.value_at_quantile(1.0), Some(100));
+
+        // Large samples stay within the bucket precision.
+        let p50 = h.value_at_quantile(0.5).unwrap();
+        assert!((50..=52).contains(&p50));
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        for value in [0, 31, 32, 33, 1_000, 123_456_789, u64::MAX] {
+            let upper = bucket_upper_bound(bucket_index(value));
+            assert!(upper >= value);
+            assert!(upper - value <= value / SUB_BUCKETS);
+        }
+    }
+
+    #[test]
+    fn histogram_merge() {
+        let mut a = Histogram::new();
+        let mut b = Histogram::new();
+        a.record(5);
+        b.record(1_000);
+        b.record(2);
+
+        a.merge(&b);
+
+        assert_eq!(a.count(), 3);
+        assert_eq!(a.min(), Some(2));
+        assert_eq!(a.max(), Some(1_000));
+    }
+
+    #[test]
+    fn latency_recorder_snapshot() {
+        let mut r = LatencyRecorder::new();
+
+        r.proposed(1, 100);
+        r.proposed(2, 100);
+        r.decided(1, 130, 1);
+        r.decided(2, 150, 4);
+        r.applied(1, 135);
+
+        let snapshot = r.snapshot();
+
+        assert_eq!(snapshot.instances, Some((1, 2)));
+        let single = &snapshot.by_batch_size[&1];
+        assert_eq!(single.proposal_to_decision.max(), Some(30));
+        assert_eq!(single.decision_to_apply.max(), Some(5));
+        let batched = &snapshot.by_batch_size[&4];
+        assert_eq!(batched.proposal_to_decision.max(), Some(50));
+        assert_eq!(batched.decision_to_apply.count(), 0);
+        assert_eq!(snapshot.total().proposal_to_decision.count(), 2);
+
+        // Instance 2 is applied after the snapshot and lands in the next one.
+        r.applied(2, 170);
+        let snapshot = r.snapshot();
+
+        assert_eq!(snapshot.instances, Some((2, 2)));
+        assert_eq!(snapshot.by_batch_size[&4].decision_to_apply.max(), Some(20));
+
+        // A `Proposer` records decisions it never applies, and a snapshot
+        // passes over values decided but not applied.
+        r.resolved(3, 200, 240, 1);
+        r.decided(4, 250, 1);
+        r.skip(5);
+        r.applied(4, 260);
+        let mut snapshot = r.snapshot();
+
+        assert_eq!(
+            snapshot.by_batch_size[&1].proposal_to_decision.max(),
+            Some(40)
+        );
+        assert_eq!(snapshot.by_batch_size[&1].decision_to_apply.count(), 0);
+
+        snapshot.merge(&LatencySnapshot::default());
+        snapshot.merge(&LatencySnapshot {
+            instances: Some((7, 7)),
+            by_batch_size: BTreeMap::new(),
+        });
+
+        assert_eq!(snapshot.instances, Some((3, 7)));
+    }
+}
@@ -0,0 +1,360 @@
+//! Model checking
+//!
+//! A `Model` explores every order in which a small cluster of `Node`s may
+//! receive the messages they exchange, and checks that each state reached
+//! satisfies the safety properties of consensus: agreement and validity
+//! across `Learner`s, and the single-decree invariants of `Acceptor`s, as
+//! checked by the `invariants` module.
+//!
+//! A `Node` is a deterministic function of the messages it received, so a
+//! state is identified by the sequence each `Node` received (and the messages
+//! lost, if any may be). Orders which only differ in how deliveries to
+//! different `Node`s interleave lead to the same state, which is explored
+//! once. Roles can't be cloned, so states are rebuilt by replaying their
+//! messages onto fresh `Node`s.
+//!
+//! `Promise`s and `Nack`s go back to the `Proposer` they answer, and every
+//! other message to every `Node`. Even so, the number of states grows quickly
+//! with the size of the cluster, and competing `Proposer`s may preempt each
+//! other forever: bound the exploration with `max_depth` in those cases.
+//!
+//! The explorer stands in for [stateright](https://docs.rs/stateright)'s
+//! `Model`, which the crate doesn't implement: stateright's checkers clone and
+//! hash states, which roles holding boxed `Messenger`s and callbacks can't be.
+//! States made of replayed message sequences could be, but would explore
+//! nothing the explorer doesn't: checking them still means replaying the
+//! crate's own `Node`s, and stateright would only add a dependency, and its
+//! thread pool, to the `model-check` feature.
+
+use crate::config::{ClusterConfig, NodeId};
+use crate::effect::Effect;
+use crate::invariants::{check_agreement, check_single_decree, check_validity, InvariantError};
+use crate::message::Message;
+use crate::node::Node;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Identifies a message by its sender, and the number of messages the sender
+/// sent before it.
+pub type MessageId = (NodeId, usize);
+
+/// A step from one state to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Action {
+    /// A message reaches a `Node`
+    Deliver { msg: MessageId, to: NodeId },
+    /// A message to a `Node` is lost
+    Lose { msg: MessageId, to: NodeId },
+}
+
+/// A safety property.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Property {
+    /// An invariant of the `invariants` module
+    Invariant(InvariantError),
+    /// A role panicked while handling a message, with the panic's message
+    Panicked(String),
+}
+
+/// A property violated, and the actions leading to the violation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The property violated
+    pub property: Property,
+    /// The actions taken from the initial state
+    pub path: Vec<Action>,
+}
+
+/// The outcome of a successful check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    /// Number of distinct states explored
+    pub states: usize,
+    /// Number of actions on the longest path explored
+    pub depth: usize,
+}
+
+/// Identifies a state: the messages each `Node` received, in order, and the
+/// messages lost.
+type Key = (
+    BTreeMap<NodeId, Vec<MessageId>>,
+    BTreeSet<(MessageId, NodeId)>,
+);
+
+/// A cluster rebuilt from a path.
+struct State<T> {
+    nodes: BTreeMap<NodeId, Node<T>>,
+    /// Messages sent by each `Node`, in order
+    sent: BTreeMap<NodeId, Vec<Message<T>>>,
+    /// Messages sent but neither delivered nor lost yet
+    pending: BTreeSet<(MessageId, NodeId)>,
+}
+
+/// A small cluster, the values proposed to it, and the faults its network
+/// may have.
+pub struct Model<T> {
+    /// The cluster's configuration, whose members run a `Node` each
+    config: ClusterConfig,
+    /// Values proposed before any message is delivered, by `Node`
+    proposals: Vec<(NodeId, T)>,
+    /// Whether messages may be lost
+    lossy: bool,
+    /// Maximum number of actions explored from the initial state
+    max_depth: Option<usize>,
+}
+
+impl<T> Model<T>
+where
+    T: PartialEq + Clone,
+{
+    /// Creates a `Model` of a cluster running `config`, whose network
+    /// delivers every message exactly once, in any order.
+    pub fn new(config: ClusterConfig) -> Self {
+        Self {
+            config,
+            proposals: Vec::new(),
+            lossy: false,
+            max_depth: None,
+        }
+    }
+
+    /// Has node `id` propose `value` in the initial state.
+    pub fn propose(mut self, id: NodeId, value: T) -> Self {
+        self.proposals.push((id, value));
+        self
+    }
+
+    /// Lets messages be lost.
+    pub fn lossy(mut self) -> Self {
+        self.lossy = true;
+        self
+    }
+
+    /// Explores states up to `depth` actions from the initial state only.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Explores every reachable state, depth first, and returns the first
+    /// violation found, if any.
+    pub fn check(&self) -> Result<Report, Violation> {
+        let mut report = Report {
+            states: 0,
+            depth: 0,
+        };
+        let root: Key = (
+            self.config
+                .members
+                .iter()
+                .map(|&id| (id, Vec::new()))
+                .collect(),
+            BTreeSet::new(),
+        );
+        let mut visited = BTreeSet::new();
+        visited.insert(root.clone());
+        let mut stack = alloc::vec![(Vec::new(), root)];
+
+        while let Some((path, key)) = stack.pop() {
+            report.states += 1;
+            report.depth = report.depth.max(path.len());
+            let state = match panic::catch_unwind(AssertUnwindSafe(|| self.replay(&path))) {
+                Ok(state) => state,
+                Err(err) => {
+                    let message = err
+                        .downcast_ref::<String>()
+                        .cloned()
+                        .or_else(|| err.downcast_ref::<&str>().map(|s| s.to_string()))
+                        .unwrap_or_default();
+                    return Err(Violation {
+                        property: Property::Panicked(message),
+                        path,
+                    });
+                }
+            };
+            if let Some(property) = self.violated(&state) {
+                return Err(Violation { property, path });
+            }
+            if self.max_depth.is_some_and(|max| path.len() >= max) {
+                continue;
+            }
+
+            for &(msg, to) in state.pending.iter().rev() {
+                let mut next = key.clone();
+                next.0.get_mut(&to).unwrap().push(msg);
+                let mut actions = alloc::vec![(Action::Deliver { msg, to }, next)];
+                if self.lossy {
+                    let mut next = key.clone();
+                    next.1.insert((msg, to));
+                    actions.push((Action::Lose { msg, to }, next));
+                }
+                for (action, next) in actions {
+                    if visited.insert(next.clone()) {
+                        let mut path = path.clone();
+                        path.push(action);
+                        stack.push((path, next));
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Rebuilds the state `path` leads to.
+    fn replay(&self, path: &[Action]) -> State<T> {
+        let mut state = State {
+            nodes: self
+                .config
+                .members
+                .iter()
+                .map(|&id| (id, Node::new(id, self.config.clone())))
+                .collect(),
+            sent: self
+                .config
+                .members
+                .iter()
+                .map(|&id| (id, Vec::new()))
+                .collect(),
+            pending: BTreeSet::new(),
+        };
+        for (id, value) in &self.proposals {
+            let node = state.nodes.get_mut(id).unwrap();
+            node.propose(value.clone());
+            let effects = node.take_effects();
+            state.send(*id, effects, None);
+        }
+        for action in path {
+            match *action {
+                Action::Deliver { msg, to } => {
+                    state.pending.remove(&(msg, to));
+                    let message = state.sent[&msg.0][msg.1].clone();
+                    let effects = state.nodes.get_mut(&to).unwrap().step(message);
+                    state.send(to, effects, Some(msg.0));
+                }
+                Action::Lose { msg, to } => {
+                    state.pending.remove(&(msg, to));
+                }
+            }
+        }
+        state
+    }
+
+    /// The first property `state` violates, if any.
+    fn violated(&self, state: &State<T>) -> Option<Property> {
+        let learners = || state.nodes.values().map(Node::learner);
+        let proposed: Vec<T> = self.proposals.iter().map(|(_, v)| v.clone()).collect();
+        check_agreement(learners())
+            .and_then(|_| check_validity(learners(), &proposed))
+            .and_then(|_| check_single_decree(state.nodes.values().map(Node::acceptor)))
+            .err()
+            .map(Property::Invariant)
+    }
+}
+
+impl<T: Clone> State<T> {
+    /// Sends the messages among `effects` from node `from`. Replies to the
+    /// first phase go back to `reply_to`, the sender of the message handled,
+    /// and the other messages to every node.
+    fn send(&mut self, from: NodeId, effects: Vec<Effect<T>>, reply_to: Option<NodeId>) {
+        let ids: Vec<NodeId> = self.nodes.keys().copied().collect();
+        for effect in effects {
+            if let Effect::SendMessage(msg) | Effect::SendMessageTo(_, msg) = effect {
+                let reply = matches!(
+                    msg,
+                    Message::Promise(_)
+                        | Message::Nack(_)
+                        | Message::ReadReply(_)
+                        | Message::Repair(_)
+                );
+                let sent = self.sent.get_mut(&from).unwrap();
+                let id = (from, sent.len());
+                sent.push(msg);
+                match reply_to {
+                    Some(to) if reply => {
+                        self.pending.insert((id, to));
+                    }
+                    _ => self.pending.extend(ids.iter().map(|&to| (id, to))),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QuorumConfig;
+    use alloc::vec;
+
+    #[test]
+    fn model_single_proposer() {
+        let report = Model::new(ClusterConfig::new(vec![1, 2]))
+            .propose(1, 10u64)
+            .check()
+            .unwrap();
+
+        // Prepare, Promise, Accept, Accepted and Learn, delivered to both.
+        assert_eq!(report.depth, 12);
+
+        let report = Model::new(ClusterConfig::new(vec![1, 2, 3]))
+            .propose(1, 10u64)
+            .max_depth(8)
+            .check()
+            .unwrap();
+
+        assert_eq!(report.depth, 8);
+        assert!(report.states > 1000);
+    }
+
+    #[test]
+    fn model_lossy() {
+        let config = ClusterConfig::new(vec![1, 2]);
+        let lossless = Model::new(config.clone())
+            .propose(1, 10u64)
+            .check()
+            .unwrap();
+        let lossy = Model::new(config)
+            .propose(1, 10u64)
+            .lossy()
+            .check()
+            .unwrap();
+
+        assert!(lossy.states > lossless.states);
+    }
+
+    #[test]
+    fn model_leaders() {
+        // Competing proposers may preempt each other forever.
+        let config = ClusterConfig::new(vec![1, 2]).with_leaders(vec![1, 2]);
+        Model::new(config)
+            .propose(1, 10u64)
+            .propose(2, 20)
+            .max_depth(8)
+            .check()
+            .unwrap();
+    }
+
+    #[test]
+    fn model_disjoint_quorums() {
+        let mut config = ClusterConfig::new(vec![1, 2]);
+        config.quorums = Some(QuorumConfig {
+            phase1: 1,
+            phase2: 1,
+        });
+        let violation = Model::new(config)
+            .propose(1, 10u64)
+            .propose(2, 20)
+            .max_depth(10)
+            .check()
+            .unwrap_err();
+
+        // The second proposal is accepted over the first, chosen already.
+        assert_eq!(
+            violation.property,
+            Property::Invariant(InvariantError::Chosen(0))
+        );
+        assert!(!violation.path.is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+//! Nack suppression
+//!
+//! An `Acceptor` answers every `Prepare` or `Accept` below its promise with a
+//! `Nack`, so that the `Proposer` learns it was outbid. During leadership
+//! churn, stale `Proposer`s retrying in a tight loop would have it answer
+//! every attempt, amplifying the load they put on it. With a Nack interval
+//! set, an `Acceptor` sends each `Proposer` at most one `Nack` per interval:
+//! proposals refused in between are coalesced, and the latest of them is
+//! answered once the interval is over, with the promise as it stands then.
+//!
+//! The `Acceptor` has no clock of its own: `Acceptor::set_time` advances it,
+//! as `Node::tick` does.
+
+use crate::acceptor::Acceptor;
+use crate::config::NodeId;
+use crate::message::{Messenger, Slot};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// The state of an `Acceptor`'s Nack suppression.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct NackLimiter {
+    /// Milliseconds between two `Nack`s to the same `Proposer`, if limited
+    interval: Option<u64>,
+    /// The current time, in milliseconds
+    now: u64,
+    /// When each `Proposer` was last sent a `Nack`
+    sent_at: BTreeMap<NodeId, u64>,
+    /// The latest proposal of each `Proposer` refused while held back,
+    /// (slot, proposal_n)
+    held: BTreeMap<NodeId, (Slot, u64)>,
+}
+
+impl NackLimiter {
+    /// Whether `Proposer` `to` may be sent a `Nack` for proposal `id` for
+    /// `slot` right away. If not, the proposal is held back to be answered
+    /// once the interval is over.
+    pub(crate) fn admit(&mut self, to: NodeId, slot: Slot, id: u64) -> bool {
+        let Some(interval) = self.interval else {
+            return true;
+        };
+        match self.sent_at.get(&to) {
+            Some(at) if at + interval > self.now => {
+                let held = self.held.entry(to).or_insert((slot, id));
+                if id >= held.1 {
+                    *held = (slot, id);
+                }
+                false
+            }
+            _ => {
+                self.sent_at.insert(to, self.now);
+                self.held.remove(&to);
+                true
+            }
+        }
+    }
+
+    /// Advances the clock to `now`, returning the proposals held back whose
+    /// `Proposer` may be sent a `Nack` again, (slot, proposal_n).
+    fn advance(&mut self, now: u64) -> Vec<(Slot, u64)> {
+        self.now = self.now.max(now);
+        let Some(interval) = self.interval else {
+            return Vec::new();
+        };
+        let due: Vec<NodeId> = self
+            .held
+            .keys()
+            .copied()
+            .filter(|to| {
+                self.sent_at
+                    .get(to)
+                    .is_none_or(|at| at + interval <= self.now)
+            })
+            .collect();
+        due.into_iter()
+            .map(|to| {
+                self.sent_at.insert(to, self.now);
+                self.held.remove(&to).unwrap()
+            })
+            .collect()
+    }
+}
+
+impl<T, M: Messenger<T>> Acceptor<T, M> {
+    /// Sets the milliseconds between two `Nack`s sent to the same `Proposer`.
+    /// Every refused proposal is answered right away if `None`, the default.
+    pub fn set_nack_interval(&mut self, interval: Option<u64>) {
+        self.nacks.interval = interval;
+        if interval.is_none() {
+            self.nacks.held.clear();
+        }
+    }
+
+    /// Answers the proposals held back that may be answered as of `now`.
+    pub(crate) fn flush_nacks(&mut self, now: u64) {
+        for (slot, id) in self.nacks.advance(now) {
+            self.send_nack(slot, id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::acceptor::Acceptor;
+    use crate::config::ClusterConfig;
+    use crate::effect::Effect;
+    use crate::message::{Handler, Message, NackData, ProposalData};
+    use crate::proposer::ballot;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn prepare(round: u64, from: u64) -> Message<u64> {
+        Message::Prepare(ProposalData {
+            slot: 0,
+            id: ballot(round, from),
+            from,
+        })
+    }
+
+    #[test]
+    fn nack_suppression() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        a.set_nack_interval(Some(100));
+        a.handle(prepare(10, 2));
+        a.take_effects();
+
+        // Node 3 retries in a tight loop: only its first attempt is answered
+        // until the interval is over.
+        for round in 1..=3 {
+            a.handle(prepare(round, 3));
+        }
+        a.handle(prepare(4, 2));
+        let nacks = |a: &mut Acceptor<u64>| -> Vec<u64> {
+            a.take_effects()
+                .into_iter()
+                .filter_map(|effect| match effect {
+                    Effect::SendMessage(Message::Nack(NackData { id, .. })) => Some(id),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert_eq!(nacks(&mut a), vec![ballot(1, 3), ballot(4, 2)]);
+
+        a.set_time(99);
+
+        assert!(nacks(&mut a).is_empty());
+
+        // The latest attempt is answered, with the promise as it stands.
+        a.handle(prepare(11, 2));
+        a.take_effects();
+        a.set_time(100);
+
+        assert_eq!(
+            a.take_effects(),
+            vec![Effect::SendMessage(Message::Nack(NackData {
+                slot: 0,
+                id: ballot(3, 3),
+                from: 1,
+                promised_n: ballot(11, 2),
+                leader: Some(2),
+            }))]
+        );
+    }
+}
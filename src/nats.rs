@@ -0,0 +1,371 @@
+//! NATS transport
+//!
+//! Carries `Message`s over [NATS](https://nats.io), through the
+//! [async-nats](https://docs.rs/async-nats) client, so that a cluster can be
+//! deployed wherever NATS already runs without opening ports of its own.
+//! Every message is published, in its `wire` encoding, to a subject naming
+//! its cluster, its recipient and its `Message::kind`:
+//!
+//! | Subject                           | Carries                            |
+//! |-----------------------------------|------------------------------------|
+//! | `paxos.{cluster}.all.{kind}`      | Messages to every node             |
+//! | `paxos.{cluster}.{node}.{kind}`   | Messages to node `node` alone      |
+//! | `paxos.{cluster}.{node}.catchup`  | Catch-up requests to node `node`   |
+//!
+//! so that e.g. an observer can follow `paxos.{cluster}.all.accepted` alone.
+//!
+//! Catching up goes through request-reply: a lagging node sends a peer a
+//! `Join` with `NatsTransport::request`, and the peer receives it along with
+//! a `Reply`, which it answers with the `State` its `Acceptor` produces
+//! through `NatsTransport::respond`.
+
+use crate::config::NodeId;
+use crate::message::{Message, Messenger, Slot};
+use crate::wire::{decode, encode};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use async_nats::{Client, Request, Subject};
+use core::time::Duration;
+use futures_util::stream::{select, StreamExt};
+use std::io;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+/// The first token of every subject.
+pub const PREFIX: &str = "paxos";
+
+/// The subject `msg` is published to in `cluster`, for node `to` alone or,
+/// if `None`, for every node.
+pub fn subject<T>(cluster: &str, to: Option<NodeId>, msg: &Message<T>) -> String {
+    match to {
+        Some(to) => format!("{PREFIX}.{cluster}.{to}.{}", msg.kind()),
+        None => format!("{PREFIX}.{cluster}.all.{}", msg.kind()),
+    }
+}
+
+/// The subject node `id` of `cluster` takes catch-up requests on.
+pub fn catch_up_subject(cluster: &str, id: NodeId) -> String {
+    format!("{PREFIX}.{cluster}.{id}.catchup")
+}
+
+/// Where to answer a request received, with `NatsTransport::respond`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply(Subject);
+
+/// Sends `Message`s to the nodes of a cluster over NATS, and receives
+/// theirs.
+pub struct NatsTransport<T> {
+    /// The node's ID
+    id: NodeId,
+    /// The cluster's name, in every subject
+    cluster: String,
+    client: Client,
+    /// Subjects and payloads waiting to be published, in order
+    outbox: UnboundedSender<(String, Vec<u8>)>,
+    /// Messages received, along with where to answer requests
+    inbox: UnboundedReceiver<(Message<T>, Option<Reply>)>,
+    /// Task reading the node's subscriptions, which end along with it
+    subscriber: JoinHandle<()>,
+}
+
+impl<T> NatsTransport<T>
+where
+    T: AsRef<[u8]> + for<'a> From<&'a [u8]> + Send + Sync + 'static,
+{
+    /// Connects node `id` of `cluster` to the NATS server at `url`, e.g.
+    /// `nats://127.0.0.1:4222`, subscribing to the messages for every node
+    /// and to those for this one.
+    pub async fn connect(url: &str, cluster: &str, id: NodeId) -> io::Result<Self> {
+        let client = async_nats::connect(url).await.map_err(io::Error::other)?;
+        let all = client
+            .subscribe(format!("{PREFIX}.{cluster}.all.*"))
+            .await
+            .map_err(io::Error::other)?;
+        let own = client
+            .subscribe(format!("{PREFIX}.{cluster}.{id}.*"))
+            .await
+            .map_err(io::Error::other)?;
+
+        let (sender, inbox) = unbounded_channel();
+        let subscriber = tokio::spawn(async move {
+            let mut messages = select(all, own);
+            while let Some(message) = messages.next().await {
+                // Malformed messages are dropped like lost ones.
+                let Ok(msg) = decode(&message.payload) else {
+                    continue;
+                };
+                if sender.send((msg, message.reply.map(Reply))).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let (outbox, mut published) = unbounded_channel::<(String, Vec<u8>)>();
+        let publisher = client.clone();
+        tokio::spawn(async move {
+            while let Some((subject, bytes)) = published.recv().await {
+                if publisher.publish(subject, bytes.into()).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Self {
+            id,
+            cluster: cluster.into(),
+            client,
+            outbox,
+            inbox,
+            subscriber,
+        })
+    }
+
+    /// The node's ID.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// The cluster's name.
+    pub fn cluster(&self) -> &str {
+        &self.cluster
+    }
+
+    /// Sends `msg` to node `to`.
+    pub fn send(&self, to: NodeId, msg: &Message<T>) {
+        let _ = self
+            .outbox
+            .send((subject(&self.cluster, Some(to), msg), encode(msg)));
+    }
+
+    /// Sends `msg` to every node, this one included.
+    pub fn broadcast(&self, msg: &Message<T>) {
+        let _ = self
+            .outbox
+            .send((subject(&self.cluster, None, msg), encode(msg)));
+    }
+
+    /// A `Messenger` broadcasting to every node.
+    pub fn messenger(&self) -> NatsMessenger<T> {
+        NatsMessenger {
+            cluster: self.cluster.clone(),
+            outbox: self.outbox.clone(),
+            resolutions: None,
+        }
+    }
+
+    /// Waits for a message from any node, along with where to answer it if
+    /// it's a request.
+    pub async fn recv(&mut self) -> Option<(Message<T>, Option<Reply>)> {
+        self.inbox.recv().await
+    }
+
+    /// Answers the request `reply` came with with `msg`.
+    pub fn respond(&self, reply: Reply, msg: &Message<T>) {
+        let _ = self.outbox.send((reply.0.into_string(), encode(msg)));
+    }
+
+    /// Sends `msg`, e.g. a `Join`, to node `to` as a catch-up request, and
+    /// waits up to `timeout` for its answer.
+    pub async fn request(
+        &self,
+        to: NodeId,
+        msg: &Message<T>,
+        timeout: Duration,
+    ) -> io::Result<Message<T>> {
+        let request = Request::new()
+            .payload(encode(msg).into())
+            .timeout(Some(timeout));
+        let answer = self
+            .client
+            .send_request(catch_up_subject(&self.cluster, to), request)
+            .await
+            .map_err(io::Error::other)?;
+        decode(&answer.payload).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+impl<T> Drop for NatsTransport<T> {
+    fn drop(&mut self) {
+        self.subscriber.abort();
+    }
+}
+
+/// A `Messenger` broadcasting every message to the nodes of a
+/// `NatsTransport`'s cluster.
+pub struct NatsMessenger<T> {
+    cluster: String,
+    outbox: UnboundedSender<(String, Vec<u8>)>,
+    /// Channel notified of every resolved proposal
+    pub resolutions: Option<UnboundedSender<(Slot, Arc<T>)>>,
+}
+
+impl<T: AsRef<[u8]>> NatsMessenger<T> {
+    fn broadcast(&self, msg: Message<T>) {
+        let _ = self
+            .outbox
+            .send((subject(&self.cluster, None, &msg), encode(&msg)));
+    }
+}
+
+impl<T: AsRef<[u8]>> Messenger<T> for NatsMessenger<T> {
+    fn send_prepare(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_promise(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_accept(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_accepted(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn on_resolution(&mut self, slot: Slot, value: Arc<T>) {
+        if let Some(ref resolutions) = self.resolutions {
+            let _ = resolutions.send((slot, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{JoinData, ProposalData, StateData};
+    use alloc::string::ToString;
+    use alloc::vec;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Whether `subject` matches the subscription `pattern`, wildcards
+    /// included.
+    fn matches(pattern: &str, subject: &str) -> bool {
+        let mut subject = subject.split('.');
+        for token in pattern.split('.') {
+            match (token, subject.next()) {
+                (">", Some(_)) => return true,
+                ("*", Some(_)) => {}
+                (token, Some(next)) if token == next => {}
+                _ => return false,
+            }
+        }
+        subject.next().is_none()
+    }
+
+    /// Subscriptions by connection and subscription ID, with their pattern
+    /// and the queue of their connection's writer.
+    type Subscriptions = Arc<Mutex<Vec<(u64, String, String, UnboundedSender<Vec<u8>>)>>>;
+
+    /// Serves just enough of the NATS protocol for the transport: no
+    /// headers, queue groups or authentication. Returns its URL.
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        let subscriptions = Subscriptions::default();
+        tokio::spawn(async move {
+            for conn in 0.. {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (read, mut write) = stream.into_split();
+                let (sender, mut writes) = unbounded_channel::<Vec<u8>>();
+                tokio::spawn(async move {
+                    while let Some(bytes) = writes.recv().await {
+                        if write.write_all(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+                let info = r#"INFO {"server_id":"test","version":"2.10.0","proto":1,"max_payload":1048576}"#;
+                let _ = sender.send(format!("{info}\r\n").into_bytes());
+                tokio::spawn(serve_conn(conn, read, sender, subscriptions.clone()));
+            }
+        });
+        url
+    }
+
+    async fn serve_conn(
+        conn: u64,
+        read: tokio::net::tcp::OwnedReadHalf,
+        sender: UnboundedSender<Vec<u8>>,
+        subscriptions: Subscriptions,
+    ) {
+        let mut read = BufReader::new(read);
+        let mut line = String::new();
+        while read.read_line(&mut line).await.unwrap_or(0) > 0 {
+            let args: Vec<&str> = line.split_whitespace().collect();
+            match args.as_slice() {
+                ["PING"] => {
+                    let _ = sender.send(b"PONG\r\n".to_vec());
+                }
+                ["SUB", subject, sid] => subscriptions.lock().unwrap().push((
+                    conn,
+                    sid.to_string(),
+                    subject.to_string(),
+                    sender.clone(),
+                )),
+                ["UNSUB", sid, ..] => subscriptions
+                    .lock()
+                    .unwrap()
+                    .retain(|(c, s, ..)| (*c, s.as_str()) != (conn, *sid)),
+                ["PUB", subject, reply @ .., len] => {
+                    let mut payload = vec![0; len.parse::<usize>().unwrap() + 2];
+                    read.read_exact(&mut payload).await.unwrap();
+                    payload.truncate(payload.len() - 2);
+                    let reply = reply.first().map(|r| format!(" {r}")).unwrap_or_default();
+                    for (_, sid, pattern, sender) in subscriptions.lock().unwrap().iter() {
+                        if matches(pattern, subject) {
+                            let header =
+                                format!("MSG {subject} {sid}{reply} {}\r\n", payload.len());
+                            let _ = sender.send([header.as_bytes(), &payload, b"\r\n"].concat());
+                        }
+                    }
+                }
+                _ => {}
+            }
+            line.clear();
+        }
+    }
+
+    #[tokio::test]
+    async fn nats_transport_routes_by_subject() {
+        let url = serve().await;
+        let mut a: NatsTransport<Vec<u8>> = NatsTransport::connect(&url, "c", 1).await.unwrap();
+        let mut b: NatsTransport<Vec<u8>> = NatsTransport::connect(&url, "c", 2).await.unwrap();
+        let prepare = Message::Prepare(ProposalData {
+            slot: 0,
+            id: 1,
+            from: 1,
+        });
+        assert_eq!(subject("c", None, &prepare), "paxos.c.all.prepare");
+
+        // Broadcasts reach every node; directed messages their node alone.
+        a.messenger().send_prepare(prepare.clone());
+        assert_eq!(a.recv().await, Some((prepare.clone(), None)));
+        assert_eq!(b.recv().await, Some((prepare.clone(), None)));
+        a.send(2, &prepare);
+        assert_eq!(b.recv().await, Some((prepare, None)));
+
+        // Node 2 catches up from node 1 through request-reply.
+        let catch_up = tokio::spawn(async move {
+            let (join, reply) = a.recv().await.unwrap();
+            let state = Message::State(StateData {
+                from: 1,
+                to: 2,
+                promised_n: 1,
+                accepted: vec![],
+                decided: vec![],
+            });
+            assert_eq!(join, Message::Join(JoinData { from: 2 }));
+            a.respond(reply.unwrap(), &state);
+            state
+        });
+        let join = Message::Join(JoinData { from: 2 });
+        let state = b.request(1, &join, Duration::from_secs(10)).await.unwrap();
+        assert_eq!(state, catch_up.await.unwrap());
+    }
+}
@@ -0,0 +1,379 @@
+//! Node
+//!
+//! Most deployments run a `Proposer`, an `Acceptor` and a `Learner` in the same
+//! process. A `Node` owns all three, hands every incoming message to the roles
+//! it is meant for, and reports decided values in log order.
+
+use crate::acceptor::Acceptor;
+use crate::admission::AdmissionLimits;
+use crate::config::{ClusterConfig, NodeId};
+#[cfg(feature = "paranoid-checks")]
+use crate::error::InvariantViolation;
+use crate::error::ProposerError;
+use crate::event::Observer;
+use crate::learner::Learner;
+use crate::message::{BoxedMessenger, Handler, Message, Messenger, Slot};
+use crate::metrics::Metrics;
+use crate::priority::Priority;
+use crate::proposal::{ProposalHandle, ProposalOutcome, ProposalStatus};
+use crate::proposer::Proposer;
+use crate::read::{ReadResult, StaleRead};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A `Proposer`, an `Acceptor` and a `Learner` sharing an ID.
+///
+/// Each role sends through its own `Messenger`. Messages a `Node` sends to the
+/// cluster are expected to be delivered back to itself as well, as its
+/// `Acceptor` votes like any other.
+pub struct Node<T, M = BoxedMessenger<T>> {
+    /// `Node`'s ID, shared by its roles
+    pub(crate) id: NodeId,
+    /// The `Proposer` values are proposed through
+    pub(crate) proposer: Proposer<T, M>,
+    /// The `Acceptor` voting on proposals
+    pub(crate) acceptor: Acceptor<T, M>,
+    /// The `Learner` values are decided by
+    pub(crate) learner: Learner<T, M>,
+    /// The next slot `poll_decided` reports
+    pub(crate) delivered: Slot,
+}
+
+impl<T> Node<T>
+where
+    T: PartialEq + Clone,
+{
+    /// Creates a new `Node`, whose roles all belong to `config`.
+    pub fn new(id: NodeId, config: ClusterConfig) -> Self {
+        Self {
+            id,
+            proposer: Proposer::new(id, config.clone()),
+            acceptor: Acceptor::new(id, config.clone()),
+            learner: Learner::new(id, config),
+            delivered: 0,
+        }
+    }
+}
+
+impl<T, M> Node<T, M>
+where
+    T: PartialEq + Clone,
+    M: Messenger<T>,
+{
+    /// Creates a new `Node` whose roles each send through a clone of
+    /// `messenger`, without boxing it.
+    pub fn with_messenger(id: NodeId, config: ClusterConfig, messenger: M) -> Self
+    where
+        M: Clone,
+    {
+        Self {
+            id,
+            proposer: Proposer::with_messenger(id, config.clone(), messenger.clone()),
+            acceptor: Acceptor::with_messenger(id, config.clone(), messenger.clone()),
+            learner: Learner::with_messenger(id, config, messenger),
+            delivered: 0,
+        }
+    }
+
+    /// The `Node`'s ID.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// The `Node`'s `Proposer`.
+    pub fn proposer(&self) -> &Proposer<T, M> {
+        &self.proposer
+    }
+
+    /// The `Node`'s `Proposer`, e.g. to take over leadership.
+    pub fn proposer_mut(&mut self) -> &mut Proposer<T, M> {
+        &mut self.proposer
+    }
+
+    /// The `Node`'s `Acceptor`.
+    pub fn acceptor(&self) -> &Acceptor<T, M> {
+        &self.acceptor
+    }
+
+    /// The `Node`'s `Acceptor`, e.g. to truncate its log.
+    pub fn acceptor_mut(&mut self) -> &mut Acceptor<T, M> {
+        &mut self.acceptor
+    }
+
+    /// The `Node`'s `Learner`.
+    pub fn learner(&self) -> &Learner<T, M> {
+        &self.learner
+    }
+
+    /// The `Node`'s `Learner`, e.g. to apply decided values.
+    pub fn learner_mut(&mut self) -> &mut Learner<T, M> {
+        &mut self.learner
+    }
+
+    /// Moves every role to `config` for slots from `from` onwards, as decided
+    /// through the log. See `Membership`.
+    pub fn reconfigure(&mut self, from: Slot, config: ClusterConfig) {
+        self.proposer.reconfigure(from, config.clone());
+        self.acceptor.reconfigure(config.clone());
+        self.learner.reconfigure(from, config);
+    }
+
+    /// Sets the `Metrics` every role reports to, each through a clone of
+    /// `metrics`.
+    pub fn set_metrics<X>(&mut self, metrics: X)
+    where
+        X: Metrics + Clone + Send + 'static,
+    {
+        self.proposer.set_metrics(metrics.clone());
+        self.acceptor.set_metrics(metrics.clone());
+        self.learner.set_metrics(metrics);
+    }
+
+    /// Sets the `Observer` of every role, each through a clone of `observer`.
+    pub fn set_observer<O>(&mut self, observer: O)
+    where
+        O: Observer<T> + Clone + Send + 'static,
+    {
+        self.proposer.set_observer(observer.clone());
+        self.acceptor.set_observer(observer.clone());
+        self.learner.set_observer(observer);
+    }
+
+    /// Proposes `value` for the next slot, returning the handle its outcome
+    /// is reported under by `poll_proposals`.
+    pub fn propose(&mut self, value: T) -> ProposalHandle {
+        self.proposer.prepare(value)
+    }
+
+    /// Like `propose`, but turns `value` away with `ProposerError::Busy`
+    /// rather than queue it past the `AdmissionLimits`. See the `admission`
+    /// module.
+    pub fn try_propose(&mut self, value: T) -> Result<ProposalHandle, ProposerError> {
+        self.proposer.try_prepare(value)
+    }
+
+    /// Sets the limits on the proposals admitted. See
+    /// `Proposer::set_admission`.
+    pub fn set_admission(&mut self, limits: AdmissionLimits) {
+        self.proposer.set_admission(limits);
+    }
+
+    /// Like `propose`, but gives up on the proposal once `tick` reaches
+    /// `deadline` without it being decided. See
+    /// `Proposer::prepare_with_deadline`.
+    pub fn propose_with_deadline(&mut self, value: T, deadline: u64) -> ProposalHandle {
+        self.proposer.prepare_with_deadline(value, deadline)
+    }
+
+    /// Like `propose`, but queues `value` ahead of every value of a lower
+    /// `priority`. See `Proposer::prepare_with_priority`.
+    pub fn propose_with_priority(&mut self, value: T, priority: Priority) -> ProposalHandle {
+        self.proposer.prepare_with_priority(value, priority)
+    }
+
+    /// Stops proposing the value `handle` was returned for. See
+    /// `Proposer::abandon`.
+    pub fn abandon(&mut self, handle: ProposalHandle) {
+        self.proposer.abandon(handle);
+    }
+
+    /// Where the proposal `handle` was returned for stands. See
+    /// `Proposer::proposal_status`.
+    pub fn proposal_status(&self, handle: ProposalHandle) -> Option<ProposalStatus> {
+        self.proposer.proposal_status(handle)
+    }
+
+    /// Returns the proposals settled since the last call, along with their
+    /// outcome.
+    pub fn poll_proposals(&mut self) -> Vec<(ProposalHandle, ProposalOutcome<T>)> {
+        self.proposer.poll_proposals()
+    }
+
+    /// Starts a linearizable read, without going through the log, and returns
+    /// its ID. See the `read` module.
+    pub fn read(&mut self) -> u64 {
+        self.learner.read()
+    }
+
+    /// Returns the reads completed since the last call.
+    pub fn poll_reads(&mut self) -> Vec<ReadResult<T>> {
+        self.learner.poll_reads()
+    }
+
+    /// Reads the latest value decided locally, if the `Node` synced with the
+    /// cluster within the last `max_age` milliseconds. See `Learner::stale_read`.
+    pub fn stale_read(&self, max_age: u64) -> Option<StaleRead<T>> {
+        self.learner.stale_read(max_age)
+    }
+
+    /// Advances the `Node`'s clock to `now`, in milliseconds: the `Proposer`
+    /// fires its timeouts, the `Acceptor` answers the proposals it held back
+    /// a `Nack` for and gossips its anti-entropy `Digest`, and the `Learner`
+    /// counts a tick towards abandoning idle slots and ages its
+    /// `stale_read`s. Expected to be called periodically, e.g. with the time
+    /// of a `Clock`.
+    pub fn tick(&mut self, now: u64) {
+        self.proposer.tick(now);
+        self.acceptor.set_time(now);
+        self.learner.set_time(now);
+        self.learner.tick();
+    }
+
+    /// Takes the transitions the `Acceptor` and the `Learner` refused since
+    /// the last call, for breaking a protocol invariant.
+    #[cfg(feature = "paranoid-checks")]
+    pub fn take_violations(&mut self) -> Vec<InvariantViolation> {
+        let mut violations = self.acceptor.take_violations();
+        violations.append(&mut self.learner.take_violations());
+        violations
+    }
+
+    /// Returns the values decided since the last call, along with their slot.
+    /// Values are returned strictly in slot order: one decided ahead of an
+    /// undecided slot is held back until that slot is decided. Slots covered
+    /// by an installed snapshot are passed over. With latencies recorded, a
+    /// value counts as applied once returned.
+    pub fn poll_decided(&mut self) -> Vec<(Slot, Arc<T>)> {
+        if let Some((index, _)) = self.learner.snapshot {
+            self.delivered = self.delivered.max(index);
+        }
+        let mut decided = Vec::new();
+        while let Some(value) = self.learner.decided.get(&self.delivered) {
+            decided.push((self.delivered, value.clone()));
+            if let Some((recorder, _)) = &mut self.learner.latency_recorder {
+                recorder.applied(self.delivered, self.learner.now);
+            }
+            self.delivered += 1;
+        }
+        decided
+    }
+}
+
+impl<T, M> Handler<T> for Node<T, M>
+where
+    T: PartialEq + Clone,
+    M: Messenger<T>,
+{
+    /// Dispatches `msg` to each role that has a use for it: `Accepted` is
+    /// counted by both the `Proposer` and the `Learner`, while `Join` and
+    /// `State` concern both the `Acceptor` and the `Learner`.
+    fn handle(&mut self, msg: Message<T>) {
+        match msg {
+            Message::Prepare(_) | Message::Accept(_) | Message::Any(_) | Message::Propose(_) => {
+                self.acceptor.handle(msg)
+            }
+            Message::Promise(_) => self.proposer.handle(msg),
+            Message::Accepted(_) => {
+                self.proposer.handle(msg.clone());
+                self.learner.handle(msg);
+            }
+            Message::Join(_) | Message::State(_) => {
+                self.acceptor.handle(msg.clone());
+                self.learner.handle(msg);
+            }
+            Message::Skip(_) | Message::Learn(_) | Message::InstallSnapshot(_) => {
+                self.learner.handle(msg)
+            }
+            Message::Nack(_) => self.proposer.handle(msg),
+            Message::Read(_) | Message::Digest(_) | Message::Repair(_) => self.acceptor.handle(msg),
+            Message::ReadReply(_) => self.learner.handle(msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effect::Effect;
+    use alloc::vec;
+    use std::sync::Mutex;
+
+    /// Posts every message to a mailbox shared by the cluster.
+    #[derive(Clone)]
+    struct Mailbox(Arc<Mutex<Vec<Message<u64>>>>);
+
+    impl Messenger<u64> for Mailbox {
+        fn send_prepare(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_promise(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_accept(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_accepted(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn on_resolution(&mut self, _slot: Slot, _value: Arc<u64>) {}
+    }
+
+    #[test]
+    fn node_new() {
+        let n: Node<u64> = Node::new(1, ClusterConfig::new(vec![1, 2, 3]));
+
+        assert_eq!(n.proposer.id, 1);
+        assert_eq!(n.acceptor.id, 1);
+        assert_eq!(n.learner.id, 1);
+        assert_eq!(n.delivered, 0);
+    }
+
+    #[test]
+    fn node_propose() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mailbox = Arc::new(Mutex::new(Vec::new()));
+        let mut nodes: Vec<Node<u64, Mailbox>> = (1..=3)
+            .map(|id| Node::with_messenger(id, config.clone(), Mailbox(mailbox.clone())))
+            .collect();
+
+        nodes[0].proposer.window = 2;
+        nodes[0].propose(10);
+        nodes[0].propose(20);
+
+        loop {
+            let sent = core::mem::take(&mut *mailbox.lock().unwrap());
+            if sent.is_empty() {
+                break;
+            }
+            for msg in sent {
+                for n in nodes.iter_mut() {
+                    n.handle(msg.clone());
+                }
+            }
+        }
+
+        for n in nodes.iter_mut() {
+            assert_eq!(n.poll_decided(), vec![(0, Arc::new(10)), (1, Arc::new(20))]);
+            assert!(n.poll_decided().is_empty());
+        }
+    }
+
+    #[test]
+    fn node_partial_eq_values() {
+        // `f64` is neither `Eq` nor `Ord`.
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut nodes: Vec<Node<Vec<f64>>> =
+            (1..=3).map(|id| Node::new(id, config.clone())).collect();
+
+        nodes[0].propose(vec![0.5, 1.5]);
+        let mut sent = nodes[0].take_effects();
+
+        while !sent.is_empty() {
+            for effect in core::mem::take(&mut sent) {
+                if let Effect::SendMessage(msg) = effect {
+                    for n in nodes.iter_mut() {
+                        sent.extend(n.step(msg.clone()));
+                    }
+                }
+            }
+        }
+
+        for n in nodes.iter_mut() {
+            assert_eq!(n.poll_decided(), vec![(0, Arc::new(vec![0.5, 1.5]))]);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Priority lanes
+//!
+//! Under heavy write load, a `Proposer`'s window and queue fill up with
+//! client values, and control operations wait their turn behind them. A
+//! proposal may be given a `Priority` so that it doesn't:
+//!
+//! - `Proposer::prepare_with_priority` queues a value ahead of every value of
+//!   a lower priority. A `High` value is moreover exempt from the admission
+//!   rate and from the `Messenger`'s backpressure, though not from the
+//!   window. Membership changes are proposed at `High` priority, and slots
+//!   finalized with a no-op never wait in the queue in the first place.
+//! - `Batcher::push_with_priority` cuts a batch as soon as a `High` value is
+//!   pushed, with it ahead of the values pending.
+//! - `Message::priority` sorts messages into lanes for the transports to send
+//!   in order of priority, e.g. the per-peer queues of `ZmqTransport`: the
+//!   control messages moving the protocol forward go first, then the values
+//!   themselves, and bulk snapshots and anti-entropy last.
+
+use crate::message::{Message, Messenger};
+use crate::proposal::ProposalHandle;
+use crate::proposer::Proposer;
+
+/// How urgently a proposal or message should go through. See the module
+/// documentation.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Priority {
+    /// Bulk traffic, which may wait for the rest
+    Low,
+    /// Regular traffic
+    #[default]
+    Normal,
+    /// Control operations, which should never wait for the rest
+    High,
+}
+
+impl<T> Message<T> {
+    /// The lane the message should be sent in: `High` for the messages
+    /// moving the protocol forward, `Normal` for those carrying values and
+    /// `Low` for snapshots and anti-entropy.
+    pub fn priority(&self) -> Priority {
+        match self {
+            Message::Prepare(_)
+            | Message::Promise(_)
+            | Message::Nack(_)
+            | Message::Skip(_)
+            | Message::Join(_)
+            | Message::State(_)
+            | Message::Read(_)
+            | Message::ReadReply(_) => Priority::High,
+            Message::Accept(_)
+            | Message::Accepted(_)
+            | Message::Any(_)
+            | Message::Propose(_)
+            | Message::Learn(_) => Priority::Normal,
+            Message::InstallSnapshot(_) | Message::Digest(_) | Message::Repair(_) => Priority::Low,
+        }
+    }
+}
+
+impl<T: PartialEq + Clone, M: Messenger<T>> Proposer<T, M> {
+    /// Like `prepare`, but queues `value` ahead of every value of a lower
+    /// `priority`, and exempts it from the admission rate and the
+    /// `Messenger`'s backpressure if `High`.
+    pub fn prepare_with_priority(&mut self, value: T, priority: Priority) -> ProposalHandle {
+        let handle = self.next_handle();
+        if priority != Priority::Normal {
+            self.priorities.insert(handle, priority);
+        }
+        self.prepare_as(handle, value);
+        handle
+    }
+
+    /// The priority the proposal `handle` was made at.
+    pub(crate) fn priority(&self, handle: ProposalHandle) -> Priority {
+        self.priorities.get(&handle).copied().unwrap_or_default()
+    }
+
+    /// Queues `value` behind every value of the same priority or higher.
+    pub(crate) fn enqueue(&mut self, handle: ProposalHandle, value: T) {
+        let priority = self.priority(handle);
+        let at = self
+            .queued
+            .iter()
+            .position(|(h, _)| self.priority(*h) < priority)
+            .unwrap_or(self.queued.len());
+        self.queued.insert(at, (handle, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admission::AdmissionLimits;
+    use crate::config::ClusterConfig;
+    use crate::node::Node;
+    use crate::proposal::ProposalStatus;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn priority_lanes() {
+        let mut node: Node<u64> = Node::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        node.proposer_mut().set_window(2);
+        node.set_admission(AdmissionLimits {
+            max_pending: None,
+            max_per_sec: Some(1),
+        });
+
+        node.propose(10);
+        node.propose_with_priority(20, Priority::Low);
+        node.propose(30);
+
+        // The admission rate holds back every value but a `High` one.
+        let urgent = node.propose_with_priority(40, Priority::High);
+
+        assert_eq!(node.proposer().in_flight.len(), 2);
+        assert_eq!(
+            node.proposal_status(urgent),
+            Some(ProposalStatus::Preparing(1))
+        );
+
+        node.propose_with_priority(50, Priority::High);
+
+        let queued: Vec<u64> = node.proposer().queued.iter().map(|(_, v)| *v).collect();
+        assert_eq!(queued, vec![50, 30, 20]);
+    }
+}
@@ -0,0 +1,373 @@
+//! Proposal handles
+//!
+//! `Proposer::prepare` hands back a `ProposalHandle` identifying the value
+//! proposed, so that callers can learn the fate of their own proposals rather
+//! than watch the log for them. Once a proposal is settled, its outcome is
+//! returned by `Proposer::poll_proposals`:
+//!
+//! - `Decided`: the value proposed was decided, in the slot given.
+//! - `Preempted`: another value was decided in the slot, which a previous
+//!   leader had gotten accepted already, or the slot was finalized with
+//!   another value. The value may be proposed again.
+//! - `TimedOut`: the proposal wasn't decided within the proposal timeout, or
+//!   by the deadline it was given through `Proposer::prepare_with_deadline`,
+//!   and the `Proposer` gave up on it. Its `Accept`s may have reached the
+//!   `Acceptor`s already, so the value may still be decided.
+//! - `Abandoned`: the caller gave up on the proposal through
+//!   `Proposer::abandon`. As with `TimedOut`, the value may still be decided.
+//!
+//! Until then, `Proposer::proposal_status` tells where a proposal stands. It
+//! is `Preempted` while an `Acceptor` has promised a higher proposal number
+//! than the one it is in flight under, which the `Proposer` outbids once
+//! the phase times out. Applications may rather redirect the request to the
+//! `Proposer` holding the higher number and abandon the proposal.
+//!
+//! Values proposed while finalizing slots, e.g. when taking over leadership,
+//! have no handle.
+
+use crate::message::{Messenger, Slot};
+use crate::proposer::Proposer;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Identifies a value proposed through `Proposer::prepare`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct ProposalHandle(pub(crate) u64);
+
+impl ProposalHandle {
+    /// The handle's ID, unique among the proposals of a `Proposer`.
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The fate of a proposal. See the module documentation.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ProposalOutcome<T> {
+    /// The value proposed was decided for the slot
+    Decided(Slot, Arc<T>),
+    /// Another value was decided for the slot
+    Preempted(Slot),
+    /// The proposal wasn't decided in time
+    TimedOut,
+    /// The proposal was abandoned by the caller
+    Abandoned,
+}
+
+/// Where an unsettled proposal stands. See the module documentation.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ProposalStatus {
+    /// The value waits for room in the window
+    Queued,
+    /// The value is in flight for the slot, in the first phase
+    Preparing(Slot),
+    /// The value is in flight for the slot, in the second phase
+    Accepting(Slot),
+    /// The value is in flight for the slot, under a proposal number outbid by
+    /// `by`, which `ballot_owner(by)` holds
+    Preempted { slot: Slot, by: u64 },
+}
+
+impl<T: PartialEq + Clone, M: Messenger<T>> Proposer<T, M> {
+    /// Sets the milliseconds a proposal may go undecided, from the moment it
+    /// was first proposed, before `tick` gives up on it. Proposals are
+    /// retried until decided if `None`.
+    pub fn set_proposal_timeout(&mut self, timeout: Option<u64>) {
+        self.proposal_timeout = timeout;
+    }
+
+    /// Where the proposal `handle` was returned for stands, or `None` once
+    /// it settled.
+    pub fn proposal_status(&self, handle: ProposalHandle) -> Option<ProposalStatus> {
+        if self.queued.iter().any(|(h, _)| *h == handle) {
+            return Some(ProposalStatus::Queued);
+        }
+        let (slot, instance) = self
+            .in_flight
+            .iter()
+            .find(|(_, f)| f.handle == Some(handle))?;
+        Some(if self.rejected_n > instance.n {
+            ProposalStatus::Preempted {
+                slot: *slot,
+                by: self.rejected_n,
+            }
+        } else if instance.accepting {
+            ProposalStatus::Accepting(*slot)
+        } else {
+            ProposalStatus::Preparing(*slot)
+        })
+    }
+
+    /// Returns the proposals settled since the last call, along with their
+    /// outcome.
+    pub fn poll_proposals(&mut self) -> Vec<(ProposalHandle, ProposalOutcome<T>)> {
+        core::mem::take(&mut self.outcomes)
+    }
+
+    /// Stops proposing the value `handle` was returned for, e.g. once the
+    /// request it came from was cancelled, and drops everything kept for it.
+    /// Its outcome is reported as `Abandoned`, unless it settled already.
+    pub fn abandon(&mut self, handle: ProposalHandle) {
+        if let Some(i) = self.queued.iter().position(|(h, _)| *h == handle) {
+            self.queued.remove(i);
+            self.settle(handle, ProposalOutcome::Abandoned);
+            return;
+        }
+        let slot = self
+            .in_flight
+            .iter()
+            .find(|(_, f)| f.handle == Some(handle))
+            .map(|(slot, _)| *slot);
+        if let Some(slot) = slot {
+            self.give_up(slot, ProposalOutcome::Abandoned);
+            self.propose_queued();
+        }
+    }
+
+    /// The handle of the next value proposed.
+    pub(crate) fn next_handle(&mut self) -> ProposalHandle {
+        let handle = ProposalHandle(self.next_proposal);
+        self.next_proposal += 1;
+        handle
+    }
+
+    /// Reports `outcome` for the proposal `handle`, which is settled.
+    pub(crate) fn settle(&mut self, handle: ProposalHandle, outcome: ProposalOutcome<T>) {
+        self.deadlines.remove(&handle);
+        self.priorities.remove(&handle);
+        self.outcomes.push((handle, outcome));
+    }
+
+    /// Gives up on the proposals undecided for longer than the proposal
+    /// timeout, or past their deadline, making room in the window.
+    pub(crate) fn expire_proposals(&mut self) {
+        if self.proposal_timeout.is_none() && self.deadlines.is_empty() {
+            return;
+        }
+        let (now, timeout) = (self.now, self.proposal_timeout);
+        let past_deadline = |handle| self.deadlines.get(&handle).is_some_and(|d| *d <= now);
+        let queued: Vec<ProposalHandle> = self
+            .queued
+            .iter()
+            .map(|(handle, _)| *handle)
+            .filter(|handle| past_deadline(*handle))
+            .collect();
+        let expired: Vec<Slot> = self
+            .in_flight
+            .iter()
+            .filter(|(_, f)| {
+                f.handle.is_some_and(|handle| {
+                    timeout.is_some_and(|timeout| f.proposed_at + timeout <= now)
+                        || past_deadline(handle)
+                })
+            })
+            .map(|(slot, _)| *slot)
+            .collect();
+        self.queued.retain(|(handle, _)| !queued.contains(handle));
+        for handle in queued {
+            self.settle(handle, ProposalOutcome::TimedOut);
+        }
+        if expired.is_empty() {
+            return;
+        }
+        for slot in expired {
+            self.give_up(slot, ProposalOutcome::TimedOut);
+        }
+        self.propose_queued();
+    }
+
+    /// Stops proposing for `slot`, reporting `outcome` for its proposal.
+    fn give_up(&mut self, slot: Slot, outcome: ProposalOutcome<T>) {
+        let instance = self.in_flight.remove(&slot).unwrap();
+        self.promises_received.remove(&(slot, instance.n));
+        self.accepted_received.remove(&(slot, instance.n));
+        event!(slot, ballot = instance.n, "given up");
+        if let Some(handle) = instance.handle {
+            self.settle(handle, outcome);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::effect::Effect;
+    use crate::message::{AcceptedData, Message, NackData, PromiseData};
+    use crate::proposer::{ballot, ballot_owner};
+    use alloc::vec;
+
+    fn promise(p: &mut Proposer<u64>, from: u64, accepted: Option<(u64, u64)>) {
+        p.receive_promise(Message::Promise(PromiseData {
+            slot: 0,
+            id: p.proposal_n,
+            from,
+            accepted_n: accepted.map(|(n, _)| n),
+            value: accepted.map(|(_, value)| Arc::new(value)),
+        }));
+    }
+
+    fn accepted(p: &mut Proposer<u64>, from: u64, value: u64) {
+        p.receive_accepted(Message::Accepted(AcceptedData {
+            slot: 0,
+            id: p.proposal_n,
+            value: Arc::new(value),
+            from,
+            fast: false,
+        }));
+    }
+
+    #[test]
+    fn proposal_decided_or_preempted() {
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let handle = p.prepare(10);
+        promise(&mut p, 1, None);
+        promise(&mut p, 2, None);
+        accepted(&mut p, 1, 10);
+        accepted(&mut p, 2, 10);
+
+        assert_eq!(
+            p.poll_proposals(),
+            vec![(handle, ProposalOutcome::Decided(0, Arc::new(10)))]
+        );
+
+        // A previous leader got 5 accepted for the slot.
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let handle = p.prepare(10);
+        promise(&mut p, 1, Some((1, 5)));
+        promise(&mut p, 2, None);
+        accepted(&mut p, 1, 5);
+        accepted(&mut p, 2, 5);
+
+        assert_eq!(
+            p.poll_proposals(),
+            vec![(handle, ProposalOutcome::Preempted(0))]
+        );
+        assert!(p.poll_proposals().is_empty());
+    }
+
+    #[test]
+    fn proposal_status_preempted() {
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.set_timeout(Some(10), 0);
+        let first = p.prepare(10);
+        let second = p.prepare(20);
+
+        assert_eq!(p.proposal_status(first), Some(ProposalStatus::Preparing(0)));
+        assert_eq!(p.proposal_status(second), Some(ProposalStatus::Queued));
+
+        // Node 3 took over with a higher proposal number.
+        let n = p.proposal_n;
+        let by = ballot(2, 3);
+        p.take_effects();
+        for from in [2, 3] {
+            p.receive_nack(Message::Nack(NackData {
+                slot: 0,
+                id: n,
+                from,
+                promised_n: by,
+                leader: Some(3),
+            }));
+        }
+
+        assert_eq!(p.take_effects(), vec![Effect::Preempted { ballot: n, by }]);
+        assert_eq!(
+            p.proposal_status(first),
+            Some(ProposalStatus::Preempted { slot: 0, by })
+        );
+        assert_eq!(ballot_owner(by), 3);
+
+        // Retried under a higher number, the proposal is back to the first
+        // phase.
+        p.tick(10);
+
+        assert!(p.current_ballot() > by);
+        assert_eq!(p.proposal_status(first), Some(ProposalStatus::Preparing(0)));
+
+        p.abandon(first);
+
+        assert_eq!(p.proposal_status(first), None);
+    }
+
+    #[test]
+    fn proposal_timed_out() {
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.set_proposal_timeout(Some(100));
+        let first = p.prepare(10);
+        let second = p.prepare(20);
+
+        assert_eq!(p.queued(), 1);
+
+        p.tick(99);
+
+        assert!(p.poll_proposals().is_empty());
+
+        // The queued value is proposed in the room made.
+        p.tick(100);
+
+        assert_eq!(p.poll_proposals(), vec![(first, ProposalOutcome::TimedOut)]);
+        assert_eq!(p.in_flight().collect::<Vec<_>>(), vec![1]);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn proposal_deadline() {
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let first = p.prepare_with_deadline(10, 50);
+        let second = p.prepare_with_deadline(20, 30);
+        let third = p.prepare(30);
+
+        // The queued value is given up on without ever being sent.
+        p.tick(30);
+
+        assert_eq!(
+            p.poll_proposals(),
+            vec![(second, ProposalOutcome::TimedOut)]
+        );
+        assert_eq!(p.queued(), 1);
+
+        p.tick(50);
+
+        assert_eq!(p.poll_proposals(), vec![(first, ProposalOutcome::TimedOut)]);
+        assert_eq!(p.proposal_status(third), Some(ProposalStatus::Preparing(1)));
+        assert!(p.deadlines.is_empty());
+    }
+
+    #[test]
+    fn proposal_abandoned() {
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.set_timeout(Some(10), 2);
+        let first = p.prepare(10);
+        let second = p.prepare(20);
+        let third = p.prepare(30);
+
+        p.abandon(second);
+        p.abandon(first);
+        p.take_effects();
+
+        assert_eq!(
+            p.poll_proposals(),
+            vec![
+                (second, ProposalOutcome::Abandoned),
+                (first, ProposalOutcome::Abandoned)
+            ]
+        );
+        assert_eq!(p.in_flight().collect::<Vec<_>>(), vec![1]);
+        assert!(p.promises_received.keys().all(|(slot, _)| *slot == 1));
+
+        // Settled proposals can't be abandoned.
+        p.abandon(first);
+        p.tick(10);
+
+        assert!(p.poll_proposals().is_empty());
+        assert!(!p.take_effects().is_empty());
+
+        p.abandon(third);
+
+        assert_eq!(
+            p.poll_proposals(),
+            vec![(third, ProposalOutcome::Abandoned)]
+        );
+        assert_eq!(p.in_flight().count(), 0);
+    }
+}
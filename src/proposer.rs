@@ -1,132 +1,1177 @@
 //! Proposer
 
-use message::{AcceptData, AcceptedData, Message, Messenger, PromiseData, ProposalData};
-use std::collections::{HashMap, HashSet};
-use std::hash::Hash;
-use std::sync::Arc;
+use crate::admission::Admission;
+use crate::config::{ClusterConfig, NodeId};
+use crate::effect::Effect;
+use crate::error::ProposerError;
+use crate::event::{EventSink, Observer, ObserverSink, PaxosEvent};
+use crate::latency::PeerLatencies;
+use crate::membership::config_at;
+use crate::message::{
+    AcceptData, AcceptedData, BoxedMessenger, Handler, LearnData, Message, Messenger, PromiseData,
+    ProposalData, ProposeData, Readiness, SkipData, Slot,
+};
+use crate::metrics::{Metrics, MetricsSink, RecordedLatencies};
+use crate::priority::Priority;
+use crate::proposal::{ProposalHandle, ProposalOutcome};
+use crate::quorum::voters;
+use crate::retry::{RetryPolicy, RetrySink};
+use crate::vertical::first_ballot;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A proposal awaiting resolution.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InFlight<T> {
+    /// The proposal number
+    pub n: u64,
+    /// The value proposed, replaced by any value the `Acceptor`s report
+    pub value: Arc<T>,
+    /// Whether the proposal is a fast round
+    pub fast: bool,
+    /// Whether the second phase has started
+    pub accepting: bool,
+    /// Whether the first phase was folded into the `Accept` sent
+    pub implicit_prepare: bool,
+    /// When the current phase started, in milliseconds
+    pub sent_at: u64,
+    /// Number of times the current phase was retransmitted
+    pub retransmits: u32,
+    /// When the value was first proposed, in milliseconds
+    pub proposed_at: u64,
+    /// The value originally proposed, which `value` may have replaced
+    pub proposed: Arc<T>,
+    /// The handle the value was proposed under, unless it was a no-op
+    pub handle: Option<ProposalHandle>,
+}
 
 /// A Proposer advocates a client request, attempting to convince the Acceptors
 /// to agree on it, and acting as a coordinator to move the protocol forward
 /// when conflicts occur.
-pub struct Proposer<T> {
+pub struct Proposer<T, M = BoxedMessenger<T>> {
     /// `Proposer`'s ID
-    pub id: u64,
+    pub(crate) id: NodeId,
     /// `Messenger` specifying communication with other nodes
-    pub messenger: Option<Box<Messenger<T>>>,
-    /// The proposed value
-    pub value: Option<Arc<T>>,
+    pub(crate) messenger: Option<M>,
+    /// Effects waiting to be taken, while no `Messenger` is set
+    pub(crate) effects: Vec<Effect<T>>,
+    /// Callback notified of the `Proposer`'s progress
+    pub(crate) events: Option<EventSink>,
+    /// `Metrics` the `Proposer` reports to
+    pub(crate) metrics: Option<MetricsSink>,
+    /// `Observer` of the `Proposer`'s transitions
+    pub(crate) observer: Option<ObserverSink<T>>,
+    /// Records the latency of each decision, along with how a value's batch
+    /// size is measured, if recording
+    pub(crate) latency_recorder: Option<RecordedLatencies<T>>,
+    /// The value of the latest proposal
+    pub(crate) value: Option<Arc<T>>,
+    /// The slot the latest proposal is made for
+    pub(crate) slot: Slot,
+    /// The slot the next call to `prepare` proposes for
+    pub(crate) next_slot: Slot,
     /// The highest proposal number seen
-    pub proposal_n: u64,
+    pub(crate) proposal_n: u64,
     /// The last proposal that was accepted
-    pub last_accepted_n: u64,
-    /// Promises received (proposal_n => data)
-    pub promises_received: HashMap<u64, HashSet<PromiseData<T>>>,
-    /// Accepted messages received (proposal_n => data)
-    pub accepted_received: HashMap<u64, HashSet<AcceptedData<T>>>,
-    /// The minimum number of `Acceptor`s needed to continue
-    pub quorum: u8,
+    pub(crate) last_accepted_n: u64,
+    /// Maximum number of slots in flight at once
+    pub(crate) window: usize,
+    /// Proposals awaiting resolution (slot => proposal)
+    pub(crate) in_flight: BTreeMap<Slot, InFlight<T>>,
+    /// Values waiting for room in the window, along with their handle
+    pub(crate) queued: VecDeque<(ProposalHandle, T)>,
+    /// Promises received ((slot, proposal_n) => from => data)
+    pub(crate) promises_received: BTreeMap<(Slot, u64), BTreeMap<NodeId, PromiseData<T>>>,
+    /// Accepted messages received ((slot, proposal_n) => from => data)
+    pub(crate) accepted_received: BTreeMap<(Slot, u64), BTreeMap<NodeId, AcceptedData<T>>>,
+    /// The cluster the proposal is made to
+    pub(crate) config: ClusterConfig,
+    /// The configuration the first phase runs against while an epoch is
+    /// being activated (Vertical Paxos)
+    pub(crate) previous_config: Option<ClusterConfig>,
+    /// Configurations decided through the log, by the slot they take effect
+    /// at. `config` is used below the first
+    pub(crate) reconfigurations: BTreeMap<Slot, ClusterConfig>,
+    /// Unresolved slots left to finalize after taking over leadership,
+    /// including the one in flight
+    pub(crate) recovering: BTreeSet<Slot>,
+    /// The value gaps are filled with while `recovering`
+    pub(crate) noop: Option<T>,
+    /// The time of the latest `tick`, in milliseconds
+    pub(crate) now: u64,
+    /// Milliseconds a phase may go without completing before its messages are
+    /// sent again. Nothing is ever sent again if `None`
+    pub(crate) timeout: Option<u64>,
+    /// Number of times a phase is sent again before the first phase is
+    /// retried under a higher proposal number
+    pub(crate) max_retransmits: u32,
+    /// Milliseconds a completed first phase makes the `Proposer` the leader
+    /// for, if leases are used
+    pub(crate) lease: Option<u64>,
+    /// When the current lease runs out, if one is held
+    pub(crate) lease_expiry: Option<u64>,
+    /// The highest proposal number `Nack`s reported promised, which the next
+    /// round outbids
+    pub(crate) rejected_n: u64,
+    /// The leader named by the latest `Nack` naming one
+    pub(crate) leader_hint: Option<NodeId>,
+    /// The ID of the next `ProposalHandle`
+    pub(crate) next_proposal: u64,
+    /// Milliseconds a proposal may go undecided before it is given up on.
+    /// Proposals are retried until decided if `None`
+    pub(crate) proposal_timeout: Option<u64>,
+    /// Deadlines of the proposals given one, in milliseconds
+    pub(crate) deadlines: BTreeMap<ProposalHandle, u64>,
+    /// Priorities of the proposals not made at `Priority::Normal`
+    pub(crate) priorities: BTreeMap<ProposalHandle, Priority>,
+    /// Outcomes of the proposals settled, waiting to be polled
+    pub(crate) outcomes: Vec<(ProposalHandle, ProposalOutcome<T>)>,
+    /// Number of slots below `next_slot` whose `Promise`s and `Accepted`
+    /// messages are kept once resolved
+    pub(crate) retention: Slot,
+    /// Limits on the proposals admitted, and the rate spent so far
+    pub(crate) admission: Admission,
+    /// `RetryPolicy` failed rounds back off by, if any
+    pub(crate) retry_policy: Option<RetrySink>,
+    /// Rounds failed in a row since a round last completed
+    pub(crate) attempts: u32,
+    /// When the next round starts, while backing off
+    pub(crate) retry_at: Option<u64>,
+    /// Round-trip times to the `Acceptor`s, as measured by the transport
+    pub(crate) latencies: PeerLatencies,
+}
+
+/// Number of bits of a proposal number holding the ID of the `Proposer`
+/// using it, below the round it was used in.
+pub const BALLOT_ID_BITS: u32 = 16;
+
+/// The proposal number the `Proposer` `id` uses in `round`.
+pub(crate) fn ballot(round: u64, id: NodeId) -> u64 {
+    round << BALLOT_ID_BITS | id
+}
+
+/// The ID of the `Proposer` using proposal number `n`.
+pub fn ballot_owner(n: u64) -> NodeId {
+    n & ((1 << BALLOT_ID_BITS) - 1)
+}
+
+impl<T: PartialEq + Clone> Proposer<T> {
+    /// Creates a new `Proposer`, with a single slot in flight at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` doesn't fit in `BALLOT_ID_BITS` bits.
+    pub fn new(id: NodeId, config: ClusterConfig) -> Self {
+        Self::init(id, config)
+    }
+
+    /// Sets the `Messenger` the `Proposer` sends through.
+    pub fn set_messenger<N>(&mut self, messenger: N)
+    where
+        N: Messenger<T> + Send + 'static,
+    {
+        self.messenger = Some(Box::new(messenger));
+    }
 }
 
-impl<T: 'static> Proposer<T>
-where
-    T: Eq + Hash + Clone,
-{
-    /// Creates a new `Proposer`.
-    pub fn new(id: u64, quorum: u8) -> Self {
+impl<T: PartialEq + Clone, M: Messenger<T>> Proposer<T, M> {
+    /// Creates a new `Proposer` sending through `messenger`, without boxing it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` doesn't fit in `BALLOT_ID_BITS` bits.
+    pub fn with_messenger(id: NodeId, config: ClusterConfig, messenger: M) -> Self {
+        Self {
+            messenger: Some(messenger),
+            ..Self::init(id, config)
+        }
+    }
+
+    fn init(id: NodeId, config: ClusterConfig) -> Self {
+        assert!(
+            id < 1 << BALLOT_ID_BITS,
+            "proposal numbers hold IDs of up to {BALLOT_ID_BITS} bits"
+        );
         Self {
             id,
-            quorum,
-            ..Self::default()
+            config,
+            value: None,
+            messenger: None,
+            effects: Vec::new(),
+            events: None,
+            metrics: None,
+            observer: None,
+            latency_recorder: None,
+            slot: 0,
+            next_slot: 0,
+            proposal_n: 0,
+            last_accepted_n: 0,
+            window: 1,
+            in_flight: BTreeMap::new(),
+            queued: VecDeque::new(),
+            promises_received: BTreeMap::new(),
+            accepted_received: BTreeMap::new(),
+            previous_config: None,
+            reconfigurations: BTreeMap::new(),
+            recovering: BTreeSet::new(),
+            noop: None,
+            now: 0,
+            timeout: None,
+            max_retransmits: 2,
+            lease: None,
+            lease_expiry: None,
+            rejected_n: 0,
+            leader_hint: None,
+            next_proposal: 0,
+            proposal_timeout: None,
+            deadlines: BTreeMap::new(),
+            priorities: BTreeMap::new(),
+            outcomes: Vec::new(),
+            retention: 0,
+            admission: Admission::default(),
+            retry_policy: None,
+            attempts: 0,
+            retry_at: None,
+            latencies: PeerLatencies::new(),
         }
     }
 
-    /// The first phase. Creates a proposal.
-    pub fn prepare(&mut self, value: T) {
+    /// The `Proposer`'s ID.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// The cluster the `Proposer` proposes to.
+    pub fn config(&self) -> &ClusterConfig {
+        &self.config
+    }
+
+    /// The configuration in force at `slot`, once reconfigured through the
+    /// log.
+    pub fn config_at(&self, slot: Slot) -> &ClusterConfig {
+        config_at(&self.reconfigurations, &self.config, slot)
+    }
+
+    /// The proposal number of the current round.
+    pub fn current_ballot(&self) -> u64 {
+        self.proposal_n
+    }
+
+    /// The leader `Acceptor`s named when rejecting a proposal, if any: the
+    /// `Proposer` they last promised. Clients and other `Proposer`s may
+    /// redirect to it rather than wait for their proposals to time out.
+    pub fn leader_hint(&self) -> Option<NodeId> {
+        self.leader_hint
+    }
+
+    /// The proposal number of the last value resolved.
+    pub fn last_accepted_n(&self) -> u64 {
+        self.last_accepted_n
+    }
+
+    /// The slot the latest value was proposed for.
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    /// The slot the next value will be proposed for.
+    pub fn next_slot(&self) -> Slot {
+        self.next_slot
+    }
+
+    /// The number of slots kept in flight at once.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// The slots proposed for and not resolved yet.
+    pub fn in_flight(&self) -> impl Iterator<Item = Slot> + '_ {
+        self.in_flight.keys().copied()
+    }
+
+    /// The number of values waiting for room in the window.
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Whether unresolved slots left by a predecessor are still being
+    /// finalized.
+    pub fn is_recovering(&self) -> bool {
+        !self.recovering.is_empty()
+    }
+
+    /// The number of `Promise`s received for `slot` in the current round.
+    pub fn promise_count(&self, slot: Slot) -> usize {
+        self.in_flight
+            .get(&slot)
+            .and_then(|f| self.promises_received.get(&(slot, f.n)))
+            .map_or(0, |p| p.len())
+    }
+
+    /// The number of `Accepted` messages received for `slot` in the current
+    /// round.
+    pub fn accepted_count(&self, slot: Slot) -> usize {
+        self.in_flight
+            .get(&slot)
+            .and_then(|f| self.accepted_received.get(&(slot, f.n)))
+            .map_or(0, |a| a.len())
+    }
+
+    /// Sets the callback notified of the `Proposer`'s progress.
+    pub fn set_events<F>(&mut self, events: F)
+    where
+        F: FnMut(PaxosEvent) + Send + 'static,
+    {
+        self.events = Some(Box::new(events));
+    }
+
+    /// Sets the `Metrics` the `Proposer` reports to.
+    pub fn set_metrics<X>(&mut self, metrics: X)
+    where
+        X: Metrics + Send + 'static,
+    {
+        self.metrics = Some(Box::new(metrics));
+    }
+
+    /// Sets the `Observer` of the `Proposer`'s transitions.
+    pub fn set_observer<O>(&mut self, observer: O)
+    where
+        O: Observer<T> + Send + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Sets the milliseconds a phase may go without completing before `tick`
+    /// sends its messages again, up to `max_retransmits` times, after which
+    /// the first phase is retried under a higher proposal number.
+    pub fn set_timeout(&mut self, timeout: Option<u64>, max_retransmits: u32) {
+        self.timeout = timeout;
+        self.max_retransmits = max_retransmits;
+    }
+
+    /// Sets the `RetryPolicy` failed rounds back off by. See the `retry`
+    /// module.
+    pub fn set_retry_policy<R>(&mut self, policy: R)
+    where
+        R: RetryPolicy + Send + 'static,
+    {
+        self.retry_policy = Some(Box::new(policy));
+    }
+
+    /// Sets the milliseconds a completed first phase makes the `Proposer` the
+    /// leader for. The lease is renewed by every later quorum, and counts
+    /// from when the messages gathering it were sent, so that it runs out no
+    /// later than the `Acceptor`s' view of it.
+    ///
+    /// `Acceptor`s don't enforce leases: other `Proposer`s are expected to
+    /// back off while they hear from a live leader, e.g. through heartbeats.
+    pub fn set_lease(&mut self, lease: Option<u64>) {
+        self.lease = lease;
+        self.lease_expiry = None;
+    }
+
+    /// Whether the `Proposer` holds an unexpired lease as of the latest
+    /// `tick`.
+    pub fn has_lease(&self) -> bool {
+        self.lease_expiry.is_some_and(|expiry| self.now < expiry)
+    }
+
+    /// Advances the `Proposer`'s clock to `now`, in milliseconds, firing the
+    /// timeouts that have passed: phases that haven't completed within
+    /// `timeout` are sent again, then retried under a higher proposal number
+    /// once the `RetryPolicy` backed off, proposals undecided past the
+    /// proposal timeout are given up on, and an expired lease is given up.
+    /// Expected to be called periodically, e.g. with the time of a `Clock`.
+    pub fn tick(&mut self, now: u64) {
+        span!("tick", proposer = self.id, now);
+        self.now = self.now.max(now);
+        if self.lease_expiry.is_some_and(|expiry| self.now >= expiry) {
+            self.lease_expiry = None;
+            self.emit(PaxosEvent::LeaseExpired);
+        }
+        self.expire_proposals();
+        self.admission.refill(self.now);
+        self.propose_queued();
+        if let Some(at) = self.retry_at {
+            // Nothing is sent again while backing off.
+            if self.now >= at {
+                self.next_round();
+            }
+            return;
+        }
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        let expired: Vec<Slot> = self
+            .in_flight
+            .iter()
+            .filter(|(_, f)| f.sent_at + timeout <= self.now)
+            .map(|(slot, _)| *slot)
+            .collect();
+        let mut retry = false;
+        for slot in expired {
+            let instance = self.in_flight.get_mut(&slot).unwrap();
+            if instance.retransmits >= self.max_retransmits || instance.fast {
+                retry = true;
+                continue;
+            }
+            instance.retransmits += 1;
+            instance.sent_at = self.now;
+            if self.implicit_prepare(slot) {
+                // The `Acceptor` ignores an implicit prepare it has seen.
+                let value = (*self.in_flight[&slot].value).clone();
+                let handle = self.in_flight[&slot].handle;
+                self.propose(slot, value, handle);
+            } else if self.in_flight[&slot].accepting {
+                self.send_accept(slot);
+            } else {
+                self.send_prepare(slot);
+            }
+        }
+        if retry {
+            self.retry();
+        }
+    }
+
+    /// Sets the number of the latest slots whose `Promise`s and `Accepted`
+    /// messages are kept once resolved, e.g. to inspect them through
+    /// `status`. Those of older slots are dropped as slots resolve, along
+    /// with those of every superseded proposal number.
+    pub fn set_retention(&mut self, slots: Slot) {
+        self.retention = slots;
+    }
+
+    /// Drops the `Promise`s and `Accepted` messages of every slot not in
+    /// flight, regardless of the retention window.
+    pub fn compact(&mut self) {
+        self.collect_garbage(0);
+    }
+
+    /// Drops the bookkeeping of the proposals superseded, and of the slots
+    /// resolved more than `retention` slots below `next_slot`.
+    fn collect_garbage(&mut self, retention: Slot) {
+        let keep_from = self.next_slot.saturating_sub(retention);
+        let in_flight = &self.in_flight;
+        let keep = |(slot, n): &(Slot, u64)| match in_flight.get(slot) {
+            Some(instance) => instance.n == *n,
+            None => *slot >= keep_from,
+        };
+        self.promises_received.retain(|key, _| keep(key));
+        self.accepted_received.retain(|key, _| keep(key));
+    }
+
+    /// Sets the number of slots kept in flight at once. Slots already in
+    /// flight beyond a smaller window are left to resolve.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn set_window(&mut self, window: usize) {
+        assert!(window > 0, "{}", ProposerError::EmptyWindow);
+        self.window = window;
+    }
+
+    /// Takes over leadership of the log, finalizing the `unresolved` slots a
+    /// crashed predecessor may have left behind, one after the other. The
+    /// first phase turns up any value already accepted for a slot, which is
+    /// then decided; gaps are filled with `noop` so the log can be applied.
+    ///
+    /// New values are proposed past the highest unresolved slot. Values
+    /// `prepare`d in the meantime are queued until `recovering` is empty.
+    pub fn take_over<I>(&mut self, unresolved: I, noop: T)
+    where
+        I: IntoIterator<Item = Slot>,
+    {
+        self.recovering.extend(unresolved);
+        self.noop = Some(noop);
+        if let Some(last) = self.recovering.last() {
+            self.next_slot = self.next_slot.max(last + 1);
+        }
+        self.recover_next();
+    }
+
+    fn recover_next(&mut self) {
+        let slot = match self.recovering.first() {
+            Some(slot) => *slot,
+            None => {
+                self.noop = None;
+                return;
+            }
+        };
+        let noop = self.noop.clone().unwrap();
+        self.propose(slot, noop, None);
+    }
+
+    /// Leads `epoch`, as bound to `config` by the `Master`. Until `complete`
+    /// is called, the first phase runs against the `previous` configuration,
+    /// so that values it may have decided are carried over to `config`.
+    ///
+    /// The leader is expected to `finalize` every undecided slot before
+    /// reporting to the `Master` that the epoch is complete.
+    pub fn activate(&mut self, epoch: u64, previous: ClusterConfig, config: ClusterConfig) {
+        self.proposal_n = self.proposal_n.max(first_ballot(epoch));
+        self.config = config;
+        self.previous_config = Some(previous);
+    }
+
+    /// Resumes numbering proposals above `ballot`, the highest proposal
+    /// number used before a restart. A `Proposer` must never use a number
+    /// twice, so callers record `current_ballot` durably before sending what
+    /// the `Proposer` produces.
+    pub fn resume(&mut self, ballot: u64) {
+        self.proposal_n = self.proposal_n.max(ballot);
+    }
+
+    /// Proposes to `config` for slots from `from` onwards, as decided through
+    /// the log. See `Membership`.
+    pub fn reconfigure(&mut self, from: Slot, config: ClusterConfig) {
+        self.reconfigurations.insert(from, config);
+    }
+
+    /// Stops consulting the previous configuration, once the `Master` has
+    /// completed the epoch.
+    pub fn complete(&mut self) {
+        self.previous_config = None;
+    }
+
+    /// The first phase. Creates a proposal for the next slot, or the next slot
+    /// owned by the `Proposer` if slots are partitioned among leaders.
+    ///
+    /// With a single `Acceptor` in the cluster the first phase is folded into
+    /// the second, and an `Accept` is sent straight away so the proposal resolves
+    /// in a single round trip.
+    ///
+    /// Up to `window` slots are kept in flight at once, each resolved on its
+    /// own. Once the window is full, values are queued and proposed as slots
+    /// are resolved.
+    ///
+    /// Returns the handle the outcome of the proposal is reported under by
+    /// `poll_proposals`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if slots are partitioned among leaders the `Proposer` isn't one of.
+    pub fn prepare(&mut self, value: T) -> ProposalHandle {
+        let handle = self.next_handle();
+        self.prepare_as(handle, value);
+        handle
+    }
+
+    /// Like `prepare`, but gives up on the proposal once `tick` reaches
+    /// `deadline`, in milliseconds, without it being decided, reporting it
+    /// `TimedOut`. A proposal still queued by then is never sent.
+    pub fn prepare_with_deadline(&mut self, value: T, deadline: u64) -> ProposalHandle {
+        let handle = self.next_handle();
+        self.deadlines.insert(handle, deadline);
+        self.prepare_as(handle, value);
+        handle
+    }
+
+    pub(crate) fn prepare_as(&mut self, handle: ProposalHandle, value: T) {
+        span!("prepare", proposer = self.id);
+        if self.is_full(self.priority(handle)) {
+            event!(queued = self.queued.len() + 1, "window full");
+            self.enqueue(handle, value);
+            return;
+        }
+        let slot = self.next_owned_slot();
+        self.admission.spend();
+        self.propose(slot, value, Some(handle));
+    }
+
+    /// Like `prepare`, but opens a fast round (Fast Paxos) once the first phase
+    /// completes: `Acceptor`s then accept the first value proposed to them
+    /// directly, be it ours or a client's, saving a message delay.
+    ///
+    /// Values proposed concurrently may collide, in which case no value gathers
+    /// a fast quorum and the `Proposer` recovers with a classic round. A value
+    /// queued for lack of room in the window is proposed in a classic round.
+    pub fn prepare_fast(&mut self, value: T) -> ProposalHandle {
+        let handle = self.next_handle();
+        if self.is_full(Priority::Normal) {
+            self.enqueue(handle, value);
+            return handle;
+        }
+        let slot = self.next_owned_slot();
+        self.admission.spend();
+        self.begin(slot, value, true, Some(handle));
+        self.send_prepare(slot);
+        handle
+    }
+
+    /// Finalizes an abandoned `slot` by proposing `noop` for it, so that the
+    /// log doesn't keep a gap. Should a value have been accepted for the slot
+    /// already, that value is decided instead, as with any other proposal.
+    pub fn finalize(&mut self, slot: Slot, noop: T) {
+        self.propose(slot, noop, None);
+    }
+
+    /// Gives up the unused slots the `Proposer` owns below `slot`, deciding
+    /// them with `noop`. A leader falling behind the others skips ahead this
+    /// way, rather than have its slots finalized one at a time.
+    pub fn skip_to(&mut self, slot: Slot, noop: T) {
+        if slot <= self.next_slot {
+            return;
+        }
+        let skip = Message::Skip(SkipData {
+            from: self.id,
+            start: self.next_slot,
+            end: slot,
+            value: Arc::new(noop),
+        });
+        self.next_slot = slot;
+        self.effect(Effect::SendMessage(skip));
+    }
+
+    /// Whether new values of `priority` must wait, for a slot to be
+    /// resolved, for the recovery of a predecessor's slots to finish or,
+    /// unless `High`, for the admission rate to allow another or, while a
+    /// slot is in flight already, for the transport to drain.
+    fn is_full(&self, priority: Priority) -> bool {
+        if !self.recovering.is_empty() || self.in_flight.len() >= self.window {
+            return true;
+        }
+        priority < Priority::High
+            && (!self.admission.has_credit() || (!self.in_flight.is_empty() && self.is_saturated()))
+    }
+
+    /// Whether the `Messenger` reports its transport saturated.
+    fn is_saturated(&self) -> bool {
+        self.messenger
+            .as_ref()
+            .is_some_and(|m| m.readiness() == Readiness::WouldBlock)
+    }
+
+    fn next_owned_slot(&self) -> Slot {
+        if self.config.leaders.is_empty() {
+            return self.next_slot;
+        }
+        match self.config.next_owned(self.id, self.next_slot) {
+            Some(slot) => slot,
+            None => panic!("{}", ProposerError::NotALeader(self.id)),
+        }
+    }
+
+    /// Whether the first phase is folded into the second.
+    fn implicit_prepare(&self, slot: Slot) -> bool {
+        let config = config_at(&self.reconfigurations, &self.config, slot);
+        config.members.len() == 1 && !config.is_transitioning() && self.previous_config.is_none()
+    }
+
+    fn propose(&mut self, slot: Slot, value: T, handle: Option<ProposalHandle>) {
+        if self.implicit_prepare(slot) {
+            self.begin(slot, value, false, handle);
+            let instance = self.in_flight.get_mut(&slot).unwrap();
+            instance.accepting = true;
+            instance.implicit_prepare = true;
+            let msg = Message::Accept(AcceptData {
+                slot,
+                id: self.proposal_n,
+                value: self.in_flight[&slot].value.clone(),
+                implicit_prepare: true,
+            });
+
+            self.effect(Effect::SendMessage(msg));
+            self.start_timer();
+            self.emit(PaxosEvent::AcceptSent {
+                instance: slot,
+                n: self.proposal_n,
+            });
+            return;
+        }
+
+        self.begin(slot, value, false, handle);
+        self.send_prepare(slot);
+    }
+
+    /// Puts a proposal of `value` for `slot` in flight. Slots join the
+    /// proposal number of those already in flight, which `Acceptor`s promise
+    /// for every slot at once. A slot proposed again needs a new number, and
+    /// the others are restarted under it. Every implicit prepare needs a new
+    /// number as well.
+    ///
+    /// A proposal already in flight for the slot under another handle is
+    /// preempted.
+    fn begin(&mut self, slot: Slot, value: T, fast: bool, handle: Option<ProposalHandle>) {
+        self.slot = slot;
+        self.next_slot = self.next_slot.max(slot + 1);
         self.value = Some(Arc::new(value));
-        self.proposal_n += 1;
-        self.promises_received
-            .insert(self.proposal_n, HashSet::new());
-        self.accepted_received
-            .insert(self.proposal_n, HashSet::new());
-        let prepare = Message::Prepare(ProposalData { id: self.id });
 
-        if let Some(ref mut messenger) = self.messenger {
-            messenger.send_prepare(prepare);
+        let previous = self.in_flight.remove(&slot);
+        let retry = previous.is_some();
+        let mut proposed = self.value.clone().unwrap();
+        if let Some(previous) = previous.as_ref() {
+            match previous.handle {
+                Some(h) if Some(h) == handle => proposed = previous.proposed.clone(),
+                Some(h) => self.settle(h, ProposalOutcome::Preempted(slot)),
+                None => {}
+            }
+        }
+        if self.in_flight.is_empty() || self.implicit_prepare(slot) {
+            self.proposal_n = self.next_ballot();
+        } else if retry {
+            self.next_round();
+        }
+        self.in_flight.insert(
+            slot,
+            InFlight {
+                n: self.proposal_n,
+                value: self.value.clone().unwrap(),
+                fast,
+                accepting: false,
+                implicit_prepare: false,
+                sent_at: self.now,
+                retransmits: 0,
+                proposed_at: previous.map_or(self.now, |f| f.proposed_at),
+                proposed,
+                handle,
+            },
+        );
+        self.track(slot);
+    }
+
+    /// Moves to a new proposal number once the `RetryPolicy` backed off, or
+    /// right away without one.
+    fn retry(&mut self) {
+        if self.retry_at.is_some() {
+            return;
+        }
+        self.attempts += 1;
+        let (attempts, now) = (self.attempts, self.now);
+        let backoff = match self.retry_policy {
+            Some(ref mut policy) => policy.backoff(attempts, now),
+            None => 0,
+        };
+        if backoff == 0 {
+            self.next_round();
+            return;
+        }
+        event!(attempts, backoff, "backing off");
+        self.retry_at = Some(now + backoff);
+        self.effect(Effect::StartTimer { at: now + backoff });
+    }
+
+    /// Moves to a new proposal number, restarting every slot in flight under
+    /// it, as the `Acceptor`s will ignore the previous one from then on.
+    fn next_round(&mut self) {
+        self.retry_at = None;
+        self.proposal_n = self.next_ballot();
+        event!(ballot = self.proposal_n, "new round");
+        let slots: Vec<Slot> = self.in_flight.keys().copied().collect();
+        for slot in slots {
+            let instance = self.in_flight.get_mut(&slot).unwrap();
+            instance.n = self.proposal_n;
+            instance.fast = false;
+            instance.accepting = false;
+            instance.implicit_prepare = false;
+            instance.sent_at = self.now;
+            instance.retransmits = 0;
+            self.track(slot);
+            self.send_prepare(slot);
+        }
+    }
+
+    /// The lowest proposal number above any used or rejected so far that
+    /// the `Proposer` owns: its low `BALLOT_ID_BITS` hold the `Proposer`'s
+    /// ID, so that no two share one whatever configuration they know of, as
+    /// `Acceptor`s answer a number they promised already again.
+    fn next_ballot(&self) -> u64 {
+        let floor = self.proposal_n.max(self.rejected_n);
+        let round = floor >> BALLOT_ID_BITS;
+        if ballot(round, self.id) > floor {
+            ballot(round, self.id)
+        } else {
+            ballot(round + 1, self.id)
         }
     }
 
-    /// Receives a `Promise` message from an `Acceptor`.
+    /// Starts counting the votes for `slot` under the current proposal
+    /// number, dropping those of any number it superseded.
+    fn track(&mut self, slot: Slot) {
+        let key = (slot, self.proposal_n);
+        let superseded = |(s, n): &(Slot, u64)| *s == slot && *n != key.1;
+        self.promises_received.retain(|k, _| !superseded(k));
+        self.accepted_received.retain(|k, _| !superseded(k));
+        self.promises_received.insert(key, BTreeMap::new());
+        self.accepted_received.insert(key, BTreeMap::new());
+    }
+
+    fn send_prepare(&mut self, slot: Slot) {
+        let n = self.in_flight[&slot].n;
+        let prepare = Message::Prepare(ProposalData {
+            slot,
+            id: n,
+            from: self.id,
+        });
+
+        self.effect(Effect::SendMessage(prepare));
+        self.start_timer();
+        event!(slot, ballot = n, "prepare sent");
+        self.emit(PaxosEvent::PrepareSent { instance: slot, n });
+        self.measure(|m| m.prepare_sent());
+    }
+
+    /// Receives a `Promise` message from an `Acceptor`. Promises from nodes
+    /// outside of the cluster are ignored, and each `Acceptor` is counted once
+    /// no matter how often its `Promise` is delivered.
     pub fn receive_promise(&mut self, msg: Message<T>) {
         if let Message::Promise(data) = msg {
-            self.promises_received
-                .get_mut(&data.id)
-                .unwrap()
-                .insert(data);
+            span!(
+                "receive_promise",
+                proposer = self.id,
+                slot = data.slot,
+                ballot = data.id,
+                from = data.from
+            );
+            let config = match self.previous_config {
+                Some(ref previous) => previous,
+                None => config_at(&self.reconfigurations, &self.config, data.slot),
+            };
+            let n = match self.in_flight.get(&data.slot) {
+                Some(instance) if config.is_phase1_voter(data.from) => instance.n,
+                _ => return,
+            };
+            let (slot, id, from) = (data.slot, data.id, data.from);
+            let promises = match self.promises_received.get_mut(&(slot, id)) {
+                Some(promises) => promises,
+                None => return,
+            };
+            let before = config.is_phase1_quorum_of(&voters(promises));
+            promises.insert(from, data);
+            let after = config.is_phase1_quorum_of(&voters(promises));
+            event!(promises = promises.len(), quorum = after, "promise counted");
 
-            if self.promises_received.get(&self.proposal_n).unwrap().len() == self.quorum as usize {
-                self.accept();
+            if id == n {
+                self.emit(PaxosEvent::PromiseReceived {
+                    instance: slot,
+                    from,
+                });
+                self.observe(|o| o.on_promise_received(slot, from));
+                if !before && after {
+                    self.attempts = 0;
+                    self.emit(PaxosEvent::QuorumReached { instance: slot, n });
+                    self.observe(|o| o.on_quorum_reached(slot, n));
+                    self.accept(slot);
+                }
             }
         }
     }
 
-    /// The second phase. Sets a value for the proposal, and builds an `Accept` request.
-    pub fn accept(&mut self) {
-        let mut promises_vec = self
-            .promises_received
-            .get_mut(&self.proposal_n)
-            .unwrap()
-            .iter()
-            .filter(|p| p.value.is_some())
-            .collect::<Vec<_>>();
+    /// The second phase. Sets a value for the proposal in flight for `slot`,
+    /// and builds an `Accept` request.
+    ///
+    /// If any `Acceptor` has already accepted a value, the value accepted under
+    /// the highest proposal number must be proposed in place of our own. Should
+    /// that have been a fast round, `Acceptor`s may report different values;
+    /// only the one reported most often can have been decided.
+    ///
+    /// In a fast round that no value constrains, an `Any` message is sent
+    /// instead, followed by our own value.
+    pub fn accept(&mut self, slot: Slot) {
+        let n = match self.in_flight.get(&slot) {
+            Some(instance) => instance.n,
+            None => return,
+        };
+        let promises = &self.promises_received[&(slot, n)];
+        let highest = promises.values().filter_map(|p| p.accepted_n).max();
+        let constrained = most_common(
+            promises
+                .values()
+                .filter(|p| p.accepted_n == highest)
+                .filter_map(|p| p.value.as_ref()),
+        )
+        .map(|(value, _)| value.clone());
 
-        promises_vec.sort_by(|a, b| b.id.cmp(&a.id));
+        let instance = self.in_flight.get_mut(&slot).unwrap();
+        if let Some(value) = constrained {
+            instance.value = value;
+            instance.fast = false;
+        }
+        instance.accepting = true;
+        instance.retransmits = 0;
+        let sent_at = core::mem::replace(&mut instance.sent_at, self.now);
+        let (value, fast) = (instance.value.clone(), instance.fast);
+        if slot == self.slot {
+            self.value = Some(value.clone());
+        }
+        // Promises are at least as recent as the `Prepare` they answer.
+        self.renew_lease(sent_at);
 
-        self.value = Some({
-            if promises_vec.len() == 0 {
-                self.value.clone().unwrap()
-            } else {
-                promises_vec[0].value.clone().unwrap()
-            }
-        });
+        if fast {
+            let any = Message::Any(ProposalData {
+                slot,
+                id: n,
+                from: self.id,
+            });
+            let propose = Message::Propose(ProposeData { slot, value });
+            self.effect(Effect::SendMessage(any));
+            self.effect(Effect::SendMessage(propose));
+            self.start_timer();
+            self.emit(PaxosEvent::AcceptSent { instance: slot, n });
+            return;
+        }
+
+        self.send_accept(slot);
+    }
+
+    fn send_accept(&mut self, slot: Slot) {
+        let instance = &self.in_flight[&slot];
+        let n = instance.n;
         let msg = Message::Accept(AcceptData {
-            id: self.proposal_n,
-            value: self.value.clone().unwrap(),
+            slot,
+            id: n,
+            value: instance.value.clone(),
+            implicit_prepare: false,
         });
 
-        if let Some(ref mut messenger) = self.messenger {
-            messenger.send_accept(msg);
+        match self.accept_targets(slot) {
+            Some(to) => self.effect(Effect::SendMessageTo(to, msg)),
+            None => self.effect(Effect::SendMessage(msg)),
+        }
+        self.start_timer();
+        event!(slot, ballot = n, "accept sent");
+        self.emit(PaxosEvent::AcceptSent { instance: slot, n });
+    }
+
+    /// Asks to be ticked once the phase just started times out.
+    fn start_timer(&mut self) {
+        if let Some(timeout) = self.timeout {
+            self.effect(Effect::StartTimer {
+                at: self.now + timeout,
+            });
         }
     }
 
-    /// Receives an `Accepted` message from an `Acceptor`.
+    /// Extends the lease, if leases are used, to run out `lease` milliseconds
+    /// after `sent_at`, when the messages of a quorum just gathered were sent.
+    fn renew_lease(&mut self, sent_at: u64) {
+        if let Some(lease) = self.lease {
+            let expiry = sent_at + lease;
+            if self.lease_expiry.is_none_or(|e| e < expiry) {
+                self.lease_expiry = Some(expiry);
+                self.effect(Effect::StartTimer { at: expiry });
+            }
+        }
+    }
+
+    /// Receives an `Accepted` message from an `Acceptor`. Messages from nodes
+    /// outside of the cluster are ignored, and each `Acceptor` is counted once.
     pub fn receive_accepted(&mut self, msg: Message<T>) {
         if let Message::Accepted(data) = msg {
-            self.accepted_received
-                .get_mut(&data.id)
-                .unwrap()
-                .insert(data);
-
-            if self.accepted_received.get(&self.proposal_n).unwrap().len() == self.quorum as usize {
-                if let Some(ref mut messenger) = self.messenger {
-                    self.last_accepted_n = self.proposal_n;
-                    messenger.on_resolution(self.proposal_n, self.value.clone().unwrap());
+            span!(
+                "receive_accepted",
+                proposer = self.id,
+                slot = data.slot,
+                ballot = data.id,
+                from = data.from
+            );
+            let (slot, id) = (data.slot, data.id);
+            let config = config_at(&self.reconfigurations, &self.config, slot);
+            let (n, fast, implicit_prepare) = match self.in_flight.get(&slot) {
+                Some(instance) if config.is_member(data.from) => {
+                    (instance.n, instance.fast, instance.implicit_prepare)
+                }
+                _ => return,
+            };
+            if id == n && fast {
+                self.receive_fast_accepted(data);
+                return;
+            }
+            // An implicit prepare may have turned up a previously accepted
+            // value. Otherwise the value was constrained by the first phase,
+            // and `Accepted` messages only echo it back.
+            if id == n && implicit_prepare {
+                self.in_flight.get_mut(&slot).unwrap().value = data.value.clone();
+                if slot == self.slot {
+                    self.value = Some(data.value.clone());
+                }
+            }
+            if let Some(accepted) = self.accepted_received.get_mut(&(slot, id)) {
+                let before = config.is_phase2_quorum_of(&voters(accepted));
+                accepted.insert(data.from, data);
+                let after = config.is_phase2_quorum_of(&voters(accepted));
+                event!(accepted = accepted.len(), quorum = after, "vote counted");
+
+                if id == n && !before && after {
+                    self.resolve(slot);
+                }
+            }
+        }
+    }
+
+    /// Receives a `Nack` message from an `Acceptor`, which promised a higher
+    /// proposal number than one in flight. The next round outbids it, and
+    /// the leader it names is kept as a hint. The first `Nack` outbidding a
+    /// proposal number reports it preempted, and retries the round right
+    /// away if a `RetryPolicy` is set.
+    pub fn receive_nack(&mut self, msg: Message<T>) {
+        if let Message::Nack(data) = msg {
+            span!(
+                "receive_nack",
+                proposer = self.id,
+                slot = data.slot,
+                ballot = data.id,
+                from = data.from
+            );
+            self.measure(|m| m.nack_received());
+            let config = config_at(&self.reconfigurations, &self.config, data.slot);
+            match self.in_flight.get(&data.slot) {
+                Some(instance) if config.is_member(data.from) && instance.n == data.id => {}
+                _ => return,
+            }
+            let preempted = self.rejected_n <= data.id;
+            self.rejected_n = self.rejected_n.max(data.promised_n);
+            if data.leader.is_some() {
+                self.leader_hint = data.leader;
+            }
+            event!(promised = data.promised_n, "rejected");
+            if preempted {
+                self.effect(Effect::Preempted {
+                    ballot: data.id,
+                    by: data.promised_n,
+                });
+                self.observe(|o| o.on_preempted(data.id, data.promised_n));
+                if self.retry_policy.is_some() {
+                    self.retry();
                 }
             }
         }
     }
+
+    /// Counts the votes of a fast round, which may be split between several
+    /// values. Once no value can gather a fast quorum anymore, a classic round
+    /// is started to recover.
+    fn receive_fast_accepted(&mut self, data: AcceptedData<T>) {
+        let slot = data.slot;
+        let config = config_at(&self.reconfigurations, &self.config, slot);
+        let accepted = self.accepted_received.get_mut(&(slot, data.id)).unwrap();
+        if accepted.contains_key(&data.from) {
+            return;
+        }
+        accepted.insert(data.from, data);
+
+        let (value, n) = most_common(accepted.values().map(|a| &a.value)).unwrap();
+        let (value, outstanding) = (
+            value.clone(),
+            config.members.len().saturating_sub(accepted.len()),
+        );
+        let fast_quorum = config.fast_quorum();
+
+        if n >= fast_quorum {
+            self.in_flight.get_mut(&slot).unwrap().value = value;
+            self.resolve(slot);
+        } else if n + outstanding < fast_quorum {
+            self.next_round();
+        }
+    }
+
+    /// Reports the value decided for `slot`, and broadcasts it to the
+    /// `Learner`s along with the `Acceptor`s that accepted it. Makes room in
+    /// the window for the next slot to recover or value queued.
+    fn resolve(&mut self, slot: Slot) {
+        let instance = self.in_flight.remove(&slot).unwrap();
+        self.attempts = 0;
+        self.last_accepted_n = instance.n;
+        self.renew_lease(instance.sent_at);
+        if slot == self.slot {
+            self.value = Some(instance.value.clone());
+        }
+        let certificate = self.accepted_received[&(slot, instance.n)]
+            .iter()
+            .filter(|(_, a)| a.value == instance.value)
+            .map(|(from, _)| *from)
+            .collect();
+        let learn = Message::Learn(LearnData {
+            slot,
+            id: instance.n,
+            from: self.id,
+            value: instance.value.clone(),
+            certificate,
+        });
+        self.effect(Effect::SendMessage(learn));
+        self.effect(Effect::Decide(slot, instance.value.clone()));
+        event!(slot, ballot = instance.n, "decided");
+        self.emit(PaxosEvent::Decided { instance: slot });
+        self.observe(|o| o.on_decided(slot, &instance.value));
+        let latency = self.now.saturating_sub(instance.proposed_at);
+        self.measure(|m| m.decision_latency(latency));
+        if let Some((recorder, batch_size)) = &mut self.latency_recorder {
+            let size = batch_size(&instance.value);
+            recorder.resolved(slot, instance.proposed_at, self.now, size);
+        }
+        if let Some(handle) = instance.handle {
+            let outcome = if instance.value == instance.proposed {
+                ProposalOutcome::Decided(slot, instance.value.clone())
+            } else {
+                ProposalOutcome::Preempted(slot)
+            };
+            self.settle(handle, outcome);
+        }
+
+        self.collect_garbage(self.retention);
+
+        if self.recovering.remove(&slot) {
+            self.recover_next();
+        }
+        self.propose_queued();
+    }
+
+    /// Proposes queued values while there is room in the window.
+    pub(crate) fn propose_queued(&mut self) {
+        while let Some((handle, _)) = self.queued.front() {
+            if self.is_full(self.priority(*handle)) {
+                break;
+            }
+            let (handle, value) = self.queued.pop_front().unwrap();
+            self.prepare_as(handle, value);
+        }
+    }
+
+    fn emit(&mut self, event: PaxosEvent) {
+        if let Some(ref mut events) = self.events {
+            events(event);
+        }
+    }
+
+    fn measure<F: FnOnce(&mut dyn Metrics)>(&mut self, f: F) {
+        if let Some(ref mut metrics) = self.metrics {
+            f(metrics.as_mut());
+        }
+    }
+
+    fn observe<F: FnOnce(&mut dyn Observer<T>)>(&mut self, f: F) {
+        if let Some(ref mut observer) = self.observer {
+            f(observer.as_mut());
+        }
+    }
 }
 
-impl<T> Default for Proposer<T> {
-    fn default() -> Self {
-        Self {
-            id: 1,
-            quorum: 7,
-            value: None,
-            messenger: None,
-            proposal_n: 0,
-            last_accepted_n: 0,
-            promises_received: HashMap::new(),
-            accepted_received: HashMap::new(),
+/// The value occurring most often in `values`, along with its count. Values
+/// are only compared for equality, and there are at most as many as there are
+/// `Acceptor`s, so they are counted by scanning.
+fn most_common<'a, T: PartialEq>(
+    values: impl Iterator<Item = &'a Arc<T>>,
+) -> Option<(&'a Arc<T>, usize)> {
+    let mut counts: Vec<(&Arc<T>, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    counts.into_iter().max_by_key(|(_, count)| *count)
+}
+
+impl<T: PartialEq + Clone, M: Messenger<T>> Handler<T> for Proposer<T, M> {
+    fn handle(&mut self, msg: Message<T>) {
+        match msg {
+            Message::Promise(_) => self.receive_promise(msg),
+            Message::Accepted(_) => self.receive_accepted(msg),
+            Message::Nack(_) => self.receive_nack(msg),
+            _ => {}
         }
     }
 }
@@ -134,10 +1179,45 @@ impl<T> Default for Proposer<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::QuorumConfig;
+    use crate::message::NackData;
+    use crate::quorum::Weighted;
+    use crate::vertical::first_ballot;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use std::sync::Mutex;
+
+    /// A cluster of seven `Acceptor`s.
+    fn cluster() -> ClusterConfig {
+        ClusterConfig::new((1..=7).collect())
+    }
+
+    /// Records every message sent, in order.
+    struct RecordingMessenger(Arc<Mutex<Vec<Message<u64>>>>);
+
+    impl Messenger<u64> for RecordingMessenger {
+        fn send_prepare(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_promise(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_accept(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn send_accepted(&mut self, msg: Message<u64>) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn on_resolution(&mut self, _slot: Slot, _value: Arc<u64>) {}
+    }
 
     #[test]
     fn proposer_new() {
-        let p: Proposer<u64> = Proposer::default();
+        let p: Proposer<u64> = Proposer::new(1, cluster());
 
         assert_eq!(p.id, 1);
         assert_eq!(p.proposal_n, 0);
@@ -145,12 +1225,26 @@ mod tests {
         assert!(p.messenger.is_none());
         assert_eq!(p.promises_received.len(), 0);
         assert_eq!(p.accepted_received.len(), 0);
-        assert_eq!(p.quorum, 7);
+        assert_eq!(p.config.quorum(), 4);
+    }
+
+    #[test]
+    fn proposer_resume() {
+        let mut p: Proposer<u64> = Proposer::new(1, cluster());
+
+        p.resume(7);
+        p.prepare(60);
+
+        assert_eq!(p.current_ballot(), ballot(1, 1));
+
+        p.resume(3);
+
+        assert_eq!(p.current_ballot(), ballot(1, 1));
     }
 
     #[test]
     fn proposer_prepare() {
-        let mut p: Proposer<u64> = Proposer::default();
+        let mut p: Proposer<u64> = Proposer::new(1, cluster());
 
         p.prepare(60);
 
@@ -161,12 +1255,14 @@ mod tests {
 
     #[test]
     fn proposer_receive_promise() {
-        let mut p: Proposer<u64> = Proposer::default();
+        let mut p: Proposer<u64> = Proposer::new(1, cluster());
 
         p.prepare(60);
 
         let msg = Message::Promise(PromiseData {
+            slot: 0,
             id: 1,
+            accepted_n: None,
             value: None,
             from: 2,
         });
@@ -174,59 +1270,970 @@ mod tests {
         p.receive_promise(msg);
 
         assert_eq!(p.promises_received.len(), 1);
-        assert!(p.promises_received.get(&1).is_some());
+        assert!(p.promises_received.contains_key(&(0, 1)));
+        assert_eq!(p.current_ballot(), 1);
+        assert_eq!(p.promise_count(0), 1);
+        assert_eq!(p.accepted_count(0), 0);
+        assert_eq!(p.in_flight().collect::<Vec<_>>(), vec![0]);
     }
 
     #[test]
     fn proposer_accept() {
-        let mut p: Proposer<u64> = Proposer::default();
+        let mut p: Proposer<u64> = Proposer::new(1, cluster());
 
         p.prepare(60);
 
         // Receive a Promise
 
         let msg = Message::Promise(PromiseData {
+            slot: 0,
             id: 1,
+            accepted_n: None,
             value: None,
             from: 2,
         });
 
         p.receive_promise(msg);
 
-        p.accept();
+        p.accept(0);
 
         assert_eq!(p.value, Some(Arc::new(60)));
 
         // Receive another Promise that has an existing value for that proposal.
 
         let msg = Message::Promise(PromiseData {
+            slot: 0,
             id: 1,
+            accepted_n: Some(0),
             value: Some(Arc::new(25)),
             from: 2,
         });
 
         p.receive_promise(msg);
 
-        p.accept();
+        p.accept(0);
 
         assert_eq!(p.value, Some(Arc::new(25)));
     }
 
+    #[test]
+    fn proposer_accept_highest_accepted_n() {
+        let mut p: Proposer<u64> = Proposer::new(1, cluster());
+
+        p.prepare(60);
+
+        // The higher sender id accepted its value under an older proposal.
+        let msg = Message::Promise(PromiseData {
+            slot: 0,
+            id: 1,
+            accepted_n: Some(3),
+            value: Some(Arc::new(25)),
+            from: 2,
+        });
+        p.receive_promise(msg);
+
+        let msg = Message::Promise(PromiseData {
+            slot: 0,
+            id: 1,
+            accepted_n: Some(5),
+            value: Some(Arc::new(40)),
+            from: 1,
+        });
+        p.receive_promise(msg);
+
+        p.accept(0);
+
+        assert_eq!(p.value, Some(Arc::new(40)));
+    }
+
     #[test]
     fn proposer_receive_accepted() {
-        let mut p: Proposer<u64> = Proposer::default();
+        let mut p: Proposer<u64> = Proposer::new(1, cluster());
 
         p.prepare(60);
 
         let msg = Message::Accepted(AcceptedData {
+            slot: 0,
             id: 1,
             value: Arc::new(60),
             from: 2,
+            fast: false,
         });
 
         p.receive_accepted(msg);
 
         assert_eq!(p.accepted_received.len(), 1);
-        assert!(p.accepted_received.get(&1).is_some());
+        assert!(p.accepted_received.contains_key(&(0, 1)));
+    }
+
+    #[test]
+    fn proposer_prepare_single_acceptor() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1]));
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+
+        p.prepare(60);
+
+        // The first phase is skipped entirely.
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![Message::Accept(AcceptData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(60),
+                implicit_prepare: true,
+            })]
+        );
+
+        // The Acceptor had already accepted another value.
+        let msg = Message::Accepted(AcceptedData {
+            slot: 0,
+            id: 1,
+            value: Arc::new(25),
+            from: 1,
+            fast: false,
+        });
+
+        p.receive_accepted(msg);
+
+        assert_eq!(p.last_accepted_n, 1);
+        assert_eq!(p.value, Some(Arc::new(25)));
+    }
+
+    #[test]
+    fn proposer_receive_accepted_keeps_value() {
+        let mut p: Proposer<u64> = Proposer::new(1, cluster());
+
+        p.prepare(60);
+        for from in [1, 2] {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from,
+            }));
+        }
+        p.accept(0);
+
+        // Only an implicit prepare lets an `Accepted` message replace the
+        // value sent.
+        p.receive_accepted(Message::Accepted(AcceptedData {
+            slot: 0,
+            id: 1,
+            value: Arc::new(25),
+            from: 2,
+            fast: false,
+        }));
+
+        assert_eq!(p.value, Some(Arc::new(60)));
+        assert_eq!(p.in_flight[&0].value, Arc::new(60));
+    }
+
+    #[test]
+    fn proposer_ignores_non_members() {
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1]));
+
+        p.prepare(60);
+
+        let msg = Message::Accepted(AcceptedData {
+            slot: 0,
+            id: 1,
+            value: Arc::new(60),
+            from: 9,
+            fast: false,
+        });
+
+        p.receive_accepted(msg);
+
+        assert!(p.accepted_received[&(0, 1)].is_empty());
+        assert_eq!(p.last_accepted_n, 0);
+    }
+
+    #[test]
+    fn proposer_counts_each_acceptor_once() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+
+        p.prepare(60);
+
+        for _ in 0..2 {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from: 2,
+            }));
+        }
+
+        // A retransmitted Promise doesn't make up a quorum.
+        assert_eq!(p.promises_received[&(0, 1)].len(), 1);
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        p.receive_promise(Message::Promise(PromiseData {
+            slot: 0,
+            id: 1,
+            accepted_n: None,
+            value: None,
+            from: 3,
+        }));
+
+        assert_eq!(sent.lock().unwrap().len(), 2);
+
+        for _ in 0..2 {
+            p.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(60),
+                from: 3,
+                fast: false,
+            }));
+        }
+
+        assert_eq!(p.accepted_received[&(0, 1)].len(), 1);
+        assert_eq!(p.last_accepted_n, 0);
+    }
+
+    #[test]
+    fn proposer_prepare_next_slot() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, cluster());
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+        p.window = 2;
+
+        p.prepare(60);
+        p.prepare(25);
+
+        // Both slots are prepared under the same proposal number.
+        assert_eq!(p.slot, 1);
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![
+                Message::Prepare(ProposalData {
+                    slot: 0,
+                    id: 1,
+                    from: 1,
+                }),
+                Message::Prepare(ProposalData {
+                    slot: 1,
+                    id: 1,
+                    from: 1,
+                }),
+            ]
+        );
+
+        // Promises for slots not in flight are ignored.
+        p.receive_promise(Message::Promise(PromiseData {
+            slot: 2,
+            id: 1,
+            accepted_n: None,
+            value: None,
+            from: 2,
+        }));
+
+        assert!(p.promises_received.values().all(BTreeMap::is_empty));
+    }
+
+    #[test]
+    fn proposer_collects_garbage() {
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.set_retention(1);
+        p.set_timeout(Some(10), 0);
+
+        let resolve = |p: &mut Proposer<u64>, slot: Slot| {
+            for from in 1..=2 {
+                p.receive_promise(Message::Promise(PromiseData {
+                    slot,
+                    id: p.proposal_n,
+                    accepted_n: None,
+                    value: None,
+                    from,
+                }));
+            }
+            for from in 1..=2 {
+                p.receive_accepted(Message::Accepted(AcceptedData {
+                    slot,
+                    id: p.proposal_n,
+                    value: Arc::new(10),
+                    from,
+                    fast: false,
+                }));
+            }
+        };
+
+        // The first round times out, and is superseded.
+        p.prepare(10);
+        p.tick(10);
+
+        assert_eq!(
+            p.promises_received.keys().collect::<Vec<_>>(),
+            vec![&(0, p.proposal_n)]
+        );
+
+        resolve(&mut p, 0);
+        p.prepare(10);
+        resolve(&mut p, 1);
+
+        // Only the latest resolved slot is retained.
+        assert_eq!(
+            p.accepted_received
+                .keys()
+                .map(|(slot, _)| *slot)
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+
+        p.compact();
+
+        assert!(p.promises_received.is_empty());
+        assert!(p.accepted_received.is_empty());
+    }
+
+    #[test]
+    fn proposer_tick_timeouts() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+        p.set_timeout(Some(10), 1);
+
+        p.prepare(60);
+        p.tick(5);
+
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        // Sent again under the same proposal number first...
+        p.tick(10);
+
+        assert_eq!(
+            sent.lock().unwrap()[1],
+            Message::Prepare(ProposalData {
+                slot: 0,
+                id: 1,
+                from: 1,
+            })
+        );
+
+        // ...then retried under a higher one.
+        p.tick(20);
+
+        assert_eq!(
+            sent.lock().unwrap()[2],
+            Message::Prepare(ProposalData {
+                slot: 0,
+                id: ballot(1, 1),
+                from: 1,
+            })
+        );
+
+        for from in 1..=2 {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id: ballot(1, 1),
+                accepted_n: None,
+                value: None,
+                from,
+            }));
+        }
+        p.tick(29);
+
+        assert_eq!(sent.lock().unwrap().len(), 4);
+
+        p.tick(30);
+
+        let sent = sent.lock().unwrap();
+
+        assert_eq!(sent[3], sent[4]);
+        assert!(matches!(sent[4], Message::Accept(_)));
+    }
+
+    #[test]
+    fn proposer_nack() {
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.set_timeout(Some(10), 0);
+        p.prepare(60);
+        p.take_effects();
+        let nack = |id, from| {
+            Message::Nack(NackData {
+                slot: 0,
+                id,
+                from,
+                promised_n: ballot(1, 2),
+                leader: Some(3),
+            })
+        };
+
+        // Stale rejections and those from outside the cluster are ignored.
+        p.step(nack(0, 2));
+        p.step(nack(1, 4));
+
+        assert_eq!(p.leader_hint(), None);
+
+        p.step(nack(1, 2));
+
+        assert_eq!(p.leader_hint(), Some(3));
+
+        // The next round outbids the promise reported.
+        p.tick(10);
+
+        assert_eq!(
+            p.take_effects()[0],
+            Effect::SendMessage(Message::Prepare(ProposalData {
+                slot: 0,
+                id: ballot(2, 1),
+                from: 1,
+            }))
+        );
+    }
+
+    #[test]
+    fn proposer_lease() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let sink = events.clone();
+        p.events = Some(Box::new(move |e| sink.lock().unwrap().push(e)));
+        p.set_lease(Some(50));
+
+        p.tick(10);
+        p.prepare(60);
+        p.tick(20);
+
+        assert!(!p.has_lease());
+
+        for from in 1..=2 {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from,
+            }));
+        }
+
+        assert!(p.has_lease());
+
+        // The lease counts from when the Prepare was sent.
+        p.tick(59);
+
+        assert!(p.has_lease());
+
+        p.tick(60);
+
+        assert!(!p.has_lease());
+        assert_eq!(
+            events.lock().unwrap().last(),
+            Some(&PaxosEvent::LeaseExpired)
+        );
+    }
+
+    #[test]
+    fn proposer_pipelining() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+        p.window = 2;
+
+        p.prepare(60);
+        p.prepare(25);
+        p.prepare(40);
+
+        assert_eq!(p.in_flight.len(), 2);
+        assert_eq!(p.queued, [(ProposalHandle(2), 40)]);
+
+        // Slots progress independently: slot 1 resolves ahead of slot 0.
+        for from in 1..=2 {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 1,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from,
+            }));
+        }
+        for from in 1..=2 {
+            p.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 1,
+                id: 1,
+                value: Arc::new(25),
+                from,
+                fast: false,
+            }));
+        }
+
+        assert!(p.queued.is_empty());
+        assert_eq!(p.in_flight.keys().copied().collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(
+            sent.lock().unwrap()[2..],
+            [
+                Message::Accept(AcceptData {
+                    slot: 1,
+                    id: 1,
+                    value: Arc::new(25),
+                    implicit_prepare: false,
+                }),
+                Message::Learn(LearnData {
+                    slot: 1,
+                    id: 1,
+                    from: 1,
+                    value: Arc::new(25),
+                    certificate: vec![1, 2],
+                }),
+                Message::Prepare(ProposalData {
+                    slot: 2,
+                    id: 1,
+                    from: 1,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn proposer_finalize() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, cluster());
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+        p.window = 2;
+
+        p.prepare(60);
+        p.prepare(25);
+        p.finalize(0, 0);
+
+        // Slot 1 is restarted under the new proposal number.
+        assert_eq!(p.slot, 0);
+        assert_eq!(p.next_slot, 2);
+        assert_eq!(p.value, Some(Arc::new(0)));
+        assert_eq!(p.in_flight[&1].n, ballot(1, 1));
+        assert_eq!(
+            sent.lock().unwrap()[2..],
+            [
+                Message::Prepare(ProposalData {
+                    slot: 1,
+                    id: ballot(1, 1),
+                    from: 1,
+                }),
+                Message::Prepare(ProposalData {
+                    slot: 0,
+                    id: ballot(1, 1),
+                    from: 1,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn proposer_take_over() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+
+        p.take_over(vec![3, 1], 0);
+
+        assert_eq!(p.slot, 1);
+        assert_eq!(p.next_slot, 4);
+
+        // The predecessor's value for slot 1 is kept.
+        for from in 1..=2 {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 1,
+                id: 1,
+                accepted_n: Some(1),
+                value: Some(Arc::new(25)),
+                from,
+            }));
+        }
+        for from in 1..=2 {
+            p.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 1,
+                id: 1,
+                value: Arc::new(25),
+                from,
+                fast: false,
+            }));
+        }
+
+        // Slot 3 is a gap, filled with the no-op.
+        assert_eq!(p.slot, 3);
+        assert_eq!(p.recovering, [3].into_iter().collect());
+
+        for from in 1..=2 {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 3,
+                id: ballot(1, 1),
+                accepted_n: None,
+                value: None,
+                from,
+            }));
+        }
+
+        assert_eq!(
+            sent.lock().unwrap().last(),
+            Some(&Message::Accept(AcceptData {
+                slot: 3,
+                id: ballot(1, 1),
+                value: Arc::new(0),
+                implicit_prepare: false,
+            }))
+        );
+
+        for from in 1..=2 {
+            p.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 3,
+                id: ballot(1, 1),
+                value: Arc::new(0),
+                from,
+                fast: false,
+            }));
+        }
+
+        assert!(p.recovering.is_empty());
+        assert_eq!(p.noop, None);
+    }
+
+    #[test]
+    fn proposer_flexible_quorums() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let config = ClusterConfig::new(vec![1, 2, 3, 4, 5])
+            .with_quorums(QuorumConfig {
+                phase1: 4,
+                phase2: 2,
+            })
+            .unwrap();
+        let mut p: Proposer<u64> = Proposer::new(1, config);
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+
+        p.prepare(60);
+
+        for from in 1..=4 {
+            assert_eq!(sent.lock().unwrap().len(), 1);
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from,
+            }));
+        }
+
+        assert_eq!(sent.lock().unwrap().len(), 2);
+
+        for from in 1..=2 {
+            p.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(60),
+                from,
+                fast: false,
+            }));
+        }
+
+        assert_eq!(p.last_accepted_n, 1);
+    }
+
+    #[test]
+    fn proposer_weighted_quorums() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let weights = [(1, 3), (2, 1), (3, 1)].into_iter().collect();
+        let config = ClusterConfig::new(vec![1, 2, 3]).with_quorum_system(Weighted::new(weights));
+        let mut p: Proposer<u64> = Proposer::new(1, config);
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+
+        p.prepare(60);
+
+        // The two light members don't make up a quorum on their own.
+        for from in 2..=3 {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from,
+            }));
+        }
+
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        p.receive_promise(Message::Promise(PromiseData {
+            slot: 0,
+            id: 1,
+            accepted_n: None,
+            value: None,
+            from: 1,
+        }));
+
+        assert_eq!(sent.lock().unwrap().len(), 2);
+
+        p.receive_accepted(Message::Accepted(AcceptedData {
+            slot: 0,
+            id: 1,
+            value: Arc::new(60),
+            from: 1,
+            fast: false,
+        }));
+
+        assert_eq!(p.last_accepted_n, 1);
+    }
+
+    #[test]
+    fn proposer_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let recorded = events.clone();
+        p.events = Some(Box::new(move |e| recorded.lock().unwrap().push(e)));
+
+        p.prepare(60);
+
+        for from in 2..=3 {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from,
+            }));
+        }
+        for from in 2..=3 {
+            p.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(60),
+                from,
+                fast: false,
+            }));
+        }
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                PaxosEvent::PrepareSent { instance: 0, n: 1 },
+                PaxosEvent::PromiseReceived {
+                    instance: 0,
+                    from: 2
+                },
+                PaxosEvent::PromiseReceived {
+                    instance: 0,
+                    from: 3
+                },
+                PaxosEvent::QuorumReached { instance: 0, n: 1 },
+                PaxosEvent::AcceptSent { instance: 0, n: 1 },
+                PaxosEvent::Decided { instance: 0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn proposer_fast_round() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3, 4]));
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+
+        p.prepare_fast(60);
+
+        for from in 1..=3 {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from,
+            }));
+        }
+
+        assert_eq!(
+            sent.lock().unwrap()[1..],
+            [
+                Message::Any(ProposalData {
+                    slot: 0,
+                    id: 1,
+                    from: 1,
+                }),
+                Message::Propose(ProposeData {
+                    slot: 0,
+                    value: Arc::new(60)
+                }),
+            ]
+        );
+
+        // A client's value beat ours to most of the Acceptors.
+        for (from, value) in [(1, 25), (2, 60), (3, 25), (4, 25)] {
+            p.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(value),
+                from,
+                fast: true,
+            }));
+        }
+
+        assert_eq!(p.last_accepted_n, 1);
+        assert_eq!(p.value, Some(Arc::new(25)));
+    }
+
+    #[test]
+    fn proposer_fast_round_collision() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3, 4]));
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+
+        p.prepare_fast(60);
+        for from in 1..=3 {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id: 1,
+                accepted_n: None,
+                value: None,
+                from,
+            }));
+        }
+        for (from, value) in [(1, 60), (2, 25), (3, 25), (4, 60)] {
+            p.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(value),
+                from,
+                fast: true,
+            }));
+        }
+
+        // Neither value can reach a fast quorum, so a classic round recovers.
+        assert_eq!(p.last_accepted_n, 0);
+        assert_eq!(
+            sent.lock().unwrap().last(),
+            Some(&Message::Prepare(ProposalData {
+                slot: 0,
+                id: ballot(1, 1),
+                from: 1,
+            }))
+        );
+
+        for (from, value) in [(1, 60), (2, 25), (3, 25)] {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id: ballot(1, 1),
+                accepted_n: Some(1),
+                value: Some(Arc::new(value)),
+                from,
+            }));
+        }
+
+        assert_eq!(
+            sent.lock().unwrap().last(),
+            Some(&Message::Accept(AcceptData {
+                slot: 0,
+                id: ballot(1, 1),
+                value: Arc::new(25),
+                implicit_prepare: false,
+            }))
+        );
+    }
+
+    #[test]
+    fn proposer_leaders() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let config = ClusterConfig::new(vec![1, 2, 3]).with_leaders(vec![1, 2]);
+        let mut p: Proposer<u64> = Proposer::new(2, config);
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+        p.window = 2;
+
+        p.prepare(60);
+
+        assert_eq!(p.slot, 1);
+
+        // Another leader got ahead, so our slots up to it are given up.
+        p.skip_to(8, 0);
+        p.prepare(25);
+
+        assert_eq!(p.slot, 9);
+        assert_eq!(
+            sent.lock().unwrap()[1],
+            Message::Skip(SkipData {
+                from: 2,
+                start: 2,
+                end: 8,
+                value: Arc::new(0),
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn proposer_not_a_leader() {
+        let config = ClusterConfig::new(vec![1, 2, 3]).with_leaders(vec![1, 2]);
+        let mut p: Proposer<u64> = Proposer::new(3, config);
+
+        p.prepare(60);
+    }
+
+    #[test]
+    fn proposer_activate() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.messenger = Some(Box::new(RecordingMessenger(sent.clone())));
+
+        p.activate(
+            1,
+            ClusterConfig::new(vec![1, 2, 3]),
+            ClusterConfig::new(vec![4, 5, 6]),
+        );
+        p.finalize(0, 0);
+
+        let id = first_ballot(1) + 1;
+        assert_eq!(p.proposal_n, id);
+
+        // The first phase runs against the previous configuration, which had
+        // accepted a value that must be carried over.
+        for (from, accepted_n) in [(4, None), (1, Some(1)), (2, None)] {
+            p.receive_promise(Message::Promise(PromiseData {
+                slot: 0,
+                id,
+                accepted_n,
+                value: accepted_n.map(|_| Arc::new(25)),
+                from,
+            }));
+        }
+
+        assert_eq!(
+            sent.lock().unwrap().last(),
+            Some(&Message::Accept(AcceptData {
+                slot: 0,
+                id,
+                value: Arc::new(25),
+                implicit_prepare: false,
+            }))
+        );
+
+        // The second phase runs against the new one.
+        for from in [1, 4, 5] {
+            p.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 0,
+                id,
+                value: Arc::new(25),
+                from,
+                fast: false,
+            }));
+        }
+
+        assert_eq!(p.last_accepted_n, id);
+
+        p.complete();
+
+        assert!(p.previous_config.is_none());
+    }
+
+    #[test]
+    fn proposer_ballots_outside_members() {
+        // Proposer 4 isn't a member, yet its ballots stay apart from
+        // Proposer 3's from the same starting point.
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut proposers: Vec<Proposer<u64>> =
+            [3, 4].map(|id| Proposer::new(id, config.clone())).into();
+
+        for p in &mut proposers {
+            p.proposal_n = 11;
+            p.prepare(60);
+        }
+
+        assert_eq!(proposers[0].proposal_n, ballot(1, 3));
+        assert_eq!(proposers[1].proposal_n, ballot(1, 4));
     }
 }
@@ -0,0 +1,471 @@
+//! Python bindings
+//!
+//! Exposes the roles and the simulator to Python through
+//! [PyO3](https://pyo3.rs), so that protocol scenarios can be scripted
+//! without writing a Rust driver. Build the extension module with
+//! [maturin](https://www.maturin.rs):
+//!
+//! ```text
+//! maturin develop --features python
+//! ```
+//!
+//! Values are `bytes`. Roles are driven as pure state machines: `step` takes
+//! a `Message` and returns the resulting `Effect`s, which the script carries
+//! out itself, e.g. by handing every `send` effect's message to the other
+//! roles.
+//!
+//! ```text
+//! from paxos_rust import Acceptor, Proposer, Simulator
+//!
+//! proposer, acceptors = Proposer(1, [1, 2, 3]), [Acceptor(id, [1, 2, 3]) for id in (1, 2, 3)]
+//! proposer.propose(b"x")
+//! sim = Simulator(5, seed=7)
+//! sim.isolate(1)
+//! ```
+
+use crate::acceptor::Acceptor;
+use crate::config::{ClusterConfig, NodeId};
+use crate::effect::Effect;
+use crate::learner::Learner;
+use crate::message::{Message, Slot};
+use crate::node::Node;
+use crate::proposer::Proposer;
+use crate::sim::{Faults, Latency, SimCluster};
+use crate::wire;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+type Value = Vec<u8>;
+
+/// A message between roles.
+#[pyclass(name = "Message", eq, frozen)]
+#[derive(PartialEq)]
+pub struct PyMessage(Message<Value>);
+
+#[pymethods]
+impl PyMessage {
+    /// Decodes a message from its `wire` encoding.
+    #[staticmethod]
+    fn decode(bytes: &[u8]) -> PyResult<Self> {
+        wire::decode(bytes)
+            .map(PyMessage)
+            .map_err(|err| PyValueError::new_err(format!("{err}")))
+    }
+
+    /// The message's `wire` encoding.
+    fn encode<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &wire::encode(&self.0))
+    }
+
+    /// The message's type, e.g. `"prepare"`.
+    #[getter]
+    fn kind(&self) -> &'static str {
+        self.0.kind()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+/// Something a role needs done on its behalf. `kind` is one of `"send"`,
+/// `"decide"`, `"persist"`, `"timer"` and `"preempted"`, and only the
+/// attributes matching it are set.
+#[pyclass(name = "Effect", frozen)]
+pub struct PyEffect(Effect<Value>);
+
+#[pymethods]
+impl PyEffect {
+    #[getter]
+    fn kind(&self) -> &'static str {
+        match self.0 {
+            Effect::SendMessage(_) | Effect::SendMessageTo(..) => "send",
+            Effect::Decide(..) => "decide",
+            Effect::PersistState { .. } => "persist",
+            Effect::StartTimer { .. } => "timer",
+            Effect::Preempted { .. } => "preempted",
+        }
+    }
+
+    /// The message to send.
+    #[getter]
+    fn message(&self) -> Option<PyMessage> {
+        match &self.0 {
+            Effect::SendMessage(msg) | Effect::SendMessageTo(_, msg) => {
+                Some(PyMessage(msg.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// The nodes to send the message to, if not every node.
+    #[getter]
+    fn to(&self) -> Option<Vec<NodeId>> {
+        match &self.0 {
+            Effect::SendMessageTo(to, _) => Some(to.clone()),
+            _ => None,
+        }
+    }
+
+    /// The slot decided, or accepted along with the vote to persist.
+    #[getter]
+    fn slot(&self) -> Option<Slot> {
+        match &self.0 {
+            Effect::Decide(slot, _) => Some(*slot),
+            Effect::PersistState {
+                accepted: Some((slot, _)),
+                ..
+            } => Some(*slot),
+            _ => None,
+        }
+    }
+
+    /// The value decided, or accepted along with the vote to persist.
+    #[getter]
+    fn value<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyBytes>> {
+        match &self.0 {
+            Effect::Decide(_, value) => Some(PyBytes::new(py, value)),
+            Effect::PersistState {
+                accepted: Some((_, proposal)),
+                ..
+            } => Some(PyBytes::new(py, &proposal.value)),
+            _ => None,
+        }
+    }
+
+    /// The highest proposal number promised, along with the vote to persist,
+    /// or instead of the proposal number preempted.
+    #[getter]
+    fn promised(&self) -> Option<u64> {
+        match &self.0 {
+            Effect::PersistState { promised_n, .. } => Some(*promised_n),
+            Effect::Preempted { by, .. } => Some(*by),
+            _ => None,
+        }
+    }
+
+    /// The proposal number preempted.
+    #[getter]
+    fn ballot(&self) -> Option<u64> {
+        match &self.0 {
+            Effect::Preempted { ballot, .. } => Some(*ballot),
+            _ => None,
+        }
+    }
+
+    /// The time a timer fires at, in milliseconds.
+    #[getter]
+    fn at(&self) -> Option<u64> {
+        match &self.0 {
+            Effect::StartTimer { at } => Some(*at),
+            _ => None,
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+fn effects(effects: Vec<Effect<Value>>) -> Vec<PyEffect> {
+    effects.into_iter().map(PyEffect).collect()
+}
+
+/// A `Proposer` over `bytes` values.
+#[pyclass(name = "Proposer", unsendable)]
+pub struct PyProposer(Proposer<Value>);
+
+#[pymethods]
+impl PyProposer {
+    #[new]
+    fn new(id: NodeId, members: Vec<NodeId>) -> Self {
+        Self(Proposer::new(id, ClusterConfig::new(members)))
+    }
+
+    /// Proposes `value` for the next slot, returning its handle's ID along
+    /// with the resulting effects.
+    fn propose(&mut self, value: &[u8]) -> (u64, Vec<PyEffect>) {
+        let handle = self.0.prepare(value.to_vec());
+        (handle.id(), effects(self.0.take_effects()))
+    }
+
+    fn step(&mut self, msg: &PyMessage) -> Vec<PyEffect> {
+        effects(self.0.step(msg.0.clone()))
+    }
+
+    /// Advances the clock to `now`, in milliseconds, firing timeouts.
+    fn tick(&mut self, now: u64) -> Vec<PyEffect> {
+        self.0.tick(now);
+        effects(self.0.take_effects())
+    }
+
+    #[getter]
+    fn ballot(&self) -> u64 {
+        self.0.current_ballot()
+    }
+}
+
+/// An `Acceptor` over `bytes` values.
+#[pyclass(name = "Acceptor", unsendable)]
+pub struct PyAcceptor(Acceptor<Value>);
+
+#[pymethods]
+impl PyAcceptor {
+    #[new]
+    fn new(id: NodeId, members: Vec<NodeId>) -> Self {
+        Self(Acceptor::new(id, ClusterConfig::new(members)))
+    }
+
+    fn step(&mut self, msg: &PyMessage) -> Vec<PyEffect> {
+        effects(self.0.step(msg.0.clone()))
+    }
+
+    #[getter]
+    fn ballot(&self) -> u64 {
+        self.0.current_ballot()
+    }
+
+    /// The proposal number and value accepted for `slot`, if any.
+    fn accepted<'py>(&self, py: Python<'py>, slot: Slot) -> Option<(u64, Bound<'py, PyBytes>)> {
+        self.0
+            .accepted(slot)
+            .map(|proposal| (proposal.n, PyBytes::new(py, &proposal.value)))
+    }
+}
+
+/// A `Learner` over `bytes` values.
+#[pyclass(name = "Learner", unsendable)]
+pub struct PyLearner(Learner<Value>);
+
+#[pymethods]
+impl PyLearner {
+    #[new]
+    fn new(id: NodeId, members: Vec<NodeId>) -> Self {
+        Self(Learner::new(id, ClusterConfig::new(members)))
+    }
+
+    fn step(&mut self, msg: &PyMessage) -> Vec<PyEffect> {
+        effects(self.0.step(msg.0.clone()))
+    }
+
+    /// The value decided for `slot`, if any.
+    fn decided<'py>(&self, py: Python<'py>, slot: Slot) -> Option<Bound<'py, PyBytes>> {
+        self.0
+            .decided_value(slot)
+            .map(|value| PyBytes::new(py, value))
+    }
+}
+
+/// A `Node`, running all three roles, over `bytes` values.
+#[pyclass(name = "Node", unsendable)]
+pub struct PyNode(Node<Value>);
+
+#[pymethods]
+impl PyNode {
+    #[new]
+    fn new(id: NodeId, members: Vec<NodeId>) -> Self {
+        Self(Node::new(id, ClusterConfig::new(members)))
+    }
+
+    /// Proposes `value` for the next slot, returning its handle's ID along
+    /// with the resulting effects.
+    fn propose(&mut self, value: &[u8]) -> (u64, Vec<PyEffect>) {
+        let handle = self.0.propose(value.to_vec());
+        (handle.id(), effects(self.0.take_effects()))
+    }
+
+    fn step(&mut self, msg: &PyMessage) -> Vec<PyEffect> {
+        effects(self.0.step(msg.0.clone()))
+    }
+
+    /// Advances the clock to `now`, in milliseconds. See `Node::tick`.
+    fn tick(&mut self, now: u64) -> Vec<PyEffect> {
+        self.0.tick(now);
+        effects(self.0.take_effects())
+    }
+
+    /// The values decided since the last call, in slot order.
+    fn poll_decided<'py>(&mut self, py: Python<'py>) -> Vec<(Slot, Bound<'py, PyBytes>)> {
+        self.0
+            .poll_decided()
+            .into_iter()
+            .map(|(slot, value)| (slot, PyBytes::new(py, &value)))
+            .collect()
+    }
+}
+
+/// A `SimCluster` of nodes over `bytes` values.
+#[pyclass(name = "Simulator", unsendable)]
+pub struct PySimulator {
+    sim: SimCluster<Value>,
+    /// Faults of every link not set otherwise
+    faults: Faults,
+}
+
+#[pymethods]
+impl PySimulator {
+    /// A cluster of `size` nodes, with IDs `1..=size`, whose network is
+    /// driven by `seed`.
+    #[new]
+    #[pyo3(signature = (size, seed = 0))]
+    fn new(size: u64, seed: u64) -> Self {
+        Self {
+            sim: SimCluster::new(size, seed),
+            faults: Faults::default(),
+        }
+    }
+
+    /// Sets the bounds of the latency of a message, in milliseconds.
+    fn set_latency(&mut self, min: u64, max: u64) -> PyResult<()> {
+        if min > max {
+            return Err(PyValueError::new_err("minimum latency above maximum"));
+        }
+        self.faults.latency = Latency::Uniform { min, max };
+        self.sim.set_faults(self.faults);
+        Ok(())
+    }
+
+    /// Sets the probability that a message is lost.
+    fn set_drop(&mut self, p: f64) {
+        self.faults.drop = p;
+        self.sim.set_faults(self.faults);
+    }
+
+    /// Sets the probability that a message is delivered twice.
+    fn set_duplicate(&mut self, p: f64) {
+        self.faults.duplicate = p;
+        self.sim.set_faults(self.faults);
+    }
+
+    /// Cuts every link between `a` and `b`, both ways.
+    fn partition(&mut self, a: Vec<NodeId>, b: Vec<NodeId>) {
+        self.sim.partition(&a, &b);
+    }
+
+    /// Cuts every link of node `id`.
+    fn isolate(&mut self, id: NodeId) {
+        self.sim.isolate(id);
+    }
+
+    /// Restores every link cut.
+    fn heal(&mut self) {
+        self.sim.heal();
+    }
+
+    /// Proposes `value` through node `id`.
+    fn propose(&mut self, id: NodeId, value: &[u8]) -> PyResult<()> {
+        if !self.sim.ids().any(|node| node == id) {
+            return Err(PyValueError::new_err(format!("no node {id}")));
+        }
+        self.sim.propose(id, value.to_vec());
+        Ok(())
+    }
+
+    /// Runs events until none is pending, or `max_steps` were run. Returns
+    /// the number of events run.
+    #[pyo3(signature = (max_steps = 100_000))]
+    fn run(&mut self, max_steps: usize) -> usize {
+        self.sim.run(max_steps)
+    }
+
+    /// Runs every event due up to `time`, in milliseconds.
+    fn run_until(&mut self, time: u64) {
+        self.sim.run_until(time);
+    }
+
+    /// The simulated time, in milliseconds.
+    #[getter]
+    fn now(&self) -> u64 {
+        self.sim.now()
+    }
+
+    /// The number of messages lost so far.
+    #[getter]
+    fn dropped(&self) -> u64 {
+        self.sim.dropped()
+    }
+
+    /// The values node `id` learned were decided, along with their slot.
+    fn decided<'py>(
+        &self,
+        py: Python<'py>,
+        id: NodeId,
+    ) -> PyResult<Vec<(Slot, Bound<'py, PyBytes>)>> {
+        if !self.sim.ids().any(|node| node == id) {
+            return Err(PyValueError::new_err(format!("no node {id}")));
+        }
+        Ok(self
+            .sim
+            .decided(id)
+            .iter()
+            .map(|(slot, value)| (*slot, PyBytes::new(py, value)))
+            .collect())
+    }
+}
+
+/// The `paxos_rust` Python module.
+#[pymodule]
+fn paxos_rust(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyMessage>()?;
+    m.add_class::<PyEffect>()?;
+    m.add_class::<PyProposer>()?;
+    m.add_class::<PyAcceptor>()?;
+    m.add_class::<PyLearner>()?;
+    m.add_class::<PyNode>()?;
+    m.add_class::<PySimulator>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::ffi::c_str;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn python_scenario() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "paxos_rust").unwrap();
+            paxos_rust(&module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("paxos", module).unwrap();
+            py.run(
+                c_str!(
+                    r#"
+members = [1, 2, 3]
+proposer = paxos.Proposer(1, members)
+acceptors = [paxos.Acceptor(id, members) for id in members]
+learner = paxos.Learner(4, members)
+
+_, pending = proposer.propose(b"x")
+while pending:
+    effect, pending = pending[0], pending[1:]
+    if effect.kind != "send":
+        continue
+    msg = paxos.Message.decode(effect.message.encode())
+    assert msg == effect.message
+    for role in [proposer, learner] + acceptors:
+        pending += role.step(msg)
+assert learner.decided(0) == b"x"
+assert acceptors[2].accepted(0) == (proposer.ballot, b"x")
+
+sim = paxos.Simulator(5, seed=7)
+sim.isolate(5)
+sim.propose(1, b"y")
+sim.run()
+assert sim.decided(1) == [(0, b"y")]
+assert sim.decided(5) == []
+"#
+                ),
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
@@ -0,0 +1,243 @@
+//! QUIC transport
+//!
+//! Carries `Message`s between nodes over [QUIC](https://docs.rs/quinn), which
+//! suits lossy links between sites better than TCP: TLS is built in,
+//! connections survive a peer's address changing, and every message travels
+//! on a stream of its own, so a packet lost from a large snapshot holds up
+//! that snapshot alone rather than the votes queued behind it. Each stream
+//! carries a single message in its `wire` encoding, compressed by the
+//! `compress` module.
+//!
+//! Every node serves and verifies the same certificate, issued for
+//! `SERVER_NAME`, as generated by `QuicTls::self_signed` for testing or
+//! signed by the cluster's own authority.
+
+use crate::compress::{compress, decompress, Compression};
+use crate::config::NodeId;
+use crate::message::Message;
+use crate::wire::{decode, encode};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use quinn::rustls::RootCertStore;
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig};
+use std::io;
+use std::net::SocketAddr;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// The name every node's certificate is issued for.
+pub const SERVER_NAME: &str = "paxos";
+
+/// The largest message read from a stream, in bytes.
+pub const MAX_MESSAGE: usize = 64 * 1024 * 1024;
+
+/// The certificate nodes present to each other, and the authorities they
+/// trust.
+#[derive(Debug)]
+pub struct QuicTls {
+    /// The node's certificate, followed by those of its issuers
+    pub cert_chain: Vec<CertificateDer<'static>>,
+    /// The certificate's private key
+    pub key: PrivateKeyDer<'static>,
+    /// The authorities a peer's certificate must be issued by
+    pub roots: RootCertStore,
+}
+
+impl QuicTls {
+    /// A self-signed certificate for `SERVER_NAME`, trusting only itself.
+    /// Every node of a cluster must be handed a clone of the same one.
+    pub fn self_signed() -> io::Result<Self> {
+        let cert = rcgen::generate_simple_self_signed(vec![String::from(SERVER_NAME)])
+            .map_err(io::Error::other)?;
+        let der = cert.cert.der().clone();
+        let mut roots = RootCertStore::empty();
+        roots.add(der.clone()).map_err(io::Error::other)?;
+        Ok(Self {
+            cert_chain: vec![der],
+            key: PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()).into(),
+            roots,
+        })
+    }
+}
+
+impl Clone for QuicTls {
+    fn clone(&self) -> Self {
+        Self {
+            cert_chain: self.cert_chain.clone(),
+            key: self.key.clone_key(),
+            roots: self.roots.clone(),
+        }
+    }
+}
+
+/// Sends `Message`s to every node of the cluster over QUIC, and receives
+/// theirs.
+pub struct QuicTransport<T> {
+    /// The node's ID
+    id: NodeId,
+    /// Address of every node, this one included
+    peers: BTreeMap<NodeId, SocketAddr>,
+    endpoint: Endpoint,
+    /// Connections to the nodes, opened as needed
+    conns: BTreeMap<NodeId, Connection>,
+    /// Codec messages are compressed with
+    compression: Compression,
+    /// Messages read from any connection
+    inbox: UnboundedReceiver<Message<T>>,
+}
+
+impl<T> QuicTransport<T>
+where
+    T: AsRef<[u8]> + for<'a> From<&'a [u8]> + Send + Sync + 'static,
+{
+    /// Listens for the other nodes on node `id`'s address among `peers`, on
+    /// a tokio task, with a task for each connection accepted. Must be called
+    /// within a tokio runtime.
+    pub fn bind(id: NodeId, peers: BTreeMap<NodeId, SocketAddr>, tls: QuicTls) -> io::Result<Self> {
+        let addr = peers.get(&id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "node missing from its peers")
+        })?;
+        let server = ServerConfig::with_single_cert(tls.cert_chain, tls.key)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let client = ClientConfig::with_root_certificates(Arc::new(tls.roots))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut endpoint = Endpoint::server(server, *addr)?;
+        endpoint.set_default_client_config(client);
+        let (sender, inbox) = unbounded_channel();
+        tokio::spawn(accept(endpoint.clone(), sender));
+        Ok(Self {
+            id,
+            peers,
+            endpoint,
+            conns: BTreeMap::new(),
+            compression: Compression::supported()[0],
+            inbox,
+        })
+    }
+
+    /// The node's ID.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Sets the codec messages are compressed with, the first built in by
+    /// default. Receivers decode any codec built in.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Sends `msg` to node `to`, on a stream of its own. The message is lost
+    /// if the node can't be reached.
+    pub async fn send(&mut self, to: NodeId, msg: &Message<T>) {
+        let conn = match self.conn(to).await {
+            Some(conn) => conn,
+            None => return,
+        };
+        let frame = compress(self.compression, &encode(msg));
+        let sent = async {
+            let mut stream = conn.open_uni().await?;
+            stream.write_all(&frame).await?;
+            stream.finish()?;
+            Ok::<_, io::Error>(())
+        };
+        if sent.await.is_err() {
+            self.conns.remove(&to);
+        }
+    }
+
+    /// Sends `msg` to every node, this one included.
+    pub async fn broadcast(&mut self, msg: &Message<T>) {
+        let ids: Vec<NodeId> = self.peers.keys().copied().collect();
+        for to in ids {
+            self.send(to, msg).await;
+        }
+    }
+
+    /// Waits for a message from any node.
+    pub async fn recv(&mut self) -> Option<Message<T>> {
+        self.inbox.recv().await
+    }
+
+    /// The connection to node `to`, opened if need be.
+    async fn conn(&mut self, to: NodeId) -> Option<Connection> {
+        if let Some(conn) = self.conns.get(&to) {
+            if conn.close_reason().is_none() {
+                return Some(conn.clone());
+            }
+        }
+        let addr = *self.peers.get(&to)?;
+        let conn = self.endpoint.connect(addr, SERVER_NAME).ok()?.await.ok()?;
+        self.conns.insert(to, conn.clone());
+        Some(conn)
+    }
+}
+
+async fn accept<T>(endpoint: Endpoint, sender: UnboundedSender<Message<T>>)
+where
+    T: for<'a> From<&'a [u8]> + Send + Sync + 'static,
+{
+    while let Some(incoming) = endpoint.accept().await {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let conn = match incoming.await {
+                Ok(conn) => conn,
+                Err(_) => return,
+            };
+            while let Ok(mut stream) = conn.accept_uni().await {
+                let sender = sender.clone();
+                // Streams are read concurrently, so that one large message
+                // doesn't hold up the others.
+                tokio::spawn(async move {
+                    let bytes = match stream.read_to_end(MAX_MESSAGE).await {
+                        Ok(bytes) => bytes,
+                        Err(_) => return,
+                    };
+                    if let Ok(msg) = decompress(&bytes).and_then(|bytes| decode(&bytes)) {
+                        let _ = sender.send(msg);
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{AcceptData, JoinData};
+    use std::net::UdpSocket;
+
+    #[tokio::test]
+    async fn quic_transport() {
+        // Bind to free ports first, then hand the addresses over.
+        let addrs: Vec<SocketAddr> = (0..2)
+            .map(|_| {
+                let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+                socket.local_addr().unwrap()
+            })
+            .collect();
+        let peers: BTreeMap<NodeId, SocketAddr> = [(1, addrs[0]), (2, addrs[1])].into();
+        let tls = QuicTls::self_signed().unwrap();
+        let mut a: QuicTransport<Vec<u8>> =
+            QuicTransport::bind(1, peers.clone(), tls.clone()).unwrap();
+        let mut b: QuicTransport<Vec<u8>> = QuicTransport::bind(2, peers, tls).unwrap();
+        let msg = Message::Join(JoinData { from: 1 });
+
+        a.broadcast(&msg).await;
+
+        assert_eq!(a.recv().await, Some(msg.clone()));
+        assert_eq!(b.recv().await, Some(msg));
+
+        let large = Message::Accept(AcceptData {
+            slot: 0,
+            id: 1,
+            value: Arc::new(vec![7; 1 << 20]),
+            implicit_prepare: false,
+        });
+        a.send(2, &large).await;
+        assert_eq!(b.recv().await, Some(large));
+    }
+}
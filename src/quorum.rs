@@ -0,0 +1,292 @@
+//! Quorum systems
+
+use crate::config::{ConfigError, NodeId};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+/// Decides which sets of `Acceptor`s make up a quorum in each phase.
+///
+/// Any Phase-1 quorum must intersect every Phase-2 quorum, which is what
+/// guarantees that a new `Proposer` learns about values that may have been
+/// decided. Implementations are expected to uphold this.
+pub trait QuorumSystem: Debug {
+    /// Whether the `Promise`s of `voters` complete the first phase.
+    fn is_phase1_quorum(&self, voters: &[NodeId]) -> bool;
+
+    /// Whether the `Accepted` messages of `voters` decide a value.
+    fn is_phase2_quorum(&self, voters: &[NodeId]) -> bool;
+}
+
+/// Counts the votes of members, requiring `phase1` and `phase2` of them
+/// (simple majorities by default).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Majority {
+    /// The voting members
+    pub members: Vec<NodeId>,
+    /// Number of members needed to complete the first phase
+    pub phase1: usize,
+    /// Number of members needed to decide a value
+    pub phase2: usize,
+}
+
+impl Majority {
+    /// Creates a new `Majority` over `members`.
+    pub fn new(members: Vec<NodeId>) -> Self {
+        let quorum = members.len() / 2 + 1;
+        Self {
+            members,
+            phase1: quorum,
+            phase2: quorum,
+        }
+    }
+
+    fn count(&self, voters: &[NodeId]) -> usize {
+        voters.iter().filter(|v| self.members.contains(v)).count()
+    }
+}
+
+impl QuorumSystem for Majority {
+    fn is_phase1_quorum(&self, voters: &[NodeId]) -> bool {
+        self.count(voters) >= self.phase1
+    }
+
+    fn is_phase2_quorum(&self, voters: &[NodeId]) -> bool {
+        self.count(voters) >= self.phase2
+    }
+}
+
+/// Gives every member a weight, and requires the weights of the voters to add
+/// up to a threshold. Useful for heterogeneous clusters, where some
+/// `Acceptor`s are more reliable than others.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Weighted {
+    /// Member => weight of its vote
+    pub weights: BTreeMap<NodeId, u64>,
+    /// Total weight needed to complete the first phase
+    pub phase1: u64,
+    /// Total weight needed to decide a value
+    pub phase2: u64,
+}
+
+impl Weighted {
+    /// Creates a new `Weighted` quorum system requiring a majority of the
+    /// total weight in both phases.
+    pub fn new(weights: BTreeMap<NodeId, u64>) -> Self {
+        let threshold = weights.values().sum::<u64>() / 2 + 1;
+        Self {
+            weights,
+            phase1: threshold,
+            phase2: threshold,
+        }
+    }
+
+    /// Uses separate thresholds for each phase, provided they intersect.
+    pub fn with_thresholds(mut self, phase1: u64, phase2: u64) -> Result<Self, ConfigError> {
+        let total = self.weights.values().sum::<u64>();
+        if phase1 == 0 || phase2 == 0 || phase1 > total || phase2 > total {
+            return Err(ConfigError::QuorumOutOfRange);
+        }
+        if phase1 + phase2 <= total {
+            return Err(ConfigError::QuorumsDoNotIntersect);
+        }
+        self.phase1 = phase1;
+        self.phase2 = phase2;
+        Ok(self)
+    }
+
+    fn weight(&self, voters: &[NodeId]) -> u64 {
+        voters.iter().filter_map(|v| self.weights.get(v)).sum()
+    }
+}
+
+impl QuorumSystem for Weighted {
+    fn is_phase1_quorum(&self, voters: &[NodeId]) -> bool {
+        self.weight(voters) >= self.phase1
+    }
+
+    fn is_phase2_quorum(&self, voters: &[NodeId]) -> bool {
+        self.weight(voters) >= self.phase2
+    }
+}
+
+/// Arranges the members in a grid. A complete row decides a value, while the
+/// first phase needs a member of every row, so the two always intersect.
+///
+/// With wide rows this keeps Phase-2 quorums small and independent of the
+/// cluster size.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Grid {
+    /// The members of each row
+    pub rows: Vec<Vec<NodeId>>,
+}
+
+impl Grid {
+    /// Creates a new `Grid` from its `rows`.
+    pub fn new(rows: Vec<Vec<NodeId>>) -> Self {
+        Self { rows }
+    }
+}
+
+impl QuorumSystem for Grid {
+    fn is_phase1_quorum(&self, voters: &[NodeId]) -> bool {
+        !self.rows.is_empty()
+            && self
+                .rows
+                .iter()
+                .all(|row| row.iter().any(|m| voters.contains(m)))
+    }
+
+    fn is_phase2_quorum(&self, voters: &[NodeId]) -> bool {
+        self.rows
+            .iter()
+            .any(|row| !row.is_empty() && row.iter().all(|m| voters.contains(m)))
+    }
+}
+
+/// Number of node IDs `Voters` keep in a bitmap.
+pub const DENSE_IDS: u64 = 64;
+
+/// The senders of a set of votes. IDs below `DENSE_IDS`, as in clusters
+/// numbering their nodes from 0 or 1, are kept in a bitmap, so that quorums
+/// are counted without allocating; larger IDs fall back to a sorted list.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Voters {
+    /// Bit `id` is set for every voter `id` below `DENSE_IDS`
+    dense: u64,
+    /// The other voters, in ascending order
+    sparse: Vec<NodeId>,
+}
+
+impl Voters {
+    /// Creates an empty set of `Voters`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `id`, returning whether it wasn't counted yet.
+    pub fn insert(&mut self, id: NodeId) -> bool {
+        if id < DENSE_IDS {
+            let new = self.dense & 1 << id == 0;
+            self.dense |= 1 << id;
+            return new;
+        }
+        match self.sparse.binary_search(&id) {
+            Ok(_) => false,
+            Err(i) => {
+                self.sparse.insert(i, id);
+                true
+            }
+        }
+    }
+
+    /// Whether `id` voted.
+    pub fn contains(&self, id: NodeId) -> bool {
+        if id < DENSE_IDS {
+            self.dense & 1 << id != 0
+        } else {
+            self.sparse.binary_search(&id).is_ok()
+        }
+    }
+
+    /// The number of voters.
+    pub fn len(&self) -> usize {
+        self.dense.count_ones() as usize + self.sparse.len()
+    }
+
+    /// Whether nobody voted.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The voters, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = NodeId> + '_ {
+        let dense = self.dense;
+        (0..DENSE_IDS)
+            .filter(move |id| dense & 1 << id != 0)
+            .chain(self.sparse.iter().copied())
+    }
+
+    /// The number of voters among `ids`.
+    pub fn count_in(&self, ids: &[NodeId]) -> usize {
+        self.iter().filter(|id| ids.contains(id)).count()
+    }
+}
+
+impl FromIterator<NodeId> for Voters {
+    fn from_iter<I: IntoIterator<Item = NodeId>>(ids: I) -> Self {
+        let mut voters = Voters::new();
+        for id in ids {
+            voters.insert(id);
+        }
+        voters
+    }
+}
+
+/// The senders of a set of votes.
+pub(crate) fn voters<V>(votes: &BTreeMap<NodeId, V>) -> Voters {
+    votes.keys().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn majority() {
+        let q = Majority::new(vec![1, 2, 3]);
+
+        assert!(!q.is_phase1_quorum(&[1]));
+        assert!(q.is_phase1_quorum(&[1, 3]));
+        assert!(q.is_phase2_quorum(&[2, 3]));
+
+        // Non-members don't count.
+        assert!(!q.is_phase2_quorum(&[1, 4]));
+    }
+
+    #[test]
+    fn weighted() {
+        let weights = [(1, 3), (2, 1), (3, 1)].into_iter().collect();
+        let q = Weighted::new(weights);
+
+        assert!(q.is_phase1_quorum(&[1]));
+        assert!(!q.is_phase2_quorum(&[2, 3]));
+
+        let q = q.with_thresholds(2, 4).unwrap();
+
+        assert!(q.is_phase1_quorum(&[2, 3]));
+        assert!(!q.is_phase2_quorum(&[1]));
+        assert!(q.is_phase2_quorum(&[1, 2]));
+
+        let weights = [(1, 3), (2, 1), (3, 1)].into_iter().collect();
+
+        assert_eq!(
+            Weighted::new(weights).with_thresholds(2, 3),
+            Err(ConfigError::QuorumsDoNotIntersect)
+        );
+    }
+
+    #[test]
+    fn voters() {
+        let mut v: Voters = [3, 1, 70].into_iter().collect();
+
+        assert!(!v.insert(3));
+        assert!(v.insert(64));
+        assert!(!v.insert(70));
+        assert!(v.contains(1) && v.contains(64) && !v.contains(2));
+        assert_eq!(v.len(), 4);
+        assert_eq!(v.iter().collect::<Vec<_>>(), vec![1, 3, 64, 70]);
+        assert_eq!(v.count_in(&[1, 2, 70]), 2);
+    }
+
+    #[test]
+    fn grid() {
+        let q = Grid::new(vec![vec![1, 2, 3], vec![4, 5, 6]]);
+
+        assert!(q.is_phase1_quorum(&[1, 6]));
+        assert!(!q.is_phase1_quorum(&[1, 2, 3]));
+        assert!(q.is_phase2_quorum(&[4, 5, 6]));
+        assert!(!q.is_phase2_quorum(&[1, 2, 4, 5]));
+    }
+}
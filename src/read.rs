@@ -0,0 +1,277 @@
+//! Linearizable reads
+//!
+//! A `Learner` may not have heard of every value decided yet, so reading its
+//! log alone may miss writes that already completed. Rather than proposing a
+//! no-op through the log for every read, a `Learner` asks the `Acceptor`s how
+//! far their logs go (Paxos Quorum Reads): any value decided before the read
+//! started was accepted by a Phase-2 quorum, which a Phase-1 quorum of replies
+//! intersects, so the highest slot reported covers it. Once every slot up to
+//! that one is decided locally, the read sees every write completed before it
+//! started.
+//!
+//! Replies may be lost: `retry_reads` asks again for the reads still waiting
+//! for a quorum. A read also waits for the slots it covers to be decided, so
+//! it only completes while a leader makes progress.
+//!
+//! Read-heavy workloads that tolerate bounded staleness can serve reads from
+//! the local log instead. A completed read proves that every value decided
+//! before it started is decided locally, so issuing reads periodically, as
+//! heartbeats, bounds how stale the local log may be: `stale_read` serves
+//! the latest value decided locally unless the last read to complete started
+//! too long ago.
+
+use crate::effect::Effect;
+use crate::learner::Learner;
+use crate::message::{Message, Messenger, ReadData, Slot};
+use crate::quorum::voters;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A read completed by `Learner::poll_reads`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReadResult<T> {
+    /// The ID `read` returned
+    pub id: u64,
+    /// Every slot below it is decided, including those of all the values
+    /// decided before the read started. A state machine serves the read once
+    /// it has applied them
+    pub index: Slot,
+    /// The value of the slot below `index`, unless compacted or `index` is 0
+    pub value: Option<Arc<T>>,
+}
+
+/// A read served from the local log by `Learner::stale_read`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StaleRead<T> {
+    /// Every slot below it is decided locally
+    pub index: Slot,
+    /// The value of the slot below `index`, unless compacted or `index` is 0
+    pub value: Option<Arc<T>>,
+    /// Milliseconds since the start of the last read to complete. Every value
+    /// decided before then is covered
+    pub age: u64,
+}
+
+impl<T: PartialEq, M: Messenger<T>> Learner<T, M> {
+    /// Starts a linearizable read, and returns its ID. The read completes
+    /// through `poll_reads`.
+    pub fn read(&mut self) -> u64 {
+        let id = self.next_read;
+        self.next_read += 1;
+        self.reads.insert(id, BTreeMap::new());
+        self.read_starts.insert(id, self.now);
+        self.send_read(id);
+        id
+    }
+
+    /// Asks again for the reads still waiting for a quorum of replies.
+    pub fn retry_reads(&mut self) {
+        let ids: Vec<u64> = self.reads.keys().copied().collect();
+        for id in ids {
+            self.send_read(id);
+        }
+    }
+
+    fn send_read(&mut self, id: u64) {
+        let read = Message::Read(ReadData { from: self.id, id });
+        self.effect(Effect::SendMessage(read));
+    }
+
+    /// Receives a `ReadReply` message from an `Acceptor`. Once a Phase-1
+    /// quorum replied, the read waits for the highest slot reported to be
+    /// decided. Replies from outside the cluster are ignored.
+    pub fn receive_read_reply(&mut self, msg: Message<T>) {
+        if let Message::ReadReply(data) = msg {
+            span!("receive_read_reply", learner = self.id, from = data.from);
+            if data.to != self.id || !self.config.is_member(data.from) {
+                return;
+            }
+            let replies = match self.reads.get_mut(&data.id) {
+                Some(replies) => replies,
+                None => return,
+            };
+            replies.insert(data.from, data.horizon);
+            if self.config.is_phase1_quorum_of(&voters(replies)) {
+                let index = replies.values().copied().max().unwrap_or(0);
+                self.reads.remove(&data.id);
+                self.ready_reads.insert(data.id, index);
+            }
+        }
+    }
+
+    /// Returns the reads completed since the last call: those a quorum
+    /// answered, once every slot they cover is decided.
+    pub fn poll_reads(&mut self) -> Vec<ReadResult<T>> {
+        let decided = self.decided_prefix();
+        let done: Vec<(u64, Slot)> = self
+            .ready_reads
+            .iter()
+            .filter(|(_, index)| **index <= decided)
+            .map(|(id, index)| (*id, *index))
+            .collect();
+        done.into_iter()
+            .map(|(id, index)| {
+                self.ready_reads.remove(&id);
+                if let Some(started) = self.read_starts.remove(&id) {
+                    self.synced_at = self.synced_at.max(Some(started));
+                }
+                let value = self.value_below(index);
+                ReadResult { id, index, value }
+            })
+            .collect()
+    }
+
+    /// Reads the latest value decided locally, without contacting the
+    /// cluster, provided the last read to complete started at most `max_age`
+    /// milliseconds ago according to `set_time`. Returns `None` if it is
+    /// older, or if no read completed yet.
+    pub fn stale_read(&self, max_age: u64) -> Option<StaleRead<T>> {
+        let age = self.now.saturating_sub(self.synced_at?);
+        if age > max_age {
+            return None;
+        }
+        let index = self.decided_prefix();
+        Some(StaleRead {
+            index,
+            value: self.value_below(index),
+            age,
+        })
+    }
+
+    /// The slot after the last one decided without any gap below it.
+    fn decided_prefix(&self) -> Slot {
+        self.decided
+            .keys()
+            .zip(self.compacted()..)
+            .take_while(|(slot, expected)| **slot == *expected)
+            .map(|(slot, _)| slot + 1)
+            .last()
+            .unwrap_or(self.compacted())
+    }
+
+    fn value_below(&self, index: Slot) -> Option<Arc<T>> {
+        index
+            .checked_sub(1)
+            .and_then(|slot| self.decided.get(&slot).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceptor::Acceptor;
+    use crate::config::ClusterConfig;
+    use crate::message::{AcceptData, AcceptedData, Handler, ReadReplyData};
+    use alloc::vec;
+
+    #[test]
+    fn read_sees_completed_writes() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut acceptors: Vec<Acceptor<u64>> = (1..=3)
+            .map(|id| Acceptor::new(id, config.clone()))
+            .collect();
+        let mut l: Learner<u64> = Learner::new(4, config);
+
+        // Slots 0 and 1 were accepted by a quorum the `Learner` never heard
+        // from.
+        for (slot, value) in [(0, 10), (1, 20)] {
+            for a in acceptors.iter_mut().take(2) {
+                a.handle(Message::Accept(AcceptData {
+                    slot,
+                    id: 1,
+                    value: Arc::new(value),
+                    implicit_prepare: false,
+                }));
+                a.take_effects();
+            }
+        }
+
+        let id = l.read();
+
+        assert_eq!(
+            l.take_effects(),
+            vec![Effect::SendMessage(Message::Read(ReadData { from: 4, id }))]
+        );
+
+        // A quorum including the lagging `Acceptor` still covers the writes.
+        for a in acceptors.iter_mut().rev().take(2) {
+            for effect in a.step(Message::Read(ReadData { from: 4, id })) {
+                if let Effect::SendMessage(reply) = effect {
+                    l.handle(reply);
+                }
+            }
+        }
+
+        // The read waits for the slots a quorum reported.
+        for slot in 0..2 {
+            assert!(l.poll_reads().is_empty());
+
+            for from in 1..=2 {
+                l.receive_accepted(Message::Accepted(AcceptedData {
+                    slot,
+                    id: 1,
+                    value: Arc::new(10 * (slot + 1)),
+                    from,
+                    fast: false,
+                }));
+            }
+        }
+
+        assert_eq!(
+            l.poll_reads(),
+            vec![ReadResult {
+                id,
+                index: 2,
+                value: Some(Arc::new(20)),
+            }]
+        );
+        assert!(l.poll_reads().is_empty());
+    }
+
+    #[test]
+    fn stale_read_bounded_by_last_read() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut l: Learner<u64> = Learner::new(4, config);
+
+        l.set_time(100);
+
+        // Nothing is served before the `Learner` synced once.
+        assert_eq!(l.stale_read(1000), None);
+
+        let id = l.read();
+        l.take_effects();
+        l.set_time(120);
+        for from in 1..=2 {
+            l.receive_read_reply(Message::ReadReply(ReadReplyData {
+                from,
+                to: 4,
+                id,
+                horizon: 0,
+            }));
+        }
+        l.poll_reads();
+
+        for from in 1..=2 {
+            l.receive_accepted(Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(10),
+                from,
+                fast: false,
+            }));
+        }
+        l.set_time(150);
+
+        // The age counts from when the read started.
+        assert_eq!(
+            l.stale_read(50),
+            Some(StaleRead {
+                index: 1,
+                value: Some(Arc::new(10)),
+                age: 50,
+            })
+        );
+        assert_eq!(l.stale_read(49), None);
+    }
+}
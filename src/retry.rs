@@ -0,0 +1,220 @@
+//! Retry policies
+//!
+//! A `Proposer` whose round fails, be it to a `Nack` outbidding it or to a
+//! phase timing out once its retransmits ran out, retries the first phase
+//! under a higher proposal number. Without a `RetryPolicy`, it does so as soon
+//! as the phase times out. With one, it also retries on the first `Nack`
+//! outbidding the round, and either way backs off for as long as the policy
+//! says first, so that competing `Proposer`s stop preempting each other:
+//!
+//! - `FixedRetry` always waits the same.
+//! - `ExponentialRetry` doubles the wait with every failed round in a row, up
+//!   to a ceiling, with random jitter spreading `Proposer`s apart.
+//! - `RateLimitedRetry` caps the rate of retries of another policy with a
+//!   token bucket, e.g. to bound the ballots burnt under sustained contention.
+
+use crate::sim::Rng;
+use alloc::boxed::Box;
+
+/// Decides how long a `Proposer` backs off before retrying a failed round.
+pub trait RetryPolicy {
+    /// The milliseconds to wait, as of `now`, before the `attempt`th retry
+    /// since a round last completed, counting from 1.
+    fn backoff(&mut self, attempt: u32, now: u64) -> u64;
+}
+
+/// `RetryPolicy` a `Proposer` consults.
+pub type RetrySink = Box<dyn RetryPolicy + Send>;
+
+/// Waits `delay` milliseconds before every retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedRetry {
+    /// Milliseconds waited before every retry
+    pub delay: u64,
+}
+
+impl FixedRetry {
+    /// Creates a policy waiting `delay` milliseconds before every retry.
+    pub fn new(delay: u64) -> Self {
+        Self { delay }
+    }
+}
+
+impl RetryPolicy for FixedRetry {
+    fn backoff(&mut self, _attempt: u32, _now: u64) -> u64 {
+        self.delay
+    }
+}
+
+/// Waits `base` milliseconds before the first retry, doubling the wait with
+/// every further one up to `max`. Half of each wait is random, drawn from a
+/// generator seeded with `seed`: `Proposer`s should be given different seeds,
+/// e.g. their IDs, so that their retries drift apart.
+#[derive(Debug, Clone)]
+pub struct ExponentialRetry {
+    base: u64,
+    max: u64,
+    rng: Rng,
+}
+
+impl ExponentialRetry {
+    /// Creates a policy waiting from `base` up to `max` milliseconds.
+    pub fn new(base: u64, max: u64, seed: u64) -> Self {
+        Self {
+            base,
+            max,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl RetryPolicy for ExponentialRetry {
+    fn backoff(&mut self, attempt: u32, _now: u64) -> u64 {
+        let doublings = attempt.saturating_sub(1).min(63);
+        let wait = self.base.saturating_mul(1 << doublings).min(self.max);
+        wait - wait / 2 + self.rng.between(0, wait / 2)
+    }
+}
+
+/// Limits the retries of `policy` to `capacity` at once, refilled by one
+/// every `interval` milliseconds. A retry with no token left waits for the
+/// next one.
+#[derive(Debug, Clone)]
+pub struct RateLimitedRetry<P> {
+    policy: P,
+    capacity: u32,
+    interval: u64,
+    tokens: u32,
+    /// When the last token was added
+    refilled_at: u64,
+}
+
+impl<P: RetryPolicy> RateLimitedRetry<P> {
+    /// Creates a policy limiting `policy` to a full bucket of `capacity`
+    /// tokens, refilled every `interval` milliseconds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is 0.
+    pub fn new(policy: P, capacity: u32, interval: u64) -> Self {
+        assert!(interval > 0, "tokens must be refilled over time");
+        Self {
+            policy,
+            capacity,
+            interval,
+            tokens: capacity,
+            refilled_at: 0,
+        }
+    }
+
+    /// Adds the tokens refilled by `at`.
+    fn refill(&mut self, at: u64) {
+        if at <= self.refilled_at {
+            return;
+        }
+        let refilled = (at - self.refilled_at) / self.interval;
+        let tokens = u64::from(self.tokens) + refilled;
+        if tokens >= u64::from(self.capacity) {
+            self.tokens = self.capacity;
+            self.refilled_at = at;
+        } else {
+            self.tokens = tokens as u32;
+            self.refilled_at += refilled * self.interval;
+        }
+    }
+}
+
+impl<P: RetryPolicy> RetryPolicy for RateLimitedRetry<P> {
+    fn backoff(&mut self, attempt: u32, now: u64) -> u64 {
+        let at = now + self.policy.backoff(attempt, now);
+        self.refill(at);
+        if self.tokens == 0 {
+            // The next token is spent as soon as it comes.
+            self.refilled_at += self.interval;
+            return self.refilled_at - now;
+        }
+        self.tokens -= 1;
+        at - now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::effect::Effect;
+    use crate::message::{Message, NackData, ProposalData};
+    use crate::proposer::Proposer;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn retry_policies() {
+        let mut fixed = FixedRetry::new(20);
+
+        assert_eq!(fixed.backoff(1, 0), 20);
+        assert_eq!(fixed.backoff(7, 0), 20);
+
+        let mut exponential = ExponentialRetry::new(10, 100, 1);
+        let waits: Vec<u64> = (1..=6).map(|a| exponential.backoff(a, 0)).collect();
+
+        for (wait, ceiling) in waits.iter().zip([10, 20, 40, 80, 100, 100]) {
+            assert!((ceiling - ceiling / 2..=ceiling).contains(wait));
+        }
+        assert!(exponential.backoff(u32::MAX, 0) <= 100);
+
+        // Two retries at once, then one every 50ms.
+        let mut limited = RateLimitedRetry::new(FixedRetry::new(0), 2, 50);
+
+        assert_eq!(limited.backoff(1, 0), 0);
+        assert_eq!(limited.backoff(2, 0), 0);
+        assert_eq!(limited.backoff(3, 0), 50);
+        assert_eq!(limited.backoff(4, 10), 90);
+        assert_eq!(limited.backoff(5, 500), 0);
+    }
+
+    #[test]
+    fn retry_on_nack() {
+        let mut p: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        p.set_retry_policy(FixedRetry::new(30));
+        p.prepare(10);
+        let n = p.current_ballot();
+        p.take_effects();
+        p.tick(5);
+        p.receive_nack(Message::Nack(NackData {
+            slot: 0,
+            id: n,
+            from: 2,
+            promised_n: n + 1,
+            leader: Some(2),
+        }));
+
+        assert_eq!(
+            p.take_effects(),
+            vec![
+                Effect::Preempted {
+                    ballot: n,
+                    by: n + 1
+                },
+                Effect::StartTimer { at: 35 },
+            ]
+        );
+
+        // The round is retried once the backoff is over.
+        p.tick(34);
+
+        assert_eq!(p.current_ballot(), n);
+
+        p.tick(35);
+
+        let ballot = p.current_ballot();
+        assert!(ballot > n + 1);
+        assert!(p
+            .take_effects()
+            .contains(&Effect::SendMessage(Message::Prepare(ProposalData {
+                slot: 0,
+                id: ballot,
+                from: 1,
+            }))));
+    }
+}
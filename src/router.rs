@@ -0,0 +1,310 @@
+//! Routing
+//!
+//! The groups of a `MultiGroup` share a transport, so every message has to
+//! carry the group it belongs to. A `Router` wraps each outgoing message in an
+//! `Envelope` naming its group, and queues it for every peer of that group:
+//! the members of its `ClusterConfig` and their shadows, the nodes an
+//! `Effect::SendMessageTo` names, or the single node a `State`,
+//! `InstallSnapshot`, `ReadReply` or `Repair` is addressed to.
+//! `flush` hands over everything queued for a peer as one batch, so that a
+//! process hosting thousands of groups sends one frame per peer rather than
+//! one per message. Inbound, `receive` hands each envelope of a batch to the
+//! `Node` of its group, unless the `Node`'s `Validator` refuses it.
+//!
+//! `encode_batch` and `decode_batch` carry batches over the wire, as a u32
+//! count of `group:u64 msg:bytes` entries, each `msg` encoded by the `wire`
+//! module.
+
+use crate::config::NodeId;
+use crate::effect::Effect;
+use crate::group::{GroupId, MultiGroup};
+use crate::message::Message;
+use crate::wire::{self, put_bytes, put_u32, put_u64, DecodeError, Reader};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// A message, along with the group it belongs to.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Envelope<T> {
+    /// The group the message belongs to
+    pub group: GroupId,
+    /// The message
+    pub msg: Message<T>,
+}
+
+/// Routes the messages of a `MultiGroup` to and from its peers. See the
+/// module documentation.
+pub struct Router<T> {
+    groups: MultiGroup<T>,
+    /// Envelopes waiting to be flushed, by peer
+    outbox: BTreeMap<NodeId, Vec<Envelope<T>>>,
+    /// Effects other than messages, waiting to be taken
+    effects: Vec<(GroupId, Effect<T>)>,
+}
+
+impl<T: PartialEq + Clone> Router<T> {
+    /// Routes the messages of `groups`.
+    pub fn new(groups: MultiGroup<T>) -> Self {
+        Self {
+            groups,
+            outbox: BTreeMap::new(),
+            effects: Vec::new(),
+        }
+    }
+
+    /// The groups routed for.
+    pub fn groups(&self) -> &MultiGroup<T> {
+        &self.groups
+    }
+
+    /// The groups routed for, mutably, e.g. to propose values. Effects they
+    /// produce are routed on the next `flush`.
+    pub fn groups_mut(&mut self) -> &mut MultiGroup<T> {
+        &mut self.groups
+    }
+
+    /// Hands every envelope of `batch` to the `Node` of its group, queueing
+    /// the messages sent in response. Envelopes the `Node`'s `Validator`
+    /// refuses are dropped, and counted through its `Metrics`.
+    pub fn receive(&mut self, batch: Vec<Envelope<T>>) {
+        for envelope in batch {
+            let effects = self.groups.step(envelope.group, envelope.msg);
+            self.route(effects);
+        }
+    }
+
+    /// Takes the effects other than messages produced since the last call,
+    /// e.g. persisting state. They must be carried out before the batches
+    /// of the next `flush` are sent.
+    pub fn take_effects(&mut self) -> Vec<(GroupId, Effect<T>)> {
+        let effects = self.groups.take_effects();
+        self.route(effects);
+        core::mem::take(&mut self.effects)
+    }
+
+    /// Takes the effects of every group, and returns the batch of envelopes
+    /// queued for each peer.
+    pub fn flush(&mut self) -> Vec<(NodeId, Vec<Envelope<T>>)> {
+        let effects = self.groups.take_effects();
+        self.route(effects);
+        core::mem::take(&mut self.outbox).into_iter().collect()
+    }
+
+    /// Queues the messages among `effects` for their peers, keeping the
+    /// others until taken.
+    fn route(&mut self, effects: Vec<(GroupId, Effect<T>)>) {
+        for (group, effect) in effects {
+            let (to, msg) = match effect {
+                Effect::SendMessage(msg) => (None, msg),
+                Effect::SendMessageTo(to, msg) => (Some(to), msg),
+                effect => {
+                    self.effects.push((group, effect));
+                    continue;
+                }
+            };
+            let peers = match (recipient(&msg), to, self.groups.group(group)) {
+                (Some(to), _, _) => alloc::vec![to],
+                (None, Some(to), _) => to,
+                (None, None, Some(node)) => {
+                    let config = &node.acceptor().config;
+                    let mut peers = config.members.clone();
+                    peers.extend(&config.shadows);
+                    peers
+                }
+                (None, None, None) => continue,
+            };
+            for peer in peers {
+                self.outbox.entry(peer).or_default().push(Envelope {
+                    group,
+                    msg: msg.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// The single node `msg` is addressed to, if any.
+fn recipient<T>(msg: &Message<T>) -> Option<NodeId> {
+    match msg {
+        Message::State(data) => Some(data.to),
+        Message::InstallSnapshot(data) => Some(data.to),
+        Message::ReadReply(data) => Some(data.to),
+        Message::Repair(data) => Some(data.to),
+        _ => None,
+    }
+}
+
+/// Encodes a batch of envelopes into a single frame.
+pub fn encode_batch<T: AsRef<[u8]>>(batch: &[Envelope<T>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    put_u32(&mut out, batch.len() as u32);
+    for envelope in batch {
+        put_u64(&mut out, envelope.group);
+        put_bytes(&mut out, &wire::encode(&envelope.msg));
+    }
+    out
+}
+
+/// Decodes a frame encoded by `encode_batch`.
+pub fn decode_batch<T>(bytes: &[u8]) -> Result<Vec<Envelope<T>>, DecodeError>
+where
+    T: for<'a> From<&'a [u8]>,
+{
+    let mut r = Reader { bytes };
+    let mut batch = Vec::new();
+    for _ in 0..r.u32()? {
+        let group = r.u64()?;
+        let msg = wire::decode(r.bytes()?)?;
+        batch.push(Envelope { group, msg });
+    }
+    if !r.bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes);
+    }
+    Ok(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceptor::Acceptor;
+    use crate::config::ClusterConfig;
+    use crate::message::{ProposalData, StateData};
+    use crate::proposer::{ballot, ballot_owner};
+    use crate::validate::tests::Rejections;
+    use crate::validate::Validator;
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    #[test]
+    fn router_batches_per_peer() {
+        let mut routers: Vec<Router<u64>> = (1..=3)
+            .map(|id| {
+                let mut groups = MultiGroup::new(id);
+                groups.add_group(1, ClusterConfig::new(vec![1, 2, 3]));
+                groups.add_group(2, ClusterConfig::new(vec![1, 2]));
+                Router::new(groups)
+            })
+            .collect();
+        routers[0].groups_mut().propose(1, 10);
+        routers[0].groups_mut().propose(2, 20);
+
+        let batches = routers[0].flush();
+
+        // A single batch per peer, carrying the Prepares of both groups to
+        // the members of each.
+        assert_eq!(
+            batches
+                .iter()
+                .map(|(peer, b)| (*peer, b.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (2, 2), (3, 1)]
+        );
+
+        let mut batches = batches;
+        while !batches.is_empty() {
+            let mut next = Vec::new();
+            for (peer, batch) in batches {
+                let router = &mut routers[peer as usize - 1];
+                router.receive(batch);
+                next.extend(router.flush());
+            }
+            batches = next;
+        }
+
+        assert_eq!(
+            routers[1].groups_mut().poll_decided(),
+            vec![(1, 0, Arc::new(10)), (2, 0, Arc::new(20))]
+        );
+    }
+
+    #[test]
+    fn router_validates() {
+        let mut groups = MultiGroup::new(1);
+        let node = groups.add_group(7, ClusterConfig::new(vec![1, 2, 3]));
+        let rejections = Rejections::default();
+        node.set_metrics(rejections.clone());
+        node.set_validator(Validator::new(7).max_rounds_ahead(10));
+        let mut router: Router<u64> = Router::new(groups);
+        let prepare = |id| Envelope {
+            group: 7,
+            msg: Message::Prepare(ProposalData {
+                slot: 0,
+                id,
+                from: ballot_owner(id),
+            }),
+        };
+
+        router.receive(vec![prepare(ballot(1, 9)), prepare(ballot(11, 2))]);
+
+        assert!(router.flush().is_empty());
+        assert_eq!(*rejections.0.lock().unwrap(), vec![203, 205]);
+
+        router.receive(vec![prepare(ballot(10, 2))]);
+
+        assert_eq!(
+            router
+                .groups()
+                .group(7)
+                .unwrap()
+                .acceptor()
+                .current_ballot(),
+            ballot(10, 2)
+        );
+        assert!(!router.flush().is_empty());
+    }
+
+    #[test]
+    fn router_validates_joining() {
+        let config = ClusterConfig::new(vec![1, 2, 3, 4]);
+        let mut groups = MultiGroup::new(4);
+        let node = groups.add_group(7, config.clone());
+        *node.acceptor_mut() = Acceptor::joining(4, config);
+        node.set_validator(Validator::new(7).max_rounds_ahead(10));
+        node.acceptor_mut().join();
+        let mut router: Router<u64> = Router::new(groups);
+
+        assert_eq!(router.flush().len(), 4);
+
+        // The cluster moved on while the node was away: its peers' State is
+        // copied however far above its own promise.
+        let n = ballot(50, 1);
+        let state = |from| Envelope {
+            group: 7,
+            msg: Message::State(StateData {
+                from,
+                to: 4,
+                promised_n: n,
+                accepted: vec![(0, n, Arc::new(10))],
+                decided: Vec::new(),
+            }),
+        };
+        router.receive(vec![state(1), state(2), state(3)]);
+
+        let acceptor = router.groups().group(7).unwrap().acceptor();
+        assert!(acceptor.is_voting());
+        assert_eq!(acceptor.current_ballot(), n);
+        assert_eq!(acceptor.accepted(0).map(|a| a.n), Some(n));
+    }
+
+    #[test]
+    fn batch_roundtrip() {
+        let batch: Vec<Envelope<Vec<u8>>> = vec![
+            Envelope {
+                group: 7,
+                msg: Message::Prepare(ProposalData {
+                    slot: 1,
+                    id: 2,
+                    from: 3,
+                }),
+            };
+            2
+        ];
+        let bytes = encode_batch(&batch);
+
+        assert_eq!(decode_batch::<Vec<u8>>(&bytes), Ok(batch));
+        assert_eq!(
+            decode_batch::<Vec<u8>>(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+    }
+}
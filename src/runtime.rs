@@ -0,0 +1,290 @@
+//! Runtime
+
+use crate::acceptor::Acceptor;
+use crate::effect::dispatch;
+use crate::learner::Learner;
+use crate::message::{Handler, Message, Messenger, Slot};
+use crate::node::Node;
+use crate::proposer::Proposer;
+use crate::storage::{persist, AsyncStorage, DurableState, Record, Storage};
+use crate::sync::{BroadcastMessenger, ChannelSender};
+use alloc::sync::Arc;
+use std::sync::{Mutex, PoisonError};
+use tokio::sync::mpsc::{unbounded_channel, Sender, UnboundedReceiver, UnboundedSender};
+
+/// A `Messenger` that broadcasts messages over tokio channels.
+pub type ChannelMessenger<T> = BroadcastMessenger<T, UnboundedSender<Message<T>>>;
+
+/// A `Messenger` that broadcasts messages over bounded tokio channels,
+/// reporting `Readiness::WouldBlock` while any of them is full. Messages sent
+/// to a full channel are dropped, as a lost message would be.
+pub type BoundedChannelMessenger<T> = BroadcastMessenger<T, Sender<Message<T>>>;
+
+impl<T> ChannelSender<T> for UnboundedSender<Message<T>> {
+    type Resolutions = UnboundedSender<(Slot, Arc<T>)>;
+
+    fn send_message(&self, msg: Message<T>) {
+        let _ = self.send(msg);
+    }
+
+    fn send_resolution(resolutions: &Self::Resolutions, slot: Slot, value: Arc<T>) {
+        let _ = resolutions.send((slot, value));
+    }
+}
+
+impl<T> ChannelSender<T> for Sender<Message<T>> {
+    type Resolutions = UnboundedSender<(Slot, Arc<T>)>;
+
+    fn send_message(&self, msg: Message<T>) {
+        let _ = self.try_send(msg);
+    }
+
+    fn send_resolution(resolutions: &Self::Resolutions, slot: Slot, value: Arc<T>) {
+        let _ = resolutions.send((slot, value));
+    }
+
+    fn is_full(&self) -> bool {
+        !self.is_closed() && self.capacity() == 0
+    }
+}
+
+impl<T, M> Learner<T, M>
+where
+    T: PartialEq + Send + Sync + 'static,
+    M: Messenger<T>,
+{
+    /// Subscribes to every value decided from `slot` onwards, delivered in
+    /// slot order over a tokio channel. See `watch_from`.
+    pub fn subscribe(&mut self, slot: Slot) -> UnboundedReceiver<(Slot, Arc<T>)> {
+        let (sender, receiver) = unbounded_channel();
+        self.watch_from(slot, move |slot, value| sender.send((slot, value)).is_ok());
+        receiver
+    }
+}
+
+/// Drives an `Acceptor` until every sender of its inbox has been dropped.
+pub async fn run_acceptor<T, M: Messenger<T>>(
+    mut acceptor: Acceptor<T, M>,
+    mut inbox: UnboundedReceiver<Message<T>>,
+) {
+    while let Some(msg) = inbox.recv().await {
+        acceptor.handle(msg);
+    }
+}
+
+/// Drives a `Learner` until every sender of its inbox has been dropped,
+/// returning it so that the learned value can be inspected.
+pub async fn run_learner<T: PartialEq, M: Messenger<T>>(
+    mut learner: Learner<T, M>,
+    mut inbox: UnboundedReceiver<Message<T>>,
+) -> Learner<T, M> {
+    while let Some(msg) = inbox.recv().await {
+        learner.handle(msg);
+    }
+    learner
+}
+
+/// Drives a `Node` until every sender of its inbox has been dropped,
+/// returning it. The `Acceptor`'s state is made durable in `storage` before
+/// the effects of each message are carried out through `messenger`, so that
+/// no `Promise` or `Accepted` is sent for a vote a crash could still undo.
+pub async fn run_node<T, M, S, N>(
+    mut node: Node<T, M>,
+    storage: &mut S,
+    mut messenger: N,
+    mut inbox: UnboundedReceiver<Message<T>>,
+) -> Result<Node<T, M>, S::Error>
+where
+    T: PartialEq + Clone,
+    M: Messenger<T>,
+    S: AsyncStorage<T>,
+    N: Messenger<T>,
+{
+    while let Some(msg) = inbox.recv().await {
+        for effect in persist(storage, node.step(msg)).await? {
+            dispatch(&mut messenger, effect);
+        }
+    }
+    Ok(node)
+}
+
+/// An `AsyncStorage` running a `Storage` on tokio's blocking threads, so that
+/// the worker threads driving the roles never wait on the disk.
+pub struct Blocking<S> {
+    storage: Arc<Mutex<S>>,
+}
+
+impl<S> Blocking<S> {
+    /// Runs the operations of `storage` on tokio's blocking threads.
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage: Arc::new(Mutex::new(storage)),
+        }
+    }
+
+    /// Runs `f` on the storage, on a blocking thread.
+    async fn run<R, F>(&self, f: F) -> R
+    where
+        S: Send + 'static,
+        R: Send + 'static,
+        F: FnOnce(&mut S) -> R + Send + 'static,
+    {
+        let storage = self.storage.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            f(&mut storage.lock().unwrap_or_else(PoisonError::into_inner))
+        });
+        match task.await {
+            Ok(result) => result,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+}
+
+impl<T, S> AsyncStorage<T> for Blocking<S>
+where
+    T: Send + Sync + 'static,
+    S: Storage<T> + Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Error = S::Error;
+
+    async fn append(&mut self, record: Record<T>) -> Result<(), S::Error> {
+        self.run(move |storage| storage.append(&record)).await
+    }
+
+    async fn sync(&mut self) -> Result<(), S::Error> {
+        self.run(|storage| storage.sync()).await
+    }
+
+    async fn load(&mut self) -> Result<DurableState<T>, S::Error> {
+        self.run(|storage| storage.load()).await
+    }
+}
+
+/// Proposes `value` and drives the `Proposer` until the proposal is resolved,
+/// returning the chosen value. Returns `None` if the inbox closes first.
+pub async fn run_proposer<T: PartialEq + Clone, M: Messenger<T>>(
+    mut proposer: Proposer<T, M>,
+    value: T,
+    mut inbox: UnboundedReceiver<Message<T>>,
+) -> Option<Arc<T>> {
+    proposer.prepare(value);
+
+    while proposer.last_accepted_n != proposer.proposal_n {
+        let msg = inbox.recv().await?;
+        match msg {
+            Message::Promise(_) => proposer.receive_promise(msg),
+            Message::Accepted(_) => proposer.receive_accepted(msg),
+            _ => {}
+        }
+    }
+    proposer.value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::message::{AcceptedData, ProposalData};
+    use crate::storage::MemoryStorage;
+    use alloc::boxed::Box;
+    use std::vec;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn runtime_single_acceptor() {
+        let (acc_sender, acc_receiver) = mpsc::unbounded_channel();
+        let (proposer_sender, proposer_receiver) = mpsc::unbounded_channel();
+        let (learner_sender, learner_receiver) = mpsc::unbounded_channel();
+
+        let mut acceptor: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1]));
+        acceptor.messenger = Some(Box::new(ChannelMessenger::new(vec![
+            proposer_sender,
+            learner_sender,
+        ])));
+
+        let mut proposer: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1]));
+        proposer.messenger = Some(Box::new(ChannelMessenger::new(vec![acc_sender])));
+
+        let learner: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1]));
+
+        let (value, _, learner) = tokio::join!(
+            run_proposer(proposer, 10, proposer_receiver),
+            run_acceptor(acceptor, acc_receiver),
+            run_learner(learner, learner_receiver),
+        );
+
+        assert_eq!(value, Some(Arc::new(10)));
+        assert_eq!(learner.value, Some(Arc::new(10)));
+    }
+
+    #[tokio::test]
+    async fn runtime_node_persists() {
+        let (sender, inbox) = mpsc::unbounded_channel();
+        let (out, mut sent) = mpsc::unbounded_channel();
+        let node: Node<u64> = Node::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let mut storage = Blocking::new(MemoryStorage::new());
+
+        sender
+            .send(Message::Prepare(ProposalData {
+                slot: 0,
+                id: 4,
+                from: 2,
+            }))
+            .unwrap();
+        drop(sender);
+        let messenger = ChannelMessenger::new(vec![out]);
+        run_node(node, &mut storage, messenger, inbox)
+            .await
+            .unwrap();
+
+        assert!(matches!(sent.recv().await, Some(Message::Promise(_))));
+        let state: DurableState<u64> = storage.load().await.unwrap();
+        assert_eq!(state.promised_n, 4);
+    }
+
+    #[tokio::test]
+    async fn runtime_bounded_channel_backpressure() {
+        let (sender, mut acc_receiver) = mpsc::channel(2);
+        let mut proposer: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1]));
+        proposer.set_window(8);
+        proposer.messenger = Some(Box::new(BoundedChannelMessenger::new(vec![sender])));
+
+        for value in 10..14 {
+            proposer.prepare(value);
+        }
+
+        // Two slots fill the channel, so the others wait in the queue.
+        assert_eq!(proposer.in_flight.len(), 2);
+        assert_eq!(proposer.queued(), 2);
+
+        acc_receiver.recv().await.unwrap();
+        proposer.tick(1);
+
+        assert_eq!(proposer.in_flight.len(), 3);
+        assert_eq!(proposer.queued(), 1);
+    }
+
+    #[tokio::test]
+    async fn runtime_subscribe() {
+        let (learner_sender, learner_receiver) = mpsc::unbounded_channel();
+        let mut learner: Learner<u64> = Learner::new(2, ClusterConfig::new(vec![1]));
+        let mut decided = learner.subscribe(0);
+
+        learner_sender
+            .send(Message::Accepted(AcceptedData {
+                slot: 0,
+                id: 1,
+                value: Arc::new(10),
+                from: 1,
+                fast: false,
+            }))
+            .unwrap();
+        drop(learner_sender);
+        let learner = run_learner(learner, learner_receiver).await;
+
+        assert_eq!(decided.recv().await, Some((0, Arc::new(10))));
+        drop(learner);
+        assert_eq!(decided.recv().await, None);
+    }
+}
@@ -0,0 +1,206 @@
+//! Client sessions
+//!
+//! A client whose proposal timed out can't tell whether it was decided, and
+//! proposing it again may get it decided twice. Commands sent through a
+//! `Session` are numbered, so that `Sessions` can apply each of them once:
+//! replicas keep the last number applied for every client, and skip commands
+//! numbered at or below it.
+//!
+//! A client waits for each command to be decided, proposing it again under
+//! the same number as needed, before sending the next one. Otherwise a retry
+//! of an earlier command could be decided after a later one, and be skipped.
+
+use crate::message::Slot;
+use crate::state_machine::StateMachine;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Identifies a client's session.
+pub type ClientId = u64;
+
+/// A command along with the session it was sent in.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct SessionCommand<T> {
+    /// The client's session
+    pub client: ClientId,
+    /// Numbers the client's commands, from 1, in the order they were sent
+    pub seq: u64,
+    /// The command itself
+    pub command: T,
+}
+
+/// A value of the log of a state machine wrapped in `Sessions`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub enum SessionValue<T> {
+    /// A client's command
+    Command(SessionCommand<T>),
+    /// A snapshot of the wrapped state machine, along with the last command
+    /// applied for each client (client, seq)
+    Snapshot(T, Vec<(ClientId, u64)>),
+}
+
+/// Numbers the commands of a client.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Session {
+    /// The client's session
+    client: ClientId,
+    /// The number of the last command sent
+    seq: u64,
+}
+
+impl Session {
+    /// Creates a new `Session` for `client`, which must be unique to it.
+    pub fn new(client: ClientId) -> Self {
+        Self { client, seq: 0 }
+    }
+
+    /// The client's session.
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    /// Numbers `command`, to be proposed. A retry proposes the same value
+    /// again rather than call `command` a second time.
+    pub fn command<T>(&mut self, command: T) -> SessionValue<T> {
+        self.seq += 1;
+        SessionValue::Command(SessionCommand {
+            client: self.client,
+            seq: self.seq,
+            command,
+        })
+    }
+}
+
+/// Wraps a state machine, applying each client's commands once.
+///
+/// The output of a command applied is `Some` of the wrapped state machine's,
+/// and that of a duplicate `None`, on every replica alike.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Sessions<S> {
+    /// The wrapped state machine
+    pub state: S,
+    /// The last command applied for each client (client => seq)
+    pub applied: BTreeMap<ClientId, u64>,
+}
+
+impl<S> Sessions<S> {
+    /// Wraps `state`.
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            applied: BTreeMap::new(),
+        }
+    }
+
+    /// Whether command `seq` of `client` was applied already.
+    pub fn is_applied(&self, client: ClientId, seq: u64) -> bool {
+        self.applied.get(&client).is_some_and(|&last| seq <= last)
+    }
+
+    /// Forgets `client`'s session, once it's closed. Retries of its commands
+    /// decided afterwards are applied again.
+    pub fn close(&mut self, client: ClientId) {
+        self.applied.remove(&client);
+    }
+}
+
+impl<T, S: StateMachine<T>> StateMachine<SessionValue<T>> for Sessions<S> {
+    type Output = Option<S::Output>;
+
+    fn apply(&mut self, slot: Slot, value: &SessionValue<T>) -> Option<S::Output> {
+        match value {
+            SessionValue::Command(command) => {
+                if self.is_applied(command.client, command.seq) {
+                    return None;
+                }
+                self.applied.insert(command.client, command.seq);
+                Some(self.state.apply(slot, &command.command))
+            }
+            SessionValue::Snapshot(..) => {
+                self.restore(value);
+                None
+            }
+        }
+    }
+
+    fn snapshot(&self) -> SessionValue<T> {
+        let applied = self.applied.iter().map(|(&c, &s)| (c, s)).collect();
+        SessionValue::Snapshot(self.state.snapshot(), applied)
+    }
+
+    fn restore(&mut self, snapshot: &SessionValue<T>) {
+        if let SessionValue::Snapshot(state, applied) = snapshot {
+            self.state.restore(state);
+            self.applied = applied.iter().copied().collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::learner::Learner;
+    use crate::message::{AcceptedData, Message};
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    /// Sums the values applied.
+    #[derive(Debug, PartialEq, Default)]
+    struct Counter(u64);
+
+    impl StateMachine<u64> for Counter {
+        type Output = u64;
+
+        fn apply(&mut self, _slot: Slot, value: &u64) -> u64 {
+            self.0 += value;
+            self.0
+        }
+
+        fn snapshot(&self) -> u64 {
+            self.0
+        }
+
+        fn restore(&mut self, snapshot: &u64) {
+            self.0 = *snapshot;
+        }
+    }
+
+    #[test]
+    fn session_applies_once() {
+        let mut l: Learner<SessionValue<u64>> = Learner::new(1, ClusterConfig::new(vec![1]));
+        let mut session = Session::new(7);
+        let first = session.command(10);
+        let second = session.command(5);
+
+        // A retry of the first command may be decided after the second.
+        for (slot, value) in [(0, &first), (1, &first), (2, &second), (3, &first)] {
+            l.receive_accepted(Message::Accepted(AcceptedData {
+                slot,
+                id: 1,
+                value: Arc::new(value.clone()),
+                from: 1,
+                fast: false,
+            }));
+        }
+        let mut sessions = Sessions::new(Counter::default());
+
+        assert_eq!(
+            l.apply(&mut sessions),
+            vec![(0, Some(10)), (1, None), (2, Some(15)), (3, None)]
+        );
+        assert!(sessions.is_applied(7, 2));
+        assert!(!sessions.is_applied(8, 1));
+
+        // Restored replicas skip the same commands.
+        let mut restored = Sessions::new(Counter::default());
+        restored.restore(&sessions.snapshot());
+
+        assert_eq!(restored, sessions);
+        assert_eq!(restored.apply(4, &first), None);
+
+        restored.close(7);
+
+        assert_eq!(restored.apply(5, &first), Some(25));
+    }
+}
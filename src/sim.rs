@@ -0,0 +1,590 @@
+//! Simulation
+//!
+//! A `SimCluster` runs a whole cluster of `Node`s in a single thread, wired
+//! through a virtual network. Messages are delivered after a latency drawn
+//! from a seeded random number generator, and time is a logical clock which
+//! jumps from one delivery to the next. Nothing depends on wall-clock time or
+//! thread scheduling, so a scenario replays exactly from its seed.
+//!
+//! The network is unreliable to the extent its `Faults` say: messages may be
+//! dropped, duplicated, delayed and reordered, on every link or on chosen
+//! ones, so a scenario can check that the protocol stays safe through the
+//! failures Paxos is meant to tolerate. The network may also be partitioned,
+//! cutting every link between groups of nodes until it heals.
+
+use crate::config::{ClusterConfig, NodeId};
+use crate::effect::Effect;
+use crate::message::{Message, Slot};
+use crate::node::Node;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A small, fast and deterministic random number generator (SplitMix64). Not
+/// suitable for anything but simulations.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Creates a new `Rng` from `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// The next random `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A random number in `low..=high`.
+    pub fn between(&mut self, low: u64, high: u64) -> u64 {
+        match (high - low).checked_add(1) {
+            Some(span) => low + self.next_u64() % span,
+            None => self.next_u64(),
+        }
+    }
+
+    /// `true` with probability `p`.
+    pub fn chance(&mut self, p: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+/// How long a message takes to arrive, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Latency {
+    /// Always the same
+    Fixed(u64),
+    /// Uniformly distributed in `min..=max`
+    Uniform { min: u64, max: u64 },
+    /// Uniformly distributed in `min..=max`, but with probability `p` in
+    /// `max..=tail` instead, modelling occasional stalls
+    Tail {
+        min: u64,
+        max: u64,
+        p: f64,
+        tail: u64,
+    },
+}
+
+impl Latency {
+    /// Draws a latency from `rng`.
+    pub fn sample(&self, rng: &mut Rng) -> u64 {
+        match *self {
+            Latency::Fixed(latency) => latency,
+            Latency::Uniform { min, max } => rng.between(min, max),
+            Latency::Tail { min, max, p, tail } => {
+                if rng.chance(p) {
+                    rng.between(max, tail)
+                } else {
+                    rng.between(min, max)
+                }
+            }
+        }
+    }
+}
+
+/// The faults of a network link.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Faults {
+    /// Probability that a message is lost
+    pub drop: f64,
+    /// Probability that a message is delivered twice, each copy with its own
+    /// latency
+    pub duplicate: f64,
+    /// How many milliseconds a message may arrive ahead of one sent before it
+    /// on the same link. `Some(0)` makes the link FIFO, `None` lets latencies
+    /// reorder messages freely
+    pub reorder: Option<u64>,
+    /// How long messages take to arrive
+    pub latency: Latency,
+}
+
+impl Faults {
+    /// A link which loses nothing and delivers in order after `latency`
+    /// milliseconds.
+    pub fn reliable(latency: u64) -> Self {
+        Self {
+            drop: 0.0,
+            duplicate: 0.0,
+            reorder: Some(0),
+            latency: Latency::Fixed(latency),
+        }
+    }
+}
+
+impl Default for Faults {
+    /// Nothing is lost, but messages take between 1 and 10 milliseconds to
+    /// arrive, in any order.
+    fn default() -> Self {
+        Self {
+            drop: 0.0,
+            duplicate: 0.0,
+            reorder: None,
+            latency: Latency::Uniform { min: 1, max: 10 },
+        }
+    }
+}
+
+/// Something scheduled to happen at a node.
+#[derive(Debug, Clone)]
+enum Event<T> {
+    /// A message from a node arrives
+    Deliver(NodeId, Message<T>),
+    /// A timer a role started fires
+    Timer,
+}
+
+/// A cluster of `Node`s exchanging messages over a simulated network.
+pub struct SimCluster<T> {
+    /// The nodes, by ID
+    nodes: BTreeMap<NodeId, Node<T>>,
+    /// Events by (time, sequence number) => (node, event). The sequence number
+    /// orders events due at the same time by when they were scheduled
+    queue: BTreeMap<(u64, u64), (NodeId, Event<T>)>,
+    /// Values each node decided so far, in slot order
+    decided: BTreeMap<NodeId, Vec<(Slot, Arc<T>)>>,
+    rng: Rng,
+    /// The logical time, in milliseconds
+    now: u64,
+    /// Number of events scheduled so far
+    seq: u64,
+    /// Faults of every link not in `links`
+    faults: Faults,
+    /// Faults of chosen links, by (sender, receiver)
+    links: BTreeMap<(NodeId, NodeId), Faults>,
+    /// Latest delivery time scheduled on each link
+    last: BTreeMap<(NodeId, NodeId), u64>,
+    /// Links cut by a partition, as (sender, receiver)
+    cut: BTreeSet<(NodeId, NodeId)>,
+    /// Number of messages dropped so far
+    dropped: u64,
+    /// Number of messages duplicated so far
+    duplicated: u64,
+}
+
+impl<T> SimCluster<T>
+where
+    T: PartialEq + Clone,
+{
+    /// Creates a cluster of `size` nodes, with IDs `1..=size`, whose network
+    /// is driven by `seed`. Links have the default `Faults`.
+    pub fn new(size: u64, seed: u64) -> Self {
+        let config = ClusterConfig::new((1..=size).collect());
+        Self {
+            nodes: (1..=size)
+                .map(|id| (id, Node::new(id, config.clone())))
+                .collect(),
+            queue: BTreeMap::new(),
+            decided: (1..=size).map(|id| (id, Vec::new())).collect(),
+            rng: Rng::new(seed),
+            now: 0,
+            seq: 0,
+            faults: Faults::default(),
+            links: BTreeMap::new(),
+            last: BTreeMap::new(),
+            cut: BTreeSet::new(),
+            dropped: 0,
+            duplicated: 0,
+        }
+    }
+
+    /// Sets the bounds of the latency of a message, in milliseconds.
+    pub fn with_latency(mut self, min: u64, max: u64) -> Self {
+        assert!(min <= max, "minimum latency above maximum");
+        self.faults.latency = Latency::Uniform { min, max };
+        self
+    }
+
+    /// Sets the faults of every link.
+    pub fn with_faults(mut self, faults: Faults) -> Self {
+        self.set_faults(faults);
+        self
+    }
+
+    /// Sets the faults of every link, overriding those of chosen links.
+    pub fn set_faults(&mut self, faults: Faults) {
+        self.faults = faults;
+        self.links.clear();
+    }
+
+    /// Sets the faults of the link from `from` to `to` only. A node sends to
+    /// itself through the link from and to its ID.
+    pub fn set_link_faults(&mut self, from: NodeId, to: NodeId, faults: Faults) {
+        self.links.insert((from, to), faults);
+    }
+
+    /// The faults of the link from `from` to `to`.
+    pub fn link_faults(&self, from: NodeId, to: NodeId) -> Faults {
+        self.links.get(&(from, to)).copied().unwrap_or(self.faults)
+    }
+
+    /// Cuts every link between a node of `a` and one of `b`, both ways.
+    /// Messages crossing the partition are dropped, those in transit
+    /// included, until the network heals. Partitions add up: the network
+    /// can be split into more than two groups by successive calls.
+    pub fn partition(&mut self, a: &[NodeId], b: &[NodeId]) {
+        for &x in a {
+            for &y in b {
+                if x != y {
+                    self.cut.insert((x, y));
+                    self.cut.insert((y, x));
+                }
+            }
+        }
+    }
+
+    /// Isolates node `id` from every other node.
+    pub fn isolate(&mut self, id: NodeId) {
+        let others: Vec<NodeId> = self.ids().filter(|&other| other != id).collect();
+        self.partition(&[id], &others);
+    }
+
+    /// Restores every link cut by a partition.
+    pub fn heal(&mut self) {
+        self.cut.clear();
+    }
+
+    /// Whether the link from `from` to `to` is cut by a partition.
+    pub fn is_cut(&self, from: NodeId, to: NodeId) -> bool {
+        self.cut.contains(&(from, to))
+    }
+
+    /// The number of messages dropped so far, by faults or partitions.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The number of messages duplicated so far.
+    pub fn duplicated(&self) -> u64 {
+        self.duplicated
+    }
+
+    /// The logical time, in milliseconds.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// The IDs of the nodes.
+    pub fn ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes.keys().copied()
+    }
+
+    /// The node `id`.
+    pub fn node(&self, id: NodeId) -> &Node<T> {
+        &self.nodes[&id]
+    }
+
+    /// The node `id`, e.g. to configure its roles.
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node<T> {
+        self.nodes.get_mut(&id).unwrap()
+    }
+
+    /// The values node `id` decided so far, in slot order.
+    pub fn decided(&self, id: NodeId) -> &[(Slot, Arc<T>)] {
+        &self.decided[&id]
+    }
+
+    /// Whether no event is pending, i.e. the cluster is quiescent.
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Proposes `value` through node `id`.
+    pub fn propose(&mut self, id: NodeId, value: T) {
+        let node = self.node_mut(id);
+        node.propose(value);
+        let effects = node.take_effects();
+        self.flush(id, effects);
+    }
+
+    /// Runs the next event, advancing the clock to it. Returns `false` if none
+    /// was pending.
+    pub fn step(&mut self) -> bool {
+        let ((at, _), (id, event)) = match self.queue.pop_first() {
+            Some(next) => next,
+            None => return false,
+        };
+        self.now = at;
+        let node = self.nodes.get_mut(&id).unwrap();
+        let effects = match event {
+            Event::Deliver(from, _) if self.cut.contains(&(from, id)) => {
+                self.dropped += 1;
+                return true;
+            }
+            Event::Deliver(_, msg) => node.step(msg),
+            Event::Timer => {
+                node.tick(at);
+                node.take_effects()
+            }
+        };
+        self.flush(id, effects);
+        true
+    }
+
+    /// Runs events until none is pending, or `max_steps` were run. Returns the
+    /// number of events run.
+    pub fn run(&mut self, max_steps: usize) -> usize {
+        let mut steps = 0;
+        while steps < max_steps && self.step() {
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Runs every event due up to `time`, then advances the clock to it.
+    pub fn run_until(&mut self, time: u64) {
+        while self
+            .queue
+            .first_key_value()
+            .is_some_and(|(&(at, _), _)| at <= time)
+        {
+            self.step();
+        }
+        self.now = self.now.max(time);
+    }
+
+    /// Carries out the `effects` node `id` produced, and records the values it
+    /// decided.
+    fn flush(&mut self, id: NodeId, effects: Vec<Effect<T>>) {
+        let decided = self.nodes.get_mut(&id).unwrap().poll_decided();
+        self.decided.get_mut(&id).unwrap().extend(decided);
+
+        for effect in effects {
+            match effect {
+                Effect::SendMessage(msg) => {
+                    let ids: Vec<NodeId> = self.nodes.keys().copied().collect();
+                    self.send(id, &ids, msg);
+                }
+                Effect::SendMessageTo(to, msg) => self.send(id, &to, msg),
+                Effect::StartTimer { at } => self.schedule(at.max(self.now), id, Event::Timer),
+                Effect::Decide(..) | Effect::PersistState { .. } | Effect::Preempted { .. } => {}
+            }
+        }
+    }
+
+    /// Sends `msg` from node `from` to the nodes `ids`, through the faults of
+    /// each link.
+    fn send(&mut self, from: NodeId, ids: &[NodeId], msg: Message<T>) {
+        for &to in ids {
+            if !self.nodes.contains_key(&to) {
+                continue;
+            }
+            let faults = self.link_faults(from, to);
+            if self.rng.chance(faults.drop) {
+                self.dropped += 1;
+                continue;
+            }
+            let copies = if self.rng.chance(faults.duplicate) {
+                self.duplicated += 1;
+                2
+            } else {
+                1
+            };
+            for _ in 0..copies {
+                self.transmit(from, to, &faults, msg.clone());
+            }
+        }
+    }
+
+    /// Schedules the delivery of `msg` on the link from `from` to `to`, no
+    /// further ahead of earlier messages than the link allows.
+    fn transmit(&mut self, from: NodeId, to: NodeId, faults: &Faults, msg: Message<T>) {
+        let mut at = self.now + faults.latency.sample(&mut self.rng);
+        let last = self.last.entry((from, to)).or_insert(0);
+        if let Some(reorder) = faults.reorder {
+            at = at.max(last.saturating_sub(reorder));
+        }
+        *last = (*last).max(at);
+        self.schedule(at, to, Event::Deliver(from, msg));
+    }
+
+    fn schedule(&mut self, at: u64, id: NodeId, event: Event<T>) {
+        self.queue.insert((at, self.seq), (id, event));
+        self.seq += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenario(seed: u64) -> SimCluster<u64> {
+        let mut sim = SimCluster::new(5, seed);
+        for id in sim.ids().collect::<Vec<_>>() {
+            sim.node_mut(id).proposer_mut().set_window(4);
+        }
+        for value in 0..8 {
+            sim.propose(1, value);
+        }
+        sim.run(100_000);
+        sim
+    }
+
+    #[test]
+    fn sim_rng() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+            assert!((3..=5).contains(&a.between(3, 5)));
+            b.between(3, 5);
+        }
+        assert_eq!(a.between(0, u64::MAX), b.between(0, u64::MAX));
+    }
+
+    #[test]
+    fn sim_deterministic() {
+        let a = scenario(42);
+        let b = scenario(42);
+        let c = scenario(43);
+
+        assert!(a.is_idle());
+        assert_eq!(a.now(), b.now());
+        assert_ne!(a.now(), c.now());
+
+        let expected: Vec<(Slot, Arc<u64>)> = (0..8).map(|v| (v, Arc::new(v))).collect();
+        for id in 1..=5 {
+            assert_eq!(a.decided(id), &expected[..]);
+            assert_eq!(a.decided(id), b.decided(id));
+            assert_eq!(c.decided(id), &expected[..]);
+        }
+    }
+
+    #[test]
+    fn sim_faults() {
+        let faults = Faults {
+            drop: 0.2,
+            duplicate: 0.2,
+            reorder: None,
+            latency: Latency::Tail {
+                min: 1,
+                max: 10,
+                p: 0.1,
+                tail: 100,
+            },
+        };
+        let mut sim = SimCluster::new(5, 7).with_faults(faults);
+        for id in 1..=5 {
+            sim.node_mut(id)
+                .proposer_mut()
+                .set_timeout(Some(50), u32::MAX);
+        }
+        for value in 0..16 {
+            sim.propose(1, value);
+        }
+        sim.run(1_000_000);
+
+        assert!(sim.is_idle());
+        assert!(sim.dropped() > 0);
+        assert!(sim.duplicated() > 0);
+        assert_eq!(sim.decided(1).len(), 16);
+
+        // Nodes may learn different prefixes of the log, never different
+        // values.
+        let log = sim.decided(1).to_vec();
+        for id in 2..=5 {
+            assert!(log.starts_with(sim.decided(id)));
+        }
+    }
+
+    #[test]
+    fn sim_fifo_links() {
+        let faults = Faults {
+            reorder: Some(0),
+            ..Faults::default()
+        };
+        let mut sim: SimCluster<u64> = SimCluster::new(3, 11).with_faults(faults);
+        sim.set_link_faults(1, 2, Faults::reliable(1000));
+        sim.propose(1, 10);
+        sim.propose(1, 20);
+        sim.run(10_000);
+
+        // Every message from 1 to 2 got through, after a second each.
+        assert_eq!(sim.link_faults(1, 2), Faults::reliable(1000));
+        assert_eq!(sim.link_faults(2, 1), faults);
+        assert!(sim.now() >= 1000);
+        assert_eq!(sim.dropped(), 0);
+        for id in 1..=3 {
+            assert_eq!(sim.decided(id), &[(0, Arc::new(10)), (1, Arc::new(20))]);
+        }
+    }
+
+    #[test]
+    fn sim_partition() {
+        let mut sim = SimCluster::new(5, 3);
+        for id in 1..=5 {
+            let proposer = sim.node_mut(id).proposer_mut();
+            proposer.set_timeout(Some(50), 2);
+            proposer.set_lease(Some(200));
+        }
+        sim.propose(1, 10);
+        sim.run_until(100);
+
+        assert!(sim.node(1).proposer().has_lease());
+
+        sim.partition(&[1, 2], &[3, 4, 5]);
+        sim.propose(1, 20);
+        sim.run_until(1000);
+
+        // The minority can't decide, and its leader's lease runs out.
+        assert!(sim.is_cut(1, 3) && sim.is_cut(5, 2));
+        assert!(!sim.is_cut(1, 2));
+        assert_eq!(sim.decided(1), &[(0, Arc::new(10))]);
+        assert_eq!(sim.decided(2), &[(0, Arc::new(10))]);
+        assert!(!sim.node(1).proposer().has_lease());
+
+        // The majority elects a leader of its own, which goes on deciding.
+        sim.propose(3, 30);
+        sim.propose(3, 40);
+        sim.run_until(1100);
+
+        assert!(sim.node(3).proposer().has_lease());
+
+        // Its first value went to slot 0, where the value decided before the
+        // partition displaced it.
+        let log = sim.decided(3).to_vec();
+        assert_eq!(log, [(0, Arc::new(10)), (1, Arc::new(40))]);
+        for id in 4..=5 {
+            assert_eq!(sim.decided(id), &log[..]);
+        }
+
+        // Once healed, the former leader catches up with the majority.
+        sim.heal();
+        sim.run_until(5000);
+
+        assert!(!sim.is_cut(1, 3));
+        let log = sim.decided(3).to_vec();
+        for id in 1..=5 {
+            assert!(log.starts_with(sim.decided(id)) || sim.decided(id).starts_with(&log));
+        }
+        assert!(sim.decided(1).len() >= 2);
+    }
+
+    #[test]
+    fn sim_run_until() {
+        let mut sim: SimCluster<u64> = SimCluster::new(3, 1).with_latency(5, 5);
+        sim.propose(2, 10);
+
+        // Prepare, Promise, Accept and Accepted take 5ms each.
+        sim.run_until(19);
+
+        assert!(sim.decided(2).is_empty());
+
+        sim.run_until(20);
+
+        assert_eq!(sim.decided(2), &[(0, Arc::new(10))]);
+        assert_eq!(sim.now(), 20);
+        sim.run(100);
+
+        assert!(sim.is_idle());
+        for id in 1..=3 {
+            assert_eq!(sim.decided(id), &[(0, Arc::new(10))]);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! State machine
+
+use crate::learner::Learner;
+use crate::message::{Messenger, Slot};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// The application the replicated log is applied to. Every replica applies
+/// the same values in the same order, so they all go through the same states.
+pub trait StateMachine<T> {
+    /// What applying a value produces, e.g. the response to a client
+    type Output;
+
+    /// Applies the value decided for `slot`.
+    fn apply(&mut self, slot: Slot, value: &T) -> Self::Output;
+
+    /// Captures the current state. Snapshots are carried as values of the
+    /// log, so that they can be sent to lagging `Learner`s.
+    fn snapshot(&self) -> T;
+
+    /// Replaces the current state with a snapshot.
+    fn restore(&mut self, snapshot: &T);
+}
+
+impl<T: PartialEq, M: Messenger<T>> Learner<T, M> {
+    /// Applies the decided values to `state_machine`, strictly in slot order.
+    /// Values decided out of order are held back until every slot below them
+    /// has been decided and applied. Returns the output of every value
+    /// applied, along with its slot.
+    ///
+    /// Should an installed snapshot be ahead of the values applied, the state
+    /// machine is restored from it first.
+    pub fn apply<S>(&mut self, state_machine: &mut S) -> Vec<(Slot, S::Output)>
+    where
+        S: StateMachine<T> + ?Sized,
+    {
+        if let Some((index, ref snapshot)) = self.snapshot {
+            if self.apply_index < index {
+                state_machine.restore(snapshot);
+                self.apply_index = index;
+                if let Some((recorder, _)) = &mut self.latency_recorder {
+                    recorder.skip(index);
+                }
+            }
+        }
+        let mut outputs = Vec::new();
+        while let Some(value) = self.decided.get(&self.apply_index) {
+            outputs.push((
+                self.apply_index,
+                state_machine.apply(self.apply_index, value),
+            ));
+            if let Some((recorder, _)) = &mut self.latency_recorder {
+                recorder.applied(self.apply_index, self.now);
+            }
+            self.apply_index += 1;
+        }
+        outputs
+    }
+
+    /// Snapshots `state_machine`, which must have been brought up to date with
+    /// `apply`, and drops the decided values the snapshot covers.
+    pub fn compact<S>(&mut self, state_machine: &S)
+    where
+        S: StateMachine<T> + ?Sized,
+    {
+        let index = self.apply_index;
+        self.truncate(index);
+        self.snapshot = Some((index, Arc::new(state_machine.snapshot())));
+    }
+
+    /// The last slot applied to the state machine, if any.
+    pub fn last_applied(&self) -> Option<Slot> {
+        self.apply_index.checked_sub(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::learner::tests::decide;
+    use crate::message::{Message, SnapshotData};
+    use alloc::vec;
+
+    /// Sums the values applied.
+    struct Counter(u64);
+
+    impl StateMachine<u64> for Counter {
+        type Output = u64;
+
+        fn apply(&mut self, _slot: Slot, value: &u64) -> u64 {
+            self.0 += value;
+            self.0
+        }
+
+        fn snapshot(&self) -> u64 {
+            self.0
+        }
+
+        fn restore(&mut self, snapshot: &u64) {
+            self.0 = *snapshot;
+        }
+    }
+
+    #[test]
+    fn state_machine_apply_in_order() {
+        let mut l: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1]));
+        let mut counter = Counter(0);
+
+        decide(&mut l, 1, 20);
+
+        assert!(l.apply(&mut counter).is_empty());
+        assert_eq!(l.last_applied(), None);
+
+        decide(&mut l, 0, 10);
+        decide(&mut l, 3, 40);
+
+        assert_eq!(l.apply(&mut counter), vec![(0, 10), (1, 30)]);
+        assert_eq!(l.last_applied(), Some(1));
+
+        decide(&mut l, 2, 30);
+
+        assert_eq!(l.apply(&mut counter), vec![(2, 60), (3, 100)]);
+        assert_eq!(l.last_applied(), Some(3));
+    }
+
+    #[test]
+    fn state_machine_snapshot() {
+        let mut l: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1]));
+        let mut counter = Counter(0);
+
+        decide(&mut l, 0, 10);
+        decide(&mut l, 1, 20);
+        decide(&mut l, 3, 40);
+        l.apply(&mut counter);
+        l.compact(&counter);
+
+        assert_eq!(l.snapshot, Some((2, Arc::new(30))));
+        assert_eq!(l.decided.keys().copied().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(l.unresolved(), vec![2]);
+
+        // Votes for compacted slots are ignored.
+        decide(&mut l, 1, 20);
+
+        assert!(!l.decided.contains_key(&1));
+
+        // A lagging Learner catches up from the snapshot.
+        let mut lagging: Learner<u64> = Learner::new(2, ClusterConfig::new(vec![1]));
+        let mut replica = Counter(0);
+
+        decide(&mut lagging, 0, 10);
+        lagging.receive_install_snapshot(Message::InstallSnapshot(SnapshotData {
+            from: 1,
+            to: 2,
+            index: 2,
+            value: Arc::new(30),
+        }));
+        decide(&mut lagging, 2, 30);
+
+        assert_eq!(lagging.apply(&mut replica), vec![(2, 60)]);
+        assert_eq!(lagging.last_applied(), Some(2));
+    }
+}
@@ -0,0 +1,311 @@
+//! Status
+//!
+//! Point-in-time snapshots of a role's state, for operators to expose on a
+//! debug endpoint and for tooling to diagnose stuck slots with. Values are
+//! left out, so that snapshots can be serialized whatever the value type;
+//! with the `serde` feature, every snapshot implements `Serialize`.
+
+use crate::acceptor::Acceptor;
+use crate::config::NodeId;
+use crate::learner::Learner;
+use crate::message::{Messenger, Slot};
+use crate::metrics::LatencySnapshot;
+use crate::proposer::Proposer;
+use alloc::vec::Vec;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A slot a `Proposer` has in flight.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InstanceStatus {
+    /// The slot proposed for
+    pub slot: Slot,
+    /// The proposal number it is proposed under
+    pub n: u64,
+    /// Whether it is a fast round
+    pub fast: bool,
+    /// Whether the second phase has started
+    pub accepting: bool,
+    /// Number of `Promise`s received under `n`
+    pub promises: usize,
+    /// Number of `Accepted` messages received under `n`
+    pub accepted: usize,
+    /// When the current phase started, in milliseconds
+    pub sent_at: u64,
+    /// Number of times the current phase was retransmitted
+    pub retransmits: u32,
+}
+
+/// A snapshot of a `Proposer`'s state.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProposerStatus {
+    /// The `Proposer`'s ID
+    pub id: NodeId,
+    /// The current proposal number
+    pub ballot: u64,
+    /// The proposal number of the last value decided
+    pub last_accepted_n: u64,
+    /// The next slot a value would be proposed for
+    pub next_slot: Slot,
+    /// The slots in flight, in slot order
+    pub in_flight: Vec<InstanceStatus>,
+    /// Number of values waiting for room in the window
+    pub queued: usize,
+    /// Slots of a predecessor still to be recovered
+    pub recovering: Vec<Slot>,
+    /// When the leader lease runs out, if one is held
+    pub lease_expiry: Option<u64>,
+    /// The latencies recorded since the last `latency_snapshot`, if
+    /// recording
+    pub latencies: Option<LatencySnapshot>,
+}
+
+/// A snapshot of an `Acceptor`'s state.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AcceptorStatus {
+    /// The `Acceptor`'s ID
+    pub id: NodeId,
+    /// The highest proposal number promised
+    pub promised_n: u64,
+    /// The proposal number each slot's value was last accepted under
+    pub accepted: Vec<(Slot, u64)>,
+    /// Fast rounds awaiting a proposed value, with their proposal number
+    pub fast_rounds: Vec<(Slot, u64)>,
+    /// Whether the `Acceptor` takes part in votes
+    pub voting: bool,
+    /// The slot below which accepted values were dropped
+    pub truncated: Slot,
+}
+
+/// The votes a `Learner` has counted for an undecided slot.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VoteStatus {
+    /// The slot voted on
+    pub slot: Slot,
+    /// The proposal number voted for
+    pub n: u64,
+    /// The `Acceptor`s that voted
+    pub from: Vec<NodeId>,
+}
+
+/// A snapshot of a `Learner`'s state.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LearnerStatus {
+    /// The `Learner`'s ID
+    pub id: NodeId,
+    /// The slot after the decided prefix of the log, i.e. the first one not
+    /// decided yet
+    pub decided_index: Slot,
+    /// Number of slots decided past `decided_index`
+    pub decided_ahead: usize,
+    /// The slot after the highest one seen so far
+    pub horizon: Slot,
+    /// The slot after the last one the latest snapshot covers, if any
+    pub snapshot_index: Option<Slot>,
+    /// The next slot to apply to the state machine
+    pub apply_index: Slot,
+    /// Votes counted for undecided slots, in slot order
+    pub votes: Vec<VoteStatus>,
+    /// The latencies recorded since the last `latency_snapshot`, if
+    /// recording
+    pub latencies: Option<LatencySnapshot>,
+}
+
+/// What a `Learner` keeps in memory, counted in entries.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LearnerMemory {
+    /// Proposals of undecided slots votes are counted for
+    pub vote_sets: usize,
+    /// Votes counted for undecided slots
+    pub votes: usize,
+    /// Votes of shadow `Acceptor`s kept for undecided slots
+    pub shadow_votes: usize,
+    /// Decided values kept, i.e. not compacted
+    pub decided: usize,
+}
+
+impl<T: PartialEq + Clone, M: Messenger<T>> Proposer<T, M> {
+    /// A snapshot of the `Proposer`'s state.
+    pub fn status(&self) -> ProposerStatus {
+        let in_flight = self
+            .in_flight
+            .iter()
+            .map(|(&slot, instance)| {
+                let key = (slot, instance.n);
+                InstanceStatus {
+                    slot,
+                    n: instance.n,
+                    fast: instance.fast,
+                    accepting: instance.accepting,
+                    promises: self.promises_received.get(&key).map_or(0, |p| p.len()),
+                    accepted: self.accepted_received.get(&key).map_or(0, |a| a.len()),
+                    sent_at: instance.sent_at,
+                    retransmits: instance.retransmits,
+                }
+            })
+            .collect();
+        ProposerStatus {
+            id: self.id,
+            ballot: self.proposal_n,
+            last_accepted_n: self.last_accepted_n,
+            next_slot: self.next_slot,
+            in_flight,
+            queued: self.queued.len(),
+            recovering: self.recovering.iter().copied().collect(),
+            lease_expiry: self.lease_expiry,
+            latencies: self
+                .latency_recorder
+                .as_ref()
+                .map(|(recorder, _)| recorder.pending().clone()),
+        }
+    }
+}
+
+impl<T, M: Messenger<T>> Acceptor<T, M> {
+    /// A snapshot of the `Acceptor`'s state.
+    pub fn status(&self) -> AcceptorStatus {
+        AcceptorStatus {
+            id: self.id,
+            promised_n: self.promised_n,
+            accepted: self.accepted.iter().map(|(&slot, a)| (slot, a.n)).collect(),
+            fast_rounds: self
+                .fast_rounds
+                .iter()
+                .filter(|(&slot, _)| !self.accepted_fast(slot))
+                .map(|(&slot, &n)| (slot, n))
+                .collect(),
+            voting: self.is_voting(),
+            truncated: self.truncated,
+        }
+    }
+}
+
+impl<T: PartialEq, M: Messenger<T>> Learner<T, M> {
+    /// A snapshot of the `Learner`'s state.
+    pub fn status(&self) -> LearnerStatus {
+        let snapshot_index = self.snapshot.as_ref().map(|(index, _)| *index);
+        let mut decided_index = snapshot_index.unwrap_or(0);
+        while self.decided.contains_key(&decided_index) {
+            decided_index += 1;
+        }
+        LearnerStatus {
+            id: self.id,
+            decided_index,
+            decided_ahead: self.decided.range(decided_index..).count(),
+            horizon: self.horizon,
+            snapshot_index,
+            apply_index: self.apply_index,
+            votes: self
+                .accepted_received
+                .iter()
+                .map(|(&(slot, n), votes)| VoteStatus {
+                    slot,
+                    n,
+                    from: votes.keys().copied().collect(),
+                })
+                .collect(),
+            latencies: self
+                .latency_recorder
+                .as_ref()
+                .map(|(recorder, _)| recorder.pending().clone()),
+        }
+    }
+
+    /// How much the `Learner` keeps in memory, e.g. to be exported as
+    /// gauges. See `set_vote_window`.
+    pub fn memory(&self) -> LearnerMemory {
+        LearnerMemory {
+            vote_sets: self.accepted_received.len(),
+            votes: self.accepted_received.values().map(|v| v.len()).sum(),
+            shadow_votes: self.shadow_votes.values().map(|v| v.len()).sum(),
+            decided: self.decided.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::message::{AcceptedData, Handler, Message};
+    use crate::node::Node;
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    #[test]
+    fn status_stuck_slot() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut nodes: Vec<Node<u64>> = (1..=3).map(|id| Node::new(id, config.clone())).collect();
+
+        // Node 3 is unreachable, and only node 1 votes in slot 1.
+        nodes[0].proposer_mut().set_window(2);
+        nodes[0].propose(10);
+        nodes[0].propose(20);
+        let mut sent = nodes[0].take_effects();
+        while !sent.is_empty() {
+            for effect in core::mem::take(&mut sent) {
+                for n in nodes.iter_mut().take(2) {
+                    let Some(msg) = effect.message_for(n.id()) else {
+                        continue;
+                    };
+                    let slot_one = matches!(msg, Message::Accept(ref a) if a.slot == 1);
+                    if n.id() == 1 || !slot_one {
+                        sent.extend(n.step(msg.clone()));
+                    }
+                }
+            }
+        }
+
+        let proposer = nodes[0].proposer().status();
+
+        assert_eq!(proposer.ballot, 1);
+        assert_eq!(proposer.next_slot, 2);
+        assert_eq!(proposer.in_flight.len(), 1);
+        assert_eq!(proposer.in_flight[0].slot, 1);
+        assert!(proposer.in_flight[0].accepting);
+        assert_eq!(proposer.in_flight[0].promises, 2);
+        assert_eq!(proposer.in_flight[0].accepted, 1);
+
+        let acceptor = nodes[1].acceptor().status();
+
+        assert_eq!(acceptor.promised_n, 1);
+        assert_eq!(acceptor.accepted, vec![(0, 1)]);
+
+        nodes[0]
+            .learner_mut()
+            .handle(Message::Accepted(AcceptedData {
+                slot: 3,
+                id: 1,
+                value: Arc::new(30),
+                from: 1,
+                fast: false,
+            }));
+        let learner = nodes[0].learner().status();
+
+        assert_eq!(learner.decided_index, 1);
+        assert_eq!(learner.decided_ahead, 0);
+        assert_eq!(learner.horizon, 4);
+        assert_eq!(
+            learner.votes,
+            vec![
+                VoteStatus {
+                    slot: 1,
+                    n: 1,
+                    from: vec![1]
+                },
+                VoteStatus {
+                    slot: 3,
+                    n: 1,
+                    from: vec![1]
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,948 @@
+//! Storage
+//!
+//! An `Acceptor` must not go back on its promises and votes after a restart,
+//! so it asks for its state to be made durable through `Effect::PersistState`
+//! before carrying out the effects that follow. A `Storage` keeps that state
+//! as a log of `Record`s, which `load` adds back up into a `DurableState` for
+//! `Acceptor::restore`. An `AsyncStorage` does the same without blocking the
+//! thread driving the roles while the disk works.
+//!
+//! Syncing each record on its own caps an `Acceptor` at one vote per fsync,
+//! a few hundred per second on spinning disks and cloud volumes. A
+//! `GroupCommit` instead lets the records persisted within a small window
+//! share one sync, holding back the effects that depend on them until then:
+//!
+//! ```
+//! use paxos_rust::storage::{GroupCommit, MemoryStorage};
+//! use paxos_rust::{Acceptor, ClusterConfig, Message, ProposalData};
+//!
+//! let mut acceptor: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+//! // Sync at most 5ms after the first record of a batch.
+//! let mut storage = GroupCommit::new(MemoryStorage::new(), 5);
+//!
+//! let prepare = Message::Prepare(ProposalData { slot: 0, id: 1, from: 2 });
+//! let effects = acceptor.step(prepare);
+//! // The Promise waits for the sync.
+//! assert!(storage.submit(effects, 0).unwrap().is_empty());
+//! assert_eq!(storage.tick(5).unwrap().len(), 1);
+//! ```
+
+use crate::acceptor::{AcceptedProposal, Acceptor};
+use crate::effect::Effect;
+use crate::message::{Messenger, Slot};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::future::Future;
+
+/// A change to an `Acceptor`'s durable state, as described by
+/// `Effect::PersistState`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Record<T> {
+    /// The highest proposal number promised
+    pub promised_n: u64,
+    /// A value accepted, along with its slot
+    pub accepted: Option<(Slot, AcceptedProposal<T>)>,
+}
+
+/// The state of an `Acceptor` its durable records add up to.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DurableState<T> {
+    /// The highest proposal number promised
+    pub promised_n: u64,
+    /// The last accepted proposal of each slot
+    pub accepted: BTreeMap<Slot, AcceptedProposal<T>>,
+    /// Damage found in the log, if some of the records were lost
+    pub recovery: Option<RecoveryReport>,
+}
+
+impl<T> Default for DurableState<T> {
+    fn default() -> Self {
+        Self {
+            promised_n: 0,
+            accepted: BTreeMap::new(),
+            recovery: None,
+        }
+    }
+}
+
+/// Damaged records found in a log on recovery. Promises and votes may have
+/// been lost with them, so an `Acceptor` restored from the log stops voting
+/// until `Acceptor::acknowledge_recovery` is called: e.g. once an operator
+/// checked that a quorum of the other `Acceptor`s is intact, or copied the
+/// state of one over.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RecoveryReport {
+    /// The segment the damage starts in, named after its first record
+    pub segment: u64,
+    /// The offset of the first damaged record in the segment
+    pub offset: u64,
+    /// The bytes dropped from the log, from the damage on
+    pub lost_bytes: u64,
+    /// The later segments dropped with them
+    pub quarantined: u64,
+}
+
+impl<T> DurableState<T> {
+    /// Applies `record`, appended after the records this state was built from.
+    pub fn apply(&mut self, record: Record<T>) {
+        self.promised_n = self.promised_n.max(record.promised_n);
+        if let Some((slot, accepted)) = record.accepted {
+            self.accepted.insert(slot, accepted);
+        }
+    }
+}
+
+/// Where an `Acceptor`'s state is made durable.
+pub trait Storage<T> {
+    /// Error raised by the underlying medium
+    type Error;
+
+    /// Appends `record` to the log. It only needs to survive a crash once
+    /// `sync` returns.
+    fn append(&mut self, record: &Record<T>) -> Result<(), Self::Error>;
+
+    /// Makes every record appended so far durable.
+    fn sync(&mut self) -> Result<(), Self::Error>;
+
+    /// Reads back the state the durable records add up to.
+    fn load(&mut self) -> Result<DurableState<T>, Self::Error>;
+
+    /// Lets go of the values accepted below `below`, once a snapshot covers
+    /// them, as `Acceptor::truncate` does. They may still be loaded back.
+    fn truncate(&mut self, below: Slot) -> Result<(), Self::Error> {
+        let _ = below;
+        Ok(())
+    }
+}
+
+/// A `Storage` whose operations complete asynchronously, so that a slow disk
+/// doesn't block the thread driving the roles. With the `runtime` feature,
+/// `runtime::Blocking` runs any `Storage` on tokio's blocking threads, and
+/// `runtime::run_node` awaits it before sending anything.
+pub trait AsyncStorage<T> {
+    /// Error raised by the underlying medium
+    type Error;
+
+    /// Appends `record` to the log. It only needs to survive a crash once
+    /// `sync` completes.
+    fn append(&mut self, record: Record<T>) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Makes every record appended so far durable.
+    fn sync(&mut self) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Reads back the state the durable records add up to.
+    fn load(&mut self) -> impl Future<Output = Result<DurableState<T>, Self::Error>>;
+}
+
+/// Appends the state persisted by `effects` to `storage`, and returns the
+/// others once it's synced.
+pub async fn persist<T, S: AsyncStorage<T>>(
+    storage: &mut S,
+    effects: Vec<Effect<T>>,
+) -> Result<Vec<Effect<T>>, S::Error> {
+    let mut rest = Vec::new();
+    let mut unsynced = false;
+    for effect in effects {
+        match effect {
+            Effect::PersistState {
+                promised_n,
+                accepted,
+            } => {
+                storage
+                    .append(Record {
+                        promised_n,
+                        accepted,
+                    })
+                    .await?;
+                unsynced = true;
+            }
+            effect => rest.push(effect),
+        }
+    }
+    if unsynced {
+        storage.sync().await?;
+    }
+    Ok(rest)
+}
+
+/// A `Storage` kept in memory, for tests and simulations. Records that
+/// weren't synced are lost by `crash`.
+#[derive(Debug, Clone)]
+pub struct MemoryStorage<T> {
+    records: Vec<Record<T>>,
+    /// How many of `records` are durable
+    synced: usize,
+    /// How many times `sync` was called
+    syncs: u64,
+}
+
+impl<T> Default for MemoryStorage<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> MemoryStorage<T> {
+    /// Creates an empty `MemoryStorage`.
+    pub fn new() -> Self {
+        Self {
+            records: Vec::new(),
+            synced: 0,
+            syncs: 0,
+        }
+    }
+
+    /// Drops the records that weren't synced, as a crash would.
+    pub fn crash(&mut self) {
+        self.records.truncate(self.synced);
+    }
+
+    /// How many times the records were synced.
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+}
+
+impl<T: Clone> Storage<T> for MemoryStorage<T> {
+    type Error = core::convert::Infallible;
+
+    fn append(&mut self, record: &Record<T>) -> Result<(), Self::Error> {
+        self.records.push(record.clone());
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        self.synced = self.records.len();
+        self.syncs += 1;
+        Ok(())
+    }
+
+    fn load(&mut self) -> Result<DurableState<T>, Self::Error> {
+        let mut state = DurableState::default();
+        for record in &self.records[..self.synced] {
+            state.apply(record.clone());
+        }
+        Ok(state)
+    }
+}
+
+/// Shares each sync of a `Storage` between the records persisted within
+/// `max_delay` milliseconds of the first, or `max_batch` records, whichever
+/// comes first. See the module documentation.
+pub struct GroupCommit<T, S> {
+    storage: S,
+    max_delay: u64,
+    max_batch: usize,
+    /// Records appended since the last sync
+    unsynced: usize,
+    /// When the first of them was appended
+    since: Option<u64>,
+    /// Effects waiting for the next sync
+    held: Vec<Effect<T>>,
+}
+
+impl<T, S: Storage<T>> GroupCommit<T, S> {
+    /// Groups the syncs of `storage`, delaying each by at most `max_delay`
+    /// milliseconds. Every batch of effects submitted is synced on its own
+    /// if `max_delay` is 0.
+    pub fn new(storage: S, max_delay: u64) -> Self {
+        Self {
+            storage,
+            max_delay,
+            max_batch: usize::MAX,
+            unsynced: 0,
+            since: None,
+            held: Vec::new(),
+        }
+    }
+
+    /// Sets the number of records a batch is synced at, even before
+    /// `max_delay` went by. Unbounded by default.
+    pub fn set_max_batch(&mut self, records: usize) {
+        self.max_batch = records.max(1);
+    }
+
+    /// The underlying `Storage`.
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// The underlying `Storage`, mutably.
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    /// Appends the state persisted by `effects`, the output of an `Acceptor`
+    /// at time `now`, and returns those that may be carried out right away.
+    /// Every effect following a `PersistState` is held back until its record
+    /// is synced, along with any submitted after it, so that effects are
+    /// released in order.
+    pub fn submit(
+        &mut self,
+        effects: Vec<Effect<T>>,
+        now: u64,
+    ) -> Result<Vec<Effect<T>>, S::Error> {
+        let mut ready = Vec::new();
+        for effect in effects {
+            match effect {
+                Effect::PersistState {
+                    promised_n,
+                    accepted,
+                } => {
+                    self.storage.append(&Record {
+                        promised_n,
+                        accepted,
+                    })?;
+                    self.unsynced += 1;
+                    self.since.get_or_insert(now);
+                }
+                effect if self.unsynced > 0 => self.held.push(effect),
+                effect => ready.push(effect),
+            }
+        }
+        if self.unsynced >= self.max_batch || self.max_delay == 0 {
+            ready.extend(self.flush()?);
+        }
+        Ok(ready)
+    }
+
+    /// Syncs the current batch once `max_delay` went by since its first
+    /// record, returning the effects released.
+    pub fn tick(&mut self, now: u64) -> Result<Vec<Effect<T>>, S::Error> {
+        match self.deadline() {
+            Some(at) if at <= now => self.flush(),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// When the current batch is due to be synced, if any.
+    pub fn deadline(&self) -> Option<u64> {
+        self.since.map(|since| since + self.max_delay)
+    }
+
+    /// Syncs the current batch right away, returning the effects released.
+    pub fn flush(&mut self) -> Result<Vec<Effect<T>>, S::Error> {
+        if self.unsynced > 0 {
+            self.storage.sync()?;
+            self.unsynced = 0;
+            self.since = None;
+        }
+        Ok(core::mem::take(&mut self.held))
+    }
+}
+
+impl<T, M: Messenger<T>> Acceptor<T, M> {
+    /// Restores the state loaded from a `Storage` after a restart. The
+    /// `Acceptor` votes right away, as it remembers every promise and vote it
+    /// made, unless records were lost: then it waits for
+    /// `acknowledge_recovery`.
+    pub fn restore(&mut self, state: DurableState<T>) {
+        self.promised_n = state.promised_n;
+        self.accepted = state.accepted;
+        self.voting = true;
+        self.recovery = state.recovery;
+    }
+
+    /// The damage found in the log the `Acceptor` was restored from, until
+    /// acknowledged.
+    pub fn recovery(&self) -> Option<&RecoveryReport> {
+        self.recovery.as_ref()
+    }
+
+    /// Lets the `Acceptor` vote again after records were lost. See
+    /// `RecoveryReport`.
+    pub fn acknowledge_recovery(&mut self) {
+        self.recovery = None;
+    }
+}
+
+/// The CRC-32 (IEEE) of `bytes`.
+#[cfg(feature = "std")]
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+pub use file::{FileStorage, SEGMENT_SIZE};
+
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+mod file {
+    use super::{crc32, DurableState, Record, RecoveryReport, Storage};
+    use crate::acceptor::AcceptedProposal;
+    use crate::message::Slot;
+    use crate::wire::{put_bytes, put_u32, put_u64, DecodeError, Reader};
+    use alloc::sync::Arc;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use std::vec::Vec;
+
+    /// Size segments are rolled over at by default, in bytes.
+    pub const SEGMENT_SIZE: u64 = 64 << 20;
+
+    /// A `Storage` appending records to a directory of segment files. Each
+    /// record is a u32 length and the CRC-32 of the bytes that follow:
+    /// `promised_n:u64 accepted:opt<slot:u64 n:u64 value:bytes>`, in the
+    /// notation of the `wire` module.
+    ///
+    /// Segments are named after the sequence number of their first record,
+    /// and a new one is started once the last grows past the segment size.
+    /// Rolling over creates the next segment and syncs the directory before
+    /// anything is written to it, so a crash in the middle leaves at most an
+    /// empty segment behind. A record torn by a crash in the middle of a
+    /// write is dropped on `open`, as it was never synced.
+    ///
+    /// Any other damage, a record failing its checksum or cut short before
+    /// the end of the log, means synced records were lost. The log is
+    /// truncated where the damage starts, the bytes dropped are moved aside
+    /// to `.corrupt` files, and `load` reports them in a `RecoveryReport`.
+    /// The report is kept in a `recovery` file until `acknowledge_recovery`
+    /// is called, so that restarting doesn't get the `Acceptor` to vote again.
+    ///
+    /// `truncate` deletes the segments holding only values accepted below a
+    /// snapshot, in a background thread. Every record carries the highest
+    /// proposal number promised, so nothing else is lost with them.
+    pub struct FileStorage {
+        dir: PathBuf,
+        /// Index of the segments, oldest first. Never empty
+        segments: Vec<Segment>,
+        /// The last segment, appended to
+        file: File,
+        segment_size: u64,
+        /// Records appended since the last sync
+        buf: Vec<u8>,
+        /// How many of them
+        buffered: u64,
+        /// Damage found by `open`
+        recovery: Option<RecoveryReport>,
+    }
+
+    /// A segment file, as indexed by a `FileStorage`.
+    struct Segment {
+        /// Sequence number of the first record
+        first: u64,
+        /// Records held
+        records: u64,
+        /// Bytes held
+        size: u64,
+        /// Highest slot a value was accepted for
+        max_slot: Option<Slot>,
+    }
+
+    impl FileStorage {
+        /// Opens the log in the directory `dir`, creating it if missing, and
+        /// indexes its segments, quarantining any damaged records.
+        pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+            let dir = dir.as_ref().to_path_buf();
+            fs::create_dir_all(&dir)?;
+            let mut firsts = Vec::new();
+            for entry in fs::read_dir(&dir)? {
+                let name = entry?.file_name();
+                let name = name.to_string_lossy();
+                if let Some(first) = name.strip_suffix(".log").and_then(|n| n.parse().ok()) {
+                    firsts.push(first);
+                }
+            }
+            firsts.sort_unstable();
+            let mut segments: Vec<Segment> = Vec::new();
+            let mut recovery: Option<RecoveryReport> = None;
+            for (i, &first) in firsts.iter().enumerate() {
+                let path = segment_path(&dir, first);
+                if let Some(report) = &mut recovery {
+                    // Nothing after the damage can be trusted.
+                    report.lost_bytes += fs::metadata(&path)?.len();
+                    report.quarantined += 1;
+                    fs::rename(&path, path.with_extension("corrupt"))?;
+                    continue;
+                }
+                let bytes = fs::read(&path)?;
+                let (records, tail) = scan(&bytes);
+                let mut segment = Segment {
+                    first,
+                    records: records.len() as u64,
+                    size: 0,
+                    max_slot: None,
+                };
+                for record in records {
+                    segment.size += 8 + record.len() as u64;
+                    segment.max_slot = segment.max_slot.max(slot(record)?);
+                }
+                let damaged = match tail {
+                    Tail::Clean => false,
+                    Tail::Torn => i + 1 < firsts.len(),
+                    Tail::Corrupt => true,
+                };
+                if damaged {
+                    let offset = segment.size as usize;
+                    fs::write(path.with_extension("corrupt"), &bytes[offset..])?;
+                    recovery = Some(RecoveryReport {
+                        segment: first,
+                        offset: segment.size,
+                        lost_bytes: (bytes.len() - offset) as u64,
+                        quarantined: 0,
+                    });
+                }
+                segments.push(segment);
+            }
+            let marker = dir.join("recovery");
+            match &recovery {
+                Some(report) => {
+                    let mut file = File::create(&marker)?;
+                    std::writeln!(
+                        file,
+                        "{} {} {} {}",
+                        report.segment,
+                        report.offset,
+                        report.lost_bytes,
+                        report.quarantined
+                    )?;
+                    file.sync_all()?;
+                }
+                None if marker.exists() => recovery = Some(read_report(&marker)?),
+                None => {}
+            }
+            if segments.is_empty() {
+                File::create(segment_path(&dir, 0))?;
+                segments.push(Segment {
+                    first: 0,
+                    records: 0,
+                    size: 0,
+                    max_slot: None,
+                });
+            }
+            let last = segments.last().unwrap();
+            let file = OpenOptions::new()
+                .append(true)
+                .open(segment_path(&dir, last.first))?;
+            // Drop a torn or damaged record, so that the next ones can be
+            // read back.
+            file.set_len(last.size)?;
+            file.sync_all()?;
+            sync_dir(&dir)?;
+            Ok(Self {
+                dir,
+                segments,
+                file,
+                segment_size: SEGMENT_SIZE,
+                buf: Vec::new(),
+                buffered: 0,
+                recovery,
+            })
+        }
+
+        /// The damage found in the log, if any, until acknowledged.
+        pub fn recovery(&self) -> Option<&RecoveryReport> {
+            self.recovery.as_ref()
+        }
+
+        /// Forgets the damage found in the log, along with
+        /// `Acceptor::acknowledge_recovery`.
+        pub fn acknowledge_recovery(&mut self) -> io::Result<()> {
+            if self.recovery.take().is_some() {
+                fs::remove_file(self.dir.join("recovery"))?;
+                sync_dir(&self.dir)?;
+            }
+            Ok(())
+        }
+
+        /// Sets the size segments are rolled over at, in bytes.
+        /// `SEGMENT_SIZE` by default.
+        pub fn set_segment_size(&mut self, bytes: u64) {
+            self.segment_size = bytes;
+        }
+
+        /// The number of segments.
+        pub fn segments(&self) -> usize {
+            self.segments.len()
+        }
+
+        /// Starts a new segment after the last one, which must be synced.
+        fn roll_over(&mut self) -> io::Result<()> {
+            let last = self.segments.last().unwrap();
+            let first = last.first + last.records;
+            let file = File::create(segment_path(&self.dir, first))?;
+            sync_dir(&self.dir)?;
+            self.file = file;
+            self.segments.push(Segment {
+                first,
+                records: 0,
+                size: 0,
+                max_slot: None,
+            });
+            Ok(())
+        }
+    }
+
+    impl<T> Storage<T> for FileStorage
+    where
+        T: AsRef<[u8]> + for<'a> From<&'a [u8]>,
+    {
+        type Error = io::Error;
+
+        fn append(&mut self, record: &Record<T>) -> io::Result<()> {
+            let mut out = Vec::new();
+            put_u64(&mut out, record.promised_n);
+            match &record.accepted {
+                Some((slot, accepted)) => {
+                    out.push(1);
+                    put_u64(&mut out, *slot);
+                    put_u64(&mut out, accepted.n);
+                    put_bytes(&mut out, accepted.value.as_ref().as_ref());
+                }
+                None => out.push(0),
+            }
+            put_u32(&mut self.buf, out.len() as u32);
+            put_u32(&mut self.buf, crc32(&out));
+            self.buf.extend_from_slice(&out);
+            self.buffered += 1;
+            let last = self.segments.last_mut().unwrap();
+            last.max_slot = last.max_slot.max(record.accepted.as_ref().map(|(s, _)| *s));
+            Ok(())
+        }
+
+        fn sync(&mut self) -> io::Result<()> {
+            let last = self.segments.last_mut().unwrap();
+            self.file.write_all(&self.buf)?;
+            last.records += self.buffered;
+            last.size += self.buf.len() as u64;
+            self.buf.clear();
+            self.buffered = 0;
+            self.file.sync_data()?;
+            if last.size >= self.segment_size {
+                self.roll_over()?;
+            }
+            Ok(())
+        }
+
+        fn load(&mut self) -> io::Result<DurableState<T>> {
+            let mut state = DurableState::default();
+            for segment in &self.segments {
+                let bytes = fs::read(segment_path(&self.dir, segment.first))?;
+                let (records, _) = scan(&bytes[..segment.size as usize]);
+                for record in records {
+                    state.apply(decode(record).map_err(invalid)?);
+                }
+            }
+            state.recovery = self.recovery.clone();
+            Ok(state)
+        }
+
+        fn truncate(&mut self, below: Slot) -> io::Result<()> {
+            let last = self.segments.len() - 1;
+            let kept = self.segments[..last]
+                .iter()
+                .position(|s| s.max_slot.is_some_and(|slot| slot >= below))
+                .unwrap_or(last);
+            let dropped: Vec<PathBuf> = self
+                .segments
+                .drain(..kept)
+                .map(|s| segment_path(&self.dir, s.first))
+                .collect();
+            if !dropped.is_empty() {
+                thread::spawn(move || {
+                    for path in dropped {
+                        let _ = fs::remove_file(path);
+                    }
+                });
+            }
+            Ok(())
+        }
+    }
+
+    fn segment_path(dir: &Path, first: u64) -> PathBuf {
+        dir.join(std::format!("{:020}.log", first))
+    }
+
+    /// Makes the creation of files in `dir` durable.
+    fn sync_dir(dir: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+        #[cfg(not(unix))]
+        let _ = dir;
+        Ok(())
+    }
+
+    /// Reads back the report kept in `path` until acknowledged.
+    fn read_report(path: &Path) -> io::Result<RecoveryReport> {
+        let text = fs::read_to_string(path)?;
+        let mut fields = text.split_whitespace().map(|n| n.parse::<u64>().ok());
+        let mut field = || {
+            fields.next().flatten().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "malformed recovery report")
+            })
+        };
+        Ok(RecoveryReport {
+            segment: field()?,
+            offset: field()?,
+            lost_bytes: field()?,
+            quarantined: field()?,
+        })
+    }
+
+    /// How the bytes of a segment end, after its last intact record.
+    enum Tail {
+        /// With that record
+        Clean,
+        /// With part of a record
+        Torn,
+        /// With a record failing its checksum
+        Corrupt,
+    }
+
+    /// Splits the intact records off the start of a segment.
+    fn scan(bytes: &[u8]) -> (Vec<&[u8]>, Tail) {
+        let mut records = Vec::new();
+        let mut r = Reader { bytes };
+        while !r.bytes.is_empty() {
+            let record = match (r.u32(), r.u32()) {
+                (Ok(len), Ok(crc)) => match r.take(len as usize) {
+                    Ok(record) if crc32(record) == crc => record,
+                    Ok(_) => return (records, Tail::Corrupt),
+                    Err(_) => return (records, Tail::Torn),
+                },
+                _ => return (records, Tail::Torn),
+            };
+            records.push(record);
+        }
+        (records, Tail::Clean)
+    }
+
+    /// The slot of an encoded record, if it holds an accepted value.
+    fn slot(bytes: &[u8]) -> io::Result<Option<Slot>> {
+        let mut r = Reader { bytes };
+        r.u64().map_err(invalid)?;
+        match r.bool().map_err(invalid)? {
+            true => Ok(Some(r.u64().map_err(invalid)?)),
+            false => Ok(None),
+        }
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<Record<T>, DecodeError>
+    where
+        T: for<'a> From<&'a [u8]>,
+    {
+        let mut r = Reader { bytes };
+        let promised_n = r.u64()?;
+        let accepted = if r.bool()? {
+            let (slot, n) = (r.u64()?, r.u64()?);
+            let value = Arc::new(T::from(r.bytes()?));
+            Some((slot, AcceptedProposal { n, value }))
+        } else {
+            None
+        };
+        Ok(Record {
+            promised_n,
+            accepted,
+        })
+    }
+
+    fn invalid(err: DecodeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::message::{AcceptData, Message, ProposalData};
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    fn prepare(slot: Slot, id: u64) -> Message<u64> {
+        Message::Prepare(ProposalData { slot, id, from: 2 })
+    }
+
+    #[test]
+    fn group_commit_shares_syncs() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let mut storage = GroupCommit::new(MemoryStorage::new(), 10);
+
+        assert!(storage.submit(a.step(prepare(0, 1)), 0).unwrap().is_empty());
+        assert!(storage.submit(a.step(prepare(1, 2)), 4).unwrap().is_empty());
+        assert_eq!(storage.deadline(), Some(10));
+        assert!(storage.tick(9).unwrap().is_empty());
+
+        let released = storage.tick(10).unwrap();
+
+        assert_eq!(released.len(), 2);
+        assert_eq!(storage.storage().syncs(), 1);
+        assert_eq!(storage.deadline(), None);
+
+        // A full batch is synced right away.
+        storage.set_max_batch(1);
+        let accept = Message::Accept(AcceptData {
+            slot: 0,
+            id: 2,
+            value: Arc::new(10),
+            implicit_prepare: false,
+        });
+        assert_eq!(storage.submit(a.step(accept), 20).unwrap().len(), 1);
+        assert_eq!(storage.storage().syncs(), 2);
+    }
+
+    #[test]
+    fn restore_synced_state() {
+        let mut a: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let mut storage = GroupCommit::new(MemoryStorage::new(), 10);
+        storage.submit(a.step(prepare(0, 1)), 0).unwrap();
+        storage.flush().unwrap();
+        storage.submit(a.step(prepare(0, 2)), 0).unwrap();
+        // The second promise was never sent, so it may be forgotten.
+        storage.storage_mut().crash();
+
+        let mut restarted: Acceptor<u64> = Acceptor::joining(1, ClusterConfig::new(vec![1, 2, 3]));
+        restarted.restore(storage.storage_mut().load().unwrap());
+
+        assert!(restarted.is_voting());
+        assert_eq!(restarted.promised_n, 1);
+    }
+
+    #[cfg(feature = "std")]
+    fn accepted(promised_n: u64, slot: Slot) -> Record<Vec<u8>> {
+        Record {
+            promised_n,
+            accepted: Some((
+                slot,
+                AcceptedProposal {
+                    n: promised_n,
+                    value: Arc::new(vec![slot as u8]),
+                },
+            )),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(std::format!("paxos-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn file_storage_roundtrip() {
+        use std::io::Write;
+
+        let dir = temp_dir("storage");
+        let mut storage = FileStorage::open(&dir).unwrap();
+        let promise: Record<Vec<u8>> = Record {
+            promised_n: 2,
+            accepted: None,
+        };
+        storage.append(&promise).unwrap();
+        storage.append(&accepted(3, 5)).unwrap();
+        Storage::<Vec<u8>>::sync(&mut storage).unwrap();
+        // A torn record is dropped.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("00000000000000000000.log"))
+            .unwrap()
+            .write_all(&[0, 0, 0, 9, 1])
+            .unwrap();
+
+        let mut storage = FileStorage::open(&dir).unwrap();
+        storage.append(&accepted(4, 6)).unwrap();
+        Storage::<Vec<u8>>::sync(&mut storage).unwrap();
+        let state: DurableState<Vec<u8>> = storage.load().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(state.promised_n, 4);
+        assert_eq!(
+            state.accepted.keys().copied().collect::<Vec<_>>(),
+            vec![5, 6]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn file_storage_segments() {
+        let dir = temp_dir("segments");
+        let mut storage = FileStorage::open(&dir).unwrap();
+        // Every record fills a segment of its own.
+        storage.set_segment_size(1);
+        for slot in 0..4 {
+            storage.append(&accepted(slot + 1, slot)).unwrap();
+            Storage::<Vec<u8>>::sync(&mut storage).unwrap();
+        }
+
+        assert_eq!(storage.segments(), 5);
+
+        Storage::<Vec<u8>>::truncate(&mut storage, 2).unwrap();
+        let state: DurableState<Vec<u8>> = storage.load().unwrap();
+
+        assert_eq!(storage.segments(), 3);
+        assert_eq!(state.promised_n, 4);
+        assert_eq!(
+            state.accepted.keys().copied().collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        // The index is rebuilt from the segments left.
+        let reopened = FileStorage::open(&dir).unwrap();
+        assert!(reopened.segments() >= 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn file_storage_quarantines_damage() {
+        let dir = temp_dir("damage");
+        let mut storage = FileStorage::open(&dir).unwrap();
+        storage.set_segment_size(1);
+        for slot in 0..3 {
+            storage.append(&accepted(slot + 1, slot)).unwrap();
+            Storage::<Vec<u8>>::sync(&mut storage).unwrap();
+        }
+        // Flip the last byte of the value accepted for slot 1.
+        let path = dir.join("00000000000000000001.log");
+        let mut bytes = std::fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut storage = FileStorage::open(&dir).unwrap();
+        let state: DurableState<Vec<u8>> = storage.load().unwrap();
+        let report = RecoveryReport {
+            segment: 1,
+            offset: 0,
+            // Slot 2's record went with it, and the empty segment after.
+            lost_bytes: 2 * bytes.len() as u64,
+            quarantined: 2,
+        };
+
+        assert_eq!(state.recovery, Some(report.clone()));
+        assert_eq!(state.accepted.keys().copied().collect::<Vec<_>>(), vec![0]);
+        assert!(dir.join("00000000000000000001.corrupt").exists());
+
+        let mut a: Acceptor<Vec<u8>> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        a.restore(state);
+        let prepare = Message::Prepare(ProposalData {
+            slot: 0,
+            id: 9,
+            from: 2,
+        });
+
+        assert!(!a.is_voting());
+        assert!(a.step(prepare.clone()).is_empty());
+
+        a.acknowledge_recovery();
+
+        assert!(!a.step(prepare).is_empty());
+
+        // The damage is reported until acknowledged.
+        let mut reopened = FileStorage::open(&dir).unwrap();
+        assert_eq!(reopened.recovery(), Some(&report));
+        reopened.acknowledge_recovery().unwrap();
+        let reopened = FileStorage::open(&dir).unwrap();
+        assert_eq!(reopened.recovery(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+//! Thread-safe handles
+//!
+//! When built with `--cfg loom` the primitives used here are swapped for
+//! [loom](https://docs.rs/loom)'s, so the interleavings of threads driving a
+//! role can be checked exhaustively.
+
+use crate::event::{EventSink, PaxosEvent};
+use crate::message::{Handler, Message, Messenger, Readiness, Slot};
+use std::sync::PoisonError;
+use std::vec::Vec;
+
+#[cfg(loom)]
+use loom::sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc, Mutex, MutexGuard,
+};
+#[cfg(not(loom))]
+use std::sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc, Mutex, MutexGuard,
+};
+
+/// A role shared between the threads driving it. Cloning a `Shared` yields
+/// another handle to the same role.
+pub struct Shared<R> {
+    inner: Arc<Mutex<R>>,
+}
+
+impl<R> Shared<R> {
+    /// Wraps `role` so it can be shared between threads.
+    pub fn new(role: R) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(role)),
+        }
+    }
+
+    /// Locks the role for exclusive access. A handle whose lock was poisoned
+    /// by a panicking thread remains usable.
+    pub fn lock(&self) -> MutexGuard<'_, R> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Delivers `msg` to the role. Messages from several threads are handled
+    /// one at a time, in the order they acquire the lock.
+    pub fn deliver<T>(&self, msg: Message<T>)
+    where
+        R: Handler<T>,
+    {
+        self.lock().handle(msg);
+    }
+}
+
+impl<R> Clone for Shared<R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// The sending half of a channel a `BroadcastMessenger` sends through.
+pub trait ChannelSender<T> {
+    /// Sending half of the channel resolved proposals are reported through
+    type Resolutions;
+
+    /// Sends `msg`, dropping it if the receiving half is gone.
+    fn send_message(&self, msg: Message<T>);
+
+    /// Reports through `resolutions` that `value` was decided for `slot`.
+    fn send_resolution(resolutions: &Self::Resolutions, slot: Slot, value: alloc::sync::Arc<T>);
+
+    /// Whether the channel is full, so that further messages would be
+    /// dropped. Unbounded channels never are.
+    fn is_full(&self) -> bool {
+        false
+    }
+}
+
+impl<T> ChannelSender<T> for Sender<Message<T>> {
+    type Resolutions = Sender<(Slot, alloc::sync::Arc<T>)>;
+
+    fn send_message(&self, msg: Message<T>) {
+        let _ = self.send(msg);
+    }
+
+    fn send_resolution(resolutions: &Self::Resolutions, slot: Slot, value: alloc::sync::Arc<T>) {
+        let _ = resolutions.send((slot, value));
+    }
+}
+
+/// A `Messenger` that utilizes Channels to pass values between threads.
+pub type ChannelMessenger<T> = BroadcastMessenger<T, Sender<Message<T>>>;
+
+/// A `Messenger` broadcasting every message to a set of channels, whichever
+/// kind of channel `S` sends through.
+pub struct BroadcastMessenger<T, S: ChannelSender<T>> {
+    /// Channels every outgoing message is sent to
+    pub senders: Vec<S>,
+    /// Channel notified of every resolved proposal
+    pub resolutions: Option<S::Resolutions>,
+}
+
+impl<T, S: ChannelSender<T>> BroadcastMessenger<T, S> {
+    /// Creates a new `BroadcastMessenger`.
+    pub fn new(senders: Vec<S>) -> Self {
+        Self {
+            senders,
+            resolutions: None,
+        }
+    }
+
+    fn broadcast(&self, msg: Message<T>)
+    where
+        T: Clone,
+    {
+        // A disconnected channel means the receiving role has shut down, which
+        // is indistinguishable from a lost message as far as Paxos is concerned.
+        for sender in &self.senders {
+            sender.send_message(msg.clone());
+        }
+    }
+}
+
+impl<T: Clone, S: ChannelSender<T>> Messenger<T> for BroadcastMessenger<T, S> {
+    fn send_prepare(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_promise(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_accept(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_accepted(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn on_resolution(&mut self, slot: Slot, value: alloc::sync::Arc<T>) {
+        if let Some(ref resolutions) = self.resolutions {
+            S::send_resolution(resolutions, slot, value);
+        }
+    }
+
+    fn readiness(&self) -> Readiness {
+        if self.senders.iter().any(ChannelSender::is_full) {
+            Readiness::WouldBlock
+        } else {
+            Readiness::Ready
+        }
+    }
+}
+
+/// Creates an `EventSink` forwarding every event to the returned channel.
+pub fn event_channel() -> (EventSink, Receiver<PaxosEvent>) {
+    let (sender, receiver) = channel();
+    let sink = move |event| {
+        let _ = sender.send(event);
+    };
+    (alloc::boxed::Box::new(sink), receiver)
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::message::{AcceptedData, PromiseData};
+    use crate::proposer::Proposer;
+    use alloc::boxed::Box;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::vec;
+
+    #[test]
+    fn shared_deliver_from_threads() {
+        let (acc_sender, acc_receiver) = mpsc::channel();
+        let (res_sender, res_receiver) = mpsc::channel();
+
+        let mut messenger = ChannelMessenger::new(vec![acc_sender]);
+        messenger.resolutions = Some(res_sender);
+        let mut proposer: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        proposer.messenger = Some(Box::new(messenger));
+        proposer.prepare(10);
+
+        let proposer = Shared::new(proposer);
+        let threads = (1..=3)
+            .map(|from| {
+                let proposer = proposer.clone();
+                thread::spawn(move || {
+                    proposer.deliver(Message::Promise(PromiseData {
+                        slot: 0,
+                        id: 1,
+                        accepted_n: None,
+                        value: None,
+                        from,
+                    }));
+                    proposer.deliver(Message::Accepted(AcceptedData {
+                        slot: 0,
+                        id: 1,
+                        value: alloc::sync::Arc::new(10),
+                        from,
+                        fast: false,
+                    }));
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // One Prepare, a single Accept and a single Learn, however the threads
+        // interleaved.
+        assert_eq!(acc_receiver.try_iter().count(), 3);
+        assert_eq!(res_receiver.try_iter().count(), 1);
+        assert_eq!(proposer.lock().last_accepted_n, 1);
+    }
+}
@@ -0,0 +1,608 @@
+//! TCP transport
+//!
+//! Carries `Message`s between nodes over TCP, each framed as a u32 length,
+//! big-endian, followed by its `wire` encoding. A `TcpTransport` listens for
+//! its peers on one address and connects to theirs as needed; connections
+//! that fail are dropped along with the message, and opened again for the
+//! next one, which Paxos tolerates like any other lost message.
+//!
+//! So that values of any size get through without holding up a connection
+//! with one huge frame, a `TcpTransport` frames the chunks of each message
+//! cut by the `chunk` module rather than the message itself, each compressed
+//! by the `compress` module. A connection opens with the sender offering the
+//! codecs it prefers, as a frame of their tags, and the receiver answering
+//! with a frame holding the tag of the one it picked.
+//!
+//! Round trips to every peer are timed, for `PeerLatencies`: the handshake of
+//! each connection is one, and `probe` makes another by sending an empty
+//! frame, which the receiver echoes back.
+//!
+//! Given a `Topology`, a `TcpTransport` broadcasts to each remote zone through
+//! a single relay there, as planned by `Topology::plan_broadcast`. The chunks
+//! of a relayed message follow a frame tagged `RELAY` listing the peers to
+//! forward it to, which the relay does as it receives the message. Relaying
+//! is negotiated along with the codec: the sender offers the `RELAY` tag after
+//! its codecs, which the receiver answers after the codec it picked if it was
+//! given a `Topology`. Broadcasts through a peer that didn't answer it go to
+//! its zone directly instead. A relay only forwards to the other peers of its
+//! own zone, each once.
+
+use crate::chunk::{Chunker, Reassembler};
+use crate::compress::{compress, decompress, negotiate, Compression};
+use crate::config::NodeId;
+use crate::latency::PeerLatencies;
+use crate::message::Message;
+use crate::metrics::{Metrics, MetricsSink};
+use crate::topology::{BroadcastPlan, Topology};
+#[cfg(feature = "bytes")]
+use crate::wire::decode_bytes;
+use crate::wire::{decode, encode, DecodeError};
+use alloc::boxed::Box;
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Writes `msg` to `writer` as a single frame.
+pub fn write_frame<T, W>(writer: &mut W, msg: &Message<T>) -> io::Result<()>
+where
+    T: AsRef<[u8]>,
+    W: Write + ?Sized,
+{
+    write_raw(writer, &encode(msg))
+}
+
+/// Reads a single frame from `reader`. Malformed messages are reported as
+/// `InvalidData`.
+pub fn read_frame<T, R>(reader: &mut R) -> io::Result<Message<T>>
+where
+    T: for<'a> From<&'a [u8]>,
+    R: Read + ?Sized,
+{
+    decode(&read_raw(reader)?).map_err(invalid)
+}
+
+/// Reads a single frame from `reader` like `read_frame`, decoding the values
+/// it carries without copying them out of the frame.
+#[cfg(feature = "bytes")]
+pub fn read_frame_bytes<R>(reader: &mut R) -> io::Result<Message<Bytes>>
+where
+    R: Read + ?Sized,
+{
+    decode_bytes(&Bytes::from(read_raw(reader)?)).map_err(invalid)
+}
+
+fn write_raw<W: Write + ?Sized>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(bytes.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too large"))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_raw<R: Read + ?Sized>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Tags a frame listing the peers to relay the next message to, as u64s,
+/// rather than a chunk compressed with the codec of the tag. Offered and
+/// answered in the handshake by nodes that relay.
+const RELAY: u8 = 0xff;
+
+fn invalid(err: DecodeError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// A connection to a node, along with the codec negotiated for it.
+struct Conn {
+    stream: TcpStream,
+    codec: Compression,
+    /// Whether the node relays broadcasts
+    relays: bool,
+}
+
+impl Conn {
+    /// Connects to `addr`, offering the codecs of `compression` and relaying.
+    /// Returns the round-trip time of the handshake along with the
+    /// connection.
+    fn open(addr: SocketAddr, compression: &[Compression]) -> io::Result<(Self, Duration)> {
+        let mut stream = TcpStream::connect(addr)?;
+        let _ = stream.set_nodelay(true);
+        let mut offer: Vec<u8> = compression.iter().map(|codec| codec.tag()).collect();
+        offer.push(RELAY);
+        let start = Instant::now();
+        write_raw(&mut stream, &offer)?;
+        let reply = read_raw(&mut stream)?;
+        let rtt = start.elapsed();
+        let (tag, relays) = match reply[..] {
+            [tag] => (tag, false),
+            [tag, RELAY] => (tag, true),
+            [] => return Err(invalid(DecodeError::UnexpectedEnd)),
+            _ => return Err(invalid(DecodeError::TrailingBytes)),
+        };
+        let codec = Compression::from_tag(tag)
+            .ok_or(DecodeError::UnknownCompression(tag))
+            .map_err(invalid)?;
+        let conn = Self {
+            stream,
+            codec,
+            relays,
+        };
+        Ok((conn, rtt))
+    }
+}
+
+/// Sends `Message`s to every node of the cluster, and receives theirs.
+pub struct TcpTransport<T> {
+    /// The node's ID
+    id: NodeId,
+    /// Address of every node, this one included
+    peers: BTreeMap<NodeId, SocketAddr>,
+    /// Connections to the nodes, opened as needed
+    conns: BTreeMap<NodeId, Conn>,
+    /// Cuts outgoing messages into chunks
+    chunker: Chunker,
+    /// Codecs offered to the nodes, preferred first
+    compression: Vec<Compression>,
+    metrics: Option<MetricsSink>,
+    /// Round-trip times to the nodes
+    latencies: PeerLatencies,
+    /// Where the nodes are deployed, if broadcasts go through relays, shared
+    /// with the connections accepted to relay them
+    topology: Arc<Mutex<Option<Topology>>>,
+    /// Messages read from any connection, along with the peers to relay
+    /// them to
+    inbox: Receiver<(Message<T>, Vec<NodeId>)>,
+}
+
+impl<T> TcpTransport<T>
+where
+    T: AsRef<[u8]> + for<'a> From<&'a [u8]> + Send + Sync + 'static,
+{
+    /// Listens for the other nodes on node `id`'s address among `peers`, on
+    /// a background thread, with a thread for each connection accepted.
+    pub fn bind(id: NodeId, peers: BTreeMap<NodeId, SocketAddr>) -> io::Result<Self> {
+        let addr = peers.get(&id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "node missing from its peers")
+        })?;
+        let listener = TcpListener::bind(addr)?;
+        let (sender, inbox) = channel();
+        let topology = Arc::new(Mutex::new(None));
+        let relay = Relay {
+            id,
+            peers: peers.keys().copied().collect(),
+            topology: topology.clone(),
+        };
+        thread::spawn(move || accept(listener, sender, relay));
+        Ok(Self {
+            id,
+            peers,
+            conns: BTreeMap::new(),
+            chunker: Chunker::default(),
+            compression: Compression::supported(),
+            metrics: None,
+            latencies: PeerLatencies::new(),
+            topology,
+            inbox,
+        })
+    }
+
+    /// The node's ID.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Sets the most bytes of a message sent in each frame, `chunk::MAX_CHUNK`
+    /// by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_chunk` is 0.
+    pub fn set_max_chunk(&mut self, max_chunk: usize) {
+        self.chunker = Chunker::new(max_chunk);
+    }
+
+    /// Sets the codecs offered to the nodes as connections open, preferred
+    /// first, every codec built in by default. Offer `Compression::None`
+    /// alone to turn compression off.
+    pub fn set_compression(&mut self, compression: Vec<Compression>) {
+        self.compression = compression;
+        self.conns.clear();
+    }
+
+    /// Reports the bytes sent before and after compression to `metrics`.
+    pub fn set_metrics<X>(&mut self, metrics: X)
+    where
+        X: Metrics + Send + 'static,
+    {
+        self.metrics = Some(Box::new(metrics));
+    }
+
+    /// Broadcasts to each remote zone of `topology` through a single relay
+    /// there, and relays broadcasts to the node's own zone for connections
+    /// opened from then on. See the module documentation.
+    pub fn set_topology(&mut self, topology: Topology) {
+        *self.topology() = Some(topology);
+    }
+
+    fn topology(&self) -> MutexGuard<'_, Option<Topology>> {
+        self.topology.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sends `msg` to node `to`. The message is lost if the node can't be
+    /// reached.
+    pub fn send(&mut self, to: NodeId, msg: &Message<T>) {
+        self.send_relayed(to, &[], msg);
+    }
+
+    /// Sends `msg` to node `to`, for it to forward to `forward_to`.
+    fn send_relayed(&mut self, to: NodeId, forward_to: &[NodeId], msg: &Message<T>) {
+        if !self.connect(to) {
+            return;
+        }
+        if !forward_to.is_empty() && !self.conns[&to].relays {
+            // The node doesn't relay: send to its zone directly.
+            self.send(to, msg);
+            for id in forward_to {
+                self.send(*id, msg);
+            }
+            return;
+        }
+        let conn = self.conns.get_mut(&to).unwrap();
+        if !forward_to.is_empty() {
+            let mut frame = vec![RELAY];
+            for id in forward_to {
+                frame.extend_from_slice(&id.to_be_bytes());
+            }
+            if write_raw(&mut conn.stream, &frame).is_err() {
+                self.conns.remove(&to);
+                return;
+            }
+        }
+        for chunk in self.chunker.split(msg) {
+            let frame = compress(conn.codec, &chunk);
+            if let Some(metrics) = &mut self.metrics {
+                metrics.compressed(chunk.len(), frame.len());
+            }
+            if write_raw(&mut conn.stream, &frame).is_err() {
+                self.conns.remove(&to);
+                return;
+            }
+        }
+    }
+
+    /// Times a round trip to node `to`, waiting up to `timeout` for it.
+    /// Returns `None` if the node can't be reached in time.
+    pub fn probe(&mut self, to: NodeId, timeout: Duration) -> Option<Duration> {
+        if !self.connect(to) {
+            return None;
+        }
+        let conn = self.conns.get_mut(&to).unwrap();
+        let start = Instant::now();
+        let pong = conn.stream.set_read_timeout(Some(timeout)).and_then(|_| {
+            write_raw(&mut conn.stream, &[])?;
+            read_raw(&mut conn.stream)
+        });
+        let _ = conn.stream.set_read_timeout(None);
+        match pong {
+            Ok(pong) if pong.is_empty() => {
+                let rtt = start.elapsed();
+                self.latencies.observe(to, rtt.as_micros() as u64);
+                Some(rtt)
+            }
+            // A late pong would leave the stream out of step: drop it.
+            _ => {
+                self.conns.remove(&to);
+                None
+            }
+        }
+    }
+
+    /// The round-trip times measured to the nodes, e.g. to hand to the
+    /// `Proposer`.
+    pub fn latencies(&self) -> &PeerLatencies {
+        &self.latencies
+    }
+
+    /// Opens a connection to node `to` unless there's one already. Returns
+    /// whether there is.
+    fn connect(&mut self, to: NodeId) -> bool {
+        let addr = match self.peers.get(&to) {
+            Some(addr) => *addr,
+            None => return false,
+        };
+        if let Entry::Vacant(entry) = self.conns.entry(to) {
+            match Conn::open(addr, &self.compression) {
+                Ok((conn, rtt)) => {
+                    self.latencies.observe(to, rtt.as_micros() as u64);
+                    entry.insert(conn);
+                }
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Sends `msg` to every node, this one included, through relays if a
+    /// `Topology` is set.
+    pub fn broadcast(&mut self, msg: &Message<T>) {
+        let ids: Vec<NodeId> = self.peers.keys().copied().collect();
+        let plan = match &*self.topology() {
+            Some(topology) => topology.plan_broadcast(self.id, &ids),
+            None => BroadcastPlan {
+                direct: ids,
+                relayed: BTreeMap::new(),
+            },
+        };
+        for to in plan.direct {
+            self.send(to, msg);
+        }
+        for (relay, forward_to) in plan.relayed {
+            self.send_relayed(relay, &forward_to, msg);
+        }
+    }
+
+    /// Waits up to `timeout` for a message from any node, forwarding it to
+    /// the peers it is to be relayed to.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Message<T>> {
+        let (msg, forward_to) = self.inbox.recv_timeout(timeout).ok()?;
+        for to in forward_to {
+            self.send(to, &msg);
+        }
+        Some(msg)
+    }
+}
+
+/// What the connections accepted need to relay broadcasts.
+#[derive(Clone)]
+struct Relay {
+    /// The node's ID
+    id: NodeId,
+    /// Every node, this one included
+    peers: Vec<NodeId>,
+    /// Where the nodes are deployed, once set
+    topology: Arc<Mutex<Option<Topology>>>,
+}
+
+impl Relay {
+    /// Whether broadcasts are relayed, as a `Topology` is set.
+    fn relays(&self) -> bool {
+        self.topology
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+
+    /// The peers to forward the next message to, out of those listed by a
+    /// `RELAY` frame: the others of the node's zone, each once. Returns
+    /// `None` if the frame is malformed, or lists more IDs than there are
+    /// peers.
+    fn forward_to(&self, ids: &[u8]) -> Option<Vec<NodeId>> {
+        if !ids.len().is_multiple_of(8) || ids.len() / 8 > self.peers.len() {
+            return None;
+        }
+        let topology = self.topology.lock().unwrap_or_else(PoisonError::into_inner);
+        let topology = topology.as_ref()?;
+        let mut forward_to: Vec<NodeId> = ids
+            .chunks(8)
+            .map(|id| u64::from_be_bytes(id.try_into().unwrap()))
+            .filter(|to| *to != self.id && self.peers.contains(to))
+            .filter(|to| topology.same_zone(self.id, *to))
+            .collect();
+        forward_to.sort_unstable();
+        forward_to.dedup();
+        Some(forward_to)
+    }
+}
+
+fn accept<T>(listener: TcpListener, sender: Sender<(Message<T>, Vec<NodeId>)>, relay: Relay)
+where
+    T: for<'a> From<&'a [u8]> + Send + Sync + 'static,
+{
+    for stream in listener.incoming().flatten() {
+        let sender = sender.clone();
+        let relay = relay.clone();
+        thread::spawn(move || {
+            let mut stream = stream;
+            let offer = match read_raw(&mut stream) {
+                Ok(offer) => offer,
+                Err(_) => return,
+            };
+            let mut reply = vec![negotiate(&offer).tag()];
+            // Peers that didn't offer to relay would take the tag for a codec.
+            let relays = offer.contains(&RELAY) && relay.relays();
+            if relays {
+                reply.push(RELAY);
+            }
+            if write_raw(&mut stream, &reply).is_err() {
+                return;
+            }
+            let mut chunks = Reassembler::default();
+            let mut forward_to = Vec::new();
+            // A malformed frame leaves the stream out of step: drop it.
+            while let Ok(frame) = read_raw(&mut stream) {
+                match frame.split_first() {
+                    None => {
+                        // A probe: echo it.
+                        if write_raw(&mut stream, &[]).is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    Some((&RELAY, ids)) => {
+                        forward_to = match relay.forward_to(ids) {
+                            Some(forward_to) if relays => forward_to,
+                            _ => return,
+                        };
+                        continue;
+                    }
+                    Some(_) => {}
+                }
+                let msg = match decompress(&frame).and_then(|chunk| chunks.receive(&chunk)) {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => continue,
+                    Err(_) => return,
+                };
+                if sender
+                    .send((msg, core::mem::take(&mut forward_to)))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk;
+    use crate::message::{AcceptData, JoinData, ProposalData};
+    use alloc::string::String;
+    use alloc::sync::Arc;
+
+    #[test]
+    fn tcp_frames() {
+        let prepare: Message<Vec<u8>> = Message::Prepare(ProposalData {
+            slot: 1,
+            id: 2,
+            from: 1,
+        });
+        let join = Message::Join(JoinData { from: 3 });
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &prepare).unwrap();
+        write_frame(&mut buf, &join).unwrap();
+
+        let mut reader = buf.as_slice();
+
+        assert_eq!(read_frame::<Vec<u8>, _>(&mut reader).unwrap(), prepare);
+        assert_eq!(read_frame::<Vec<u8>, _>(&mut reader).unwrap(), join);
+        assert!(read_frame::<Vec<u8>, _>(&mut reader).is_err());
+
+        let malformed = [0, 0, 0, 1, 0xff];
+
+        assert_eq!(
+            read_frame::<Vec<u8>, _>(&mut malformed.as_slice())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn tcp_transport() {
+        // Bind to free ports first, then hand the addresses over.
+        let addrs: Vec<SocketAddr> = (0..2)
+            .map(|_| {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                listener.local_addr().unwrap()
+            })
+            .collect();
+        let peers: BTreeMap<NodeId, SocketAddr> = [(1, addrs[0]), (2, addrs[1])].into();
+        let mut a: TcpTransport<Vec<u8>> = TcpTransport::bind(1, peers.clone()).unwrap();
+        let mut b: TcpTransport<Vec<u8>> = TcpTransport::bind(2, peers).unwrap();
+        let msg = Message::Join(JoinData { from: 1 });
+
+        a.broadcast(&msg);
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(a.recv_timeout(timeout), Some(msg.clone()));
+        assert_eq!(b.recv_timeout(timeout), Some(msg));
+        assert_eq!(a.id(), 1);
+        assert!(a.probe(2, timeout).is_some());
+        assert!(a.latencies().rtt(2).is_some());
+
+        // A value spanning many frames.
+        a.set_max_chunk(16);
+        let large = Message::Accept(AcceptData {
+            slot: 0,
+            id: 1,
+            value: Arc::new(vec![7; 1000]),
+            implicit_prepare: false,
+        });
+        a.send(2, &large);
+        assert_eq!(b.recv_timeout(timeout), Some(large.clone()));
+
+        // Compressed with whichever codec was negotiated.
+        a.set_max_chunk(chunk::MAX_CHUNK);
+        a.set_compression(Compression::supported());
+        a.send(2, &large);
+        assert_eq!(b.recv_timeout(timeout), Some(large));
+    }
+
+    #[test]
+    fn tcp_relay() {
+        let addrs: Vec<SocketAddr> = (0..3)
+            .map(|_| {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                listener.local_addr().unwrap()
+            })
+            .collect();
+        let peers: BTreeMap<NodeId, SocketAddr> =
+            [(1, addrs[0]), (2, addrs[1]), (3, addrs[2])].into();
+        let mut transports: Vec<TcpTransport<Vec<u8>>> = (1..=3)
+            .map(|id| TcpTransport::bind(id, peers.clone()).unwrap())
+            .collect();
+        let zones: BTreeMap<NodeId, String> = [(1, "us"), (2, "eu"), (3, "eu")]
+            .into_iter()
+            .map(|(id, zone)| (id, zone.into()))
+            .collect();
+        transports[0].set_topology(Topology::new(zones.clone()));
+        let msg = Message::Join(JoinData { from: 1 });
+
+        // Node 2 doesn't relay without a topology: node 3 is sent to directly.
+        transports[0].broadcast(&msg);
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(transports[0].recv_timeout(timeout), Some(msg.clone()));
+        assert_eq!(transports[2].recv_timeout(timeout), Some(msg.clone()));
+        assert_eq!(transports[1].recv_timeout(timeout), Some(msg.clone()));
+
+        // Node 3 only gets the message once node 2 relays it.
+        transports[1].set_topology(Topology::new(zones));
+        transports[0].conns.clear();
+        transports[0].broadcast(&msg);
+
+        assert_eq!(transports[0].recv_timeout(timeout), Some(msg.clone()));
+        assert_eq!(transports[2].recv_timeout(Duration::from_millis(100)), None);
+        assert_eq!(transports[1].recv_timeout(timeout), Some(msg.clone()));
+        assert_eq!(transports[2].recv_timeout(timeout), Some(msg));
+    }
+
+    #[test]
+    fn tcp_relay_forward_to() {
+        let zones = [(1, "us"), (2, "eu"), (3, "eu"), (4, "eu")]
+            .into_iter()
+            .map(|(id, zone)| (id, zone.into()))
+            .collect();
+        let relay = Relay {
+            id: 2,
+            peers: vec![1, 2, 3, 4],
+            topology: Arc::new(Mutex::new(Some(Topology::new(zones)))),
+        };
+        let frame =
+            |ids: &[NodeId]| -> Vec<u8> { ids.iter().flat_map(|id| id.to_be_bytes()).collect() };
+
+        // Other zones, the relay itself and unknown nodes are left out, and
+        // duplicates forwarded to once.
+        assert_eq!(relay.forward_to(&frame(&[4, 1, 9, 4])), Some(vec![4]));
+        assert_eq!(relay.forward_to(&frame(&[3, 2, 4, 3])), Some(vec![3, 4]));
+
+        // No more IDs than there are peers.
+        assert_eq!(relay.forward_to(&frame(&[3; 5])), None);
+        assert_eq!(relay.forward_to(&[0; 7]), None);
+    }
+}
@@ -0,0 +1,254 @@
+//! Testing
+//!
+//! Tools for end-to-end tests of a replicated state machine. A `History`
+//! records the operations clients run against it, each from the moment it is
+//! called to the moment it returns, and `linearize` checks that the history is
+//! linearizable: that every operation appears to take effect at once,
+//! somewhere between its call and its return, in an order a sequential `Spec`
+//! agrees with.
+//!
+//! The checker searches the orders operations may take effect in, as described
+//! by Wing & Gong, and skips states reached before by another order, as
+//! described by Lowe. Histories of a few hundred operations are checked
+//! quickly, unless most of them are concurrent.
+
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The sequential specification of a state machine.
+pub trait Spec {
+    /// The state of the machine
+    type State: Clone + Ord;
+    /// An operation run against the machine
+    type Input;
+    /// What an operation returns
+    type Output: PartialEq;
+
+    /// The initial state.
+    fn init(&self) -> Self::State;
+
+    /// Runs `input` against `state`, and returns its output.
+    fn step(&self, state: &mut Self::State, input: &Self::Input) -> Self::Output;
+}
+
+/// An operation run by a client.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Operation<I, O> {
+    /// The client running the operation
+    pub client: u64,
+    /// The operation
+    pub input: I,
+    /// What the operation returned, if it did
+    pub output: Option<O>,
+    /// When the operation was called
+    pub call: u64,
+    /// When the operation returned, if it did
+    pub ret: Option<u64>,
+}
+
+/// The operations clients ran, with the order of their calls and returns.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct History<I, O> {
+    /// The operations, in the order they were called
+    ops: Vec<Operation<I, O>>,
+    /// Number of calls and returns recorded so far
+    clock: u64,
+}
+
+impl<I, O> Default for History<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> History<I, O> {
+    /// Creates an empty `History`.
+    pub fn new() -> Self {
+        Self {
+            ops: Vec::new(),
+            clock: 0,
+        }
+    }
+
+    /// Records that `client` called `input`, and returns the operation's index
+    /// to record its return with.
+    pub fn call(&mut self, client: u64, input: I) -> usize {
+        let call = self.tick();
+        self.ops.push(Operation {
+            client,
+            input,
+            output: None,
+            call,
+            ret: None,
+        });
+        self.ops.len() - 1
+    }
+
+    /// Records that operation `op` returned `output`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `op` already returned.
+    pub fn ret(&mut self, op: usize, output: O) {
+        assert!(self.ops[op].ret.is_none(), "operation already returned");
+        let ret = self.tick();
+        let op = &mut self.ops[op];
+        op.output = Some(output);
+        op.ret = Some(ret);
+    }
+
+    /// The operations recorded, in the order they were called. Operations
+    /// which never returned may or may not have taken effect.
+    pub fn operations(&self) -> &[Operation<I, O>] {
+        &self.ops
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+/// Returns an order the operations of `history` may have taken effect in,
+/// according to `spec`, or `None` if the history isn't linearizable. The
+/// order lists operations by index. Operations which never returned are only
+/// listed if the order has them take effect.
+pub fn linearize<S: Spec>(spec: &S, history: &History<S::Input, S::Output>) -> Option<Vec<usize>> {
+    let mut search = Search {
+        spec,
+        ops: &history.ops,
+        done: vec![false; history.ops.len()],
+        order: Vec::new(),
+        seen: BTreeSet::new(),
+    };
+    if search.run(spec.init()) {
+        Some(search.order)
+    } else {
+        None
+    }
+}
+
+/// Whether `history` is linearizable, according to `spec`.
+pub fn is_linearizable<S: Spec>(spec: &S, history: &History<S::Input, S::Output>) -> bool {
+    linearize(spec, history).is_some()
+}
+
+struct Search<'a, S: Spec> {
+    spec: &'a S,
+    ops: &'a [Operation<S::Input, S::Output>],
+    /// Whether each operation took effect already
+    done: Vec<bool>,
+    /// Operations which took effect, in order
+    order: Vec<usize>,
+    /// Operations done and the resulting state of every order tried so far
+    seen: BTreeSet<(Vec<bool>, S::State)>,
+}
+
+impl<S: Spec> Search<'_, S> {
+    /// Tries every operation which may take effect next from `state`,
+    /// backtracking from those which lead nowhere.
+    fn run(&mut self, state: S::State) -> bool {
+        // Only operations called before every other one left returned may
+        // take effect next.
+        let deadline = (0..self.ops.len())
+            .filter(|&i| !self.done[i])
+            .filter_map(|i| self.ops[i].ret)
+            .min();
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            // Only operations which never returned are left.
+            None => return true,
+        };
+
+        for i in 0..self.ops.len() {
+            let op = &self.ops[i];
+            if self.done[i] || op.call > deadline {
+                continue;
+            }
+            let mut next = state.clone();
+            let output = self.spec.step(&mut next, &op.input);
+            if op.output.as_ref().is_some_and(|o| *o != output) {
+                continue;
+            }
+            self.done[i] = true;
+            if self.seen.insert((self.done.clone(), next.clone())) {
+                self.order.push(i);
+                if self.run(next) {
+                    return true;
+                }
+                self.order.pop();
+            }
+            self.done[i] = false;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A register holding a single value.
+    struct Register;
+
+    #[derive(Debug)]
+    enum Op {
+        Write(u64),
+        Read,
+    }
+
+    impl Spec for Register {
+        type State = u64;
+        type Input = Op;
+        type Output = u64;
+
+        fn init(&self) -> u64 {
+            0
+        }
+
+        fn step(&self, state: &mut u64, input: &Op) -> u64 {
+            if let Op::Write(value) = *input {
+                *state = value;
+            }
+            *state
+        }
+    }
+
+    #[test]
+    fn testing_concurrent() {
+        let mut history = History::new();
+        let write = history.call(1, Op::Write(1));
+        let read = history.call(2, Op::Read);
+        history.ret(read, 1);
+        history.ret(write, 1);
+
+        assert_eq!(linearize(&Register, &history), Some(vec![0, 1]));
+    }
+
+    #[test]
+    fn testing_stale_read() {
+        let mut history = History::new();
+        let write = history.call(1, Op::Write(1));
+        history.ret(write, 1);
+        let read = history.call(2, Op::Read);
+        history.ret(read, 0);
+
+        assert!(!is_linearizable(&Register, &history));
+    }
+
+    #[test]
+    fn testing_pending() {
+        let mut history = History::new();
+        history.call(1, Op::Write(1));
+        history.call(1, Op::Write(2));
+        let read = history.call(2, Op::Read);
+        history.ret(read, 2);
+
+        // The second write must have taken effect before the read.
+        let order = linearize(&Register, &history).unwrap();
+
+        assert!(order.ends_with(&[1, 2]));
+        assert_eq!(history.operations()[0].ret, None);
+    }
+}
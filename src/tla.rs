@@ -0,0 +1,446 @@
+//! TLA+ trace conformance
+//!
+//! Checks that the roles take the steps of single-decree Paxos as specified
+//! in TLA+ by `tla/Paxos.tla`, a transcription of Lamport's canonical spec.
+//! A `TlaTrace` watches the messages a cluster's `Node`s send for a slot,
+//! each of which is a step of the spec: `Prepare` is `Phase1a`, `Promise`
+//! `Phase1b`, `Accept` `Phase2a` and `Accepted` `Phase2b`. After the steps
+//! of `Acceptor`s, it also notes their variables, `maxBal`, `maxVBal` and
+//! `maxVal`, as read from `Acceptor::current_ballot` and
+//! `Acceptor::accepted`.
+//!
+//! `TlaTrace::check` replays the steps against the spec's guards and
+//! updates, so that tests catch a refactor of the `proposer` or `acceptor`
+//! that strays from the model. `TlaTrace::to_module` renders the trace as a
+//! TLA+ module, `PaxosTraceLog`, which `tla/PaxosTrace.tla` has TLC follow
+//! through the spec itself, checking `Consistency` along the way:
+//!
+//! ```text
+//! java -cp tla2tools.jar tlc2.TLC -config PaxosTrace.cfg PaxosTrace
+//! ```
+//!
+//! Only classic rounds are covered: implicit prepares, fast rounds and
+//! Mencius skips have no counterpart in the spec. The `Acceptor`'s promise
+//! is shared by every slot, so the trace of a slot only holds if no `Prepare`
+//! for another slot raises it meanwhile. A message sent again, e.g. on
+//! retry, is a stuttering step.
+
+use crate::config::NodeId;
+use crate::effect::Effect;
+use crate::message::{Message, Messenger, Slot};
+use crate::node::Node;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// A step of the spec, named after its action, and identified by the message
+/// it sends. Values are written as their bytes in hex.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Action {
+    Phase1a {
+        bal: u64,
+    },
+    Phase1b {
+        acc: NodeId,
+        bal: u64,
+        mbal: Option<u64>,
+        mval: Option<String>,
+    },
+    Phase2a {
+        bal: u64,
+        val: String,
+    },
+    Phase2b {
+        acc: NodeId,
+        bal: u64,
+        val: String,
+    },
+}
+
+/// An `Acceptor`'s variables in the spec.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AcceptorVars {
+    pub max_bal: Option<u64>,
+    pub max_vbal: Option<u64>,
+    pub max_val: Option<String>,
+}
+
+/// A step taken, along with the variables of the `Acceptor` that took it,
+/// if any, as of after the step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub action: Action,
+    pub vars: Option<AcceptorVars>,
+}
+
+/// A step of the trace the spec doesn't allow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Index of the step in the trace
+    pub index: usize,
+    pub action: Action,
+    /// The guard or update that doesn't hold
+    pub reason: &'static str,
+}
+
+/// The steps of the spec taken for a slot. See the module documentation.
+#[derive(Debug, Clone)]
+pub struct TlaTrace {
+    slot: Slot,
+    /// The `Acceptor`s, any majority of which is a quorum
+    acceptors: Vec<NodeId>,
+    transitions: Vec<Transition>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(out, "{byte:02x}");
+    }
+    out
+}
+
+impl TlaTrace {
+    /// Creates an empty trace of `slot`, among `acceptors`.
+    pub fn new(slot: Slot, mut acceptors: Vec<NodeId>) -> Self {
+        acceptors.sort_unstable();
+        acceptors.dedup();
+        Self {
+            slot,
+            acceptors,
+            transitions: Vec::new(),
+        }
+    }
+
+    /// The steps recorded so far.
+    pub fn transitions(&self) -> &[Transition] {
+        &self.transitions
+    }
+
+    /// Records the steps `node` took, as the messages among `effects`, which
+    /// it just produced.
+    pub fn observe<T, M>(&mut self, node: &Node<T, M>, effects: &[Effect<T>])
+    where
+        T: PartialEq + Clone + AsRef<[u8]>,
+        M: Messenger<T>,
+    {
+        for effect in effects {
+            let (Effect::SendMessage(msg) | Effect::SendMessageTo(_, msg)) = effect else {
+                continue;
+            };
+            let action = match msg {
+                Message::Prepare(data) if data.slot == self.slot => {
+                    Action::Phase1a { bal: data.id }
+                }
+                Message::Promise(data) if data.slot == self.slot => Action::Phase1b {
+                    acc: data.from,
+                    bal: data.id,
+                    mbal: data.accepted_n,
+                    mval: data.value.as_ref().map(|v| hex((**v).as_ref())),
+                },
+                Message::Accept(data) if data.slot == self.slot => Action::Phase2a {
+                    bal: data.id,
+                    val: hex((*data.value).as_ref()),
+                },
+                Message::Accepted(data) if data.slot == self.slot => Action::Phase2b {
+                    acc: data.from,
+                    bal: data.id,
+                    val: hex((*data.value).as_ref()),
+                },
+                _ => continue,
+            };
+            let vars = match action {
+                Action::Phase1b { .. } | Action::Phase2b { .. } => {
+                    let accepted = node.acceptor().accepted(self.slot);
+                    Some(AcceptorVars {
+                        max_bal: Some(node.acceptor().current_ballot()),
+                        max_vbal: accepted.map(|a| a.n),
+                        max_val: accepted.map(|a| hex((*a.value).as_ref())),
+                    })
+                }
+                _ => None,
+            };
+            self.transitions.push(Transition { action, vars });
+        }
+    }
+
+    /// Whether `quorum` is a majority of the `Acceptor`s.
+    fn is_quorum(&self, quorum: &BTreeSet<NodeId>) -> bool {
+        2 * quorum.len() > self.acceptors.len()
+    }
+
+    /// Whether `Phase2a(bal, val)` is enabled by the `Phase1b` messages of
+    /// `msgs`: some quorum promised `bal`, and `val` is the value of the
+    /// highest ballot any of them voted in, if any.
+    fn phase2a_enabled(&self, msgs: &BTreeSet<Action>, bal: u64, val: &str) -> bool {
+        let promises: Vec<(NodeId, Option<u64>, Option<&String>)> = msgs
+            .iter()
+            .filter_map(|m| match m {
+                Action::Phase1b {
+                    acc,
+                    bal: b,
+                    mbal,
+                    mval,
+                } if *b == bal => Some((*acc, *mbal, mval.as_ref())),
+                _ => None,
+            })
+            .collect();
+        let promised: Vec<NodeId> = promises
+            .iter()
+            .map(|(acc, ..)| *acc)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        // Every set of the `Acceptor`s that promised, as a bitmask: clusters
+        // are small.
+        (0u64..1 << promised.len()).any(|mask| {
+            let quorum: BTreeSet<NodeId> = (0..promised.len())
+                .filter(|i| mask & 1 << i != 0)
+                .map(|i| promised[i])
+                .collect();
+            if !self.is_quorum(&quorum) {
+                return false;
+            }
+            let votes = promises
+                .iter()
+                .filter(|(acc, mbal, _)| quorum.contains(acc) && mbal.is_some());
+            match votes.clone().map(|(_, mbal, _)| *mbal).max() {
+                None => true,
+                Some(max) => votes
+                    .into_iter()
+                    .any(|(_, mbal, mval)| *mbal == max && mval.map(String::as_str) == Some(val)),
+            }
+        })
+    }
+
+    /// Replays the trace against the spec, returning the first step it
+    /// doesn't allow, if any.
+    pub fn check(&self) -> Result<(), Violation> {
+        let mut vars: BTreeMap<NodeId, AcceptorVars> = BTreeMap::new();
+        let mut msgs: BTreeSet<Action> = BTreeSet::new();
+        for (index, transition) in self.transitions.iter().enumerate() {
+            let action = &transition.action;
+            let violation = |reason| Violation {
+                index,
+                action: action.clone(),
+                reason,
+            };
+            if !msgs.contains(action) {
+                match action {
+                    Action::Phase1a { .. } => {}
+                    Action::Phase1b {
+                        acc,
+                        bal,
+                        mbal,
+                        mval,
+                    } => {
+                        let a = vars.entry(*acc).or_default();
+                        if !msgs.contains(&Action::Phase1a { bal: *bal }) {
+                            return Err(violation("no 1a message for the ballot"));
+                        }
+                        if a.max_bal > Some(*bal) {
+                            return Err(violation("ballot below maxBal"));
+                        }
+                        if (mbal, mval) != (&a.max_vbal, &a.max_val) {
+                            return Err(violation("vote reported isn't maxVBal, maxVal"));
+                        }
+                        a.max_bal = Some(*bal);
+                    }
+                    Action::Phase2a { bal, val } => {
+                        if msgs
+                            .iter()
+                            .any(|m| matches!(m, Action::Phase2a { bal: b, .. } if b == bal))
+                        {
+                            return Err(violation("second 2a message for the ballot"));
+                        }
+                        if !self.phase2a_enabled(&msgs, *bal, val) {
+                            return Err(violation("no quorum of 1b messages allows the value"));
+                        }
+                    }
+                    Action::Phase2b { acc, bal, val } => {
+                        let a = vars.entry(*acc).or_default();
+                        let phase2a = Action::Phase2a {
+                            bal: *bal,
+                            val: val.clone(),
+                        };
+                        if !msgs.contains(&phase2a) {
+                            return Err(violation("no 2a message for the vote"));
+                        }
+                        if a.max_bal > Some(*bal) {
+                            return Err(violation("ballot below maxBal"));
+                        }
+                        a.max_bal = Some(*bal);
+                        a.max_vbal = Some(*bal);
+                        a.max_val = Some(val.clone());
+                    }
+                }
+                msgs.insert(action.clone());
+            }
+            if let (Some(noted), Action::Phase1b { acc, .. } | Action::Phase2b { acc, .. }) =
+                (&transition.vars, action)
+            {
+                if vars.get(acc) != Some(noted) {
+                    return Err(violation("acceptor's variables differ from the spec's"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the trace as the TLA+ module `PaxosTraceLog`, which
+    /// `tla/PaxosTrace.tla` checks against the spec. Missing ballots are
+    /// written as `-1`, and missing values as `"none"`.
+    pub fn to_module(&self) -> String {
+        fn ballot(b: Option<u64>) -> String {
+            b.map_or_else(|| String::from("-1"), |b| alloc::format!("{b}"))
+        }
+        fn value(v: Option<&String>) -> String {
+            alloc::format!("\"{}\"", v.map_or("none", String::as_str))
+        }
+
+        let mut ballots = BTreeSet::new();
+        let mut values = BTreeSet::new();
+        let mut log = Vec::new();
+        for transition in &self.transitions {
+            let (name, acc, bal, mbal, val) = match &transition.action {
+                Action::Phase1a { bal } => ("Phase1a", None, *bal, None, None),
+                Action::Phase1b {
+                    acc,
+                    bal,
+                    mbal,
+                    mval,
+                } => ("Phase1b", Some(*acc), *bal, *mbal, mval.as_ref()),
+                Action::Phase2a { bal, val } => ("Phase2a", None, *bal, None, Some(val)),
+                Action::Phase2b { acc, bal, val } => ("Phase2b", Some(*acc), *bal, None, Some(val)),
+            };
+            ballots.insert(bal);
+            values.extend(val.cloned());
+            let vars = transition.vars.clone().unwrap_or_default();
+            log.push(alloc::format!(
+                "[action |-> \"{name}\", acc |-> {}, bal |-> {bal}, mbal |-> {}, val |-> {}, \
+                 maxBal |-> {}, maxVBal |-> {}, maxVal |-> {}]",
+                acc.map_or(-1, |a| a as i64),
+                ballot(mbal),
+                value(val),
+                ballot(vars.max_bal),
+                ballot(vars.max_vbal),
+                value(vars.max_val.as_ref()),
+            ));
+        }
+
+        let set = |items: Vec<String>| alloc::format!("{{{}}}", items.join(", "));
+        let mut out = String::from("---- MODULE PaxosTraceLog ----\nEXTENDS Integers\n");
+        let _ = writeln!(
+            out,
+            "TraceAcceptor == {}",
+            set(self
+                .acceptors
+                .iter()
+                .map(|a| alloc::format!("{a}"))
+                .collect())
+        );
+        let _ = writeln!(
+            out,
+            "TraceBallot == {}",
+            set(ballots.iter().map(|b| alloc::format!("{b}")).collect())
+        );
+        let _ = writeln!(
+            out,
+            "TraceValue == {}",
+            set(values.iter().map(|v| value(Some(v))).collect())
+        );
+        let _ = writeln!(out, "TraceLog == <<\n    {}\n>>", log.join(",\n    "));
+        out.push_str("====\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use alloc::vec;
+
+    /// Runs a cluster of three nodes in which nodes 1 and 2 compete for slot
+    /// 0, delivering, duplicating and dropping messages in an order drawn
+    /// from `seed`, and timing out now and then.
+    fn run(seed: u64) -> TlaTrace {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut nodes: Vec<Node<Vec<u8>>> =
+            (1..=3).map(|id| Node::new(id, config.clone())).collect();
+        let mut trace = TlaTrace::new(0, vec![1, 2, 3]);
+        let mut rng = seed;
+        let mut next = move |n: usize| {
+            rng = rng
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (rng >> 33) as usize % n
+        };
+
+        // Messages in flight, along with the index of their recipient
+        let mut pool = Vec::new();
+        let send = |pool: &mut Vec<_>, effects: Vec<Effect<Vec<u8>>>| {
+            for effect in effects {
+                for to in 0..3 {
+                    if let Some(msg) = effect.message_for(to as NodeId + 1) {
+                        pool.push((msg.clone(), to));
+                    }
+                }
+            }
+        };
+        for (i, value) in [(0, b"x"), (1, b"y")] {
+            nodes[i].propose(value.to_vec());
+            let effects = nodes[i].take_effects();
+            trace.observe(&nodes[i], &effects);
+            send(&mut pool, effects);
+        }
+        for now in 1..1000 {
+            let (i, effects) = if pool.is_empty() || next(50) == 0 {
+                let i = next(3);
+                nodes[i].tick(now * 1000);
+                (i, nodes[i].take_effects())
+            } else {
+                let (msg, to) = pool.swap_remove(next(pool.len()));
+                match next(10) {
+                    0 => continue,
+                    1 => pool.push((msg.clone(), to)),
+                    _ => {}
+                }
+                (to, nodes[to].step(msg))
+            };
+            trace.observe(&nodes[i], &effects);
+            send(&mut pool, effects);
+        }
+        trace
+    }
+
+    #[test]
+    fn tla_trace_conforms() {
+        for seed in 0..50 {
+            let trace = run(seed);
+            assert_eq!(trace.check(), Ok(()), "seed {seed}");
+        }
+
+        // A vote for a value nobody proposed strays from the spec.
+        let mut trace = run(0);
+        let accepted = trace
+            .transitions
+            .iter()
+            .position(|t| matches!(t.action, Action::Phase2b { .. }))
+            .unwrap();
+        if let Action::Phase2b { val, .. } = &mut trace.transitions[accepted].action {
+            *val = hex(b"z");
+        }
+        assert_eq!(
+            trace.check().map_err(|v| (v.index, v.reason)),
+            Err((accepted, "no 2a message for the vote"))
+        );
+
+        // With `PAXOS_TLA_DIR` set, the trace is written there for TLC.
+        if let Some(dir) = std::env::var_os("PAXOS_TLA_DIR") {
+            let path = std::path::Path::new(&dir).join("PaxosTraceLog.tla");
+            std::fs::write(path, run(0).to_module()).unwrap();
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Topology
+
+use crate::config::NodeId;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Where each node is deployed, so that traffic can favor nearby peers.
+///
+/// Nodes without a zone tag are treated as remote to everyone, and are always
+/// sent to directly.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Topology {
+    /// Node => zone it is deployed in
+    pub zones: BTreeMap<NodeId, String>,
+    /// Whether broadcasts to a remote zone go through a single peer there,
+    /// which forwards them to the rest of its zone
+    pub relay_broadcasts: bool,
+    /// Whether reads prefer replicas in the reader's zone
+    pub prefer_local_reads: bool,
+}
+
+/// A broadcast split into direct sends and relayed ones.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct BroadcastPlan {
+    /// Peers sent to directly, without further forwarding
+    pub direct: Vec<NodeId>,
+    /// Relay => peers of its zone it forwards the message to
+    pub relayed: BTreeMap<NodeId, Vec<NodeId>>,
+}
+
+impl Topology {
+    /// Creates a new `Topology`, with relaying and local reads enabled.
+    pub fn new(zones: BTreeMap<NodeId, String>) -> Self {
+        Self {
+            zones,
+            relay_broadcasts: true,
+            prefer_local_reads: true,
+        }
+    }
+
+    /// The zone `id` is deployed in, if tagged.
+    pub fn zone(&self, id: NodeId) -> Option<&str> {
+        self.zones.get(&id).map(String::as_str)
+    }
+
+    /// Whether `a` and `b` are deployed in the same zone.
+    pub fn same_zone(&self, a: NodeId, b: NodeId) -> bool {
+        match (self.zone(a), self.zone(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Plans a broadcast from `from` to `peers`. Peers of the sender's zone
+    /// are sent to directly, as is a single relay (the lowest ID) in every
+    /// remote zone, which forwards to the others there.
+    pub fn plan_broadcast(&self, from: NodeId, peers: &[NodeId]) -> BroadcastPlan {
+        let mut plan = BroadcastPlan::default();
+        let mut remote: BTreeMap<&str, Vec<NodeId>> = BTreeMap::new();
+        for peer in peers.iter().copied() {
+            match self.zone(peer) {
+                Some(zone) if self.relay_broadcasts && !self.same_zone(from, peer) => {
+                    remote.entry(zone).or_default().push(peer)
+                }
+                _ => plan.direct.push(peer),
+            }
+        }
+        for (_, mut zone_peers) in remote {
+            zone_peers.sort_unstable();
+            let relay = zone_peers.remove(0);
+            plan.relayed.insert(relay, zone_peers);
+        }
+        plan
+    }
+
+    /// Orders `replicas` to read from, those in the reader's zone first. The
+    /// relative order is otherwise kept.
+    pub fn read_order(&self, reader: NodeId, replicas: &[NodeId]) -> Vec<NodeId> {
+        let mut ordered = replicas.to_vec();
+        if self.prefer_local_reads {
+            ordered.sort_by_key(|r| !self.same_zone(reader, *r));
+        }
+        ordered
+    }
+}
+
+#[cfg(feature = "std")]
+pub use zoned::{ZonedEnvelope, ZonedMessenger};
+
+#[cfg(feature = "std")]
+mod zoned {
+    use super::*;
+    use crate::message::{Message, Messenger, Slot};
+    use alloc::sync::Arc;
+    use std::sync::mpsc::Sender;
+
+    /// A message along with the peers it is to be forwarded to by its
+    /// receiver.
+    #[derive(Debug, Clone)]
+    pub struct ZonedEnvelope<T> {
+        /// The message itself
+        pub msg: Message<T>,
+        /// Peers the receiver relays the message to, if any
+        pub forward_to: Vec<NodeId>,
+    }
+
+    /// A `Messenger` aware of the `Topology` of the cluster. Broadcasts to
+    /// remote zones go through a single relay there, saving WAN bandwidth.
+    pub struct ZonedMessenger<T> {
+        /// ID of the node sending messages
+        pub id: NodeId,
+        /// Where each peer is deployed
+        pub topology: Topology,
+        /// Channel of each peer
+        pub peers: BTreeMap<NodeId, Sender<ZonedEnvelope<T>>>,
+        /// Channel notified of every resolved proposal
+        pub resolutions: Option<Sender<(Slot, Arc<T>)>>,
+    }
+
+    impl<T: Clone> ZonedMessenger<T> {
+        /// Creates a new `ZonedMessenger`.
+        pub fn new(
+            id: NodeId,
+            topology: Topology,
+            peers: BTreeMap<NodeId, Sender<ZonedEnvelope<T>>>,
+        ) -> Self {
+            Self {
+                id,
+                topology,
+                peers,
+                resolutions: None,
+            }
+        }
+
+        /// Relays a received `envelope` to the peers listed in it, returning
+        /// the message for local delivery.
+        pub fn forward(&self, envelope: ZonedEnvelope<T>) -> Message<T> {
+            for peer in &envelope.forward_to {
+                self.send(*peer, envelope.msg.clone(), Vec::new());
+            }
+            envelope.msg
+        }
+
+        fn send(&self, peer: NodeId, msg: Message<T>, forward_to: Vec<NodeId>) {
+            if let Some(sender) = self.peers.get(&peer) {
+                let _ = sender.send(ZonedEnvelope { msg, forward_to });
+            }
+        }
+
+        fn broadcast(&self, msg: Message<T>) {
+            let peers: Vec<NodeId> = self.peers.keys().copied().collect();
+            let plan = self.topology.plan_broadcast(self.id, &peers);
+            for peer in plan.direct {
+                self.send(peer, msg.clone(), Vec::new());
+            }
+            for (relay, forward_to) in plan.relayed {
+                self.send(relay, msg.clone(), forward_to);
+            }
+        }
+    }
+
+    impl<T: Clone> Messenger<T> for ZonedMessenger<T> {
+        fn send_prepare(&mut self, msg: Message<T>) {
+            self.broadcast(msg);
+        }
+
+        fn send_promise(&mut self, msg: Message<T>) {
+            self.broadcast(msg);
+        }
+
+        fn send_accept(&mut self, msg: Message<T>) {
+            self.broadcast(msg);
+        }
+
+        fn send_accepted(&mut self, msg: Message<T>) {
+            self.broadcast(msg);
+        }
+
+        fn on_resolution(&mut self, slot: Slot, value: Arc<T>) {
+            if let Some(ref resolutions) = self.resolutions {
+                let _ = resolutions.send((slot, value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::message::{Message, Messenger, ProposalData};
+    use alloc::vec;
+
+    fn topology() -> Topology {
+        let zones = [(1, "us"), (2, "us"), (3, "eu"), (4, "eu"), (5, "eu")]
+            .into_iter()
+            .map(|(id, zone)| (id, String::from(zone)))
+            .collect();
+        Topology::new(zones)
+    }
+
+    #[test]
+    fn topology_plan_broadcast() {
+        let t = topology();
+
+        let plan = t.plan_broadcast(1, &[2, 3, 4, 5, 6]);
+
+        assert_eq!(plan.direct, vec![2, 6]);
+        assert_eq!(plan.relayed, [(3, vec![4, 5])].into_iter().collect());
+
+        let t = Topology {
+            relay_broadcasts: false,
+            ..t
+        };
+
+        assert_eq!(t.plan_broadcast(1, &[2, 3, 4]).direct, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn topology_read_order() {
+        let t = topology();
+
+        assert_eq!(t.read_order(4, &[1, 2, 3, 5]), vec![3, 5, 1, 2]);
+
+        let t = Topology {
+            prefer_local_reads: false,
+            ..t
+        };
+
+        assert_eq!(t.read_order(4, &[1, 2, 3, 5]), vec![1, 2, 3, 5]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn topology_zoned_messenger() {
+        let t = topology();
+        let (senders, receivers): (BTreeMap<_, _>, BTreeMap<_, _>) = (2..=5)
+            .map(|id| {
+                let (sender, receiver) = std::sync::mpsc::channel::<ZonedEnvelope<u64>>();
+                ((id, sender), (id, receiver))
+            })
+            .unzip();
+
+        let mut messenger = ZonedMessenger::new(1, t.clone(), senders.clone());
+        messenger.send_prepare(Message::Prepare(ProposalData {
+            slot: 0,
+            id: 1,
+            from: 1,
+        }));
+
+        // The WAN link to "eu" is only crossed once.
+        assert_eq!(receivers[&2].try_iter().count(), 1);
+        assert!(receivers[&4].try_recv().is_err());
+
+        let envelope = receivers[&3].try_recv().unwrap();
+
+        assert_eq!(envelope.forward_to, vec![4, 5]);
+
+        let relay = ZonedMessenger::new(3, t, senders);
+        let msg = relay.forward(envelope);
+
+        assert!(matches!(msg, Message::Prepare(_)));
+        assert_eq!(receivers[&4].try_recv().unwrap().msg, msg);
+        assert_eq!(receivers[&5].try_recv().unwrap().msg, msg);
+    }
+}
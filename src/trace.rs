@@ -0,0 +1,616 @@
+//! Tracing
+//!
+//! With the `tracing` feature, protocol handlers open
+//! [tracing](https://docs.rs/tracing) spans, and record events carrying the
+//! slot, ballot and sender concerned, along with quorum progress. Without it,
+//! these macros expand to nothing.
+//!
+//! With `std`, a `Recorder` runs a `Node` while writing down everything that
+//! goes in and out of it: the messages it receives and sends, the values
+//! proposed through it and the ticks of its clock, each stamped with its
+//! position in the trace. As the roles are deterministic, `Trace::replay`
+//! feeds the same inputs through a fresh `Node` to reproduce a production
+//! incident step by step, and reports where the messages it sends part from
+//! those recorded, if anywhere.
+//!
+//! The traces of the nodes of a run can be drawn together, each message
+//! received matched with its sending: `mermaid` renders the run as a
+//! sequence diagram, optionally narrowed to the messages of one slot, while
+//! `shiviz` logs it with vector clocks for exploring in ShiViz.
+
+/// Enters a debug span until the end of the enclosing block.
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($($args:tt)*) => {
+        let _span = tracing::debug_span!($($args)*).entered();
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($args:tt)*) => {};
+}
+
+/// Records a debug event in the current span.
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($($args:tt)*) => {
+        tracing::debug!($($args)*)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($($args:tt)*) => {};
+}
+
+#[cfg(feature = "std")]
+use crate::{
+    config::{ClusterConfig, NodeId},
+    effect::Effect,
+    message::{Message, Slot},
+    node::Node,
+    proposal::ProposalHandle,
+    wire::{decode, encode, put_bytes, put_u64, DecodeError, Reader},
+};
+#[cfg(feature = "std")]
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Write as _,
+    format,
+    io::{self, Read, Write},
+    string::String,
+    vec,
+    vec::Vec,
+};
+
+#[cfg(feature = "std")]
+const MAGIC: &[u8] = b"PAXT";
+#[cfg(feature = "std")]
+const VERSION: u8 = 1;
+
+#[cfg(feature = "std")]
+const RECEIVED: u8 = 0;
+#[cfg(feature = "std")]
+const SENT: u8 = 1;
+#[cfg(feature = "std")]
+const PROPOSED: u8 = 2;
+#[cfg(feature = "std")]
+const TICKED: u8 = 3;
+
+/// Something that happened to a traced `Node`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent<T> {
+    /// The node was handed a message
+    Received(Message<T>),
+    /// The node sent a message
+    Sent(Message<T>),
+    /// A value was proposed through the node
+    Proposed(T),
+    /// The node's clock was advanced to the given time
+    Ticked(u64),
+}
+
+/// A `TraceEvent`, along with its logical timestamp: its position among the
+/// events of the trace.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord<T> {
+    pub at: u64,
+    pub event: TraceEvent<T>,
+}
+
+/// Runs a `Node`, recording every message it receives and sends, along with
+/// the values proposed through it and the ticks of its clock, to a writer.
+///
+/// The node's roles must not have a `Messenger` set, so that every message
+/// it sends goes through `take_effects` or `step`.
+#[cfg(feature = "std")]
+pub struct Recorder<T, W> {
+    node: Node<T>,
+    writer: W,
+    /// Logical timestamp of the next record
+    at: u64,
+}
+
+#[cfg(feature = "std")]
+impl<T, W> Recorder<T, W>
+where
+    T: PartialEq + Clone + AsRef<[u8]>,
+    W: Write,
+{
+    /// Starts recording `node` to `writer`.
+    pub fn new(node: Node<T>, mut writer: W) -> io::Result<Self> {
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        put_u64(&mut header, node.id());
+        writer.write_all(&header)?;
+        Ok(Self {
+            node,
+            writer,
+            at: 0,
+        })
+    }
+
+    /// The node recorded.
+    pub fn node(&self) -> &Node<T> {
+        &self.node
+    }
+
+    /// Stops recording, returning the node and the writer.
+    pub fn into_inner(self) -> (Node<T>, W) {
+        (self.node, self.writer)
+    }
+
+    fn record(&mut self, tag: u8, body: impl FnOnce(&mut Vec<u8>)) -> io::Result<()> {
+        let mut out = vec![tag];
+        put_u64(&mut out, self.at);
+        body(&mut out);
+        self.at += 1;
+        self.writer.write_all(&out)
+    }
+
+    /// Records the messages among `effects`, in order.
+    fn record_sent(&mut self, effects: &[Effect<T>]) -> io::Result<()> {
+        for effect in effects {
+            if let Effect::SendMessage(msg) | Effect::SendMessageTo(_, msg) = effect {
+                self.record(SENT, |out| put_bytes(out, &encode(msg)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Proposes `value` through the node. See `Node::propose`.
+    pub fn propose(&mut self, value: T) -> io::Result<ProposalHandle> {
+        self.record(PROPOSED, |out| put_bytes(out, value.as_ref()))?;
+        Ok(self.node.propose(value))
+    }
+
+    /// Hands `msg` to the node, returning the resulting effects. See
+    /// `Node::step`.
+    pub fn step(&mut self, msg: Message<T>) -> io::Result<Vec<Effect<T>>> {
+        self.record(RECEIVED, |out| put_bytes(out, &encode(&msg)))?;
+        let effects = self.node.step(msg);
+        self.record_sent(&effects)?;
+        Ok(effects)
+    }
+
+    /// Advances the node's clock to `now`. See `Node::tick`.
+    pub fn tick(&mut self, now: u64) -> io::Result<()> {
+        self.record(TICKED, |out| put_u64(out, now))?;
+        self.node.tick(now);
+        Ok(())
+    }
+
+    /// Takes the effects the node produced since the last call. See
+    /// `Node::take_effects`.
+    pub fn take_effects(&mut self) -> io::Result<Vec<Effect<T>>> {
+        let effects = self.node.take_effects();
+        self.record_sent(&effects)?;
+        Ok(effects)
+    }
+}
+
+/// A trace written by a `Recorder`. In the notation of the `wire` module:
+///
+/// ```text
+/// "PAXT" version:u8 id:u64 record*
+/// record: tag:u8 at:u64 body
+/// ```
+///
+/// where the body of a message received (`0x00`) or sent (`0x01`) is its
+/// `wire` encoding as `bytes`, that of a value proposed (`0x02`) the value as
+/// `bytes`, and that of a tick (`0x03`) the time as a `u64`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace<T> {
+    /// ID of the node recorded
+    pub id: NodeId,
+    pub records: Vec<TraceRecord<T>>,
+}
+
+/// Where a replay parted from its trace.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence<T> {
+    /// Logical timestamp of the message recorded as sent
+    pub at: u64,
+    /// The message recorded as sent
+    pub recorded: Message<T>,
+    /// The message sent in its place on replay, if any
+    pub replayed: Option<Message<T>>,
+}
+
+#[cfg(feature = "std")]
+impl<T> Trace<T>
+where
+    T: PartialEq + Clone + for<'a> From<&'a [u8]>,
+{
+    /// Reads a trace from `reader`. A trace cut short, e.g. by a crash while
+    /// recording, is read up to its last whole record.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() < MAGIC.len() + 9 || !bytes.starts_with(MAGIC) {
+            return Err(invalid("not a trace"));
+        }
+        let mut r = Reader {
+            bytes: &bytes[MAGIC.len()..],
+        };
+        if r.u8().map_err(invalid)? != VERSION {
+            return Err(invalid("unknown trace version"));
+        }
+        let id = r.u64().map_err(invalid)?;
+        let mut records = Vec::new();
+        while !r.bytes.is_empty() {
+            match read_record(&mut r) {
+                Ok(record) => records.push(record),
+                Err(DecodeError::UnexpectedEnd) => break,
+                Err(err) => return Err(invalid(err)),
+            }
+        }
+        Ok(Self { id, records })
+    }
+
+    /// Feeds the trace's inputs through a fresh `Node` of `config`, checking
+    /// that it sends the same messages as the node recorded. Returns the
+    /// node, as of the end of the trace or of the first `Divergence`.
+    pub fn replay(&self, config: ClusterConfig) -> (Node<T>, Option<Divergence<T>>) {
+        let mut node = Node::new(self.id, config);
+        // Messages sent on replay, not yet matched with the trace's
+        let mut sent = VecDeque::new();
+        for record in &self.records {
+            match &record.event {
+                TraceEvent::Received(msg) => {
+                    let effects = node.step(msg.clone());
+                    sent.extend(sends(effects));
+                }
+                TraceEvent::Proposed(value) => {
+                    node.propose(value.clone());
+                }
+                TraceEvent::Ticked(now) => node.tick(*now),
+                TraceEvent::Sent(recorded) => {
+                    sent.extend(sends(node.take_effects()));
+                    let replayed = sent.pop_front();
+                    if replayed.as_ref() != Some(recorded) {
+                        let divergence = Divergence {
+                            at: record.at,
+                            recorded: recorded.clone(),
+                            replayed,
+                        };
+                        return (node, Some(divergence));
+                    }
+                }
+            }
+        }
+        (node, None)
+    }
+}
+
+/// A record of a run merged from the traces of its nodes.
+#[cfg(feature = "std")]
+struct Step<'a, T> {
+    node: NodeId,
+    event: &'a TraceEvent<T>,
+    /// The node a message received was sent by, if traced
+    from: Option<NodeId>,
+    /// Whether a message sent was received by any node traced
+    received: bool,
+    /// The node's vector clock, as of the step
+    clock: BTreeMap<NodeId, u64>,
+}
+
+/// Merges the traces of the nodes of a run into a single sequence of steps,
+/// ordered so that every message is sent before it's received. The `k`th
+/// time a node receives a message, it's taken to be the `k`th time any node
+/// sent it; messages from nodes that weren't traced are received from none.
+#[cfg(feature = "std")]
+fn merge<T: AsRef<[u8]>>(traces: &[Trace<T>]) -> Vec<Step<'_, T>> {
+    // Where each message was sent from, by trace and record index
+    let mut sends: BTreeMap<Vec<u8>, Vec<(usize, usize)>> = BTreeMap::new();
+    for (t, trace) in traces.iter().enumerate() {
+        for (i, record) in trace.records.iter().enumerate() {
+            if let TraceEvent::Sent(msg) = &record.event {
+                sends.entry(encode(msg)).or_default().push((t, i));
+            }
+        }
+    }
+    let mut matches = BTreeMap::new();
+    for (t, trace) in traces.iter().enumerate() {
+        let mut seen: BTreeMap<Vec<u8>, usize> = BTreeMap::new();
+        for (i, record) in trace.records.iter().enumerate() {
+            if let TraceEvent::Received(msg) = &record.event {
+                let bytes = encode(msg);
+                let k = seen.entry(bytes.clone()).or_default();
+                if let Some(send) = sends.get(&bytes).and_then(|sends| sends.get(*k)) {
+                    matches.insert((t, i), *send);
+                }
+                *k += 1;
+            }
+        }
+    }
+    let received: BTreeSet<(usize, usize)> = matches.values().copied().collect();
+
+    let mut steps = Vec::new();
+    let mut cursors = vec![0; traces.len()];
+    let mut clocks = vec![BTreeMap::new(); traces.len()];
+    // Clocks of the messages sent so far, to be merged on receipt
+    let mut sent: BTreeMap<(usize, usize), BTreeMap<NodeId, u64>> = BTreeMap::new();
+    loop {
+        let mut progress = false;
+        let mut blocked = None;
+        for (t, trace) in traces.iter().enumerate() {
+            while let Some(record) = trace.records.get(cursors[t]) {
+                let i = cursors[t];
+                let send = matches.get(&(t, i));
+                if send.is_some_and(|send| !sent.contains_key(send)) {
+                    blocked.get_or_insert((t, i));
+                    break;
+                }
+                let clock: &mut BTreeMap<NodeId, u64> = &mut clocks[t];
+                *clock.entry(trace.id).or_default() += 1;
+                if let Some(theirs) = send.and_then(|send| sent.get(send)) {
+                    for (node, time) in theirs {
+                        let time = (*time).max(clock.get(node).copied().unwrap_or(0));
+                        clock.insert(*node, time);
+                    }
+                }
+                if let TraceEvent::Sent(_) = record.event {
+                    sent.insert((t, i), clock.clone());
+                }
+                steps.push(Step {
+                    node: trace.id,
+                    event: &record.event,
+                    from: send.map(|(from, _)| traces[*from].id),
+                    received: received.contains(&(t, i)),
+                    clock: clock.clone(),
+                });
+                cursors[t] += 1;
+                progress = true;
+            }
+        }
+        match blocked {
+            None => return steps,
+            // Traces that contradict each other, e.g. of different runs,
+            // can't be ordered: the first receipt waiting is taken to be
+            // of a message from no node traced.
+            Some(receipt) if !progress => {
+                matches.remove(&receipt);
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// The slots a message concerns, if any.
+#[cfg(feature = "std")]
+fn slots<T>(msg: &Message<T>) -> Option<(Slot, Slot)> {
+    match msg {
+        Message::Prepare(data) | Message::Any(data) => Some((data.slot, data.slot + 1)),
+        Message::Promise(data) => Some((data.slot, data.slot + 1)),
+        Message::Accept(data) => Some((data.slot, data.slot + 1)),
+        Message::Accepted(data) => Some((data.slot, data.slot + 1)),
+        Message::Propose(data) => Some((data.slot, data.slot + 1)),
+        Message::Learn(data) => Some((data.slot, data.slot + 1)),
+        Message::Nack(data) => Some((data.slot, data.slot + 1)),
+        Message::Skip(data) => Some((data.start, data.end)),
+        _ => None,
+    }
+}
+
+/// A short description of a message, e.g. `prepare slot=0 n=3`.
+#[cfg(feature = "std")]
+fn label<T>(msg: &Message<T>) -> String {
+    let n = match msg {
+        Message::Prepare(data) | Message::Any(data) => Some(data.id),
+        Message::Promise(data) => Some(data.id),
+        Message::Accept(data) => Some(data.id),
+        Message::Accepted(data) => Some(data.id),
+        Message::Learn(data) => Some(data.id),
+        Message::Nack(data) => Some(data.id),
+        _ => None,
+    };
+    let mut label = String::from(msg.kind());
+    let _ = match slots(msg) {
+        Some((start, end)) if end > start + 1 => write!(label, " slots={start}..{end}"),
+        Some((slot, _)) => write!(label, " slot={slot}"),
+        None => Ok(()),
+    };
+    if let Some(n) = n {
+        let _ = write!(label, " n={n}");
+    }
+    label
+}
+
+/// Draws the run recorded by `traces`, one per node, as a
+/// [Mermaid](https://mermaid.js.org) sequence diagram: an arrow per message
+/// received, from its sender. Messages from nodes that weren't traced, and
+/// messages no traced node received, are noted over their node. Only the
+/// messages concerning `slot` are drawn, if given, e.g. to see why an
+/// instance is stuck; values proposed are noted otherwise.
+#[cfg(feature = "std")]
+pub fn mermaid<T: AsRef<[u8]>>(traces: &[Trace<T>], slot: Option<Slot>) -> String {
+    let mut out = String::from("sequenceDiagram\n");
+    for trace in traces {
+        let _ = writeln!(out, "    participant n{0} as node {0}", trace.id);
+    }
+    let concerns = |msg: &Message<T>| match (slot, slots(msg)) {
+        (None, _) => true,
+        (Some(slot), Some((start, end))) => (start..end).contains(&slot),
+        (Some(_), None) => false,
+    };
+    for step in merge(traces) {
+        let node = step.node;
+        let _ = match step.event {
+            TraceEvent::Received(msg) if concerns(msg) => match step.from {
+                Some(from) => writeln!(out, "    n{from}->>n{node}: {}", label(msg)),
+                None => writeln!(out, "    Note over n{node}: received {}", label(msg)),
+            },
+            TraceEvent::Sent(msg) if concerns(msg) && !step.received => {
+                writeln!(
+                    out,
+                    "    Note over n{node}: sent {}, never received",
+                    label(msg)
+                )
+            }
+            TraceEvent::Proposed(_) if slot.is_none() => {
+                writeln!(out, "    Note over n{node}: propose")
+            }
+            _ => Ok(()),
+        };
+    }
+    out
+}
+
+/// Logs the run recorded by `traces`, one per node, for
+/// [ShiViz](https://bestchai.bitbucket.io/shiviz/): every event on a line,
+/// followed by a line with its node and vector clock, as matched by ShiViz's
+/// default expression, `(?<event>.*)\n(?<host>\S*) (?<clock>{.*})`.
+#[cfg(feature = "std")]
+pub fn shiviz<T: AsRef<[u8]>>(traces: &[Trace<T>]) -> String {
+    let mut out = String::new();
+    for step in merge(traces) {
+        let event = match step.event {
+            TraceEvent::Received(msg) => match step.from {
+                Some(from) => format!("receive {} from node{from}", label(msg)),
+                None => format!("receive {}", label(msg)),
+            },
+            TraceEvent::Sent(msg) => format!("send {}", label(msg)),
+            TraceEvent::Proposed(_) => String::from("propose"),
+            TraceEvent::Ticked(now) => format!("tick {now}"),
+        };
+        let clock: Vec<String> = step
+            .clock
+            .iter()
+            .map(|(node, time)| format!("\"node{node}\":{time}"))
+            .collect();
+        let _ = writeln!(out, "{event}\nnode{} {{{}}}", step.node, clock.join(","));
+    }
+    out
+}
+
+#[cfg(feature = "std")]
+fn sends<T>(effects: Vec<Effect<T>>) -> impl Iterator<Item = Message<T>> {
+    effects.into_iter().filter_map(|effect| match effect {
+        Effect::SendMessage(msg) | Effect::SendMessageTo(_, msg) => Some(msg),
+        _ => None,
+    })
+}
+
+#[cfg(feature = "std")]
+fn read_record<T>(r: &mut Reader) -> Result<TraceRecord<T>, DecodeError>
+where
+    T: for<'a> From<&'a [u8]>,
+{
+    let tag = r.u8()?;
+    let at = r.u64()?;
+    let event = match tag {
+        RECEIVED => TraceEvent::Received(decode(r.bytes()?)?),
+        SENT => TraceEvent::Sent(decode(r.bytes()?)?),
+        PROPOSED => TraceEvent::Proposed(T::from(r.bytes()?)),
+        TICKED => TraceEvent::Ticked(r.u64()?),
+        tag => return Err(DecodeError::UnknownTag(tag)),
+    };
+    Ok(TraceRecord { at, event })
+}
+
+#[cfg(feature = "std")]
+fn invalid<E>(err: E) -> io::Error
+where
+    E: Into<std::boxed::Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+
+    #[test]
+    fn trace_record_and_replay() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut recorder = Recorder::new(Node::new(1, config.clone()), Vec::new()).unwrap();
+        let mut others: Vec<Node<Vec<u8>>> =
+            (2..=3).map(|id| Node::new(id, config.clone())).collect();
+
+        recorder.tick(5).unwrap();
+        recorder.propose(b"x".to_vec()).unwrap();
+        let mut sent = sends(recorder.take_effects().unwrap()).collect::<Vec<_>>();
+        while !sent.is_empty() {
+            let mut next = Vec::new();
+            for msg in sent {
+                next.extend(sends(recorder.step(msg.clone()).unwrap()));
+                for node in &mut others {
+                    next.extend(sends(node.step(msg.clone())));
+                }
+            }
+            sent = next;
+        }
+        let (mut node, bytes) = recorder.into_inner();
+        assert_eq!(node.poll_decided(), vec![(0, Arc::new(b"x".to_vec()))]);
+
+        // The last record, cut short, is dropped.
+        let trace: Trace<Vec<u8>> = Trace::read(&bytes[..bytes.len() - 1]).unwrap();
+        let mut whole: Trace<Vec<u8>> = Trace::read(&bytes[..]).unwrap();
+        assert_eq!(trace.records, whole.records[..whole.records.len() - 1]);
+
+        let (mut replayed, divergence) = whole.replay(config.clone());
+        assert_eq!(divergence, None);
+        assert_eq!(replayed.poll_decided(), vec![(0, Arc::new(b"x".to_vec()))]);
+
+        // Proposing another value, the replay parts from the trace at the
+        // first `Accept`.
+        for record in &mut whole.records {
+            if let TraceEvent::Proposed(value) = &mut record.event {
+                *value = b"y".to_vec();
+            }
+        }
+        let (_, divergence) = whole.replay(config);
+        let divergence = divergence.unwrap();
+        assert!(matches!(divergence.recorded, Message::Accept(_)));
+        assert!(matches!(divergence.replayed, Some(Message::Accept(_))));
+    }
+
+    #[test]
+    fn trace_export() {
+        let config = ClusterConfig::new(vec![1, 2, 3]);
+        let mut recorders: Vec<Recorder<Vec<u8>, Vec<u8>>> = (1..=2)
+            .map(|id| Recorder::new(Node::new(id, config.clone()), Vec::new()).unwrap())
+            .collect();
+        // Node 3 isn't traced.
+        let mut untraced: Node<Vec<u8>> = Node::new(3, config);
+
+        recorders[0].propose(b"x".to_vec()).unwrap();
+        let mut sent = sends(recorders[0].take_effects().unwrap()).collect::<Vec<_>>();
+        while !sent.is_empty() {
+            let mut next = Vec::new();
+            for msg in sent {
+                for recorder in &mut recorders {
+                    next.extend(sends(recorder.step(msg.clone()).unwrap()));
+                }
+                next.extend(sends(untraced.step(msg)));
+            }
+            sent = next;
+        }
+        let traces: Vec<Trace<Vec<u8>>> = recorders
+            .into_iter()
+            .map(|recorder| Trace::read(&recorder.into_inner().1[..]).unwrap())
+            .collect();
+
+        let diagram = mermaid(&traces, Some(0));
+        assert!(diagram.starts_with("sequenceDiagram\n    participant n1 as node 1\n"));
+        assert!(diagram.contains("    n1->>n2: prepare slot=0 n=1\n"));
+        assert!(diagram.contains("    n2->>n1: accepted slot=0 n=1\n"));
+        // Node 3's votes come from no node traced.
+        assert!(diagram.contains("    Note over n1: received promise slot=0 n=1\n"));
+        assert!(!mermaid(&traces, Some(1)).contains("->>"));
+
+        // Node 2 receives the `Prepare` after node 1 sends it.
+        let log = shiviz(&traces);
+        assert!(log.starts_with("propose\nnode1 {\"node1\":1}\nsend prepare slot=0 n=1\n"));
+        assert!(log
+            .contains("receive prepare slot=0 n=1 from node1\nnode2 {\"node1\":2,\"node2\":1}\n"));
+    }
+}
@@ -0,0 +1,286 @@
+//! Transactions
+//!
+//! A `MultiGroup` decides the values of each group independently, so a write
+//! spanning several groups could be decided in some and not in others. Two-
+//! phase commit makes such writes atomic, with every step decided through the
+//! logs of the groups involved rather than kept by a single coordinator that
+//! could fail at the wrong time:
+//!
+//! 1. `Coordinator::begin` hands back a `TxnRecord::Prepared` for each
+//!    participant group, carrying its part of the write, to be proposed to
+//!    that group. Deciding it is the group's vote to commit. A group may
+//!    instead decide `TxnRecord::Aborted`, e.g. when the write conflicts, and
+//!    only the first of the two decided for a transaction counts.
+//! 2. The coordinator is handed every record decided in the participants
+//!    through `Coordinator::observe`. Once every group voted to commit, it
+//!    hands back a `TxnRecord::Committed` for each of them; as soon as one
+//!    voted to abort, a `TxnRecord::Aborted` for the others.
+//!
+//! The outcome of a transaction follows from the participants' logs alone, so
+//! a coordinator restarted by observing them again carries on where it left
+//! off. Each participant group applies its records through a `Participant`,
+//! which holds the writes prepared until they commit.
+
+use crate::group::GroupId;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+/// Identifies a transaction.
+pub type TxnId = u64;
+
+/// A step of a transaction, decided in the log of a participant group.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TxnRecord<T> {
+    /// The group's part of the write, prepared: a vote to commit
+    Prepared(TxnId, T),
+    /// Every participant prepared the transaction
+    Committed(TxnId),
+    /// A participant voted to abort, or the transaction was abandoned
+    Aborted(TxnId),
+}
+
+impl<T> TxnRecord<T> {
+    /// The transaction the record belongs to.
+    pub fn txn(&self) -> TxnId {
+        match self {
+            TxnRecord::Prepared(txn, _) | TxnRecord::Committed(txn) | TxnRecord::Aborted(txn) => {
+                *txn
+            }
+        }
+    }
+}
+
+/// The fate of a transaction, as seen by its coordinator.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TxnStatus {
+    /// Waiting for the participants' votes
+    Preparing,
+    /// Every participant voted to commit
+    Committed,
+    /// A participant voted to abort
+    Aborted,
+}
+
+/// A transaction, as tracked by its coordinator.
+struct Txn {
+    /// The participant groups, along with their vote once decided
+    votes: BTreeMap<GroupId, Option<bool>>,
+    status: TxnStatus,
+}
+
+/// Drives transactions across participant groups. See the module
+/// documentation.
+#[derive(Default)]
+pub struct Coordinator {
+    next: TxnId,
+    txns: BTreeMap<TxnId, Txn>,
+}
+
+impl Coordinator {
+    /// Creates a `Coordinator` numbering its transactions from `first`, which
+    /// must be unique among the coordinators of the participant groups.
+    pub fn new(first: TxnId) -> Self {
+        Self {
+            next: first,
+            txns: BTreeMap::new(),
+        }
+    }
+
+    /// Starts a transaction writing each value of `writes` to its group,
+    /// returning its ID along with the records to propose to the groups.
+    pub fn begin<T>(&mut self, writes: Vec<(GroupId, T)>) -> (TxnId, Vec<(GroupId, TxnRecord<T>)>) {
+        let txn = self.next;
+        self.next += 1;
+        self.txns.insert(
+            txn,
+            Txn {
+                votes: writes.iter().map(|(group, _)| (*group, None)).collect(),
+                status: TxnStatus::Preparing,
+            },
+        );
+        let records = writes
+            .into_iter()
+            .map(|(group, value)| (group, TxnRecord::Prepared(txn, value)))
+            .collect();
+        (txn, records)
+    }
+
+    /// The status of `txn`, if it's known.
+    pub fn status(&self, txn: TxnId) -> Option<TxnStatus> {
+        self.txns.get(&txn).map(|t| t.status)
+    }
+
+    /// Abandons `txn` while it's preparing, e.g. once a participant has been
+    /// unreachable for too long, returning the `Aborted` records to propose
+    /// to the participants that didn't vote yet. It may still commit, if
+    /// they all vote to commit first.
+    pub fn abort<T>(&mut self, txn: TxnId) -> Vec<(GroupId, TxnRecord<T>)> {
+        match self.txns.get(&txn) {
+            Some(t) if t.status == TxnStatus::Preparing => t
+                .votes
+                .iter()
+                .filter(|(_, vote)| vote.is_none())
+                .map(|(group, _)| (*group, TxnRecord::Aborted(txn)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Observes `record`, decided in the log of `group`, returning the
+    /// records to propose to the participants as a result.
+    pub fn observe<T>(
+        &mut self,
+        group: GroupId,
+        record: &TxnRecord<T>,
+    ) -> Vec<(GroupId, TxnRecord<T>)> {
+        let txn = record.txn();
+        let t = match self.txns.get_mut(&txn) {
+            Some(t) => t,
+            None => return Vec::new(),
+        };
+        let vote = match (record, t.votes.get_mut(&group)) {
+            (TxnRecord::Prepared(..), Some(vote @ None)) => vote.insert(true),
+            (TxnRecord::Aborted(_), Some(vote @ None)) => vote.insert(false),
+            _ => return Vec::new(),
+        };
+        if t.status != TxnStatus::Preparing {
+            return Vec::new();
+        }
+        if !*vote {
+            t.status = TxnStatus::Aborted;
+            t.votes
+                .iter()
+                .filter(|(_, vote)| vote.is_none())
+                .map(|(group, _)| (*group, TxnRecord::Aborted(txn)))
+                .collect()
+        } else if t.votes.values().all(|vote| *vote == Some(true)) {
+            t.status = TxnStatus::Committed;
+            t.votes
+                .keys()
+                .map(|group| (*group, TxnRecord::Committed(txn)))
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Forgets `txn`, once its outcome has been decided in every participant.
+    pub fn forget(&mut self, txn: TxnId) {
+        self.txns.remove(&txn);
+    }
+}
+
+/// A transaction's write, as applied by a participant group.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Applied<T> {
+    /// The write was prepared, and may be committed. Conflicting writes are
+    /// expected to be refused until it's settled
+    Prepared(TxnId),
+    /// The write committed, and is to be applied
+    Committed(TxnId, T),
+    /// The write was aborted, and is to be dropped
+    Aborted(TxnId),
+}
+
+/// Applies the transaction records decided in the log of a participant
+/// group, in log order.
+pub struct Participant<T> {
+    /// Writes prepared, waiting for the outcome
+    prepared: BTreeMap<TxnId, T>,
+    /// Transactions the group voted to abort
+    aborted: BTreeSet<TxnId>,
+}
+
+impl<T> Default for Participant<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Participant<T> {
+    /// Creates a `Participant` with no transactions in progress.
+    pub fn new() -> Self {
+        Self {
+            prepared: BTreeMap::new(),
+            aborted: BTreeSet::new(),
+        }
+    }
+
+    /// The writes prepared and waiting for their outcome.
+    pub fn prepared(&self) -> impl Iterator<Item = (TxnId, &T)> {
+        self.prepared.iter().map(|(txn, value)| (*txn, value))
+    }
+
+    /// Applies the next record decided in the group's log, returning what
+    /// became of the transaction's write, if anything.
+    pub fn apply(&mut self, record: TxnRecord<T>) -> Option<Applied<T>> {
+        match record {
+            // Only the first vote decided counts.
+            TxnRecord::Prepared(txn, _) if self.aborted.contains(&txn) => None,
+            TxnRecord::Prepared(txn, _) if self.prepared.contains_key(&txn) => None,
+            TxnRecord::Prepared(txn, value) => {
+                self.prepared.insert(txn, value);
+                Some(Applied::Prepared(txn))
+            }
+            TxnRecord::Committed(txn) => self
+                .prepared
+                .remove(&txn)
+                .map(|value| Applied::Committed(txn, value)),
+            TxnRecord::Aborted(txn) => {
+                self.prepared.remove(&txn);
+                self.aborted.insert(txn).then_some(Applied::Aborted(txn))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn txn_commits_once_every_group_prepared() {
+        let mut c = Coordinator::new(0);
+        let mut groups = [Participant::new(), Participant::new()];
+        let (txn, prepares) = c.begin(vec![(0, "a"), (1, "b")]);
+
+        let mut commits = Vec::new();
+        for (group, record) in prepares {
+            commits.extend(c.observe(group, &record));
+            assert_eq!(
+                groups[group as usize].apply(record),
+                Some(Applied::Prepared(txn))
+            );
+        }
+
+        assert_eq!(c.status(txn), Some(TxnStatus::Committed));
+        for (group, record) in commits {
+            assert_eq!(
+                groups[group as usize].apply(record),
+                Some(Applied::Committed(txn, ["a", "b"][group as usize]))
+            );
+        }
+    }
+
+    #[test]
+    fn txn_aborts_on_first_no_vote() {
+        let mut c = Coordinator::new(0);
+        let mut group = Participant::new();
+        let (txn, _) = c.begin(vec![(0, "a"), (1, "b")]);
+
+        // Group 1 votes to abort before preparing.
+        let aborts = c.observe::<&str>(1, &TxnRecord::Aborted(txn));
+        assert_eq!(aborts, vec![(0, TxnRecord::Aborted(txn))]);
+        assert!(c.observe(1, &TxnRecord::Prepared(txn, "b")).is_empty());
+        assert_eq!(c.status(txn), Some(TxnStatus::Aborted));
+
+        // Group 0 prepared before learning of it.
+        assert!(group.apply(TxnRecord::Prepared(txn, "a")).is_some());
+        assert_eq!(
+            group.apply(aborts[0].1.clone()),
+            Some(Applied::Aborted(txn))
+        );
+        assert_eq!(group.prepared().count(), 0);
+    }
+}
@@ -0,0 +1,315 @@
+//! Message validation
+//!
+//! An `Acceptor` trusts every message it's handed to be well-formed, so a
+//! buggy or malicious peer can raise its promise to `u64::MAX`, locking out
+//! every `Proposer` for good, or fill its log with huge values. Handed
+//! `Envelope`s through `Acceptor::receive_envelope` instead, it first checks
+//! them against a `Validator`, refusing:
+//!
+//! - envelopes for another group than the `Acceptor`'s, as when a node is
+//!   misconfigured to talk to the wrong cluster;
+//! - messages from senders unknown to its `ClusterConfig`, save `Join`s, sent
+//!   by newcomers, and `Propose`s, sent by clients;
+//! - proposal numbers more than `max_rounds_ahead` rounds above the highest
+//!   ballot a Phase-1 quorum was seen at, or its own promise if higher;
+//! - values larger than `max_value_size`.
+//!
+//! The bound follows the cluster rather than the `Acceptor`'s own promise, so
+//! that one fresh or partitioned for a while catches up once a quorum of its
+//! peers is heard from at their current ballot, while no single peer can move
+//! it. A `State` or `Join` isn't bounded at all while the `Acceptor` isn't
+//! voting, so that a joining one can copy the state of its peers.
+//!
+//! Each refusal is returned as an `AcceptorError`, and counted through
+//! `Metrics::message_rejected` under its code. A `Node` given a `Validator`
+//! through `Node::set_validator` has its `Acceptor` check the envelopes
+//! `MultiGroup::step` and `Router::receive` hand it, dropping those refused.
+
+use crate::acceptor::Acceptor;
+use crate::config::{ClusterConfig, NodeId};
+use crate::error::AcceptorError;
+use crate::group::GroupId;
+use crate::message::{Handler, Message, Messenger};
+use crate::node::Node;
+use crate::proposer::{ballot_owner, BALLOT_ID_BITS};
+use crate::router::Envelope;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Checks the messages an `Acceptor` receives. See the module documentation.
+#[derive(Debug, Clone)]
+pub struct Validator<T> {
+    /// The group messages must belong to
+    group: GroupId,
+    /// Most rounds a proposal number may be above the promise
+    max_rounds_ahead: Option<u64>,
+    /// Most bytes a value may hold
+    max_value_size: Option<usize>,
+    /// Measures values, in bytes
+    value_size: fn(&T) -> usize,
+    /// The highest ballot seen from each node
+    seen: BTreeMap<NodeId, u64>,
+}
+
+impl<T> Validator<T> {
+    /// Creates a `Validator` accepting the messages of `group` from any node
+    /// of the cluster, however large their ballots and values.
+    pub fn new(group: GroupId) -> Self {
+        Self {
+            group,
+            max_rounds_ahead: None,
+            max_value_size: None,
+            value_size: |_| 0,
+            seen: BTreeMap::new(),
+        }
+    }
+
+    /// Refuses proposal numbers more than `rounds` rounds above the highest
+    /// ballot a Phase-1 quorum was seen at, or the promise if higher.
+    pub fn max_rounds_ahead(mut self, rounds: u64) -> Self {
+        self.max_rounds_ahead = Some(rounds);
+        self
+    }
+
+    /// Refuses values larger than `max` bytes, as measured by `size`.
+    pub fn max_value_size(mut self, max: usize, size: fn(&T) -> usize) -> Self {
+        self.max_value_size = Some(max);
+        self.value_size = size;
+        self
+    }
+
+    /// Checks `envelope` as received by an `Acceptor` of `config` which
+    /// promised `promised_n`, and is `voting` or not.
+    pub fn validate(
+        &mut self,
+        config: &ClusterConfig,
+        promised_n: u64,
+        voting: bool,
+        envelope: &Envelope<T>,
+    ) -> Result<(), AcceptorError> {
+        if envelope.group != self.group {
+            return Err(AcceptorError::WrongGroup(envelope.group));
+        }
+        let msg = &envelope.msg;
+        if let Some(from) = sender(msg) {
+            if !config.is_known(from) {
+                return Err(AcceptorError::UnknownSender(from));
+            }
+        }
+        let copying = !voting && matches!(msg, Message::State(_) | Message::Join(_));
+        if let (Some(n), Some(rounds)) = (ballot(msg), self.max_rounds_ahead) {
+            if let Some(from) = sender(msg).filter(|from| config.is_phase1_voter(*from)) {
+                let seen = self.seen.entry(from).or_default();
+                *seen = (*seen).max(n);
+            }
+            let base = promised_n.max(self.quorum_ballot(config));
+            let bound = (base >> BALLOT_ID_BITS).saturating_add(rounds);
+            if n >> BALLOT_ID_BITS > bound && !copying {
+                return Err(AcceptorError::BallotTooHigh(n));
+            }
+        }
+        if let Some(max) = self.max_value_size {
+            let mut sizes = values(msg).into_iter().map(self.value_size);
+            if let Some(len) = sizes.find(|len| *len > max) {
+                return Err(AcceptorError::ValueTooLarge(len));
+            }
+        }
+        Ok(())
+    }
+
+    /// The highest ballot a Phase-1 quorum of `config` was seen at, if any.
+    fn quorum_ballot(&self, config: &ClusterConfig) -> u64 {
+        let mut ballots: Vec<u64> = self.seen.values().copied().collect();
+        ballots.sort_unstable_by(|a, b| b.cmp(a));
+        ballots.dedup();
+        ballots
+            .into_iter()
+            .find(|n| {
+                let voters: Vec<NodeId> = self
+                    .seen
+                    .iter()
+                    .filter(|(_, seen)| *seen >= n)
+                    .map(|(from, _)| *from)
+                    .collect();
+                config.is_phase1_quorum(&voters)
+            })
+            .unwrap_or(0)
+    }
+}
+
+/// The node an `Acceptor`-bound message claims to come from, unless anyone
+/// may send it.
+fn sender<T>(msg: &Message<T>) -> Option<NodeId> {
+    match msg {
+        Message::Prepare(data) | Message::Any(data) => Some(data.from),
+        Message::Accept(data) => Some(ballot_owner(data.id)),
+        Message::State(data) => Some(data.from),
+        Message::Read(data) => Some(data.from),
+        Message::Digest(data) => Some(data.from),
+        Message::Repair(data) => Some(data.from),
+        _ => None,
+    }
+}
+
+/// The values an `Acceptor`-bound message would have it store.
+fn values<T>(msg: &Message<T>) -> Vec<&T> {
+    match msg {
+        Message::Accept(data) => vec![&*data.value],
+        Message::Propose(data) => vec![&*data.value],
+        Message::State(data) => {
+            let accepted = data.accepted.iter().map(|(_, _, value)| &**value);
+            accepted
+                .chain(data.decided.iter().map(|(_, value)| &**value))
+                .collect()
+        }
+        Message::Repair(data) => data.accepted.iter().map(|(_, _, _, v)| &**v).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The proposal number an `Acceptor`-bound message would have it promise.
+fn ballot<T>(msg: &Message<T>) -> Option<u64> {
+    match msg {
+        Message::Prepare(data) | Message::Any(data) => Some(data.id),
+        Message::Accept(data) => Some(data.id),
+        Message::State(data) => Some(data.promised_n),
+        Message::Repair(data) => data.accepted.iter().map(|(_, n, _, _)| *n).max(),
+        _ => None,
+    }
+}
+
+impl<T, M: Messenger<T>> Acceptor<T, M> {
+    /// Sets the `Validator` the envelopes handed to `receive_envelope` are
+    /// checked against.
+    pub fn set_validator(&mut self, validator: Validator<T>) {
+        self.validator = Some(validator);
+    }
+
+    /// Handles the message of `envelope`, unless the `Validator` refuses it.
+    /// Every envelope is handled while no `Validator` is set.
+    pub fn receive_envelope(&mut self, envelope: Envelope<T>) -> Result<(), AcceptorError> {
+        self.check_envelope(&envelope)?;
+        self.handle(envelope.msg);
+        Ok(())
+    }
+
+    /// Checks `envelope` against the `Validator`, if any, counting a refusal
+    /// through the `Metrics`.
+    pub(crate) fn check_envelope(&mut self, envelope: &Envelope<T>) -> Result<(), AcceptorError> {
+        let voting = self.is_voting();
+        if let Some(validator) = &mut self.validator {
+            if let Err(err) = validator.validate(&self.config, self.promised_n, voting, envelope) {
+                event!(acceptor = self.id, code = err.code(), "message rejected");
+                if let Some(metrics) = &mut self.metrics {
+                    metrics.message_rejected(err.code());
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T, M: Messenger<T>> Node<T, M> {
+    /// Sets the `Validator` the `Acceptor` checks envelopes against, as
+    /// handed in by a `Router` or `MultiGroup::step`.
+    pub fn set_validator(&mut self, validator: Validator<T>) {
+        self.acceptor.set_validator(validator);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::message::{AcceptData, ProposalData};
+    use crate::metrics::Metrics;
+    use crate::proposer::ballot;
+    use alloc::sync::Arc;
+    use std::sync::Mutex;
+
+    /// Records the codes of the messages rejected.
+    #[derive(Clone, Default)]
+    pub(crate) struct Rejections(pub(crate) Arc<Mutex<Vec<u16>>>);
+
+    impl Metrics for Rejections {
+        fn message_rejected(&mut self, code: u16) {
+            self.0.lock().unwrap().push(code);
+        }
+    }
+
+    fn prepare(group: GroupId, id: u64) -> Envelope<Vec<u8>> {
+        Envelope {
+            group,
+            msg: Message::Prepare(ProposalData {
+                slot: 0,
+                id,
+                from: ballot_owner(id),
+            }),
+        }
+    }
+
+    #[test]
+    fn acceptor_validation() {
+        let mut a: Acceptor<Vec<u8>> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        let rejections = Rejections::default();
+        a.set_metrics(rejections.clone());
+        a.set_validator(
+            Validator::new(7)
+                .max_rounds_ahead(10)
+                .max_value_size(4, Vec::len),
+        );
+
+        assert_eq!(
+            a.receive_envelope(prepare(8, ballot(1, 2))),
+            Err(AcceptorError::WrongGroup(8))
+        );
+        assert_eq!(
+            a.receive_envelope(prepare(7, ballot(1, 9))),
+            Err(AcceptorError::UnknownSender(9))
+        );
+        assert_eq!(
+            a.receive_envelope(prepare(7, ballot(11, 2))),
+            Err(AcceptorError::BallotTooHigh(ballot(11, 2)))
+        );
+        assert_eq!(a.current_ballot(), 0);
+
+        assert_eq!(a.receive_envelope(prepare(7, ballot(10, 2))), Ok(()));
+        assert_eq!(a.current_ballot(), ballot(10, 2));
+
+        let accept = |value: &[u8]| Envelope {
+            group: 7,
+            msg: Message::Accept(AcceptData {
+                slot: 0,
+                id: ballot(10, 2),
+                value: Arc::new(value.to_vec()),
+                implicit_prepare: false,
+            }),
+        };
+
+        assert_eq!(
+            a.receive_envelope(accept(b"large")),
+            Err(AcceptorError::ValueTooLarge(5))
+        );
+        assert_eq!(a.receive_envelope(accept(b"tiny")), Ok(()));
+        assert_eq!(*rejections.0.lock().unwrap(), vec![204, 203, 205, 206]);
+    }
+
+    #[test]
+    fn validator_follows_quorum() {
+        let mut a: Acceptor<Vec<u8>> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        a.set_validator(Validator::new(7).max_rounds_ahead(10));
+
+        // A single peer far ahead doesn't move the bound...
+        assert_eq!(
+            a.receive_envelope(prepare(7, ballot(40, 2))),
+            Err(AcceptorError::BallotTooHigh(ballot(40, 2)))
+        );
+        assert_eq!(a.current_ballot(), 0);
+
+        // ...but a Phase-1 quorum of them does.
+        assert_eq!(a.receive_envelope(prepare(7, ballot(40, 3))), Ok(()));
+        assert_eq!(a.current_ballot(), ballot(40, 3));
+        assert_eq!(a.receive_envelope(prepare(7, ballot(50, 2))), Ok(()));
+    }
+}
@@ -0,0 +1,102 @@
+//! Vertical Paxos
+//!
+//! Configuration changes are managed by an external `Master`. Proposal numbers
+//! are bound to configurations through their epoch, held in the upper 32 bits,
+//! so that every role can tell which configuration a vote was cast in.
+//!
+//! A new leader activates an epoch by running the first phase against the
+//! previous configuration, transferring any value it may have decided to the
+//! new one. Once the leader has finalized the undecided slots this way, it
+//! reports to the `Master`, which completes the epoch and retires the old
+//! configuration.
+
+use crate::config::ClusterConfig;
+use alloc::collections::BTreeMap;
+
+/// Number of bits of a proposal number below its epoch.
+pub const EPOCH_SHIFT: u32 = 32;
+
+/// The epoch proposal number `n` belongs to.
+pub fn epoch_of(n: u64) -> u64 {
+    n >> EPOCH_SHIFT
+}
+
+/// The lowest proposal number of `epoch`.
+pub fn first_ballot(epoch: u64) -> u64 {
+    epoch << EPOCH_SHIFT
+}
+
+/// Assigns configurations to epochs, and tracks which one is active.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Master {
+    /// Configurations not retired yet (epoch => configuration)
+    pub configs: BTreeMap<u64, ClusterConfig>,
+    /// The epoch whose configuration is complete
+    pub active: u64,
+}
+
+impl Master {
+    /// Creates a new `Master`, with `initial` active in epoch 0.
+    pub fn new(initial: ClusterConfig) -> Self {
+        Self {
+            configs: [(0, initial)].into_iter().collect(),
+            active: 0,
+        }
+    }
+
+    /// Binds `config` to a new epoch, which is returned. Its leader transfers
+    /// state from the configuration of the active epoch.
+    pub fn reconfigure(&mut self, config: ClusterConfig) -> u64 {
+        let epoch = self.configs.keys().next_back().map_or(0, |e| e + 1);
+        self.configs.insert(epoch, config);
+        epoch
+    }
+
+    /// The configuration bound to `epoch`, unless it was retired.
+    pub fn config(&self, epoch: u64) -> Option<&ClusterConfig> {
+        self.configs.get(&epoch)
+    }
+
+    /// The configuration of the active epoch, which new epochs transfer state
+    /// from.
+    pub fn previous(&self) -> &ClusterConfig {
+        &self.configs[&self.active]
+    }
+
+    /// Marks `epoch` as complete once its leader has transferred state,
+    /// retiring the configurations of earlier epochs.
+    pub fn complete(&mut self, epoch: u64) {
+        if epoch > self.active && self.configs.contains_key(&epoch) {
+            self.active = epoch;
+            self.configs = self.configs.split_off(&epoch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn master_reconfigure() {
+        let mut m = Master::new(ClusterConfig::new(vec![1, 2, 3]));
+
+        let epoch = m.reconfigure(ClusterConfig::new(vec![3, 4, 5]));
+
+        assert_eq!(epoch, 1);
+        assert_eq!(m.previous().members, vec![1, 2, 3]);
+        assert_eq!(epoch_of(first_ballot(epoch) + 7), 1);
+
+        // A leader of epoch 2 may take over before epoch 1 completes.
+        assert_eq!(m.reconfigure(ClusterConfig::new(vec![4, 5, 6])), 2);
+        assert_eq!(m.previous().members, vec![1, 2, 3]);
+
+        m.complete(2);
+        m.complete(1);
+
+        assert_eq!(m.active, 2);
+        assert_eq!(m.config(0), None);
+        assert_eq!(m.previous().members, vec![4, 5, 6]);
+    }
+}
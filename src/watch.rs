@@ -0,0 +1,90 @@
+//! Watches
+//!
+//! Rather than polling a `Learner` for new decisions, applications can watch
+//! its log: each watcher is handed every decided value in slot order, as soon
+//! as every slot below it is decided too. Slots compacted into a snapshot
+//! before a watcher got to them are passed over, just like
+//! `Node::poll_decided` does.
+
+use crate::learner::Learner;
+use crate::message::{Messenger, Slot};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+
+/// Callback handed every value decided from some slot onwards, in slot order.
+/// It returns whether it should keep watching.
+pub type WatchCallback<T> = Box<dyn FnMut(Slot, Arc<T>) -> bool + Send>;
+
+/// A callback watching the log of a `Learner`.
+pub(crate) struct Watcher<T> {
+    /// The next slot to hand to `callback`
+    pub(crate) next: Slot,
+    pub(crate) callback: WatchCallback<T>,
+}
+
+impl<T: PartialEq, M: Messenger<T>> Learner<T, M> {
+    /// Hands `callback` every value decided from `slot` onwards, in slot
+    /// order, including those decided already. The callback is dropped once
+    /// it returns `false`.
+    pub fn watch_from<F>(&mut self, slot: Slot, callback: F)
+    where
+        F: FnMut(Slot, Arc<T>) -> bool + Send + 'static,
+    {
+        self.watchers.push(Watcher {
+            next: slot,
+            callback: Box::new(callback),
+        });
+        self.notify_watchers();
+    }
+
+    /// Hands every watcher the values decided since it was last notified.
+    pub(crate) fn notify_watchers(&mut self) {
+        let compacted = self.compacted();
+        let decided = &self.decided;
+        self.watchers.retain_mut(|watcher| {
+            watcher.next = watcher.next.max(compacted);
+            while let Some(value) = decided.get(&watcher.next) {
+                if !(watcher.callback)(watcher.next, value.clone()) {
+                    return false;
+                }
+                watcher.next += 1;
+            }
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClusterConfig;
+    use crate::learner::tests::decide;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use std::sync::Mutex;
+
+    #[test]
+    fn watch_in_slot_order() {
+        let mut l: Learner<u64> = Learner::new(4, ClusterConfig::new(vec![1, 2, 3]));
+        decide(&mut l, 0, 10);
+        decide(&mut l, 1, 20);
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let watched = seen.clone();
+        l.watch_from(1, move |slot, value| {
+            watched.lock().unwrap().push((slot, *value));
+            slot < 3
+        });
+
+        assert_eq!(*seen.lock().unwrap(), vec![(1, 20)]);
+
+        // Slot 3 is held back until slot 2 is decided.
+        decide(&mut l, 3, 40);
+        decide(&mut l, 2, 30);
+        decide(&mut l, 4, 50);
+
+        // The watcher stopped after slot 3.
+        assert_eq!(*seen.lock().unwrap(), vec![(1, 20), (2, 30), (3, 40)]);
+        assert!(l.watchers.is_empty());
+    }
+}
@@ -0,0 +1,524 @@
+//! Wire format
+//!
+//! A compact binary encoding of `Message`s, with values carried as opaque
+//! bytes. All integers are big-endian:
+//!
+//! | Message    | Layout                                                      |
+//! |------------|-------------------------------------------------------------|
+//! | `Prepare`  | `0x00` slot:u64 id:u64 from:u64                             |
+//! | `Promise`  | `0x01` slot:u64 id:u64 from:u64 accepted_n:opt<u64> value:opt<bytes> |
+//! | `Accept`   | `0x02` slot:u64 id:u64 implicit_prepare:bool value:bytes    |
+//! | `Accepted` | `0x03` slot:u64 id:u64 from:u64 fast:bool value:bytes       |
+//! | `Nack`     | `0x04` slot:u64 id:u64 from:u64 promised_n:u64 leader:opt<u64> |
+//! | `Any`      | `0x05` slot:u64 id:u64 from:u64                             |
+//! | `Propose`  | `0x06` slot:u64 value:bytes                                 |
+//! | `Skip`     | `0x07` from:u64 start:u64 end:u64 value:bytes               |
+//! | `Join`     | `0x08` from:u64                                             |
+//! | `State`    | `0x09` from:u64 to:u64 promised_n:u64 accepted:list<slot:u64 n:u64 value:bytes> decided:list<slot:u64 value:bytes> |
+//! | `InstallSnapshot` | `0x0a` from:u64 to:u64 index:u64 value:bytes         |
+//! | `Learn`    | `0x0b` slot:u64 id:u64 from:u64 certificate:list<u64> value:bytes |
+//! | `Read`     | `0x0c` from:u64 id:u64                                      |
+//! | `ReadReply` | `0x0d` from:u64 to:u64 id:u64 horizon:u64                  |
+//! | `Digest`   | `0x0e` from:u64 start:u64 truncated:u64 range:u64 hashes:list<u64> |
+//! | `Repair`   | `0x0f` from:u64 to:u64 accepted:list<slot:u64 n:u64 fast:bool value:bytes> |
+//!
+//! `bool`s are a single `0x00` or `0x01` byte, `opt<X>` is a `bool` followed by
+//! `X` if set, `bytes` is a u32 length followed by that many bytes, and
+//! `list<X>` is a u32 count followed by that many `X`s.
+//!
+//! Values only need to be viewed as bytes to be encoded. With the `bytes`
+//! feature, `decode_bytes` decodes `Message<Bytes>`s whose values share the
+//! buffer received, so payloads are never copied on their way to the roles.
+
+use crate::message::{
+    AcceptData, AcceptedData, DigestData, JoinData, LearnData, Message, NackData, PromiseData,
+    ProposalData, ProposeData, ReadData, ReadReplyData, RepairData, SkipData, SnapshotData,
+    StateData,
+};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
+use thiserror::Error;
+
+const PREPARE: u8 = 0;
+const PROMISE: u8 = 1;
+const ACCEPT: u8 = 2;
+const ACCEPTED: u8 = 3;
+const NACK: u8 = 4;
+const ANY: u8 = 5;
+const PROPOSE: u8 = 6;
+const SKIP: u8 = 7;
+const JOIN: u8 = 8;
+const STATE: u8 = 9;
+const INSTALL_SNAPSHOT: u8 = 10;
+const LEARN: u8 = 11;
+const READ: u8 = 12;
+const READ_REPLY: u8 = 13;
+const DIGEST: u8 = 14;
+const REPAIR: u8 = 15;
+
+/// Errors raised when decoding malformed bytes.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+pub enum DecodeError {
+    /// The input ended in the middle of a message
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+    /// The message type is not known
+    #[error("unknown message type {0}")]
+    UnknownTag(u8),
+    /// A `bool` was neither `0x00` nor `0x01`
+    #[error("invalid bool {0:#04x}")]
+    InvalidBool(u8),
+    /// Bytes were left over after the message
+    #[error("trailing bytes after message")]
+    TrailingBytes,
+    /// A chunk's index or count doesn't match its message
+    #[error("invalid chunk")]
+    InvalidChunk,
+    /// The payload was compressed with a codec that isn't built in
+    #[error("unknown compression {0}")]
+    UnknownCompression(u8),
+    /// A compressed payload is malformed
+    #[error("malformed compressed payload")]
+    Decompress,
+}
+
+/// Encodes `msg` into its wire representation.
+pub fn encode<T: AsRef<[u8]>>(msg: &Message<T>) -> Vec<u8> {
+    let mut out = Vec::new();
+    match msg {
+        Message::Prepare(data) => {
+            out.push(PREPARE);
+            put_u64(&mut out, data.slot);
+            put_u64(&mut out, data.id);
+            put_u64(&mut out, data.from);
+        }
+        Message::Promise(data) => {
+            out.push(PROMISE);
+            put_u64(&mut out, data.slot);
+            put_u64(&mut out, data.id);
+            put_u64(&mut out, data.from);
+            out.push(data.accepted_n.is_some() as u8);
+            if let Some(n) = data.accepted_n {
+                put_u64(&mut out, n);
+            }
+            out.push(data.value.is_some() as u8);
+            if let Some(ref value) = data.value {
+                put_bytes(&mut out, value.as_ref().as_ref());
+            }
+        }
+        Message::Accept(data) => {
+            out.push(ACCEPT);
+            put_u64(&mut out, data.slot);
+            put_u64(&mut out, data.id);
+            out.push(data.implicit_prepare as u8);
+            put_bytes(&mut out, data.value.as_ref().as_ref());
+        }
+        Message::Accepted(data) => {
+            out.push(ACCEPTED);
+            put_u64(&mut out, data.slot);
+            put_u64(&mut out, data.id);
+            put_u64(&mut out, data.from);
+            out.push(data.fast as u8);
+            put_bytes(&mut out, data.value.as_ref().as_ref());
+        }
+        Message::Nack(data) => {
+            out.push(NACK);
+            put_u64(&mut out, data.slot);
+            put_u64(&mut out, data.id);
+            put_u64(&mut out, data.from);
+            put_u64(&mut out, data.promised_n);
+            out.push(data.leader.is_some() as u8);
+            if let Some(leader) = data.leader {
+                put_u64(&mut out, leader);
+            }
+        }
+        Message::Any(data) => {
+            out.push(ANY);
+            put_u64(&mut out, data.slot);
+            put_u64(&mut out, data.id);
+            put_u64(&mut out, data.from);
+        }
+        Message::Propose(data) => {
+            out.push(PROPOSE);
+            put_u64(&mut out, data.slot);
+            put_bytes(&mut out, data.value.as_ref().as_ref());
+        }
+        Message::Skip(data) => {
+            out.push(SKIP);
+            put_u64(&mut out, data.from);
+            put_u64(&mut out, data.start);
+            put_u64(&mut out, data.end);
+            put_bytes(&mut out, data.value.as_ref().as_ref());
+        }
+        Message::Join(data) => {
+            out.push(JOIN);
+            put_u64(&mut out, data.from);
+        }
+        Message::State(data) => {
+            out.push(STATE);
+            put_u64(&mut out, data.from);
+            put_u64(&mut out, data.to);
+            put_u64(&mut out, data.promised_n);
+            put_u32(&mut out, data.accepted.len() as u32);
+            for (slot, n, value) in &data.accepted {
+                put_u64(&mut out, *slot);
+                put_u64(&mut out, *n);
+                put_bytes(&mut out, value.as_ref().as_ref());
+            }
+            put_u32(&mut out, data.decided.len() as u32);
+            for (slot, value) in &data.decided {
+                put_u64(&mut out, *slot);
+                put_bytes(&mut out, value.as_ref().as_ref());
+            }
+        }
+        Message::InstallSnapshot(data) => {
+            out.push(INSTALL_SNAPSHOT);
+            put_u64(&mut out, data.from);
+            put_u64(&mut out, data.to);
+            put_u64(&mut out, data.index);
+            put_bytes(&mut out, data.value.as_ref().as_ref());
+        }
+        Message::Learn(data) => {
+            out.push(LEARN);
+            put_u64(&mut out, data.slot);
+            put_u64(&mut out, data.id);
+            put_u64(&mut out, data.from);
+            put_u32(&mut out, data.certificate.len() as u32);
+            for from in &data.certificate {
+                put_u64(&mut out, *from);
+            }
+            put_bytes(&mut out, data.value.as_ref().as_ref());
+        }
+        Message::Read(data) => {
+            out.push(READ);
+            put_u64(&mut out, data.from);
+            put_u64(&mut out, data.id);
+        }
+        Message::ReadReply(data) => {
+            out.push(READ_REPLY);
+            put_u64(&mut out, data.from);
+            put_u64(&mut out, data.to);
+            put_u64(&mut out, data.id);
+            put_u64(&mut out, data.horizon);
+        }
+        Message::Digest(data) => {
+            out.push(DIGEST);
+            put_u64(&mut out, data.from);
+            put_u64(&mut out, data.start);
+            put_u64(&mut out, data.truncated);
+            put_u64(&mut out, data.range);
+            put_u32(&mut out, data.hashes.len() as u32);
+            for hash in &data.hashes {
+                put_u64(&mut out, *hash);
+            }
+        }
+        Message::Repair(data) => {
+            out.push(REPAIR);
+            put_u64(&mut out, data.from);
+            put_u64(&mut out, data.to);
+            put_u32(&mut out, data.accepted.len() as u32);
+            for (slot, n, fast, value) in &data.accepted {
+                put_u64(&mut out, *slot);
+                put_u64(&mut out, *n);
+                out.push(*fast as u8);
+                put_bytes(&mut out, value.as_ref().as_ref());
+            }
+        }
+    }
+    out
+}
+
+/// Decodes a single message from `bytes`, which must hold nothing else.
+pub fn decode<T>(bytes: &[u8]) -> Result<Message<T>, DecodeError>
+where
+    T: for<'a> From<&'a [u8]>,
+{
+    decode_with(bytes, T::from)
+}
+
+/// Decodes a single message from `bytes` like `decode`, without copying the
+/// values it carries: each one is a `Bytes` pointing into `bytes`.
+#[cfg(feature = "bytes")]
+pub fn decode_bytes(bytes: &Bytes) -> Result<Message<Bytes>, DecodeError> {
+    decode_with(bytes, |value| bytes.slice_ref(value))
+}
+
+/// Decodes a single message from `bytes`, building each value it carries
+/// with `value`.
+fn decode_with<'a, T, F>(bytes: &'a [u8], value: F) -> Result<Message<T>, DecodeError>
+where
+    F: Fn(&'a [u8]) -> T,
+{
+    let mut r = Reader { bytes };
+    let msg = match r.u8()? {
+        PREPARE => Message::Prepare(ProposalData {
+            slot: r.u64()?,
+            id: r.u64()?,
+            from: r.u64()?,
+        }),
+        PROMISE => {
+            let (slot, id, from) = (r.u64()?, r.u64()?, r.u64()?);
+            let accepted_n = if r.bool()? { Some(r.u64()?) } else { None };
+            let value = if r.bool()? {
+                Some(Arc::new(value(r.bytes()?)))
+            } else {
+                None
+            };
+            Message::Promise(PromiseData {
+                slot,
+                id,
+                accepted_n,
+                value,
+                from,
+            })
+        }
+        ACCEPT => Message::Accept(AcceptData {
+            slot: r.u64()?,
+            id: r.u64()?,
+            implicit_prepare: r.bool()?,
+            value: Arc::new(value(r.bytes()?)),
+        }),
+        ACCEPTED => Message::Accepted(AcceptedData {
+            slot: r.u64()?,
+            id: r.u64()?,
+            from: r.u64()?,
+            fast: r.bool()?,
+            value: Arc::new(value(r.bytes()?)),
+        }),
+        NACK => {
+            let (slot, id, from, promised_n) = (r.u64()?, r.u64()?, r.u64()?, r.u64()?);
+            let leader = if r.bool()? { Some(r.u64()?) } else { None };
+            Message::Nack(NackData {
+                slot,
+                id,
+                from,
+                promised_n,
+                leader,
+            })
+        }
+        ANY => Message::Any(ProposalData {
+            slot: r.u64()?,
+            id: r.u64()?,
+            from: r.u64()?,
+        }),
+        PROPOSE => Message::Propose(ProposeData {
+            slot: r.u64()?,
+            value: Arc::new(value(r.bytes()?)),
+        }),
+        SKIP => Message::Skip(SkipData {
+            from: r.u64()?,
+            start: r.u64()?,
+            end: r.u64()?,
+            value: Arc::new(value(r.bytes()?)),
+        }),
+        JOIN => Message::Join(JoinData { from: r.u64()? }),
+        STATE => {
+            let (from, to, promised_n) = (r.u64()?, r.u64()?, r.u64()?);
+            let mut accepted = Vec::new();
+            for _ in 0..r.u32()? {
+                let (slot, n) = (r.u64()?, r.u64()?);
+                accepted.push((slot, n, Arc::new(value(r.bytes()?))));
+            }
+            let mut decided = Vec::new();
+            for _ in 0..r.u32()? {
+                let slot = r.u64()?;
+                decided.push((slot, Arc::new(value(r.bytes()?))));
+            }
+            Message::State(StateData {
+                from,
+                to,
+                promised_n,
+                accepted,
+                decided,
+            })
+        }
+        INSTALL_SNAPSHOT => Message::InstallSnapshot(SnapshotData {
+            from: r.u64()?,
+            to: r.u64()?,
+            index: r.u64()?,
+            value: Arc::new(value(r.bytes()?)),
+        }),
+        LEARN => {
+            let (slot, id, from) = (r.u64()?, r.u64()?, r.u64()?);
+            let mut certificate = Vec::new();
+            for _ in 0..r.u32()? {
+                certificate.push(r.u64()?);
+            }
+            Message::Learn(LearnData {
+                slot,
+                id,
+                from,
+                value: Arc::new(value(r.bytes()?)),
+                certificate,
+            })
+        }
+        READ => Message::Read(ReadData {
+            from: r.u64()?,
+            id: r.u64()?,
+        }),
+        READ_REPLY => Message::ReadReply(ReadReplyData {
+            from: r.u64()?,
+            to: r.u64()?,
+            id: r.u64()?,
+            horizon: r.u64()?,
+        }),
+        DIGEST => {
+            let (from, start, truncated) = (r.u64()?, r.u64()?, r.u64()?);
+            let range = r.u64()?;
+            let mut hashes = Vec::new();
+            for _ in 0..r.u32()? {
+                hashes.push(r.u64()?);
+            }
+            Message::Digest(DigestData {
+                from,
+                start,
+                truncated,
+                range,
+                hashes,
+            })
+        }
+        REPAIR => {
+            let (from, to) = (r.u64()?, r.u64()?);
+            let mut accepted = Vec::new();
+            for _ in 0..r.u32()? {
+                let (slot, n, fast) = (r.u64()?, r.u64()?, r.bool()?);
+                accepted.push((slot, n, fast, Arc::new(value(r.bytes()?))));
+            }
+            Message::Repair(RepairData { from, to, accepted })
+        }
+        tag => return Err(DecodeError::UnknownTag(tag)),
+    };
+    if !r.bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes);
+    }
+    Ok(msg)
+}
+
+pub(crate) fn put_u64(out: &mut Vec<u8>, n: u64) {
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+pub(crate) fn put_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+pub(crate) fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(DecodeError::InvalidBool(b)),
+        }
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, DecodeError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(buf))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, DecodeError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.u32()?;
+        self.take(len as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn wire_roundtrip() {
+        let msg: Message<Vec<u8>> = Message::Promise(PromiseData {
+            slot: 3,
+            id: 7,
+            accepted_n: Some(5),
+            value: Some(Arc::new(vec![1, 2, 3])),
+            from: 2,
+        });
+
+        assert_eq!(decode(&encode(&msg)), Ok(msg));
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn wire_decode_bytes() {
+        let msg: Message<Bytes> = Message::Accepted(AcceptedData {
+            slot: 3,
+            id: 7,
+            from: 2,
+            fast: false,
+            value: Arc::new(Bytes::from_static(b"value")),
+        });
+        let bytes = Bytes::from(encode(&msg));
+        let decoded = decode_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded, msg);
+
+        // The value points into the buffer decoded.
+        if let Message::Accepted(data) = decoded {
+            let range = bytes.as_ptr_range();
+            assert!(range.contains(&data.value.as_ptr()));
+        }
+    }
+
+    #[test]
+    fn wire_decode_errors() {
+        assert_eq!(
+            decode::<Vec<u8>>(&[PREPARE, 0]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        assert_eq!(
+            decode::<Vec<u8>>(&[0xff]),
+            Err(DecodeError::UnknownTag(0xff))
+        );
+
+        let mut bytes = encode::<Vec<u8>>(&Message::Join(JoinData { from: 1 }));
+        bytes.push(0);
+
+        assert_eq!(decode::<Vec<u8>>(&bytes), Err(DecodeError::TrailingBytes));
+
+        let mut bytes = encode::<Vec<u8>>(&Message::Prepare(ProposalData {
+            slot: 0,
+            id: 1,
+            from: 1,
+        }));
+        // An `Accept` whose `implicit_prepare` is neither `0x00` nor `0x01`.
+        bytes[0] = ACCEPT;
+        bytes.truncate(17);
+        bytes.push(2);
+
+        assert_eq!(decode::<Vec<u8>>(&bytes), Err(DecodeError::InvalidBool(2)));
+    }
+}
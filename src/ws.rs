@@ -0,0 +1,235 @@
+//! WebSocket transport
+//!
+//! Browsers can neither listen for connections nor open plain TCP or QUIC
+//! ones, but they speak WebSocket. A `WsHub` accepts WebSocket connections,
+//! e.g. from `Learner`s compiled to WASM observing the log from a browser,
+//! and dials out to other hubs. Every connection carries messages both ways,
+//! each in a binary WebSocket message holding its `wire` encoding, which is
+//! all a browser peer needs to implement.
+//!
+//! Messages received from any peer are handed out by `recv`, while a
+//! `WsMessenger` broadcasts those a role sends to every connected peer.
+
+use crate::message::{Message, Messenger, Slot};
+use crate::wire::{decode, encode};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use futures_util::{SinkExt, StreamExt};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::WebSocketStream;
+
+/// The outgoing queue of every connected peer, by connection.
+#[derive(Default)]
+struct Peers {
+    next: u64,
+    queues: BTreeMap<u64, UnboundedSender<Vec<u8>>>,
+}
+
+type SharedPeers = Arc<Mutex<Peers>>;
+
+fn lock(peers: &SharedPeers) -> MutexGuard<'_, Peers> {
+    peers.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// WebSocket connections to the peers of a node. See the module
+/// documentation.
+pub struct WsHub<T> {
+    /// Address listened on, if any
+    addr: Option<SocketAddr>,
+    peers: SharedPeers,
+    /// Sending half of `inbox`, handed to every connection
+    sender: UnboundedSender<Message<T>>,
+    /// Messages read from any connection
+    inbox: UnboundedReceiver<Message<T>>,
+}
+
+impl<T> WsHub<T>
+where
+    T: for<'a> From<&'a [u8]> + Send + Sync + 'static,
+{
+    /// Creates a `WsHub` with no connections, which only dials out.
+    pub fn new() -> Self {
+        let (sender, inbox) = unbounded_channel();
+        Self {
+            addr: None,
+            peers: SharedPeers::default(),
+            sender,
+            inbox,
+        }
+    }
+
+    /// Creates a `WsHub` accepting connections on `addr`, on a tokio task.
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let mut hub = Self::new();
+        hub.addr = Some(listener.local_addr()?);
+        let (peers, sender) = (hub.peers.clone(), hub.sender.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (peers, sender) = (peers.clone(), sender.clone());
+                tokio::spawn(async move {
+                    if let Ok(ws) = tokio_tungstenite::accept_async(stream).await {
+                        attach(ws, peers, sender);
+                    }
+                });
+            }
+        });
+        Ok(hub)
+    }
+
+    /// The address the hub accepts connections on, if any.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// Connects to the hub at `url`, e.g. `ws://10.0.0.1:9000`.
+    pub async fn connect(&self, url: &str) -> io::Result<()> {
+        let (ws, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .map_err(io::Error::other)?;
+        attach(ws, self.peers.clone(), self.sender.clone());
+        Ok(())
+    }
+
+    /// The number of peers connected.
+    pub fn peers(&self) -> usize {
+        lock(&self.peers).queues.len()
+    }
+
+    /// A `Messenger` broadcasting to every peer connected.
+    pub fn messenger(&self) -> WsMessenger<T> {
+        WsMessenger {
+            peers: self.peers.clone(),
+            resolutions: None,
+        }
+    }
+
+    /// Waits for a message from any peer.
+    pub async fn recv(&mut self) -> Option<Message<T>> {
+        self.inbox.recv().await
+    }
+}
+
+impl<T> Default for WsHub<T>
+where
+    T: for<'a> From<&'a [u8]> + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Adds `ws` to `peers`, carrying messages both ways over it on tokio tasks
+/// until it closes.
+fn attach<S, T>(ws: WebSocketStream<S>, peers: SharedPeers, inbox: UnboundedSender<Message<T>>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T: for<'a> From<&'a [u8]> + Send + Sync + 'static,
+{
+    let (mut sink, mut stream) = ws.split();
+    let (queue, mut outbox) = unbounded_channel::<Vec<u8>>();
+    let id = {
+        let mut peers = lock(&peers);
+        let id = peers.next;
+        peers.next += 1;
+        peers.queues.insert(id, queue);
+        id
+    };
+    tokio::spawn(async move {
+        while let Some(bytes) = outbox.recv().await {
+            if sink.send(WsMessage::binary(bytes)).await.is_err() {
+                return;
+            }
+        }
+        let _ = sink.close().await;
+    });
+    tokio::spawn(async move {
+        while let Some(Ok(frame)) = stream.next().await {
+            match frame {
+                WsMessage::Binary(bytes) => {
+                    // A malformed message is dropped like a lost one.
+                    if let Ok(msg) = decode(&bytes) {
+                        let _ = inbox.send(msg);
+                    }
+                }
+                WsMessage::Close(_) => break,
+                _ => {}
+            }
+        }
+        lock(&peers).queues.remove(&id);
+    });
+}
+
+/// A `Messenger` broadcasting every message to the peers connected to a
+/// `WsHub`.
+pub struct WsMessenger<T> {
+    peers: SharedPeers,
+    /// Channel notified of every resolved proposal
+    pub resolutions: Option<UnboundedSender<(Slot, Arc<T>)>>,
+}
+
+impl<T: AsRef<[u8]>> WsMessenger<T> {
+    fn broadcast(&self, msg: Message<T>) {
+        let bytes = encode(&msg);
+        // A peer gone is as good as a lost message.
+        for queue in lock(&self.peers).queues.values() {
+            let _ = queue.send(bytes.clone());
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>> Messenger<T> for WsMessenger<T> {
+    fn send_prepare(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_promise(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_accept(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn send_accepted(&mut self, msg: Message<T>) {
+        self.broadcast(msg);
+    }
+
+    fn on_resolution(&mut self, slot: Slot, value: Arc<T>) {
+        if let Some(ref resolutions) = self.resolutions {
+            let _ = resolutions.send((slot, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::JoinData;
+
+    #[tokio::test]
+    async fn ws_hub() {
+        let mut server: WsHub<Vec<u8>> = WsHub::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let mut client: WsHub<Vec<u8>> = WsHub::new();
+        let url = std::format!("ws://{}", server.local_addr().unwrap());
+        client.connect(&url).await.unwrap();
+        while server.peers() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let join = Message::Join(JoinData { from: 1 });
+        client.messenger().send_join(join.clone());
+        assert_eq!(server.recv().await, Some(join));
+
+        let join = Message::Join(JoinData { from: 2 });
+        server.messenger().send_join(join.clone());
+        assert_eq!(client.recv().await, Some(join));
+    }
+}
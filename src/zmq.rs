@@ -0,0 +1,283 @@
+//! ZeroMQ transport
+//!
+//! Carries `Message`s over [ZeroMQ](https://zeromq.org) sockets, through the
+//! pure Rust [zeromq](https://docs.rs/zeromq) crate, so that nodes can sit
+//! behind the proxies and brokers of an existing ZMQ deployment. Every node
+//! binds a ROUTER socket, and connects a DEALER socket to the ROUTER of each
+//! peer, announcing its node ID as its identity: the ROUTER maps the identity
+//! each message arrives with back to the node it came from. Every message is
+//! a single frame holding its `wire` encoding.
+//!
+//! Each peer is sent to from a task of its own, which dials it until it
+//! answers, and dials it again whenever the connection breaks. Messages wait
+//! for the connection in a lane per `Message::priority`, the higher lanes
+//! drained first, so that control messages aren't held up behind values.
+//! Up to `MAX_QUEUED` messages wait in each lane; past that, they are lost,
+//! which Paxos tolerates like any other lost message.
+
+use crate::config::NodeId;
+use crate::message::{Message, Messenger, Slot};
+use crate::priority::Priority;
+use crate::wire::{decode, encode};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use std::io;
+use tokio::sync::mpsc::{
+    channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender,
+};
+use tokio::task::JoinHandle;
+use zeromq::util::PeerIdentity;
+use zeromq::{
+    DealerSocket, RouterSocket, Socket, SocketOptions, SocketRecv, SocketSend, ZmqMessage,
+};
+
+/// The most messages of a priority waiting for a peer to be connected to.
+pub const MAX_QUEUED: usize = 1024;
+
+/// The identity node `id` announces to its peers.
+fn identity(id: NodeId) -> PeerIdentity {
+    PeerIdentity::try_from(id.to_be_bytes().to_vec()).expect("identity within 255 bytes")
+}
+
+/// The node announcing `identity`, if it's one of ours.
+fn node_id(identity: &[u8]) -> Option<NodeId> {
+    identity.try_into().ok().map(NodeId::from_be_bytes)
+}
+
+/// The queues of messages waiting for a peer, one per `Priority`.
+struct Lanes([Sender<Vec<u8>>; 3]);
+
+impl Lanes {
+    /// Queues `bytes` in the lane of `priority`, dropping them if it's full.
+    fn send(&self, priority: Priority, bytes: Vec<u8>) {
+        let _ = self.0[priority as usize].try_send(bytes);
+    }
+}
+
+/// Sends `Message`s to every node of the cluster over ZeroMQ, and receives
+/// theirs.
+pub struct ZmqTransport<T> {
+    /// The node's ID
+    id: NodeId,
+    /// The endpoint the ROUTER socket is bound to
+    endpoint: String,
+    /// Queues of every node's DEALER task
+    queues: Arc<BTreeMap<NodeId, Lanes>>,
+    /// Messages read from any node, along with their sender
+    inbox: UnboundedReceiver<(NodeId, Message<T>)>,
+    /// Task reading the ROUTER socket, which is closed along with it
+    router: JoinHandle<()>,
+}
+
+impl<T> ZmqTransport<T>
+where
+    T: AsRef<[u8]> + for<'a> From<&'a [u8]> + Send + Sync + 'static,
+{
+    /// Binds a ROUTER socket to node `id`'s endpoint among `peers`, e.g.
+    /// `tcp://10.0.0.1:5555`, and starts dialling every node, this one
+    /// included, on tokio tasks.
+    pub async fn bind(id: NodeId, peers: BTreeMap<NodeId, String>) -> io::Result<Self> {
+        let endpoint = peers.get(&id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "node missing from its peers")
+        })?;
+        let mut router = RouterSocket::new();
+        let endpoint = router
+            .bind(endpoint)
+            .await
+            .map_err(io::Error::other)?
+            .to_string();
+
+        let (sender, inbox) = unbounded_channel();
+        let router = tokio::spawn(async move {
+            while let Ok(frames) = router.recv().await {
+                let (Some(from), Some(bytes)) = (frames.get(0), frames.get(1)) else {
+                    continue;
+                };
+                // Messages from strangers, or malformed, are dropped like
+                // lost ones.
+                let (Some(from), Ok(msg)) = (node_id(from), decode(bytes)) else {
+                    continue;
+                };
+                if sender.send((from, msg)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let queues = peers
+            .into_iter()
+            .map(|(peer, endpoint)| {
+                let (low, low_outbox) = channel(MAX_QUEUED);
+                let (normal, normal_outbox) = channel(MAX_QUEUED);
+                let (high, high_outbox) = channel(MAX_QUEUED);
+                let outboxes = [low_outbox, normal_outbox, high_outbox];
+                tokio::spawn(dial(id, endpoint, outboxes));
+                (peer, Lanes([low, normal, high]))
+            })
+            .collect();
+        Ok(Self {
+            id,
+            endpoint,
+            queues: Arc::new(queues),
+            inbox,
+            router,
+        })
+    }
+
+    /// The node's ID.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// The endpoint the node's ROUTER socket is bound to, with the port
+    /// resolved.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Sends `msg` to node `to`. The message is lost if too many of its
+    /// priority are already waiting for the node.
+    pub fn send(&self, to: NodeId, msg: &Message<T>) {
+        if let Some(lanes) = self.queues.get(&to) {
+            lanes.send(msg.priority(), encode(msg));
+        }
+    }
+
+    /// Sends `msg` to every node, this one included.
+    pub fn broadcast(&self, msg: &Message<T>) {
+        broadcast(&self.queues, msg);
+    }
+
+    /// A `Messenger` broadcasting to every node.
+    pub fn messenger(&self) -> ZmqMessenger<T> {
+        ZmqMessenger {
+            queues: self.queues.clone(),
+            resolutions: None,
+        }
+    }
+
+    /// Waits for a message from any node, along with the node it came from.
+    pub async fn recv(&mut self) -> Option<(NodeId, Message<T>)> {
+        self.inbox.recv().await
+    }
+}
+
+impl<T> Drop for ZmqTransport<T> {
+    fn drop(&mut self) {
+        self.router.abort();
+    }
+}
+
+fn broadcast<T: AsRef<[u8]>>(queues: &BTreeMap<NodeId, Lanes>, msg: &Message<T>) {
+    let (priority, bytes) = (msg.priority(), encode(msg));
+    for lanes in queues.values() {
+        lanes.send(priority, bytes.clone());
+    }
+}
+
+/// Sends the messages of `outboxes`, one per `Priority`, to `endpoint` as
+/// node `id`, the higher priorities first, connecting again whenever the
+/// connection breaks, until the transport is dropped.
+async fn dial(id: NodeId, endpoint: String, outboxes: [Receiver<Vec<u8>>; 3]) {
+    let [mut low, mut normal, mut high] = outboxes;
+    loop {
+        let mut options = SocketOptions::default();
+        options.peer_identity(identity(id));
+        let mut dealer = DealerSocket::with_options(options);
+        // Retries until the peer answers.
+        if dealer.connect(&endpoint).await.is_err() {
+            return;
+        }
+        loop {
+            let bytes = tokio::select! {
+                biased;
+                Some(bytes) = high.recv() => bytes,
+                Some(bytes) = normal.recv() => bytes,
+                Some(bytes) = low.recv() => bytes,
+                else => return,
+            };
+            if dealer.send(ZmqMessage::from(bytes)).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// A `Messenger` broadcasting every message to the nodes of a
+/// `ZmqTransport`.
+pub struct ZmqMessenger<T> {
+    queues: Arc<BTreeMap<NodeId, Lanes>>,
+    /// Channel notified of every resolved proposal
+    pub resolutions: Option<UnboundedSender<(Slot, Arc<T>)>>,
+}
+
+impl<T: AsRef<[u8]>> Messenger<T> for ZmqMessenger<T> {
+    fn send_prepare(&mut self, msg: Message<T>) {
+        broadcast(&self.queues, &msg);
+    }
+
+    fn send_promise(&mut self, msg: Message<T>) {
+        broadcast(&self.queues, &msg);
+    }
+
+    fn send_accept(&mut self, msg: Message<T>) {
+        broadcast(&self.queues, &msg);
+    }
+
+    fn send_accepted(&mut self, msg: Message<T>) {
+        broadcast(&self.queues, &msg);
+    }
+
+    fn on_resolution(&mut self, slot: Slot, value: Arc<T>) {
+        if let Some(ref resolutions) = self.resolutions {
+            let _ = resolutions.send((slot, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::JoinData;
+    use core::time::Duration;
+    use std::net::TcpListener;
+
+    fn free_endpoint() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        std::format!("tcp://{}", listener.local_addr().unwrap())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn zmq_transport_reconnects() {
+        let peers: BTreeMap<NodeId, String> = [(1, free_endpoint()), (2, free_endpoint())].into();
+        let mut a: ZmqTransport<Vec<u8>> = ZmqTransport::bind(1, peers.clone()).await.unwrap();
+        let mut b: ZmqTransport<Vec<u8>> = ZmqTransport::bind(2, peers.clone()).await.unwrap();
+
+        let join = Message::Join(JoinData { from: 1 });
+        a.messenger().send_join(join.clone());
+        assert_eq!(a.recv().await, Some((1, join.clone())));
+        assert_eq!(b.recv().await, Some((1, join.clone())));
+
+        // Node 2 restarts: node 1 dials it again. Its port is freed once
+        // the ROUTER's task winds down.
+        drop(b);
+        let mut b: ZmqTransport<Vec<u8>> = loop {
+            match ZmqTransport::bind(2, peers.clone()).await {
+                Ok(b) => break b,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let received = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                a.send(2, &join);
+                let recv = tokio::time::timeout(Duration::from_millis(50), b.recv());
+                if let Ok(received) = recv.await {
+                    return received;
+                }
+            }
+        });
+        assert_eq!(received.await.unwrap(), Some((1, join)));
+    }
+}
@@ -0,0 +1,114 @@
+#![cfg(all(feature = "arbitrary", not(loom)))]
+
+extern crate paxos_rust;
+
+use arbitrary::{Arbitrary, Unstructured};
+use paxos_rust::invariants::check_single_decree;
+use paxos_rust::sim::Rng;
+use paxos_rust::wire::{decode, encode};
+use paxos_rust::{
+    AcceptedData, Acceptor, ClusterConfig, LearnData, Learner, Message, Node, Proposer,
+};
+use std::sync::Arc;
+
+/// Slots a `Learner` follows beyond the highest one seen, as arbitrary slots
+/// would otherwise have it track every gap below them.
+const MAX_LEAD: u64 = 1024;
+
+/// A member's vote, or relayed decision: slot, sender, value and whether it's
+/// a `Learn`.
+type Vote = (u8, u8, u8, bool);
+
+/// Seeded stand-in for the inputs of the fuzz targets.
+fn arbitrary<T: for<'a> Arbitrary<'a>>(seed: u64) -> T {
+    let mut rng = Rng::new(seed);
+    let bytes: Vec<u8> = (0..4096).map(|_| rng.next_u64() as u8).collect();
+    T::arbitrary(&mut Unstructured::new(&bytes)).unwrap()
+}
+
+#[test]
+fn arbitrary_acceptor() {
+    for seed in 0..200 {
+        let msgs: Vec<Message<u64>> = arbitrary(seed);
+        let mut acceptor: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        for msg in msgs {
+            acceptor.step(msg);
+            assert_eq!(check_single_decree([&acceptor]), Ok(()));
+        }
+    }
+}
+
+#[test]
+fn arbitrary_proposer() {
+    for seed in 0..200 {
+        let (values, msgs): (Vec<u64>, Vec<Message<u64>>) = arbitrary(seed);
+        let mut proposer: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        for value in values {
+            proposer.prepare(value);
+        }
+        for msg in msgs {
+            proposer.step(msg);
+        }
+    }
+}
+
+#[test]
+fn arbitrary_learner() {
+    for seed in 0..200 {
+        let (msgs, votes): (Vec<Message<u64>>, Vec<Vote>) = arbitrary(seed);
+        let mut learner: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        learner.set_max_lead(Some(MAX_LEAD));
+        for msg in msgs {
+            learner.step(msg);
+        }
+        // Members voting for, or relaying, conflicting values for a handful of
+        // slots, as faulty `Acceptor`s would.
+        for (slot, from, value, learn) in votes {
+            let (slot, value) = (u64::from(slot % 4), Arc::new(u64::from(value % 2)));
+            let msg = if learn {
+                Message::Learn(LearnData {
+                    slot,
+                    id: 1,
+                    from: 1,
+                    value,
+                    certificate: vec![],
+                })
+            } else {
+                Message::Accepted(AcceptedData {
+                    slot,
+                    id: 1,
+                    value,
+                    from: u64::from(from % 3) + 1,
+                    fast: false,
+                })
+            };
+            learner.step(msg);
+        }
+    }
+}
+
+#[test]
+fn arbitrary_node() {
+    for seed in 0..200 {
+        let (values, msgs): (Vec<u64>, Vec<Message<u64>>) = arbitrary(seed);
+        let mut node: Node<u64> = Node::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        node.learner_mut().set_max_lead(Some(MAX_LEAD));
+        for value in values {
+            node.propose(value);
+        }
+        for msg in msgs {
+            node.step(msg);
+            node.poll_decided();
+        }
+    }
+}
+
+#[test]
+fn arbitrary_wire() {
+    for seed in 0..200 {
+        let msg: Message<Vec<u8>> = arbitrary(seed);
+        let decoded: Message<Vec<u8>> = decode(&encode(&msg)).unwrap();
+
+        assert_eq!(decoded, msg);
+    }
+}
@@ -1,51 +1,12 @@
+#![cfg(all(feature = "std", not(loom)))]
+
 extern crate paxos_rust;
 
-use paxos_rust::{Acceptor, Learner, Message, Messenger, Proposer};
-use std::hash::Hash;
-use std::sync::mpsc::{self, Sender};
-use std::sync::Arc;
+use paxos_rust::sync::{event_channel, ChannelMessenger};
+use paxos_rust::{Acceptor, ClusterConfig, Learner, Message, PaxosEvent, Proposer};
+use std::sync::mpsc;
 use std::thread;
 
-/// A `Messenger` that utilizes Channels to pass values between threads.
-pub struct ChannelMessenger<T> {
-    pub senders: Vec<Sender<Message<T>>>,
-}
-
-impl<T> Messenger<T> for ChannelMessenger<T>
-where
-    T: Eq + Hash + Clone,
-{
-    fn send_prepare(&mut self, msg: Message<T>) {
-        println!("PREPARE");
-        for sender in &self.senders {
-            sender.send(msg.clone()).unwrap();
-        }
-    }
-
-    fn send_promise(&mut self, msg: Message<T>) {
-        println!("PROMISE");
-        for sender in &self.senders {
-            sender.send(msg.clone()).unwrap();
-        }
-    }
-
-    fn send_accept(&mut self, msg: Message<T>) {
-        println!("ACCEPT");
-        for sender in &self.senders {
-            sender.send(msg.clone()).unwrap();
-        }
-    }
-
-    fn send_accepted(&mut self, msg: Message<T>) {
-        println!("ACCEPTED");
-        for sender in &self.senders {
-            sender.send(msg.clone()).unwrap();
-        }
-    }
-
-    fn on_resolution(&mut self, _proposal_n: u64, _value: Arc<T>) {}
-}
-
 #[test]
 /// Should demonstrate sending messages between Proposers and Acceptors,
 /// eventually reaching consensus on the proposed value.
@@ -53,14 +14,15 @@ fn basic_paxos() {
     let (acc_sender, acc_receiver) = mpsc::channel();
     let (proposer_sender, proposer_receiver) = mpsc::channel();
     let (learner_sender, learner_receiver) = mpsc::channel();
+    let (acc_events, acc_event_receiver) = event_channel();
+    let (proposer_events, proposer_event_receiver) = event_channel();
 
     thread::spawn(move || {
-        let mut acc: Acceptor<u64> = Acceptor::new(1);
-        let messenger = ChannelMessenger {
-            senders: vec![proposer_sender, learner_sender],
-        };
+        let mut acc: Acceptor<u64> = Acceptor::new(1, ClusterConfig::new(vec![1]));
+        let messenger = ChannelMessenger::new(vec![proposer_sender, learner_sender]);
 
-        acc.messenger = Some(Box::new(messenger));
+        acc.set_messenger(messenger);
+        acc.set_events(acc_events);
 
         loop {
             if let Ok(msg) = acc_receiver.recv() {
@@ -74,16 +36,15 @@ fn basic_paxos() {
     });
 
     let p_thread = thread::spawn(move || {
-        let mut proposer: Proposer<u64> = Proposer::new(1, 1); // quorum of 1
-        let messenger = ChannelMessenger {
-            senders: vec![acc_sender],
-        };
-        proposer.messenger = Some(Box::new(messenger));
+        let mut proposer: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1])); // quorum of 1
+        let messenger = ChannelMessenger::new(vec![acc_sender]);
+        proposer.set_messenger(messenger);
+        proposer.set_events(proposer_events);
 
         proposer.prepare(10);
 
         loop {
-            if proposer.last_accepted_n == 1 {
+            if proposer.last_accepted_n() == 1 {
                 break;
             }
             if let Ok(msg) = proposer_receiver.recv() {
@@ -97,21 +58,31 @@ fn basic_paxos() {
     });
 
     let l_thread = thread::spawn(move || {
-        let mut learner: Learner<u64> = Learner::new(1, 1); // quorum of 1
+        let mut learner: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1])); // quorum of 1
 
         loop {
-            if learner.last_accepted_n == 1 {
+            if learner.last_accepted_n() == 1 {
                 break;
             }
-            if let Ok(msg) = learner_receiver.recv() {
-                match msg {
-                    Message::Accepted(_) => learner.receive_accepted(msg),
-                    _ => {}
-                }
+            if let Ok(msg @ Message::Accepted(_)) = learner_receiver.recv() {
+                learner.receive_accepted(msg);
             }
         }
     });
 
     assert!(p_thread.join().is_ok());
     assert!(l_thread.join().is_ok());
+
+    // A single Acceptor lets the Proposer skip straight to the second phase.
+    assert_eq!(
+        proposer_event_receiver.try_iter().collect::<Vec<_>>(),
+        vec![
+            PaxosEvent::AcceptSent { instance: 0, n: 1 },
+            PaxosEvent::Decided { instance: 0 },
+        ]
+    );
+    assert_eq!(
+        acc_event_receiver.recv(),
+        Ok(PaxosEvent::Accepted { instance: 0, n: 1 })
+    );
 }
@@ -0,0 +1,144 @@
+#![cfg(not(loom))]
+
+extern crate paxos_rust;
+
+use paxos_rust::invariants::{check_agreement, check_single_decree, check_validity};
+use paxos_rust::{ClusterConfig, Effect, Message, Node};
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+/// A step of a schedule. Indices pick a pending message or a node, modulo
+/// their number.
+#[derive(Debug, Clone)]
+enum Op {
+    Deliver(usize),
+    Drop(usize),
+    Duplicate(usize),
+    Tick(usize),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        8 => any::<usize>().prop_map(Op::Deliver),
+        1 => any::<usize>().prop_map(Op::Drop),
+        1 => any::<usize>().prop_map(Op::Duplicate),
+        1 => any::<usize>().prop_map(Op::Tick),
+    ]
+}
+
+/// Nodes exchanging messages through a network the schedule controls.
+struct Cluster {
+    nodes: Vec<Node<u64>>,
+    /// Messages in transit, with the index of the node they're sent to
+    pending: Vec<(usize, Message<u64>)>,
+    now: u64,
+}
+
+impl Cluster {
+    fn new(config: ClusterConfig) -> Self {
+        let nodes = config
+            .members
+            .iter()
+            .map(|&id| {
+                let mut node = Node::new(id, config.clone());
+                node.proposer_mut().set_timeout(Some(50), u32::MAX);
+                node
+            })
+            .collect();
+        Self {
+            nodes,
+            pending: Vec::new(),
+            now: 0,
+        }
+    }
+
+    fn propose(&mut self, node: usize, value: u64) {
+        self.nodes[node].propose(value);
+        let effects = self.nodes[node].take_effects();
+        self.send(effects);
+    }
+
+    fn send(&mut self, effects: Vec<Effect<u64>>) {
+        for effect in effects {
+            for (to, node) in self.nodes.iter().enumerate() {
+                if let Some(msg) = effect.message_for(node.id()) {
+                    self.pending.push((to, msg.clone()));
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, op: Op) {
+        match op {
+            Op::Tick(i) => {
+                let size = self.nodes.len();
+                let node = &mut self.nodes[i % size];
+                self.now += 25;
+                node.tick(self.now);
+                let effects = node.take_effects();
+                self.send(effects);
+            }
+            _ if self.pending.is_empty() => {}
+            Op::Deliver(i) => {
+                let (to, msg) = self.pending.swap_remove(i % self.pending.len());
+                let effects = self.nodes[to].step(msg);
+                self.send(effects);
+            }
+            Op::Drop(i) => {
+                self.pending.swap_remove(i % self.pending.len());
+            }
+            Op::Duplicate(i) => {
+                let copy = self.pending[i % self.pending.len()].clone();
+                self.pending.push(copy);
+            }
+        }
+    }
+
+    fn check(&self, proposed: &[u64]) -> Result<(), TestCaseError> {
+        let learners = || self.nodes.iter().map(Node::learner);
+        prop_assert_eq!(check_agreement(learners()), Ok(()));
+        prop_assert_eq!(check_validity(learners(), proposed), Ok(()));
+        prop_assert_eq!(
+            check_single_decree(self.nodes.iter().map(Node::acceptor)),
+            Ok(())
+        );
+        Ok(())
+    }
+}
+
+proptest! {
+    #[test]
+    fn invariants_single_proposer(
+        size in 1..=5u64,
+        values in vec(any::<u64>(), 1..4),
+        ops in vec(op(), 0..300),
+    ) {
+        let mut cluster = Cluster::new(ClusterConfig::new((1..=size).collect()));
+        for &value in &values {
+            cluster.propose(0, value);
+        }
+        for op in ops {
+            cluster.apply(op);
+            cluster.check(&values)?;
+        }
+    }
+
+    #[test]
+    fn invariants_leaders(
+        size in 2..=5u64,
+        values in vec((any::<usize>(), any::<u64>()), 1..6),
+        ops in vec(op(), 0..300),
+    ) {
+        let members: Vec<u64> = (1..=size).collect();
+        let config = ClusterConfig::new(members.clone()).with_leaders(members);
+        let mut cluster = Cluster::new(config);
+        for &(node, value) in &values {
+            cluster.propose(node % size as usize, value);
+        }
+        let proposed: Vec<u64> = values.iter().map(|&(_, value)| value).collect();
+        for op in ops {
+            cluster.apply(op);
+            cluster.check(&proposed)?;
+        }
+    }
+}
@@ -0,0 +1,86 @@
+#![cfg(not(loom))]
+
+extern crate paxos_rust;
+
+use paxos_rust::sim::{Rng, SimCluster};
+use paxos_rust::testing::{is_linearizable, History, Spec};
+
+/// An operation on a replicated register.
+#[derive(Debug, PartialEq, Clone)]
+enum Op {
+    Write(u64),
+    Read,
+}
+
+/// A log entry: an operation, tagged with the index of its `History` entry.
+type Entry = (usize, Op);
+
+struct Register;
+
+impl Spec for Register {
+    type State = u64;
+    type Input = Op;
+    type Output = u64;
+
+    fn init(&self) -> u64 {
+        0
+    }
+
+    fn step(&self, state: &mut u64, input: &Op) -> u64 {
+        if let Op::Write(value) = *input {
+            *state = value;
+        }
+        *state
+    }
+}
+
+/// Clients on every node run operations through the leader, node 1. An
+/// operation returns once the client's own node applied it, with the
+/// register's value at that point of the log.
+fn run(seed: u64) -> History<Op, u64> {
+    let mut sim: SimCluster<Entry> = SimCluster::new(3, seed);
+    let mut rng = Rng::new(seed);
+    let mut history = History::new();
+    let mut pending: Vec<(u64, usize)> = Vec::new();
+    let mut applied = [0; 3];
+    let mut register = [0; 3];
+
+    for step in 0..2000 {
+        if step % 50 == 0 && history.operations().len() < 30 {
+            let client = rng.between(1, 3);
+            let op = if rng.between(0, 1) == 0 {
+                Op::Write(rng.between(1, 100))
+            } else {
+                Op::Read
+            };
+            let index = history.call(client, op.clone());
+            sim.propose(1, (index, op));
+            pending.push((client, index));
+        }
+        sim.step();
+
+        for client in 1..=3u64 {
+            let node = (client - 1) as usize;
+            while let Some((_, entry)) = sim.decided(client).get(applied[node]) {
+                let (index, ref op) = **entry;
+                let output = Register.step(&mut register[node], op);
+                if let Some(i) = pending.iter().position(|&p| p == (client, index)) {
+                    pending.swap_remove(i);
+                    history.ret(index, output);
+                }
+                applied[node] += 1;
+            }
+        }
+    }
+    history
+}
+
+#[test]
+fn linearizability_register() {
+    for seed in 0..20 {
+        let history = run(seed);
+
+        assert!(history.operations().iter().all(|op| op.ret.is_some()));
+        assert!(is_linearizable(&Register, &history));
+    }
+}
@@ -0,0 +1,85 @@
+//! Exhaustively checks the interleavings of threads driving shared roles.
+//!
+//! Run with `RUSTFLAGS="--cfg loom" cargo test --test loom --release`.
+#![cfg(loom)]
+
+extern crate paxos_rust;
+
+use loom::sync::mpsc;
+use loom::thread;
+use paxos_rust::sync::{ChannelMessenger, Shared};
+use paxos_rust::{AcceptedData, ClusterConfig, Learner, Message, PromiseData, Proposer};
+use std::sync::Arc;
+
+#[test]
+/// Promises racing each other must complete the first phase exactly once.
+fn concurrent_promises_send_one_accept() {
+    loom::model(|| {
+        let (acc_sender, acc_receiver) = mpsc::channel();
+
+        let mut proposer: Proposer<u64> = Proposer::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        proposer.set_messenger(ChannelMessenger::new(vec![acc_sender]));
+        proposer.prepare(10);
+        let proposer = Shared::new(proposer);
+
+        let threads: Vec<_> = (2..=3)
+            .map(|from| {
+                let proposer = proposer.clone();
+                thread::spawn(move || {
+                    proposer.deliver(Message::Promise(PromiseData {
+                        slot: 0,
+                        id: 1,
+                        accepted_n: None,
+                        value: None,
+                        from,
+                    }));
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(matches!(acc_receiver.try_recv(), Ok(Message::Prepare(_))));
+        assert!(matches!(acc_receiver.try_recv(), Ok(Message::Accept(_))));
+        assert!(acc_receiver.try_recv().is_err());
+    });
+}
+
+#[test]
+/// Accepted messages racing each other must resolve the slot exactly once.
+fn concurrent_accepted_resolve_once() {
+    loom::model(|| {
+        let (res_sender, res_receiver) = mpsc::channel();
+
+        let mut messenger = ChannelMessenger::new(Vec::new());
+        messenger.resolutions = Some(res_sender);
+        let mut learner: Learner<u64> = Learner::new(1, ClusterConfig::new(vec![1, 2, 3]));
+        learner.set_messenger(messenger);
+        let learner = Shared::new(learner);
+
+        let threads: Vec<_> = (1..=3)
+            .map(|from| {
+                let learner = learner.clone();
+                thread::spawn(move || {
+                    learner.deliver(Message::Accepted(AcceptedData {
+                        slot: 0,
+                        id: 1,
+                        value: Arc::new(10),
+                        from,
+                        fast: false,
+                    }));
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(res_receiver.try_recv().unwrap(), (0, Arc::new(10)));
+        assert!(res_receiver.try_recv().is_err());
+        assert_eq!(learner.lock().decided_value(0), Some(&Arc::new(10)));
+    });
+}
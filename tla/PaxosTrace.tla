@@ -0,0 +1,59 @@
+----------------------------- MODULE PaxosTrace -----------------------------
+(***************************************************************************)
+(* Follows a trace of paxos-rust, written as the module PaxosTraceLog by   *)
+(* TlaTrace::to_module, through the steps of Paxos. Each entry of the log  *)
+(* must be a step of the spec sending its message, or a stuttering step if *)
+(* the message was sent before, after which the variables of the acceptor  *)
+(* taking it must be those it noted. TLC reports a deadlock if the trace   *)
+(* strays from the spec, and checks Consistency along the way.             *)
+(***************************************************************************)
+EXTENDS Integers, FiniteSets, Sequences, PaxosTraceLog
+
+None == "none"
+
+Majorities == {Q \in SUBSET TraceAcceptor :
+                 2 * Cardinality(Q) > Cardinality(TraceAcceptor)}
+
+VARIABLES maxBal, maxVBal, maxVal, msgs, i
+
+P == INSTANCE Paxos WITH Value <- TraceValue, Acceptor <- TraceAcceptor,
+                         Quorum <- Majorities, Ballot <- TraceBallot
+
+Msg(e) ==
+  CASE e.action = "Phase1a" -> [type |-> "1a", bal |-> e.bal]
+    [] e.action = "Phase1b" -> [type |-> "1b", acc |-> e.acc, bal |-> e.bal,
+                                mbal |-> e.mbal, mval |-> e.val]
+    [] e.action = "Phase2a" -> [type |-> "2a", bal |-> e.bal, val |-> e.val]
+    [] e.action = "Phase2b" -> [type |-> "2b", acc |-> e.acc, bal |-> e.bal,
+                                val |-> e.val]
+
+Step(e) ==
+  CASE e.action = "Phase1a" -> P!Phase1a(e.bal)
+    [] e.action = "Phase1b" -> P!Phase1b(e.acc)
+    [] e.action = "Phase2a" -> P!Phase2a(e.bal, e.val)
+    [] e.action = "Phase2b" -> P!Phase2b(e.acc)
+
+Noted(e) ==
+  e.action \in {"Phase1b", "Phase2b"} =>
+    /\ maxBal'[e.acc] = e.maxBal
+    /\ maxVBal'[e.acc] = e.maxVBal
+    /\ maxVal'[e.acc] = e.maxVal
+
+Init == P!Init /\ i = 1
+
+Next ==
+  \/ /\ i \leq Len(TraceLog)
+     /\ LET e == TraceLog[i] IN
+          /\ IF Msg(e) \in msgs
+               THEN UNCHANGED <<maxBal, maxVBal, maxVal, msgs>>
+               ELSE Step(e) /\ Msg(e) \in msgs'
+          /\ Noted(e)
+     /\ i' = i + 1
+  \* The end of the trace is reached.
+  \/ /\ i > Len(TraceLog)
+     /\ UNCHANGED <<maxBal, maxVBal, maxVal, msgs, i>>
+
+Spec == Init /\ [][Next]_<<maxBal, maxVBal, maxVal, msgs, i>>
+
+Consistency == P!Consistency
+===============================================================================